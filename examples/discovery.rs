@@ -12,8 +12,8 @@ fn get_chromecast_name(dns_packet: &DnsResponse) -> String {
 			if let Some(RData::SRV(_)) = record.data() {
 				let name = record.name().to_utf8();
 				let name = name.strip_suffix('.').unwrap_or(&name);
-				let name = name.strip_suffix("_googlecast._tcp.local").unwrap_or(&name);
-				let name = name.strip_suffix('.').unwrap_or(&name);
+				let name = name.strip_suffix("_googlecast._tcp.local").unwrap_or(name);
+				let name = name.strip_suffix('.').unwrap_or(name);
 				Some(name.to_string())
 			} else {
 				None
@@ -45,7 +45,7 @@ fn main() {
 				);
 			}
 
-			DiscoveryEvent::ResponseUpdate { .. } => {}
+			_ => {}
 		})
 		.unwrap();
 }
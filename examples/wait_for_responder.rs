@@ -0,0 +1,47 @@
+use searchlight::{
+	broadcast::{BroadcasterBuilder, ServiceBuilder},
+	discovery::DiscoveryBuilder,
+	net::IpVersion,
+};
+use std::{
+	net::{IpAddr, Ipv4Addr},
+	str::FromStr,
+	time::Duration,
+};
+
+fn main() {
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.add_service(
+			ServiceBuilder::new("_searchlight._udp.local.", "HELLO-WORLD", 1234)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+				.build()
+				.unwrap(),
+		)
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background();
+
+	let discovery = DiscoveryBuilder::new()
+		.loopback()
+		.service("_searchlight._udp.local.")
+		.unwrap()
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background(|_| {});
+
+	println!("Waiting for discovery to find responder...");
+
+	match discovery.wait_for_responder(Duration::from_secs(10)) {
+		Some(responder) => println!("Found responder at {}", responder.addr),
+		None => println!("No responder was found within 10 seconds"),
+	}
+
+	println!("Shutting down...");
+
+	broadcaster.shutdown().unwrap();
+	discovery.shutdown().unwrap();
+
+	println!("Done!");
+}
@@ -0,0 +1,56 @@
+use std::{any::Any, future::Future};
+
+/// Where a discovery, broadcaster or node instance is running in the background.
+pub(crate) enum Background<T> {
+	/// On a thread of its own, with its own Tokio runtime.
+	Thread(std::thread::JoinHandle<T>),
+
+	/// On a blocking thread of a Tokio runtime that was provided by the user.
+	Runtime {
+		task: tokio::task::JoinHandle<()>,
+		result: std::sync::mpsc::Receiver<T>,
+	},
+}
+impl<T: Send + 'static> Background<T> {
+	/// Runs the future returned by `run` to completion on one of `runtime`'s blocking threads, using the runtime's I/O and timer drivers.
+	///
+	/// The future itself doesn't need to be `Send`, because it never leaves the thread it was created on.
+	pub(crate) fn spawn_on<F, Fut>(runtime: tokio::runtime::Handle, run: F) -> Self
+	where
+		F: FnOnce() -> Fut + Send + 'static,
+		Fut: Future<Output = T>,
+	{
+		let (result_tx, result) = std::sync::mpsc::sync_channel(1);
+
+		let handle = runtime.clone();
+		let task = runtime.spawn_blocking(move || {
+			result_tx.send(handle.block_on(run())).ok();
+		});
+
+		Self::Runtime { task, result }
+	}
+
+	#[inline]
+	pub(crate) fn is_finished(&self) -> bool {
+		match self {
+			Self::Thread(thread) => thread.is_finished(),
+			Self::Runtime { task, .. } => task.is_finished(),
+		}
+	}
+
+	#[inline]
+	/// Whether this is running on a runtime provided by the user, in which case blocking until it stops could deadlock that runtime.
+	pub(crate) fn is_on_runtime(&self) -> bool {
+		matches!(self, Self::Runtime { .. })
+	}
+
+	/// Blocks until the instance stops running, returning its result.
+	pub(crate) fn join(self) -> std::thread::Result<T> {
+		match self {
+			Self::Thread(thread) => thread.join(),
+			Self::Runtime { result, .. } => result
+				.recv()
+				.map_err(|_| Box::new("Background task panicked or its runtime was shut down") as Box<dyn Any + Send>),
+		}
+	}
+}
@@ -9,16 +9,15 @@
 //! ```rust, no_run
 //! use searchlight::{
 //!     broadcast::{BroadcasterBuilder, ServiceBuilder},
-//!     discovery::{DiscoveryBuilder, DiscoveryEvent},
+//!     discovery::DiscoveryBuilder,
 //!     net::IpVersion,
 //! };
 //! use std::{
 //!     net::{IpAddr, Ipv4Addr},
 //!     str::FromStr,
+//!     time::Duration,
 //! };
 //!
-//! let (found_tx, found_rx) = std::sync::mpsc::sync_channel(0);
-//!
 //! let broadcaster = BroadcasterBuilder::new()
 //!     .loopback()
 //!     .add_service(
@@ -40,15 +39,11 @@
 //!     .unwrap()
 //!     .build(IpVersion::V4)
 //!     .unwrap()
-//!     .run_in_background(move |event| {
-//!         if let DiscoveryEvent::ResponderFound(responder) = event {
-//!             found_tx.try_send(responder).ok();
-//!         }
-//!     });
+//!     .run_in_background(|_| {});
 //!
 //! println!("Waiting for discovery to find responder...");
 //!
-//! println!("{:#?}", found_rx.recv().unwrap());
+//! println!("{:#?}", discovery.wait_for_responder(Duration::from_secs(10)).unwrap());
 //!
 //! println!("Shutting down...");
 //!
@@ -59,16 +54,25 @@
 //! ```
 
 use crate::{
-	errors::MultiIpIoError,
-	socket::{AsyncMdnsSocket, MdnsSocket, MdnsSocketRecv},
+	background::Background,
+	errors::{BadDnsNameError, MultiIpIoError, NameField},
+	logging,
+	net::{reply_addr, ReconnectPolicy, RecvInterface},
+	socket::{AsyncMdnsSocket, LoopError, MdnsSocket, MdnsSocketRecipe, RecvErrors},
+	transport::{MdnsTransport, MdnsTransportRecv},
+	util::{IntoDnsName, BROWSE_DOMAINS_QUERIES, SERVICES_META_QUERY},
 };
 use std::{
+	borrow::Cow,
 	collections::BTreeSet,
+	net::SocketAddr,
 	sync::{Arc, RwLock},
 };
 use trust_dns_client::{
-	op::Message as DnsMessage,
-	serialize::binary::{BinDecodable, BinEncodable, BinEncoder},
+	op::{Message as DnsMessage, MessageType as DnsMessageType, OpCode as DnsOpCode, Query as DnsQuery},
+	proto::error::ProtoError,
+	rr::{Name as DnsName, RecordType as DnsRecordType},
+	serialize::binary::BinDecodable,
 };
 
 /// Errors that can occur while broadcasting or initializing a broadcaster.
@@ -81,12 +85,86 @@ mod service;
 use service::ServiceDnsResponse;
 pub use service::{IntoServiceTxt, Service, ServiceBuilder};
 
+mod txt;
+pub use txt::TxtRecord;
+
+mod host;
+pub use host::Host;
+
+mod mtu;
+#[cfg(test)]
+pub(crate) use mtu::{encode, max_payload_v6, split_response};
+
 mod handle;
 pub use handle::BroadcasterHandle;
-use handle::*;
+
+mod throttle;
+pub(crate) use throttle::ResponseThrottle;
+
+mod delay;
+pub(crate) use delay::{DelayedResponse, DelayedResponses, ResponseDelay};
+
+mod announce;
+pub(crate) use announce::Announcements;
+
+type DecodeErrorFn = dyn Fn(SocketAddr, &[u8], &ProtoError) + Send + Sync;
+
+#[derive(Clone)]
+/// A function that is called with packets that couldn't be decoded, set using [`BroadcasterBuilder::on_decode_error`].
+pub(crate) struct DecodeErrorHook(Arc<DecodeErrorFn>);
+impl std::fmt::Debug for DecodeErrorHook {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("DecodeErrorHook").finish_non_exhaustive()
+	}
+}
+
+type UnmatchedQueryFn = dyn Fn(&DnsQuery, SocketAddr) + Send + Sync;
+
+#[derive(Clone)]
+/// A function that is called with queries that weren't answered, set using [`BroadcasterBuilder::on_unmatched_query`].
+pub(crate) struct UnmatchedQueryHook(Arc<UnmatchedQueryFn>);
+impl std::fmt::Debug for UnmatchedQueryHook {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("UnmatchedQueryHook").finish_non_exhaustive()
+	}
+}
 
 pub(crate) struct BroadcasterConfig {
 	services: BTreeSet<ServiceDnsResponse>,
+	answer_reverse_lookups: bool,
+	answer_service_types: bool,
+	combine_responses: bool,
+	minimal_unicast: bool,
+	browse_domains: Vec<DnsName>,
+	mtu: u16,
+	disable_name_compression: bool,
+	throttle: Option<ResponseThrottle>,
+	response_delay: Option<ResponseDelay>,
+	reconnect: ReconnectPolicy,
+	on_decode_error: Option<DecodeErrorHook>,
+	on_unmatched_query: Option<UnmatchedQueryHook>,
+	announcements: Arc<Announcements>,
+}
+impl BroadcasterConfig {
+	#[inline(always)]
+	pub(crate) fn reconnect(&self) -> &ReconnectPolicy {
+		&self.reconnect
+	}
+
+	#[inline(always)]
+	pub(crate) fn announcements(&self) -> Arc<Announcements> {
+		self.announcements.clone()
+	}
+
+	/// The responses that would be sent to a multicast `ANY` query for `name`, regardless of which interface it arrived on.
+	pub(crate) fn simulate_query(&self, name: DnsName) -> Vec<DnsMessage> {
+		let query = DnsQuery::query(name, DnsRecordType::ANY);
+		Broadcaster::answers(self, self.services.iter(), &query)
+			.unwrap_or_default()
+			.into_iter()
+			.map(|answer| answer.response.into_owned())
+			.collect()
+	}
 }
 
 /// A built mDNS broadcaster (server) instance, ready to be started.
@@ -96,18 +174,39 @@ pub(crate) struct BroadcasterConfig {
 /// A `Broadcaster` can be built using [`BroadcasterBuilder`].
 pub struct Broadcaster {
 	socket: MdnsSocket,
+	recipe: MdnsSocketRecipe,
 	config: Arc<RwLock<BroadcasterConfig>>,
 }
 impl Broadcaster {
+	/// Returns the local addresses that the broadcaster's socket(s) are bound to, one for each IP version in use (IPv4 first).
+	///
+	/// This is useful for logging. If the socket has to be [recreated](BroadcasterBuilder::reconnect_policy), it is bound the same way again.
+	pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, MultiIpIoError> {
+		self.socket.local_addrs()
+	}
+
+	/// Returns the responses that the broadcaster would send if it were asked about `name`, without sending anything.
+	///
+	/// The question is asked as a multicast `ANY` query, and is answered the same way a received query is, including subtypes, service instances,
+	/// DNS-SD meta-queries and reverse lookups. Services [restricted to some interfaces](ServiceBuilder::add_interface_v4) are answered as if the query
+	/// arrived on one of their interfaces. Each returned message is a separate packet.
+	///
+	/// This is useful for testing, and for comparing exactly what is advertised with what other mDNS responders advertise.
+	pub fn simulate_query(&self, name: impl IntoDnsName) -> Result<Vec<DnsMessage>, BadDnsNameError> {
+		let name = name.into_fqdn().map_err(BadDnsNameError::map(NameField::QueryName))?;
+		Ok(self.config.read().unwrap().simulate_query(name))
+	}
+
 	/// Run broadcasting on a new thread; in the background.
 	///
 	/// Returns a [`BroadcasterHandle`] that can be used to cleanly shut down the background thread.
 	pub fn run_in_background(self) -> BroadcasterHandle {
-		let Broadcaster { socket, config } = self;
+		let Broadcaster { socket, recipe, config } = self;
 
 		let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
 
 		let config_ref = config.clone();
+		let counters = recipe.options.counters.clone();
 		let thread = std::thread::spawn(move || {
 			tokio::runtime::Builder::new_current_thread()
 				.thread_name("Searchlight mDNS Broadcaster (Tokio)")
@@ -116,19 +215,42 @@ impl Broadcaster {
 				.unwrap()
 				.block_on(async move {
 					let socket = socket.into_async().await?;
-					Self::impl_run(&socket, socket.recv(vec![0; 4096]), config_ref, Some(shutdown_rx)).await;
-					Ok(())
+					Self::impl_run(socket, recipe, config_ref, Some(shutdown_rx)).await
 				})
 		});
 
-		BroadcasterHandle(BroadcasterHandleDrop(Some(BroadcasterHandleInner { config, thread, shutdown_tx })))
+		BroadcasterHandle::new(config, Background::Thread(thread), shutdown_tx, counters)
+	}
+
+	/// Run broadcasting in the background, on a Tokio runtime that you already have, instead of on a new thread with its own runtime.
+	///
+	/// The broadcaster runs on one of the runtime's blocking threads, using its I/O and timer drivers, so any thread names, stack sizes or
+	/// hooks configured on the runtime apply.
+	///
+	/// If the runtime is a current-thread runtime, it must be kept running (with [`Runtime::block_on`](tokio::runtime::Runtime::block_on))
+	/// for the broadcaster to make progress.
+	///
+	/// Returns a [`BroadcasterHandle`] that can be used to cleanly shut down the broadcaster.
+	pub fn run_on(self, runtime: tokio::runtime::Handle) -> BroadcasterHandle {
+		let Broadcaster { socket, recipe, config } = self;
+
+		let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+
+		let config_ref = config.clone();
+		let counters = recipe.options.counters.clone();
+		let background = Background::spawn_on(runtime, move || async move {
+			let socket = socket.into_async().await?;
+			Self::impl_run(socket, recipe, config_ref, Some(shutdown_rx)).await
+		});
+
+		BroadcasterHandle::new(config, background, shutdown_tx, counters)
 	}
 
 	/// Run broadcasting on the current thread.
 	///
 	/// This will start a new Tokio runtime on the current thread and block until a fatal error occurs.
 	pub fn run(self) -> Result<(), MultiIpIoError> {
-		let Broadcaster { socket, config } = self;
+		let Broadcaster { socket, recipe, config } = self;
 
 		tokio::runtime::Builder::new_current_thread()
 			.thread_name("Searchlight mDNS Broadcaster (Tokio)")
@@ -137,85 +259,409 @@ impl Broadcaster {
 			.unwrap()
 			.block_on(async move {
 				let socket = socket.into_async().await?;
-				Self::impl_run(&socket, socket.recv(vec![0; 4096]), config, None).await;
-				Ok(())
+				Self::impl_run(socket, recipe, config, None).await
 			})
 	}
 }
 impl Broadcaster {
 	async fn impl_run(
-		tx: &AsyncMdnsSocket,
-		mut rx: MdnsSocketRecv<'_>,
+		mut socket: AsyncMdnsSocket,
+		recipe: MdnsSocketRecipe,
 		config: Arc<RwLock<BroadcasterConfig>>,
 		shutdown_rx: Option<tokio::sync::oneshot::Receiver<()>>,
-	) {
+	) -> Result<(), MultiIpIoError> {
+		let run = async {
+			loop {
+				let err = match Self::recv_loop(&socket, &mut socket.recv(vec![0; 4096]), &config).await {
+					LoopError::Recreate(err) => err,
+					LoopError::Abort(err) => return Err(err),
+				};
+				logging::warn!("Receiving on mDNS socket keeps failing, recreating it: {err}");
+
+				let reconnect = config.read().unwrap().reconnect.clone();
+				socket = recipe.recreate(&reconnect).await;
+			}
+		};
+
 		if let Some(shutdown_rx) = shutdown_rx {
 			tokio::select! {
 				biased;
-				_ = Self::recv_loop(tx, &mut rx, &config) => (),
-				_ = shutdown_rx => (),
+				res = run => res,
+				_ = shutdown_rx => Ok(()),
 			}
 		} else {
-			Self::recv_loop(tx, &mut rx, &config).await
+			run.await
 		}
 	}
 
-	#[allow(clippy::await_holding_lock)]
-	// It's fine to hold the lock in this case because we're using the current-thread runtime.
-	// The future just won't be Send.
-	async fn recv_loop(tx: &AsyncMdnsSocket, rx: &mut MdnsSocketRecv<'_>, config: &RwLock<BroadcasterConfig>) {
+	/// Answers queries until receiving keeps failing, or an error is fatal according to the [`ReconnectPolicy`].
+	pub(crate) async fn recv_loop(tx: &impl MdnsTransport, rx: &mut impl MdnsTransportRecv, config: &RwLock<BroadcasterConfig>) -> LoopError {
+		let (reconnect, announcements) = {
+			let config = config.read().unwrap();
+			(config.reconnect.clone(), config.announcements())
+		};
+		let mut errors = RecvErrors::new(&reconnect);
+
 		let mut send_buf = vec![0u8; 4096];
+		let mut delayed = DelayedResponses::default();
 		loop {
-			let ((count, addr), packet) = match rx.recv_multicast().await {
+			let recv = tokio::select! {
+				recv = rx.recv_multicast() => recv,
+				_ = delayed.due() => {
+					if let Err(err) = delayed.send_due(tx, &reconnect).await {
+						return err;
+					}
+					continue;
+				}
+				_ = announcements.ready() => {
+					if let Err(err) = announcements.send(tx, &reconnect).await {
+						return err;
+					}
+					continue;
+				}
+			};
+
+			let ((count, addr), interface, packet) = match recv {
 				Ok(recv) => recv,
 				Err(err) => {
-					log::warn!("Failed to receive on mDNS socket: {err}");
+					logging::warn!("Failed to receive on mDNS socket: {err}");
+					if let Some(stop) = errors.error().await {
+						return stop(err);
+					}
 					continue;
 				}
 			};
+			errors.ok();
 			if count == 0 {
 				continue;
 			}
 
-			let message = match DnsMessage::from_bytes(packet) {
-				Ok(message) if !message.truncated() => message,
-				_ => continue,
+			let message = match DnsMessage::from_bytes(&packet[..count]) {
+				Ok(message) => message,
+				Err(err) => {
+					Self::decode_error(config, addr, &packet[..count], &err);
+					continue;
+				}
 			};
 
-			let query = match message.query() {
-				Some(query) => query,
-				None => continue,
-			};
+			if let Err(err) = Self::handle_query(tx, &mut send_buf, &mut delayed, config, &message, addr, interface).await {
+				return err;
+			}
+		}
+	}
 
-			for service in config.read().unwrap().services.iter().filter(|service| {
-				if service.service_type() == query.name() {
-					return true;
+	/// Logs a packet that couldn't be decoded, and passes it to the [decode error hook](BroadcasterBuilder::on_decode_error), if any.
+	pub(crate) fn decode_error(config: &RwLock<BroadcasterConfig>, addr: SocketAddr, packet: &[u8], err: &ProtoError) {
+		logging::debug!("Failed to decode {} byte mDNS packet from {addr}: {err}", packet.len());
+
+		let hook = config.read().unwrap().on_decode_error.clone();
+		if let Some(DecodeErrorHook(hook)) = hook {
+			hook(addr, packet, err);
+		}
+	}
+
+	#[allow(clippy::await_holding_lock)]
+	// It's fine to hold the lock here because the receive loop's future is always driven by `block_on` on a single thread (its own thread, or one of
+	// the blocking threads of the runtime given to `run_on`), and is never sent to another thread. The future just won't be Send.
+	/// Answers a query received from `addr` on `interface`, if it is for anything we're broadcasting there.
+	///
+	/// Every question in the query is answered, as if each had been sent in a query of its own.
+	/// Multicast responses that are [delayed](BroadcasterBuilder::response_delay) are queued in `delayed` for the caller to send.
+	/// Responses, and messages with an opcode other than a standard query (such as DNS updates and notifies), are ignored.
+	///
+	/// Returns an error if sending an answer failed and the [`ReconnectPolicy`] says to abort.
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(level = "debug", name = "query", skip_all, fields(addr = %addr, questions = message.queries().len()))
+	)]
+	pub(crate) async fn handle_query(
+		tx: &impl MdnsTransport,
+		send_buf: &mut Vec<u8>,
+		delayed: &mut DelayedResponses,
+		config: &RwLock<BroadcasterConfig>,
+		message: &DnsMessage,
+		addr: SocketAddr,
+		interface: Option<RecvInterface>,
+	) -> Result<(), LoopError> {
+		if message.truncated() {
+			return Ok(());
+		}
+
+		// Only standard queries are supported over mDNS, so anything else must be silently ignored (RFC 6762, section 18.3)
+		if message.message_type() != DnsMessageType::Query || message.op_code() != DnsOpCode::Query {
+			logging::debug!("Ignoring mDNS {:?} {:?} message from {addr}", message.op_code(), message.message_type());
+			return Ok(());
+		}
+
+		let config = config.read().unwrap();
+
+		for query in message.queries() {
+			Self::answer_question(tx, send_buf, delayed, &config, query, addr, interface).await?;
+		}
+
+		Ok(())
+	}
+
+	/// Answers a single question of a query, if it is for anything we're broadcasting on `interface`.
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(level = "debug", name = "question", skip_all, fields(name = %query.name(), query_type = %query.query_type()))
+	)]
+	async fn answer_question(
+		tx: &impl MdnsTransport,
+		send_buf: &mut Vec<u8>,
+		delayed: &mut DelayedResponses,
+		config: &BroadcasterConfig,
+		query: &DnsQuery,
+		addr: SocketAddr,
+		interface: Option<RecvInterface>,
+	) -> Result<(), LoopError> {
+		let services = config.services.iter().filter(|service| service.is_available_on(interface));
+
+		match Self::answers(config, services, query) {
+			Some(answers) => {
+				for Answer { response, unicast } in answers {
+					Self::send_response(tx, send_buf, delayed, &response, unicast, addr, interface, config).await?;
 				}
+			}
 
-				if let Some(subtype_suffix) = &service.service_subtype_suffix {
-					if query.name().to_utf8().ends_with(subtype_suffix) {
-						return true;
-					}
+			None => {
+				logging::trace!("Query from {addr} didn't match anything we're broadcasting");
+				if let Some(UnmatchedQueryHook(hook)) = &config.on_unmatched_query {
+					hook(query, addr);
 				}
+			}
+		}
 
-				false
-			}) {
-				send_buf.clear();
+		Ok(())
+	}
 
-				if service.dns_response.emit(&mut BinEncoder::new(&mut send_buf)).is_ok() {
-					if query.mdns_unicast_response() {
-						// Send unicast packet
-						if let Err(err) = tx.send_to(&send_buf, addr).await {
-							log::warn!("Failed to send unicast mDNS response to {addr}: {err}");
-						}
-					} else {
-						// Send multicast packet
-						if let Err(err) = tx.send_multicast(&send_buf).await {
-							log::warn!("Failed to send multicast mDNS response (requested by {addr}): {err}");
+	/// Builds the answers to a single question about `services`.
+	///
+	/// Returns `None` if the question didn't match anything, and an empty list if it did but building the answers failed.
+	fn answers<'a>(
+		config: &'a BroadcasterConfig,
+		services: impl Iterator<Item = &'a ServiceDnsResponse> + Clone,
+		query: &DnsQuery,
+	) -> Option<Vec<Answer<'a>>> {
+		// Answers for interface-restricted services are sent by unicast, so that they don't leak onto the socket's other interfaces
+		let unicast = |service: &Service| query.mdns_unicast_response() || service.is_interface_restricted();
+
+		let matching = services.clone().filter(|service| {
+			if service.service_type() == query.name() {
+				return true;
+			}
+
+			if let Some(subtype_suffix) = &service.service_subtype_suffix {
+				if query.name().to_utf8().ends_with(subtype_suffix) {
+					return true;
+				}
+			}
+
+			false
+		});
+
+		let mut answers = vec![];
+
+		// Whether the query matched anything we're broadcasting, even if answering it failed
+		let mut answered = false;
+
+		if config.minimal_unicast && query.mdns_unicast_response() {
+			for service in matching {
+				answered = true;
+				match service.minimal_dns_response() {
+					Ok(response) => answers.push(Answer::owned(response, true)),
+					Err(err) => logging::warn!("Failed to build minimal mDNS response: {err}"),
+				}
+			}
+		} else if config.combine_responses {
+			let matching = matching.collect::<Vec<_>>();
+			answered |= !matching.is_empty();
+			match matching.as_slice() {
+				[] => {}
+				[service] => answers.push(Answer::borrowed(&service.dns_response, unicast(service))),
+				_ => {
+					// Batch the services so that each packet fits the MTU without having to be split
+					let services = matching.iter().map(|service| &***service).collect::<Vec<_>>();
+					match Service::batched_dns_responses(&services, mtu::max_payload_v6(config.mtu)) {
+						Ok(responses) => {
+							let any_unicast = matching.iter().any(|service| unicast(service));
+							answers.extend(responses.into_iter().map(|response| Answer::owned(response, any_unicast)));
 						}
+						Err(err) => logging::warn!("Failed to build combined mDNS response: {err}"),
 					}
 				}
 			}
+		} else {
+			for service in matching {
+				answered = true;
+				answers.push(Answer::borrowed(&service.dns_response, unicast(service)));
+			}
+		}
+
+		// Resolving a known service instance directly
+		for service in services.clone().filter(|service| service.service_id() == query.name()) {
+			if let Some(response) = service.instance_dns_response(query.query_type()) {
+				answered = true;
+				answers.push(Answer::owned(response, unicast(service)));
+			}
+		}
+
+		// Resolving a host's addresses directly, such as the SRV target of a service that a client already knows about
+		if matches!(query.query_type(), DnsRecordType::A | DnsRecordType::AAAA | DnsRecordType::ANY) {
+			let hosts = services
+				.clone()
+				.filter(|service| service.ip_addresses().iter().any(|ip| service.host().ip_address_name(ip) == query.name()));
+
+			if let Some(response) = Service::address_dns_response(hosts.clone().map(|service| &**service), query.name(), query.query_type()) {
+				answered = true;
+				let unicast = query.mdns_unicast_response() || hosts.clone().any(|service| service.is_interface_restricted());
+				answers.push(Answer::owned(response, unicast));
+			}
+		}
+
+		// DNS-SD service type enumeration, or a PTR query for the root name (sent by discovery when it isn't looking for a specific service)
+		if config.answer_service_types
+			&& matches!(query.query_type(), DnsRecordType::PTR | DnsRecordType::ANY)
+			&& is_service_types_query(query.name())
+		{
+			if let Some(response) = Service::service_types_response(services.clone().map(|service| &**service)) {
+				answered = true;
+				let unicast = query.mdns_unicast_response() || services.clone().any(|service| service.is_interface_restricted());
+				answers.push(Answer::owned(response, unicast));
+			}
+		}
+
+		// DNS-SD domain enumeration
+		if matches!(query.query_type(), DnsRecordType::PTR | DnsRecordType::ANY) && is_browse_domains_query(query.name()) {
+			if let Some(response) = Service::browse_domains_response(query.name(), &config.browse_domains) {
+				answered = true;
+				answers.push(Answer::owned(response, query.mdns_unicast_response()));
+			}
+		}
+
+		if config.answer_reverse_lookups {
+			for (service, ip) in services
+				.flat_map(|service| service.ip_addresses().iter().map(move |ip| (service, *ip)))
+				.filter(|(_, ip)| DnsName::from(*ip) == *query.name())
+			{
+				answered = true;
+				answers.push(Answer::owned(service.reverse_lookup_response(ip), unicast(service)));
+			}
+		}
+
+		answered.then_some(answers)
+	}
+
+	/// Sends a response to `addr`, which sent a query on `interface`, returning an error only if sending failed and the [`ReconnectPolicy`] says to abort.
+	///
+	/// Multicast responses are queued in `delayed` instead if a [response delay](BroadcasterBuilder::response_delay) is set.
+	#[allow(clippy::too_many_arguments)]
+	async fn send_response(
+		tx: &impl MdnsTransport,
+		send_buf: &mut Vec<u8>,
+		delayed: &mut DelayedResponses,
+		response: &DnsMessage,
+		unicast: bool,
+		addr: SocketAddr,
+		interface: Option<RecvInterface>,
+		config: &BroadcasterConfig,
+	) -> Result<(), LoopError> {
+		if let Some(throttle) = &config.throttle {
+			if !throttle.try_send() {
+				logging::debug!("Dropping mDNS response (requested by {addr}), as the maximum number of responses per second has been reached");
+				return Ok(());
+			}
+		}
+
+		send_buf.clear();
+
+		logging::debug!(
+			"Sending {} mDNS response with {} answer(s)",
+			if unicast { "unicast" } else { "multicast" },
+			response.answer_count()
+		);
+
+		if mtu::encode_into(response, send_buf, !config.disable_name_compression).is_err() {
+			return Ok(());
+		}
+
+		// IPv6 routers never fragment packets, so responses that don't fit the MTU are split into multiple packets
+		let max_payload_v6 = mtu::max_payload_v6(config.mtu);
+		let packets_v6 = if send_buf.len() > max_payload_v6 {
+			Some(mtu::split_response(response, max_payload_v6, !config.disable_name_compression))
+		} else {
+			None
+		};
+
+		if unicast {
+			// Send unicast packet(s) back out of the interface the query was received on
+			let addr = reply_addr(addr, interface);
+			let packets = match (&packets_v6, addr) {
+				(Some(packets_v6), SocketAddr::V6(_)) => packets_v6.as_slice(),
+				_ => std::slice::from_ref(&*send_buf),
+			};
+			for packet in packets {
+				if let Err(err) = tx.send_to(packet, addr).await {
+					logging::warn!("Failed to send unicast mDNS response to {addr}: {err}");
+					return config.reconnect.send_error(err);
+				}
+			}
+		} else if let Some(delay) = &config.response_delay {
+			// Delaying multicast responses by a random amount stops the responses of several responders to the same query from colliding
+			delayed.push(
+				delay.next(),
+				DelayedResponse {
+					requested_by: addr,
+					packet: send_buf.clone(),
+					packets_v6,
+				},
+			);
+		} else {
+			// Send multicast packet(s)
+			let res = match &packets_v6 {
+				Some(packets_v6) => tx.send_multicast_split_v6(send_buf, packets_v6).await,
+				None => tx.send_multicast(send_buf).await,
+			};
+			if let Err(err) = res {
+				logging::warn!("Failed to send multicast mDNS response (requested by {addr}): {err}");
+				return config.reconnect.send_error(err);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// A response to a question, and whether it should be sent by unicast.
+struct Answer<'a> {
+	response: Cow<'a, DnsMessage>,
+	unicast: bool,
+}
+impl<'a> Answer<'a> {
+	#[inline]
+	fn borrowed(response: &'a DnsMessage, unicast: bool) -> Self {
+		Self {
+			response: Cow::Borrowed(response),
+			unicast,
+		}
+	}
+
+	#[inline]
+	fn owned(response: DnsMessage, unicast: bool) -> Self {
+		Self {
+			response: Cow::Owned(response),
+			unicast,
 		}
 	}
 }
+
+/// Whether a query is asking which service types are being broadcast: either the DNS-SD meta-query, or the root name.
+fn is_service_types_query(name: &DnsName) -> bool {
+	name.num_labels() == 0 || *name == DnsName::from_ascii(SERVICES_META_QUERY).unwrap()
+}
+
+/// Whether a query is asking which browsing domains are in use, as described in [RFC 6763 §11](https://datatracker.ietf.org/doc/html/rfc6763#section-11).
+fn is_browse_domains_query(name: &DnsName) -> bool {
+	BROWSE_DOMAINS_QUERIES.iter().any(|query| *name == DnsName::from_ascii(query).unwrap())
+}
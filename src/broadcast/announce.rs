@@ -0,0 +1,66 @@
+use super::mtu;
+use crate::{logging, net::ReconnectPolicy, socket::LoopError, transport::MdnsTransport};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+use trust_dns_client::op::Message as DnsMessage;
+
+/// An encoded multicast response that wasn't asked for.
+struct Announcement {
+	packet: Vec<u8>,
+
+	/// The response split into packets that fit the MTU, if it doesn't fit in one for IPv6.
+	packets_v6: Option<Vec<Vec<u8>>>,
+}
+
+/// Unsolicited multicast responses queued by a [`BroadcasterHandle`](super::BroadcasterHandle) for the receive loop to send, such as the
+/// records of a service whose port changed ([RFC 6762 §8.4](https://datatracker.ietf.org/doc/html/rfc6762#section-8.4)).
+///
+/// The handle doesn't have the socket, so it can't send them itself.
+#[derive(Default)]
+pub(crate) struct Announcements {
+	queue: Mutex<Vec<Announcement>>,
+	notify: Notify,
+}
+
+impl Announcements {
+	/// Encodes `response` like a response to a query, and queues it to be multicast.
+	pub(crate) fn push(&self, response: &DnsMessage, mtu: u16, compress: bool) {
+		let packet = match mtu::encode(response, compress) {
+			Ok(packet) => packet,
+			Err(err) => {
+				logging::warn!("Failed to encode mDNS announcement: {err}");
+				return;
+			}
+		};
+
+		// IPv6 routers never fragment packets, so announcements that don't fit the MTU are split into multiple packets
+		let max_payload_v6 = mtu::max_payload_v6(mtu);
+		let packets_v6 = (packet.len() > max_payload_v6).then(|| mtu::split_response(response, max_payload_v6, compress));
+
+		self.queue.lock().unwrap().push(Announcement { packet, packets_v6 });
+		self.notify.notify_one();
+	}
+
+	/// Waits until there is something to announce.
+	pub(crate) async fn ready(&self) {
+		self.notify.notified().await
+	}
+
+	/// Sends everything that was queued, returning an error only if sending failed and the [`ReconnectPolicy`] says to abort.
+	pub(crate) async fn send(&self, tx: &impl MdnsTransport, reconnect: &ReconnectPolicy) -> Result<(), LoopError> {
+		let queue = std::mem::take(&mut *self.queue.lock().unwrap());
+		for announcement in queue {
+			logging::debug!("Sending mDNS announcement");
+
+			let res = match &announcement.packets_v6 {
+				Some(packets_v6) => tx.send_multicast_split_v6(&announcement.packet, packets_v6).await,
+				None => tx.send_multicast(&announcement.packet).await,
+			};
+			if let Err(err) = res {
+				logging::warn!("Failed to send mDNS announcement: {err}");
+				reconnect.send_error(err)?;
+			}
+		}
+		Ok(())
+	}
+}
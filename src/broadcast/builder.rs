@@ -1,20 +1,40 @@
-use super::{errors::BroadcasterBuilderError, service::ServiceDnsResponse, Broadcaster, BroadcasterConfig, Service};
+use super::{
+	errors::{BroadcasterBuilderError, ServiceDnsPacketBuilderError},
+	service::ServiceDnsResponse,
+	Broadcaster, BroadcasterConfig, DecodeErrorHook, ResponseDelay, ResponseThrottle, Service, UnmatchedQueryHook,
+};
 use crate::{
-	errors::MultiIpIoError,
-	net::{IpVersion, TargetInterfaceV4, TargetInterfaceV6},
-	socket::MdnsSocket,
+	net::{ConfigureSocket, IpVersion, ReconnectPolicy, SocketOptions, TargetInterfaceV4, TargetInterfaceV6},
+	socket::{MdnsSocket, MdnsSocketOptions, MdnsSocketRecipe},
 };
 use std::{
 	collections::BTreeSet,
+	net::SocketAddr,
 	sync::{Arc, RwLock},
+	time::Duration,
 };
+use trust_dns_client::{op::Query as DnsQuery, proto::error::ProtoError, rr::Name as DnsName};
 
+#[derive(Debug, Clone)]
 /// Builder for [`Broadcaster`].
 pub struct BroadcasterBuilder {
 	services: BTreeSet<Service>,
 	interface_v4: TargetInterfaceV4,
 	interface_v6: TargetInterfaceV6,
-	loopback: bool,
+	socket_options: MdnsSocketOptions,
+	answer_reverse_lookups: bool,
+	answer_service_types: bool,
+	combine_responses: bool,
+	minimal_unicast: bool,
+	browse_domains: Vec<DnsName>,
+	mtu: u16,
+	disable_name_compression: bool,
+	max_responses_per_sec: Option<u32>,
+	response_delay: Option<(Duration, Duration)>,
+	response_delay_seed: Option<u64>,
+	reconnect: ReconnectPolicy,
+	on_decode_error: Option<DecodeErrorHook>,
+	on_unmatched_query: Option<UnmatchedQueryHook>,
 }
 impl BroadcasterBuilder {
 	/// Creates a new [`BroadcasterBuilder`].
@@ -23,15 +43,345 @@ impl BroadcasterBuilder {
 			services: BTreeSet::new(),
 			interface_v4: TargetInterfaceV4::All,
 			interface_v6: TargetInterfaceV6::All,
-			loopback: false,
+			socket_options: MdnsSocketOptions::default(),
+			answer_reverse_lookups: false,
+			answer_service_types: true,
+			combine_responses: false,
+			minimal_unicast: false,
+			browse_domains: Vec::new(),
+			mtu: 1500,
+			disable_name_compression: false,
+			max_responses_per_sec: None,
+			response_delay: None,
+			response_delay_seed: None,
+			reconnect: ReconnectPolicy::default(),
+			on_decode_error: None,
+			on_unmatched_query: None,
 		}
 	}
 
 	/// If loopback is enabled, any multicast packets that are sent can be received by the same socket and any other local sockets bound to the same port.
 	///
 	/// This is useful for testing, but is probably not very useful in production.
+	///
+	/// This enables loopback for both IPv4 and IPv6; use [`loopback_v4`](Self::loopback_v4) and [`loopback_v6`](Self::loopback_v6) to configure them separately.
 	pub fn loopback(mut self) -> Self {
-		self.loopback = true;
+		self.socket_options.loopback_v4 = true;
+		self.socket_options.loopback_v6 = true;
+		self
+	}
+
+	/// Whether multicast packets sent over IPv4 can be received by the same socket and any other local sockets bound to the same port.
+	///
+	/// See [`loopback`](Self::loopback).
+	///
+	/// **Default: false**
+	pub fn loopback_v4(mut self, loopback: bool) -> Self {
+		self.socket_options.loopback_v4 = loopback;
+		self
+	}
+
+	/// Whether multicast packets sent over IPv6 can be received by the same socket and any other local sockets bound to the same port.
+	///
+	/// See [`loopback`](Self::loopback).
+	///
+	/// **Default: false**
+	pub fn loopback_v6(mut self, loopback: bool) -> Self {
+		self.socket_options.loopback_v6 = loopback;
+		self
+	}
+
+	/// Whether to exclude IPv4 link-local addresses (`169.254.0.0/16`) when using [`TargetInterfaceV4::All`] or [`TargetInterfaceV4::Filter`].
+	///
+	/// Link-local addresses are usually assigned to interfaces that failed to get an address via DHCP, or to virtual adapters,
+	/// so joining multicast on them is often just noise.
+	///
+	/// **Default: false**
+	pub fn exclude_link_local_v4(mut self, exclude: bool) -> Self {
+		self.socket_options.exclude_link_local_v4 = exclude;
+		self
+	}
+
+	/// Whether to fail to [build](BroadcasterBuilder::build) if the mDNS multicast group can't be joined on any network interface when using
+	/// [`TargetInterfaceV4::All`] or [`TargetInterfaceV6::All`] (or a [filter](TargetInterfaceV4::filter)).
+	///
+	/// Normally, if no usable interfaces are found, the socket falls back to letting the OS choose a default interface, which may not
+	/// work at all, so queries are never received (a warning is logged). With this enabled, [`BroadcasterBuilderError::NoInterfaces`] is returned instead.
+	///
+	/// When broadcasting over both IPv4 and IPv6, it's enough for either of them to have a usable interface.
+	///
+	/// **Default: false**
+	pub fn require_interface(mut self, require: bool) -> Self {
+		self.socket_options.require_interface = require;
+		self
+	}
+
+	/// Whether to also send every packet to the IPv4 limited broadcast address (`255.255.255.255`), as well as the mDNS multicast group.
+	///
+	/// **This is not part of the mDNS standard.** It is a best-effort fallback for networks that drop IPv4 multicast
+	/// (for example because of broken IGMP snooping) but still pass broadcast traffic, and only helps if the other side enables it too.
+	/// Broadcast packets are received on the mDNS port either way, but are only sent over the default IPv4 interface, and are always looped back to the local machine.
+	///
+	/// **Default: false**
+	pub fn also_broadcast(mut self, also_broadcast: bool) -> Self {
+		self.socket_options.also_broadcast = also_broadcast;
+		self
+	}
+
+	/// Whether sending multicast packets must succeed over both IPv4 and IPv6 when broadcasting over [`IpVersion::Both`].
+	///
+	/// Normally, sending a packet only fails if it failed over both IP versions, so a silent failure over one of them means that clients
+	/// on that IP version never hear from the broadcaster. With this enabled, a failure over either IP version is reported as an error.
+	///
+	/// **Default: false**
+	pub fn strict_multicast(mut self, strict: bool) -> Self {
+		self.socket_options.strict_multicast = strict;
+		self
+	}
+
+	/// Whether the IPv6 socket only handles IPv6 traffic (`IPV6_V6ONLY`).
+	///
+	/// Turning this off makes the IPv6 socket dual-stack, so it can also receive IPv4 traffic (with IPv4-mapped addresses such as `::ffff:192.168.1.69`)
+	/// on platforms that support it. Only the IPv6 multicast group is joined either way, so this mostly affects unicast traffic from IPv4 peers.
+	///
+	/// This only applies to [`IpVersion::V6`]. With [`IpVersion::Both`], the IPv4 socket already receives IPv4 traffic on the mDNS port,
+	/// so a dual-stack IPv6 socket would receive it a second time; the IPv6 socket is always IPv6-only in that case.
+	///
+	/// **Default: true**
+	pub fn only_v6(mut self, only_v6: bool) -> Self {
+		self.socket_options.dual_stack_v6 = !only_v6;
+		self
+	}
+
+	/// Binds the broadcaster's socket(s) to a local port other than the mDNS port (5353), for example to satisfy firewall rules or to coexist
+	/// with another mDNS responder. Passing `0` lets the OS choose a free port; use [`Broadcaster::local_addrs`](super::Broadcaster::local_addrs) to find out which.
+	///
+	/// Multicast responses are still sent to the mDNS multicast group on port 5353, but this comes with some constraints:
+	///
+	/// * Multicast queries are sent to port 5353, so they are **not received** on any other port. The broadcaster only answers
+	///   queries that are sent directly (by unicast) to the port it is bound to.
+	/// * Packets are sent from the bound port, and [RFC 6762](https://datatracker.ietf.org/doc/html/rfc6762#section-11) requires queriers to
+	///   ignore multicast responses whose source port isn't 5353, so strict mDNS implementations may ignore the broadcaster entirely.
+	/// * `SO_REUSEADDR`/`SO_REUSEPORT` are still set, so the port can be shared with other sockets that set them too.
+	///
+	/// **Default: 5353**
+	pub fn bind_port(mut self, port: u16) -> Self {
+		self.socket_options.bind_port = Some(port);
+		self
+	}
+
+	/// Sets the low-level options applied to the mDNS sockets, such as the read timeout, receive buffer size and multicast TTL.
+	///
+	/// **Default: [`SocketOptions::default()`]**
+	pub fn socket_options(mut self, options: SocketOptions) -> Self {
+		self.socket_options.socket = options;
+		self
+	}
+
+	/// Sets the TTL (IPv4) or hop limit (IPv6) of outgoing multicast packets.
+	///
+	/// Standard mDNS never leaves the local link, but this can be raised to reach across routed segments on networks with mDNS reflection or multicast routing.
+	///
+	/// This is a shorthand for setting [`SocketOptions::multicast_ttl`].
+	///
+	/// **Default: 1**
+	pub fn multicast_ttl(mut self, ttl: u32) -> Self {
+		self.socket_options.socket.multicast_ttl = ttl;
+		self
+	}
+
+	/// Marks outgoing packets with a DSCP (Differentiated Services Code Point), so that they can be prioritized on networks with QoS policies.
+	///
+	/// This is a shorthand for setting [`SocketOptions::dscp`], which describes the platforms that support it.
+	///
+	/// **Default: the OS default (usually 0, best effort)**
+	///
+	/// [`build`](BroadcasterBuilder::build) returns [`BroadcasterBuilderError::InvalidDscp`] if `dscp` is larger than 63, as DSCPs are 6-bit values.
+	pub fn dscp(mut self, dscp: u8) -> Self {
+		self.socket_options.socket.dscp = Some(dscp);
+		self
+	}
+
+	/// Calls `configure` on each of the broadcaster's sockets before they are bound, to set socket options that Searchlight doesn't set itself,
+	/// such as `SO_BINDTODEVICE`, `SO_PRIORITY` or `SO_SNDBUF`.
+	///
+	/// `configure` is called with the IP version of the socket, after Searchlight has set its own options (so it can override them) and before
+	/// the socket joins the mDNS multicast group. It is called again whenever the socket is [recreated](BroadcasterBuilder::reconnect_policy).
+	/// Returning an error fails setting up the socket at [`SocketSetupStage::SetOption`](crate::errors::SocketSetupStage::SetOption).
+	pub fn configure_socket<F>(mut self, configure: F) -> Self
+	where
+		F: Fn(&socket2::Socket, IpVersion) -> Result<(), std::io::Error> + Send + Sync + 'static,
+	{
+		self.socket_options.configure = Some(ConfigureSocket(Arc::new(configure)));
+		self
+	}
+
+	/// How to recover when receiving on the mDNS socket keeps failing, for example because the network went down.
+	///
+	/// **Default: [`ReconnectPolicy::default()`]**
+	pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+		self.reconnect = policy;
+		self
+	}
+
+	/// Whether to answer reverse lookups (PTR queries for `in-addr.arpa.` and `ip6.arpa.` names) for the IP addresses of the broadcasted services.
+	///
+	/// When enabled, clients can map a discovered address back to the hostname of the service that advertised it.
+	///
+	/// **Default: false**
+	pub fn answer_reverse_lookups(mut self, answer_reverse_lookups: bool) -> Self {
+		self.answer_reverse_lookups = answer_reverse_lookups;
+		self
+	}
+
+	/// Whether to answer service type enumeration queries with the types of the broadcasted services.
+	///
+	/// A PTR query for the DNS-SD meta-query name (`_services._dns-sd._udp.local.`), or for the root name (which is what discovery sends when it isn't
+	/// looking for a specific service), is answered with a PTR record for each distinct service type, as described in
+	/// [RFC 6763 §9](https://datatracker.ietf.org/doc/html/rfc6763#section-9).
+	/// The records are always named `_services._dns-sd._udp.local.`, so discovery can read them using `Responder::service_types`.
+	///
+	/// **Default: true**
+	pub fn answer_service_types(mut self, answer_service_types: bool) -> Self {
+		self.answer_service_types = answer_service_types;
+		self
+	}
+
+	/// Whether to combine the responses for all services that match a query into a single DNS packet.
+	///
+	/// When enabled, the A/AAAA records of each [`Host`](super::Host) are only included once per response, no matter how many of the matching services are available on it.
+	/// This is useful when advertising many service types from the same machine.
+	///
+	/// If the matching services don't all fit in one packet of the [MTU](BroadcasterBuilder::mtu), they are spread over as few packets as possible,
+	/// without splitting any one service's records across packets.
+	///
+	/// **Default: false** (each matching service is sent in its own packet)
+	pub fn combine_responses(mut self, combine_responses: bool) -> Self {
+		self.combine_responses = combine_responses;
+		self
+	}
+
+	/// Whether to answer queries that ask for a unicast response (with the QU bit set) with only the PTR, SRV and A/AAAA records of the matching services.
+	///
+	/// Unicast responses only reach the querier, so the TXT and [extra](super::ServiceBuilder::add_extra_record) records that would otherwise populate the caches
+	/// of other devices on the network are left out, saving bandwidth on metered links. The querier can still resolve the rest with a follow-up query for the
+	/// service instance name, which is always answered in full. Multicast responses are unaffected.
+	///
+	/// Responses to QU queries aren't [combined](BroadcasterBuilder::combine_responses) when this is enabled.
+	///
+	/// **Default: false**
+	pub fn minimal_unicast(mut self, minimal_unicast: bool) -> Self {
+		self.minimal_unicast = minimal_unicast;
+		self
+	}
+
+	/// Answers DNS-SD domain enumeration queries (`b._dns-sd._udp.local.`, `db._dns-sd._udp.local.` and `lb._dns-sd._udp.local.`) with a PTR record pointing to each of `domains`,
+	/// as described in [RFC 6763 §11](https://datatracker.ietf.org/doc/html/rfc6763#section-11).
+	///
+	/// This tells clients which domains to browse for services in. On a local network this is usually just `local.`, but some enterprise clients
+	/// won't browse at all without an answer. Discovery can find these domains using `Discovery::enumerate_domains`.
+	///
+	/// **Default: none** (domain enumeration queries aren't answered)
+	pub fn browse_domains(mut self, domains: Vec<DnsName>) -> Self {
+		self.browse_domains = domains;
+		self
+	}
+
+	/// The path MTU to assume when sending responses over IPv6.
+	///
+	/// IPv6 routers never fragment packets, so responses that would exceed the MTU are split into multiple packets.
+	/// The A and AAAA records of a host are always kept in the same packet, so that clients can resolve it in one round trip.
+	///
+	/// When [combining responses](BroadcasterBuilder::combine_responses), this also decides how many services are answered per packet, over both IPv4 and IPv6.
+	///
+	/// **Default: 1500**
+	pub fn mtu(mut self, mtu: u16) -> Self {
+		self.mtu = mtu;
+		self
+	}
+
+	/// Whether to send responses without DNS name compression.
+	///
+	/// Some minimal or buggy mDNS clients can't follow the compression pointers that are normally used to avoid repeating names in a packet.
+	/// Disabling compression makes responses larger, and because uncompressed names are written in DNSSEC canonical form, names inside
+	/// records (such as the service instance name in PTR records, and SRV targets) are lowercased too.
+	///
+	/// **Default: false**
+	pub fn disable_name_compression(mut self, disable: bool) -> Self {
+		self.disable_name_compression = disable;
+		self
+	}
+
+	/// The maximum number of responses to send per second, across all services.
+	///
+	/// This protects the network (and battery-powered devices) from query storms, such as a buggy client querying in a tight loop.
+	/// Up to a second's worth of responses can be sent in a burst; once the limit is reached, queries go unanswered until the next
+	/// response is allowed. The number of responses dropped this way is reported in [`Stats::dropped_responses`](crate::net::Stats::dropped_responses).
+	///
+	/// **Default: unlimited**
+	///
+	/// # Panics
+	///
+	/// Panics if `max` is zero.
+	pub fn max_responses_per_sec(mut self, max: u32) -> Self {
+		assert_ne!(max, 0, "Maximum responses per second must be non-zero");
+		self.max_responses_per_sec = Some(max);
+		self
+	}
+
+	/// Delays each multicast response by a random amount of time between `min` and `max`.
+	///
+	/// [RFC 6762 §6](https://datatracker.ietf.org/doc/html/rfc6762#section-6) recommends a delay of 20-120 ms for responses to
+	/// queries that several responders may answer at once, so that their responses don't all collide on the network. Unicast responses
+	/// aren't delayed, and the broadcaster keeps answering other queries while a response is being delayed.
+	///
+	/// The delays are random unless a seed is set with [`response_delay_seed`](BroadcasterBuilder::response_delay_seed).
+	///
+	/// **Default: no delay**
+	///
+	/// [`build`](BroadcasterBuilder::build) returns [`BroadcasterBuilderError::InvalidResponseDelay`] if `min` is greater than `max`.
+	pub fn response_delay(mut self, min: Duration, max: Duration) -> Self {
+		self.response_delay = Some((min, max));
+		self
+	}
+
+	/// Seeds the random number generator used for the [response delay](BroadcasterBuilder::response_delay), so that the same
+	/// sequence of delays is used every time.
+	///
+	/// This is meant for tests that need reproducible timing; real deployments should leave the delays random.
+	///
+	/// **Default: seeded from the OS's entropy**
+	pub fn response_delay_seed(mut self, seed: u64) -> Self {
+		self.response_delay_seed = Some(seed);
+		self
+	}
+
+	/// Calls `hook` with the sender, the raw packet and the error whenever a received packet can't be decoded as a DNS message.
+	///
+	/// Such packets are otherwise ignored (and logged at debug level). This is useful for diagnosing devices that send queries
+	/// Searchlight can't decode, and for parsing them by hand.
+	///
+	/// The hook is called on the broadcaster's thread, so it should return quickly.
+	pub fn on_decode_error<F>(mut self, hook: F) -> Self
+	where
+		F: Fn(SocketAddr, &[u8], &ProtoError) + Send + Sync + 'static,
+	{
+		self.on_decode_error = Some(DecodeErrorHook(Arc::new(hook)));
+		self
+	}
+
+	/// Calls `hook` with the query and its sender whenever a query is received that doesn't match anything the broadcaster is advertising,
+	/// so it isn't answered.
+	///
+	/// This is useful for debugging clients that don't find a service, for example because they are asking for a different service type.
+	/// Queries for a service that arrive over an interface it is [restricted from](super::ServiceBuilder::add_interface_v4) count as unmatched too.
+	///
+	/// The hook is called on the broadcaster's thread, so it should return quickly.
+	pub fn on_unmatched_query<F>(mut self, hook: F) -> Self
+	where
+		F: Fn(&DnsQuery, SocketAddr) + Send + Sync + 'static,
+	{
+		self.on_unmatched_query = Some(UnmatchedQueryHook(Arc::new(hook)));
 		self
 	}
 
@@ -63,36 +413,79 @@ impl BroadcasterBuilder {
 	///
 	/// You must specify whether to broadcast over IPv4, IPv6, or both.
 	pub fn build(self, ip_version: IpVersion) -> Result<Broadcaster, BroadcasterBuilderError> {
+		let (socket, recipe, config) = self.into_parts(ip_version)?;
+
+		Ok(Broadcaster {
+			socket,
+			recipe,
+			config: Arc::new(RwLock::new(config)),
+		})
+	}
+
+	/// Creates the socket and configuration of the broadcaster, which are shared with [`Node`](crate::node::Node).
+	pub(crate) fn into_parts(self, ip_version: IpVersion) -> Result<(MdnsSocket, MdnsSocketRecipe, BroadcasterConfig), BroadcasterBuilderError> {
+		if let Some((min, max)) = self.response_delay {
+			if min > max {
+				return Err(BroadcasterBuilderError::InvalidResponseDelay { min, max });
+			}
+		}
+
+		if let Some(dscp) = self.socket_options.socket.dscp.filter(|dscp| *dscp > 63) {
+			return Err(BroadcasterBuilderError::InvalidDscp(dscp));
+		}
+
+		let recipe = MdnsSocketRecipe {
+			options: self.socket_options.clone(),
+			ip_version,
+			interface_v4: self.interface_v4.clone(),
+			interface_v6: self.interface_v6.clone(),
+		};
+		let socket = recipe
+			.create()
+			.map_err(|err| BroadcasterBuilderError::socket_setup(err, recipe.options.port()))?;
+		Ok((socket, recipe, self.into_config()?))
+	}
+
+	pub(crate) fn into_config(self) -> Result<BroadcasterConfig, ServiceDnsPacketBuilderError> {
 		let BroadcasterBuilder {
 			services,
-			interface_v4,
-			interface_v6,
-			loopback,
+			answer_reverse_lookups,
+			answer_service_types,
+			combine_responses,
+			minimal_unicast,
+			browse_domains,
+			mtu,
+			disable_name_compression,
+			max_responses_per_sec,
+			response_delay,
+			response_delay_seed,
+			reconnect,
+			on_decode_error,
+			on_unmatched_query,
+			..
 		} = self;
 
-		Ok(Broadcaster {
-			socket: match ip_version {
-				IpVersion::V4 => {
-					MdnsSocket::new_v4(loopback, interface_v4).map_err(|v4| BroadcasterBuilderError::MultiIpIoError(MultiIpIoError::V4(v4)))?
+		Ok(BroadcasterConfig {
+			services: {
+				let mut dns_services = BTreeSet::new();
+				for service in services {
+					dns_services.replace(ServiceDnsResponse::try_from(service)?);
 				}
-
-				IpVersion::V6 => {
-					MdnsSocket::new_v6(loopback, interface_v6).map_err(|v6| BroadcasterBuilderError::MultiIpIoError(MultiIpIoError::V6(v6)))?
-				}
-
-				IpVersion::Both => MdnsSocket::new(loopback, interface_v4, interface_v6)
-					.map_err(|(v4, v6)| BroadcasterBuilderError::MultiIpIoError(MultiIpIoError::Both { v4, v6 }))?,
+				dns_services
 			},
-
-			config: Arc::new(RwLock::new(BroadcasterConfig {
-				services: {
-					let mut dns_services = BTreeSet::new();
-					for service in services {
-						dns_services.replace(ServiceDnsResponse::try_from(service)?);
-					}
-					dns_services
-				},
-			})),
+			answer_reverse_lookups,
+			answer_service_types,
+			combine_responses,
+			minimal_unicast,
+			browse_domains,
+			mtu,
+			disable_name_compression,
+			throttle: max_responses_per_sec.map(ResponseThrottle::new),
+			response_delay: response_delay.map(|(min, max)| ResponseDelay::new(min, max, response_delay_seed)),
+			reconnect,
+			on_decode_error,
+			on_unmatched_query,
+			announcements: Default::default(),
 		})
 	}
 }
@@ -0,0 +1,99 @@
+use crate::{logging, net::ReconnectPolicy, socket::LoopError, transport::MdnsTransport};
+use std::{
+	collections::hash_map::RandomState,
+	hash::{BuildHasher, Hasher},
+	net::SocketAddr,
+	sync::Mutex,
+	time::Duration,
+};
+use tokio::time::Instant;
+
+/// Picks a random delay for each multicast response, between a minimum and a maximum.
+///
+/// The random numbers come from a small SplitMix64 generator, which is seeded from the OS's entropy unless a seed is given,
+/// so that tests can reproduce the same delays.
+pub(crate) struct ResponseDelay {
+	min: Duration,
+	max: Duration,
+	state: Mutex<u64>,
+}
+
+impl ResponseDelay {
+	pub(crate) fn new(min: Duration, max: Duration, seed: Option<u64>) -> Self {
+		Self {
+			min,
+			max,
+			state: Mutex::new(seed.unwrap_or_else(|| RandomState::new().build_hasher().finish())),
+		}
+	}
+
+	/// Returns the delay before sending the next multicast response.
+	pub(crate) fn next(&self) -> Duration {
+		let range = (self.max - self.min).as_nanos() as u64;
+		if range == 0 {
+			return self.min;
+		}
+
+		let random = {
+			let mut state = self.state.lock().unwrap();
+			*state = state.wrapping_add(0x9e3779b97f4a7c15);
+
+			let mut z = *state;
+			z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+			z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+			z ^ (z >> 31)
+		};
+
+		self.min + Duration::from_nanos(random % (range + 1))
+	}
+}
+
+/// An encoded multicast response that is waiting for its [delay](ResponseDelay) to pass.
+pub(crate) struct DelayedResponse {
+	/// Who sent the query that this is a response to, for logging.
+	pub(crate) requested_by: SocketAddr,
+
+	pub(crate) packet: Vec<u8>,
+
+	/// The response split into packets that fit the MTU, if it doesn't fit in one for IPv6.
+	pub(crate) packets_v6: Option<Vec<Vec<u8>>>,
+}
+
+/// Multicast responses waiting to be sent by the receive loop once their delay has passed.
+///
+/// Queueing them here, rather than sleeping before sending, means the receive loop keeps answering other queries in the
+/// meantime, and the broadcaster's config isn't locked while a response waits.
+#[derive(Default)]
+pub(crate) struct DelayedResponses(Vec<(Instant, DelayedResponse)>);
+
+impl DelayedResponses {
+	pub(crate) fn push(&mut self, delay: Duration, response: DelayedResponse) {
+		self.0.push((Instant::now() + delay, response));
+	}
+
+	/// Waits until the earliest response is due, or forever if there are none.
+	pub(crate) async fn due(&self) {
+		match self.0.iter().map(|(deadline, _)| *deadline).min() {
+			Some(deadline) => tokio::time::sleep_until(deadline).await,
+			None => std::future::pending().await,
+		}
+	}
+
+	/// Sends every response that is due, returning an error only if sending failed and the [`ReconnectPolicy`] says to abort.
+	pub(crate) async fn send_due(&mut self, tx: &impl MdnsTransport, reconnect: &ReconnectPolicy) -> Result<(), LoopError> {
+		let now = Instant::now();
+		while let Some(i) = self.0.iter().position(|(deadline, _)| *deadline <= now) {
+			let (_, response) = self.0.swap_remove(i);
+
+			let res = match &response.packets_v6 {
+				Some(packets_v6) => tx.send_multicast_split_v6(&response.packet, packets_v6).await,
+				None => tx.send_multicast(&response.packet).await,
+			};
+			if let Err(err) = res {
+				logging::warn!("Failed to send multicast mDNS response (requested by {}): {err}", response.requested_by);
+				reconnect.send_error(err)?;
+			}
+		}
+		Ok(())
+	}
+}
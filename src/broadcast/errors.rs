@@ -1,4 +1,5 @@
-use crate::errors::MultiIpIoError;
+use crate::errors::{BadDnsNameError, MultiIpIoError, SocketSetupError, SocketSetupStage};
+use std::time::Duration;
 
 #[derive(Debug, Error)]
 /// An error occurred while building a [`Service`](super::Service)
@@ -10,6 +11,52 @@ pub enum ServiceBuilderError {
 	#[error("TXT record too long (max 255 bytes)")]
 	/// The TXT record is too long (max 255 bytes)
 	RecordTooLong,
+
+	#[error("The service's DNS response is too large ({estimated} bytes, max {max} bytes)")]
+	/// The service's DNS response is larger than [`ServiceBuilder::max_response_size`](super::ServiceBuilder::max_response_size)
+	ResponseTooLarge {
+		/// The size of the encoded response, in bytes
+		estimated: usize,
+
+		/// The maximum size, in bytes
+		max: usize,
+	},
+}
+
+#[derive(Debug, Error)]
+/// An error occurred while reconstructing a [`Service`](super::Service) from a DNS response
+pub enum ServiceFromResponseError {
+	#[error("The response doesn't contain an SRV record for a service instance")]
+	/// The response doesn't contain an SRV record for a service instance, so the service's name, type and port are unknown
+	MissingSrvRecord,
+
+	#[error("{0}")]
+	/// A name in the response can't be used for a service
+	BadDnsName(#[from] BadDnsNameError),
+
+	#[error("{0}")]
+	/// The reconstructed service is invalid, for example because the response didn't include any IP addresses
+	ServiceBuilderError(#[from] ServiceBuilderError),
+}
+
+#[derive(Debug, Error)]
+/// An error occurred while building a [`TxtRecord`](super::TxtRecord)
+pub enum TxtRecordError {
+	#[error("TXT record keys must not be empty")]
+	/// TXT record keys must not be empty
+	EmptyKey,
+
+	#[error("Invalid TXT record key {0:?} (must be printable US-ASCII, excluding '=')")]
+	/// The TXT record key contains characters other than printable US-ASCII, or contains `=`
+	InvalidKey(String),
+
+	#[error("Duplicate TXT record key {0:?}")]
+	/// The TXT record key has already been inserted (keys are case insensitive)
+	DuplicateKey(String),
+
+	#[error("TXT record for key {0:?} too long (max 255 bytes)")]
+	/// The TXT record is too long (max 255 bytes)
+	RecordTooLong(String),
 }
 
 #[derive(Debug, Error)]
@@ -18,10 +65,29 @@ pub enum ServiceDnsPacketBuilderError {
 	#[error("There are too many IP addresses to advertise")]
 	/// There are too many IP addresses to advertise
 	TooManyIpAddresses,
+
+	#[error("There are too many records to fit in a DNS response")]
+	/// There are too many records (including extra records) to fit in a DNS response
+	TooManyRecords,
 }
 
 #[derive(Debug, Error)]
+/// An error occurred while updating a service on a running broadcaster
+pub enum UpdateServiceError {
+	#[error("{0}")]
+	/// The service type or name was invalid
+	BadDnsName(#[from] BadDnsNameError),
+
+	#[error("{0}")]
+	/// An error occurred while rebuilding the service's DNS packet
+	ServiceDnsPacketBuilderError(#[from] ServiceDnsPacketBuilderError),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
 /// An error occurred while building a [`Broadcaster`](super::Broadcaster)
+///
+/// More kinds of errors may be added in the future, so matches on this should have a catch-all arm.
 pub enum BroadcasterBuilderError {
 	#[error("{0}")]
 	/// An error occurred while building a service DNS packet
@@ -34,4 +100,63 @@ pub enum BroadcasterBuilderError {
 	#[error("{0}")]
 	/// An I/O error occurred (on potentially both IPv4 and IPv6 sockets)
 	MultiIpIoError(MultiIpIoError),
+
+	#[error("Port {port} is already in use by another process that doesn't allow it to be shared, such as mDNSResponder or avahi-daemon: {error}")]
+	/// The port (the mDNS port, unless [`bind_port`](super::BroadcasterBuilder::bind_port) was set) is already bound by another socket (probably another mDNS responder, such as mDNSResponder or avahi-daemon) that doesn't allow it to be shared.
+	///
+	/// Consider stopping the other mDNS responder, or configuring it to allow the port to be shared.
+	PortInUse {
+		/// The port that couldn't be bound
+		port: u16,
+
+		/// The underlying error
+		error: MultiIpIoError,
+	},
+
+	#[error("{}", SocketSetupError::describe(.v4.as_ref(), .v6.as_ref()))]
+	/// An mDNS socket couldn't be set up; [`SocketSetupError::stage`] says which step failed.
+	///
+	/// At least one of `v4` and `v6` is set, depending on which sockets were being set up and failed.
+	SocketSetup {
+		/// Why the IPv4 socket couldn't be set up, if it failed
+		v4: Option<SocketSetupError>,
+
+		/// Why the IPv6 socket couldn't be set up, if it failed
+		v6: Option<SocketSetupError>,
+	},
+
+	#[error("No usable network interfaces were found: {0}")]
+	/// [`require_interface`](super::BroadcasterBuilder::require_interface) is enabled, but the mDNS multicast group couldn't be joined on any network interface.
+	///
+	/// This usually means there's no network connection.
+	NoInterfaces(MultiIpIoError),
+
+	#[error("The minimum response delay ({min:?}) is greater than the maximum ({max:?})")]
+	/// The minimum [response delay](super::BroadcasterBuilder::response_delay) is greater than the maximum
+	InvalidResponseDelay {
+		/// The minimum delay
+		min: Duration,
+
+		/// The maximum delay
+		max: Duration,
+	},
+
+	#[error("The DSCP ({0}) must be 63 or less")]
+	/// The [DSCP](super::BroadcasterBuilder::dscp) is larger than 63, as DSCPs are 6-bit values
+	InvalidDscp(u8),
+}
+impl BroadcasterBuilderError {
+	/// Classifies an error that occurred while setting up sockets bound to `port`.
+	pub(crate) fn socket_setup(err: MultiIpIoError, port: u16) -> Self {
+		if err.is_addr_in_use() {
+			Self::PortInUse { port, error: err }
+		} else if err.socket_setup_errors().any(|err| err.stage == SocketSetupStage::RequireInterface) {
+			Self::NoInterfaces(err)
+		} else {
+			match err.into_socket_setup_errors() {
+				Ok((v4, v6)) => Self::SocketSetup { v4, v6 },
+				Err(err) => Self::MultiIpIoError(err),
+			}
+		}
+	}
 }
@@ -1,29 +1,37 @@
-use super::{errors::ServiceDnsPacketBuilderError, BroadcasterConfig, Service};
+use super::{
+	errors::{ServiceDnsPacketBuilderError, UpdateServiceError},
+	service::ServiceDnsResponse,
+	BroadcasterConfig, ResponseThrottle, Service,
+};
 use crate::{
-	errors::{BadDnsNameError, MultiIpIoError, ShutdownError},
+	background::Background,
+	errors::{BadDnsNameError, MultiIpIoError, NameField, ShutdownError},
+	net::{Stats, TrafficCounters},
 	util::IntoDnsName,
 };
 use std::sync::{Arc, RwLock};
+use trust_dns_client::op::Message as DnsMessage;
 
 pub(super) struct BroadcasterHandleInner {
 	pub(super) config: Arc<RwLock<BroadcasterConfig>>,
-	pub(super) thread: std::thread::JoinHandle<Result<(), MultiIpIoError>>,
+	pub(super) background: Background<Result<(), MultiIpIoError>>,
 	pub(super) shutdown_tx: tokio::sync::oneshot::Sender<()>,
+	pub(super) counters: Arc<TrafficCounters>,
 }
 
 pub(super) struct BroadcasterHandleDrop(pub(super) Option<BroadcasterHandleInner>);
 impl BroadcasterHandleDrop {
 	fn shutdown(&mut self) -> Result<(), ShutdownError> {
-		let BroadcasterHandleInner { thread, shutdown_tx, .. } = match self.0.take() {
+		let BroadcasterHandleInner { background, shutdown_tx, .. } = match self.0.take() {
 			Some(inner) => inner,
 			None => return Ok(()),
 		};
 
-		if !thread.is_finished() {
+		if !background.is_finished() {
 			shutdown_tx.send(()).ok();
 		}
 
-		match thread.join() {
+		match background.join() {
 			Ok(Ok(_)) => Ok(()),
 			Ok(Err(err)) => Err(ShutdownError::MultiIpIoError(err)),
 			Err(err) => Err(ShutdownError::ThreadJoinError(err)),
@@ -32,7 +40,17 @@ impl BroadcasterHandleDrop {
 }
 impl Drop for BroadcasterHandleDrop {
 	fn drop(&mut self) {
-		self.shutdown().ok();
+		match self.0.take() {
+			// We might be dropped on the runtime that the broadcaster is running on, so waiting for it to stop could deadlock
+			Some(inner) if inner.background.is_on_runtime() => {
+				inner.shutdown_tx.send(()).ok();
+			}
+
+			inner => {
+				self.0 = inner;
+				self.shutdown().ok();
+			}
+		}
 	}
 }
 
@@ -42,6 +60,20 @@ impl Drop for BroadcasterHandleDrop {
 #[must_use = "The broadcaster instance will shut down if the handle is dropped; store the handle somewhere or use `std::mem::forget` to keep it running"]
 pub struct BroadcasterHandle(pub(super) BroadcasterHandleDrop);
 impl BroadcasterHandle {
+	pub(crate) fn new(
+		config: Arc<RwLock<BroadcasterConfig>>,
+		background: Background<Result<(), MultiIpIoError>>,
+		shutdown_tx: tokio::sync::oneshot::Sender<()>,
+		counters: Arc<TrafficCounters>,
+	) -> Self {
+		Self(BroadcasterHandleDrop(Some(BroadcasterHandleInner {
+			config,
+			background,
+			shutdown_tx,
+			counters,
+		})))
+	}
+
 	#[inline(always)]
 	fn with_config<F, R>(&self, handle: F) -> Option<R>
 	where
@@ -58,12 +90,81 @@ impl BroadcasterHandle {
 	/// Shuts down the broadcaster instance if it is still running.
 	///
 	/// This function will block until the broadcaster instance has shut down, and will return an error if the shutdown failed, or the broadcaster instance encountered a fatal error during its lifetime.
+	///
+	/// If the broadcaster is running on a runtime of your own (see [`Broadcaster::run_on`](super::Broadcaster::run_on)), don't call this from a task on a
+	/// current-thread runtime, as it would block the runtime from shutting the broadcaster down. Drop the handle instead, which asks the broadcaster to
+	/// shut down without waiting for it.
 	pub fn shutdown(mut self) -> Result<(), ShutdownError> {
 		let res = self.0.shutdown();
 		std::mem::forget(self.0);
 		res
 	}
 
+	/// Shuts down the broadcaster instance if it is still running, without blocking the async runtime that this is awaited on.
+	///
+	/// This is the same as [`shutdown`](Self::shutdown), but waits for the broadcaster to stop on one of the runtime's blocking threads,
+	/// so it can be used in the shutdown sequence of an async application, including on a current-thread runtime.
+	///
+	/// # Panics
+	///
+	/// Panics if not called from within a Tokio runtime.
+	pub async fn shutdown_async(self) -> Result<(), ShutdownError> {
+		tokio::task::spawn_blocking(move || self.shutdown())
+			.await
+			.unwrap_or_else(|err| Err(ShutdownError::ThreadJoinError(Box::new(err))))
+	}
+
+	/// Returns the services that are currently being broadcasted.
+	pub fn services(&self) -> Vec<Service> {
+		self.with_config(|broadcaster| {
+			broadcaster
+				.read()
+				.unwrap()
+				.services
+				.iter()
+				.map(|service| Service::clone(service))
+				.collect()
+		})
+		.unwrap_or_default()
+	}
+
+	/// Returns `true` if a service with the given type and name is currently being broadcasted.
+	pub fn contains_service(&self, service_type: impl IntoDnsName, service_name: impl IntoDnsName) -> Result<bool, BadDnsNameError> {
+		let service_type = service_type.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceType))?;
+		let service_name = service_name.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceName))?;
+
+		Ok(self
+			.with_config(|broadcaster| {
+				broadcaster
+					.read()
+					.unwrap()
+					.services
+					.iter()
+					.any(|service| *service.service_name() == service_name && *service.service_type() == service_type)
+			})
+			.unwrap_or(false))
+	}
+
+	/// Returns the traffic statistics of the broadcaster, such as how many packets were sent and received on each network interface.
+	pub fn stats(&self) -> Stats {
+		let mut stats = self.0 .0.as_ref().map(|inner| inner.counters.stats()).unwrap_or_default();
+		stats.dropped_responses = self
+			.with_config(|broadcaster| broadcaster.read().unwrap().throttle.as_ref().map(ResponseThrottle::dropped))
+			.flatten()
+			.unwrap_or(0);
+		stats
+	}
+
+	/// Returns the responses that the broadcaster would send if it were asked about `name`, without sending anything.
+	///
+	/// See [`Broadcaster::simulate_query`](super::Broadcaster::simulate_query).
+	pub fn simulate_query(&self, name: impl IntoDnsName) -> Result<Vec<DnsMessage>, BadDnsNameError> {
+		let name = name.into_fqdn().map_err(BadDnsNameError::map(NameField::QueryName))?;
+		Ok(self
+			.with_config(|broadcaster| broadcaster.read().unwrap().simulate_query(name))
+			.unwrap_or_default())
+	}
+
 	/// Adds a service to the broadcaster configuration.
 	///
 	/// The service will be broadcasted at the next opportunity.
@@ -74,12 +175,58 @@ impl BroadcasterHandle {
 		}
 	}
 
+	/// Changes the port of a service in the broadcaster configuration, finding it by name.
+	///
+	/// The service's DNS response is rebuilt in place, so there's no need to remove and re-add it. The service is then announced with its new port
+	/// (unless it is [restricted to some interfaces](super::ServiceBuilder::add_interface_v4), as it is only ever answered by unicast), and its SRV
+	/// record has the cache-flush bit set, so clients that see the announcement replace the old port straight away.
+	///
+	/// Returns `true` if the service was found and updated, and `false` if it was not found.
+	pub fn update_port(&self, service_type: impl IntoDnsName, service_name: impl IntoDnsName, port: u16) -> Result<bool, UpdateServiceError> {
+		let service_type = service_type.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceType))?;
+		let service_name = service_name.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceName))?;
+
+		self.with_config(|broadcaster| {
+			let mut broadcaster = broadcaster.write().unwrap();
+
+			let outdated = broadcaster
+				.services
+				.iter()
+				.filter(|service| *service.service_name() == service_name && *service.service_type() == service_type)
+				.map(|service| Service::clone(service))
+				.collect::<Vec<_>>();
+
+			// Rebuild everything first, so nothing is changed if rebuilding fails
+			let updated = outdated
+				.iter()
+				.map(|service| {
+					let mut service = service.clone();
+					service.set_port(port);
+					ServiceDnsResponse::try_from(service)
+				})
+				.collect::<Result<Vec<_>, _>>()?;
+
+			for service in &outdated {
+				broadcaster.services.remove(service);
+			}
+			for service in updated.iter().filter(|service| !service.is_interface_restricted()) {
+				broadcaster
+					.announcements
+					.push(&service.dns_response, broadcaster.mtu, !broadcaster.disable_name_compression);
+			}
+			broadcaster.services.extend(updated);
+
+			Ok(!outdated.is_empty())
+		})
+		.unwrap_or(Ok(false))
+	}
+
 	/// Removes a service from the broadcaster configuration, finding it by name.
 	///
 	/// Returns `true` if the service was found and removed, and `false` if it was not found.
 	pub fn remove_named_service(&self, service_type: impl IntoDnsName, service_name: impl IntoDnsName) -> Result<bool, BadDnsNameError> {
-		let service_type = service_type.into_fqdn().map_err(|_| BadDnsNameError)?;
-		let service_name = service_name.into_fqdn().map_err(|_| BadDnsNameError)?;
+		let service_type = service_type.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceType))?;
+		let service_name = service_name.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceName))?;
 
 		let mut found = false;
 		self.with_config(|broadcaster| {
@@ -100,7 +247,7 @@ impl BroadcasterHandle {
 	///
 	/// Returns `true` if the service was found and removed, and `false` if it was not found.
 	pub fn remove_service_type(&self, service_type: impl IntoDnsName) -> Result<bool, BadDnsNameError> {
-		let service_type = service_type.into_fqdn().map_err(|_| BadDnsNameError)?;
+		let service_type = service_type.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceType))?;
 
 		let mut found = false;
 		self.with_config(|broadcaster| {
@@ -0,0 +1,124 @@
+use crate::{
+	errors::{BadDnsNameError, NameField},
+	util::IntoDnsName,
+};
+use std::{collections::BTreeMap, net::IpAddr};
+use trust_dns_client::rr::{DNSClass as DnsClass, Name as DnsName, RData, Record as DnsRecord, RecordType as DnsRecordType};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A host that one or more [`Service`](super::Service)s are available on.
+///
+/// A host has a hostname and a list of IP addresses, which are advertised as A/AAAA records alongside each service.
+///
+/// The addresses are advertised in the order they were added. Clients often try them in that order, so add the address you'd prefer
+/// clients to use (e.g. the Ethernet address rather than the Wi-Fi one) first.
+///
+/// By default, each service gets its own host, named after the service. If you're advertising several services that live on
+/// the same machine, you can create a single host wrapped in an [`Arc`](std::sync::Arc) and share it between them using
+/// [`ServiceBuilder::host`](super::ServiceBuilder::host), so that the broadcaster only stores its addresses once, and (if
+/// [`combine_responses`](super::BroadcasterBuilder::combine_responses) is enabled) only advertises them once per response.
+pub struct Host {
+	pub(super) hostname: DnsName,
+	pub(super) ip_addresses: Vec<IpAddr>,
+	address_names: BTreeMap<IpAddr, DnsName>,
+}
+impl Host {
+	/// Creates a new [`Host`] with the given hostname and no IP addresses.
+	pub fn new(hostname: impl IntoDnsName) -> Result<Self, BadDnsNameError> {
+		Ok(Self {
+			hostname: hostname.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceHostname))?,
+			ip_addresses: Vec::new(),
+			address_names: BTreeMap::new(),
+		})
+	}
+
+	#[inline(always)]
+	/// Adds an IP address that the host is available on.
+	///
+	/// Addresses are advertised in the order they were added. Adding an address that was already added does nothing.
+	pub fn add_ip_address(mut self, ip_address: IpAddr) -> Self {
+		self.insert_ip_address(ip_address);
+		self
+	}
+
+	/// Adds an IP address that the host is available on, advertised under `hostname` instead of the host's own hostname.
+	///
+	/// This is useful for split-hostname setups, such as advertising the IPv4 addresses as `host4.local.` and the IPv6 addresses as `host6.local.`.
+	/// If the address was already added, it keeps its place in the list but is advertised under `hostname` from now on.
+	pub fn add_ip_address_named(mut self, ip_address: IpAddr, hostname: impl IntoDnsName) -> Result<Self, BadDnsNameError> {
+		self.insert_ip_address_named(
+			ip_address,
+			hostname.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceHostname))?,
+		);
+		Ok(self)
+	}
+
+	#[inline(always)]
+	/// The hostname of the host.
+	pub fn hostname(&self) -> &DnsName {
+		&self.hostname
+	}
+
+	#[inline(always)]
+	/// The IP addresses this host is available on, in the order they are advertised.
+	pub fn ip_addresses(&self) -> &[IpAddr] {
+		&self.ip_addresses
+	}
+
+	/// The name that the A/AAAA record of `ip_address` is advertised under.
+	///
+	/// This is the [hostname](Host::hostname), unless the address was added with [`add_ip_address_named`](Host::add_ip_address_named).
+	pub fn ip_address_name(&self, ip_address: &IpAddr) -> &DnsName {
+		self.address_names.get(ip_address).unwrap_or(&self.hostname)
+	}
+
+	/// The name that SRV records should point to, unless overridden by the service.
+	///
+	/// This is the hostname, unless none of the addresses are advertised under it, in which case it's the name of the first (preferred) address.
+	pub(super) fn srv_target(&self) -> &DnsName {
+		match self.ip_addresses.first() {
+			Some(first) if self.ip_addresses.iter().all(|ip| self.address_names.contains_key(ip)) => self.ip_address_name(first),
+			_ => &self.hostname,
+		}
+	}
+
+	pub(super) fn insert_ip_address_named(&mut self, ip_address: IpAddr, hostname: DnsName) {
+		self.insert_ip_address(ip_address);
+		self.address_names.insert(ip_address, hostname);
+	}
+
+	/// Adds an IP address to the end of the list, unless it's already in it.
+	pub(super) fn insert_ip_address(&mut self, ip_address: IpAddr) {
+		if !self.ip_addresses.contains(&ip_address) {
+			self.ip_addresses.push(ip_address);
+		}
+	}
+
+	pub(super) fn address_records(&self, ttl: u32) -> impl Iterator<Item = DnsRecord> + '_ {
+		self.ip_addresses.iter().map(move |addr| {
+			let mut record = address_record(self.ip_address_name(addr).clone(), *addr, ttl);
+			record.set_mdns_cache_flush(true);
+			record
+		})
+	}
+}
+
+/// Builds the A or AAAA record of `addr`, advertised under `name`, without the cache flush bit.
+pub(super) fn address_record(name: DnsName, addr: IpAddr, ttl: u32) -> DnsRecord {
+	let mut record = DnsRecord::new();
+
+	record
+		.set_dns_class(DnsClass::IN)
+		.set_rr_type(match addr {
+			IpAddr::V4(_) => DnsRecordType::A,
+			IpAddr::V6(_) => DnsRecordType::AAAA,
+		})
+		.set_data(Some(match addr {
+			IpAddr::V4(addr) => RData::A(addr),
+			IpAddr::V6(addr) => RData::AAAA(addr),
+		}))
+		.set_name(name)
+		.set_ttl(ttl);
+
+	record
+}
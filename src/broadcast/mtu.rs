@@ -0,0 +1,96 @@
+use crate::logging;
+use std::collections::BTreeMap;
+use trust_dns_client::{
+	op::Message as DnsMessage,
+	proto::error::ProtoResult,
+	rr::{Name as DnsName, Record as DnsRecord},
+	serialize::binary::{BinEncodable, BinEncoder},
+};
+
+/// The size of an IPv6 header plus a UDP header, which must fit in the MTU alongside the DNS packet.
+const IPV6_UDP_OVERHEAD: usize = 40 + 8;
+
+/// Returns the largest DNS packet that can be sent over IPv6 without exceeding the given MTU.
+#[inline]
+pub(crate) fn max_payload_v6(mtu: u16) -> usize {
+	(mtu as usize).saturating_sub(IPV6_UDP_OVERHEAD)
+}
+
+/// Encodes a DNS message into `buf`, with or without name compression.
+///
+/// trust-dns only writes uncompressed names in DNSSEC canonical form, so without compression, names in record data (such as PTR and SRV targets) are also lowercased.
+pub(crate) fn encode_into(message: &DnsMessage, buf: &mut Vec<u8>, compress: bool) -> ProtoResult<()> {
+	let mut encoder = BinEncoder::new(buf);
+	encoder.set_canonical_names(!compress);
+	message.emit(&mut encoder)
+}
+
+/// Encodes a DNS message, with or without name compression.
+pub(crate) fn encode(message: &DnsMessage, compress: bool) -> ProtoResult<Vec<u8>> {
+	let mut buf = Vec::with_capacity(512);
+	encode_into(message, &mut buf, compress)?;
+	Ok(buf)
+}
+
+/// Splits a response into as few packets as possible that are each no larger than `max_len`.
+///
+/// Answers are kept in the answer section, and additional records are kept together by name, so that the A and AAAA records of a host are always sent in the same packet.
+/// A single record (or group of records) that is too large by itself is sent in its own packet anyway.
+pub(crate) fn split_response(response: &DnsMessage, max_len: usize, compress: bool) -> Vec<Vec<u8>> {
+	let mut additionals = BTreeMap::<&DnsName, Vec<&DnsRecord>>::new();
+	for record in response.additionals() {
+		additionals.entry(record.name()).or_default().push(record);
+	}
+
+	let groups = response
+		.answers()
+		.iter()
+		.map(|record| (true, vec![record]))
+		.chain(additionals.into_values().map(|records| (false, records)));
+
+	let new_packet = || {
+		let mut packet = DnsMessage::new();
+		packet.set_header(*response.header());
+		packet
+	};
+
+	let mut packets = Vec::new();
+	let mut packet = new_packet();
+	let mut packet_bytes = None;
+	for (answer, records) in groups {
+		let mut candidate = packet.clone();
+		if answer {
+			candidate.add_answers(records.iter().map(|record| (*record).clone()));
+		} else {
+			candidate.add_additionals(records.iter().map(|record| (*record).clone()));
+		}
+
+		let candidate_bytes = match encode(&candidate, compress) {
+			Ok(bytes) => bytes,
+			Err(err) => {
+				logging::warn!("Failed to serialize mDNS response: {err}");
+				continue;
+			}
+		};
+
+		if candidate_bytes.len() <= max_len || packet_bytes.is_none() {
+			packet = candidate;
+			packet_bytes = Some(candidate_bytes);
+			continue;
+		}
+
+		// Doesn't fit; flush what we have and start a new packet with this group
+		packets.extend(packet_bytes.take());
+
+		packet = new_packet();
+		if answer {
+			packet.add_answers(records.into_iter().cloned());
+		} else {
+			packet.add_additionals(records.into_iter().cloned());
+		}
+		packet_bytes = encode(&packet, compress).ok();
+	}
+	packets.extend(packet_bytes);
+
+	packets
+}
@@ -1,20 +1,35 @@
-use super::errors::{ServiceBuilderError, ServiceDnsPacketBuilderError};
-use crate::{errors::BadDnsNameError, util::IntoDnsName};
+use super::{
+	errors::{ServiceBuilderError, ServiceDnsPacketBuilderError, ServiceFromResponseError},
+	host::address_record,
+	Host, TxtRecord,
+};
+use crate::{
+	errors::{BadDnsNameError, NameField},
+	net::{Ipv6Interface, RecvInterface},
+	util::{IntoDnsName, SERVICES_META_QUERY},
+};
 use std::{
 	borrow::{Borrow, Cow},
 	collections::BTreeSet,
-	net::IpAddr,
+	hash::Hash,
+	net::{IpAddr, Ipv4Addr},
 	ops::Deref,
+	sync::Arc,
 };
 use trust_dns_client::{
 	op::{Header as DnsHeader, Message as DnsMessage, MessageType as DnsMessageType, OpCode as DnsOpCode},
+	proto::error::ProtoError,
 	rr::{
 		rdata::{SRV, TXT},
 		DNSClass as DnsClass, Name as DnsName, RData, Record as DnsRecord, RecordType as DnsRecordType,
 	},
+	serialize::binary::BinEncodable,
 };
 
-const TXT_MAX_LEN: usize = 255;
+pub(super) const TXT_MAX_LEN: usize = 255;
+
+/// The largest mDNS message that may be sent, from [RFC 6762 §17](https://datatracker.ietf.org/doc/html/rfc6762#section-17).
+const DEFAULT_MAX_RESPONSE_SIZE: usize = 9000;
 
 /// Convenience trait implemented for types that can be interpreted as a DNS TXT record.
 ///
@@ -116,7 +131,7 @@ impl Borrow<Service> for ServiceDnsResponse {
 impl PartialOrd for ServiceDnsResponse {
 	#[inline(always)]
 	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-		self.service.partial_cmp(&other.service)
+		Some(self.cmp(other))
 	}
 }
 impl Ord for ServiceDnsResponse {
@@ -133,6 +148,17 @@ impl PartialEq for ServiceDnsResponse {
 }
 impl Eq for ServiceDnsResponse {}
 
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ExtraRecord(DnsRecord);
+impl Hash for ExtraRecord {
+	fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+		// Must agree with `DnsRecord`'s `PartialEq`, which ignores the TTL
+		self.0.name().hash(state);
+		self.0.record_type().hash(state);
+		self.0.dns_class().hash(state);
+	}
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 /// A service that can be advertised on the network.
 ///
@@ -140,13 +166,19 @@ impl Eq for ServiceDnsResponse {}
 pub struct Service {
 	service_type: DnsName,
 	service_name: DnsName,
-	service_hostname: DnsName,
 	service_id: DnsName,
 	pub(crate) service_subtype_suffix: Option<String>,
-	ip_addresses: BTreeSet<IpAddr>,
+	host: Arc<Host>,
+	srv_target: Option<DnsName>,
+	additional_addresses: Vec<(IpAddr, DnsName)>,
 	port: u16,
 	txt: Vec<Cow<'static, [u8]>>,
 	ttl: u32,
+	extra_records: Vec<ExtraRecord>,
+	interfaces_v4: BTreeSet<Ipv4Addr>,
+	interfaces_v6: BTreeSet<Ipv6Interface>,
+	authoritative: bool,
+	srv_txt_in_answers: bool,
 }
 impl Service {
 	#[inline(always)]
@@ -162,9 +194,53 @@ impl Service {
 	}
 
 	#[inline(always)]
-	/// The IP addresses this service is available on.
-	pub fn ip_addresses(&self) -> &BTreeSet<IpAddr> {
-		&self.ip_addresses
+	/// The full service instance name, which is the service name followed by the service type.
+	///
+	/// This is the name that appears in the PTR record's data, and the name of the SRV and TXT records.
+	pub fn service_id(&self) -> &DnsName {
+		&self.service_id
+	}
+
+	#[inline(always)]
+	/// The hostname of the service, which is the target of the SRV record and the name of the A/AAAA records.
+	///
+	/// Addresses added with [`ServiceBuilder::add_ip_address_named`] are advertised under their own name instead.
+	pub fn hostname(&self) -> &DnsName {
+		&self.host.hostname
+	}
+
+	#[inline(always)]
+	/// The target of the service's SRV record.
+	///
+	/// This is the [hostname](Service::hostname) unless it was overridden with [`ServiceBuilder::srv_target`], or every address was
+	/// [added under a name of its own](ServiceBuilder::add_ip_address_named), in which case it's the name of the first address.
+	pub fn srv_target(&self) -> &DnsName {
+		self.srv_target.as_ref().unwrap_or_else(|| self.host.srv_target())
+	}
+
+	#[inline(always)]
+	/// The addresses of other hosts that are included in the additional section of the service's DNS responses, and the names they're advertised under.
+	///
+	/// See [`ServiceBuilder::add_additional_address`].
+	pub fn additional_addresses(&self) -> &[(IpAddr, DnsName)] {
+		&self.additional_addresses
+	}
+
+	#[inline(always)]
+	pub(super) fn set_port(&mut self, port: u16) {
+		self.port = port;
+	}
+
+	#[inline(always)]
+	/// The IP addresses this service is available on, in the order they are advertised.
+	pub fn ip_addresses(&self) -> &[IpAddr] {
+		&self.host.ip_addresses
+	}
+
+	#[inline(always)]
+	/// The host this service is available on.
+	pub fn host(&self) -> &Arc<Host> {
+		&self.host
 	}
 
 	#[inline(always)]
@@ -185,155 +261,782 @@ impl Service {
 		&self.txt
 	}
 
+	#[inline(always)]
+	/// Any extra records that are appended to the additional section of this service's DNS response.
+	pub fn extra_records(&self) -> impl Iterator<Item = &DnsRecord> {
+		self.extra_records.iter().map(|record| &record.0)
+	}
+
+	#[inline(always)]
+	/// The IPv4 interfaces this service is restricted to, if any.
+	///
+	/// See [`ServiceBuilder::add_interface_v4`].
+	pub fn interfaces_v4(&self) -> &BTreeSet<Ipv4Addr> {
+		&self.interfaces_v4
+	}
+
+	#[inline(always)]
+	/// The IPv6 interfaces this service is restricted to, if any.
+	///
+	/// See [`ServiceBuilder::add_interface_v6`].
+	pub fn interfaces_v6(&self) -> &BTreeSet<Ipv6Interface> {
+		&self.interfaces_v6
+	}
+
+	#[inline(always)]
+	/// Whether this service is only advertised on specific interfaces.
+	pub fn is_interface_restricted(&self) -> bool {
+		!self.interfaces_v4.is_empty() || !self.interfaces_v6.is_empty()
+	}
+
+	/// Whether a query that arrived on `interface` should be answered with this service.
+	///
+	/// If the service is restricted to specific interfaces, queries that arrived on an unknown interface are never answered.
+	pub(crate) fn is_available_on(&self, interface: Option<RecvInterface>) -> bool {
+		if !self.is_interface_restricted() {
+			return true;
+		}
+
+		match interface {
+			Some(RecvInterface::V4(iface)) => self.interfaces_v4.contains(&iface),
+			Some(RecvInterface::V6(iface)) => self.interfaces_v6.contains(&iface),
+			None => false,
+		}
+	}
+
+	#[inline(always)]
+	/// Whether the service's DNS responses have the authoritative answer bit set.
+	///
+	/// See [`ServiceBuilder::authoritative`].
+	pub fn is_authoritative(&self) -> bool {
+		self.authoritative
+	}
+
+	#[inline(always)]
+	/// Whether the service's SRV and TXT records are sent in the answer section of its DNS response, rather than the additional section.
+	///
+	/// See [`ServiceBuilder::srv_txt_in_answers`].
+	pub fn srv_txt_in_answers(&self) -> bool {
+		self.srv_txt_in_answers
+	}
+
 	#[inline(always)]
 	/// Whether the service can be subtyped.
 	pub fn can_subtype(&self) -> bool {
 		self.service_subtype_suffix.is_some()
 	}
 
+	/// Reconstructs a service from a DNS response that advertises it, such as the last response of a discovered responder.
+	///
+	/// See [`ServiceBuilder::from_response`] for details.
+	pub fn from_response(response: &DnsMessage) -> Result<Self, ServiceFromResponseError> {
+		Ok(ServiceBuilder::from_response(response)?.build()?)
+	}
+
 	/// Builds a DNS packet that can be sent to a client to respond to a DNS query for this service.
 	pub fn dns_response(&self) -> Result<DnsMessage, ServiceDnsPacketBuilderError> {
 		let mut response = DnsMessage::new();
 
 		response.set_header({
+			let (answers, srv_txt_additionals) = if self.srv_txt_in_answers { (3, 0) } else { (1, 2) };
+
 			let mut header = DnsHeader::new();
-			header.set_authoritative(true);
+			header.set_authoritative(self.authoritative);
 			header.set_message_type(DnsMessageType::Response);
 			header.set_op_code(DnsOpCode::Query);
-			header.set_answer_count(1);
+			header.set_answer_count(answers);
 			header.set_additional_count(
-				(self.ip_addresses.len() + 1 + 1)
-					.try_into()
-					.map_err(|_| ServiceDnsPacketBuilderError::TooManyIpAddresses)?,
+				u16::try_from(self.host.ip_addresses.len() + srv_txt_additionals)
+					.map_err(|_| ServiceDnsPacketBuilderError::TooManyIpAddresses)?
+					.checked_add(
+						u16::try_from(self.extra_records.len() + self.additional_addresses.len())
+							.map_err(|_| ServiceDnsPacketBuilderError::TooManyRecords)?,
+					)
+					.ok_or(ServiceDnsPacketBuilderError::TooManyRecords)?,
 			);
 			header
 		});
 
-		response.add_answer({
-			let mut record = DnsRecord::new();
+		response.add_answer(self.ptr_record());
+		if self.srv_txt_in_answers {
+			response.add_answer(self.srv_record());
+			response.add_answer(self.txt_record());
+		}
 
-			record
-				.set_dns_class(DnsClass::IN)
-				.set_rr_type(DnsRecordType::PTR)
-				.set_data(Some(RData::PTR(self.service_id.clone())))
-				.set_name(self.service_type.clone())
-				.set_ttl(self.ttl);
+		for record in self.host.address_records(self.ttl).chain(self.additional_address_records()) {
+			response.add_additional(record);
+		}
 
-			record
+		if !self.srv_txt_in_answers {
+			response.add_additional(self.srv_record());
+			response.add_additional(self.txt_record());
+		}
+
+		for record in self.extra_records.iter() {
+			response.add_additional(record.0.clone());
+		}
+
+		Ok(response)
+	}
+
+	/// Builds a leaner version of [`dns_response`](Service::dns_response) for answering QU (unicast) queries, with only the PTR, SRV and A/AAAA records.
+	///
+	/// The client can query the service instance name directly if it needs the TXT record.
+	pub(crate) fn minimal_dns_response(&self) -> Result<DnsMessage, ServiceDnsPacketBuilderError> {
+		let mut answers = vec![self.ptr_record()];
+		let mut additionals = self
+			.host
+			.address_records(self.ttl)
+			.chain(self.additional_address_records())
+			.collect::<Vec<_>>();
+		if self.srv_txt_in_answers {
+			answers.push(self.srv_record());
+		} else {
+			additionals.push(self.srv_record());
+		}
+
+		let mut response = DnsMessage::new();
+
+		response.set_header({
+			let mut header = DnsHeader::new();
+			header.set_authoritative(self.authoritative);
+			header.set_message_type(DnsMessageType::Response);
+			header.set_op_code(DnsOpCode::Query);
+			header.set_answer_count(answers.len() as u16);
+			header.set_additional_count(u16::try_from(additionals.len()).map_err(|_| ServiceDnsPacketBuilderError::TooManyIpAddresses)?);
+			header
+		});
+
+		response.add_answers(answers);
+		response.add_additionals(additionals);
+
+		Ok(response)
+	}
+
+	/// The A/AAAA records of the [additional addresses](ServiceBuilder::add_additional_address).
+	///
+	/// These belong to other hosts, which may advertise them too, so they're sent without the cache flush bit.
+	fn additional_address_records(&self) -> impl Iterator<Item = DnsRecord> + '_ {
+		self.additional_addresses
+			.iter()
+			.map(move |(addr, name)| address_record(name.clone(), *addr, self.ttl))
+	}
+
+	fn ptr_record(&self) -> DnsRecord {
+		let mut record = DnsRecord::new();
+
+		record
+			.set_dns_class(DnsClass::IN)
+			.set_rr_type(DnsRecordType::PTR)
+			.set_data(Some(RData::PTR(self.service_id.clone())))
+			.set_name(self.service_type.clone())
+			.set_ttl(self.ttl);
+
+		record
+	}
+
+	fn srv_record(&self) -> DnsRecord {
+		let mut record = DnsRecord::new();
+
+		record
+			.set_dns_class(DnsClass::IN)
+			.set_rr_type(DnsRecordType::SRV)
+			.set_data(Some(RData::SRV(SRV::new(0, 0, self.port, self.srv_target().clone()))))
+			.set_name(self.service_id.clone())
+			.set_ttl(self.ttl)
+			.set_mdns_cache_flush(true);
+
+		record
+	}
+
+	fn txt_record(&self) -> DnsRecord {
+		let mut record = DnsRecord::new();
+
+		record
+			.set_dns_class(DnsClass::IN)
+			.set_rr_type(DnsRecordType::TXT)
+			.set_data(Some(RData::TXT(TXT::from_bytes(
+				self.txt.iter().map(|txt| txt.as_ref()).collect::<Vec<&[u8]>>(),
+			))))
+			.set_name(self.service_id.clone())
+			.set_ttl(self.ttl)
+			.set_mdns_cache_flush(true);
+
+		record
+	}
+
+	/// Builds a single DNS packet that responds to a DNS query for all of the given services.
+	///
+	/// The A/AAAA records of each distinct host are only included once, no matter how many of the services are available on it.
+	pub(crate) fn combined_dns_response<'a>(services: impl IntoIterator<Item = &'a Service>) -> Result<DnsMessage, ServiceDnsPacketBuilderError> {
+		let mut response = DnsMessage::new();
+
+		let mut hosts = BTreeSet::new();
+		let mut additionals = Vec::new();
+		let mut authoritative = true;
+		for service in services {
+			authoritative &= service.authoritative;
+
+			response.add_answer(service.ptr_record());
+
+			if hosts.insert(&*service.host) {
+				additionals.extend(service.host.address_records(service.ttl));
+			}
+			for record in service.additional_address_records() {
+				if !additionals.contains(&record) {
+					additionals.push(record);
+				}
+			}
+
+			if service.srv_txt_in_answers {
+				response.add_answer(service.srv_record());
+				response.add_answer(service.txt_record());
+			} else {
+				additionals.push(service.srv_record());
+				additionals.push(service.txt_record());
+			}
+			additionals.extend(service.extra_records.iter().map(|record| record.0.clone()));
+		}
+
+		response.set_header({
+			let mut header = DnsHeader::new();
+			header.set_authoritative(authoritative);
+			header.set_message_type(DnsMessageType::Response);
+			header.set_op_code(DnsOpCode::Query);
+			header.set_answer_count(u16::try_from(response.answers().len()).map_err(|_| ServiceDnsPacketBuilderError::TooManyRecords)?);
+			header.set_additional_count(u16::try_from(additionals.len()).map_err(|_| ServiceDnsPacketBuilderError::TooManyRecords)?);
+			header
 		});
 
-		for addr in self.ip_addresses.iter() {
-			response.add_additional({
+		response.add_additionals(additionals);
+
+		Ok(response)
+	}
+
+	/// Like [`combined_dns_response`](Service::combined_dns_response), but spreads the services over as few packets as possible that are each no larger than `max_len`.
+	///
+	/// The records of a service are never split across packets, so each packet is a complete answer for the services in it.
+	/// A service that is too large by itself is sent in its own packet anyway.
+	pub(crate) fn batched_dns_responses(services: &[&Service], max_len: usize) -> Result<Vec<DnsMessage>, ServiceDnsPacketBuilderError> {
+		let mut responses = Vec::new();
+
+		let mut batch_start = 0;
+		let mut batch = None;
+		for end in 1..=services.len() {
+			let candidate = Self::combined_dns_response(services[batch_start..end].iter().copied())?;
+
+			// A response that fails to serialize is treated as too large, so the service responsible ends up in a packet of its own
+			let fits = candidate.to_bytes().map(|bytes| bytes.len() <= max_len).unwrap_or(false);
+
+			match batch.take() {
+				Some(response) if !fits => {
+					// Doesn't fit; flush what we have and start a new batch with this service
+					responses.push(response);
+					batch_start = end - 1;
+					batch = Some(Self::combined_dns_response([services[batch_start]])?);
+				}
+				_ => batch = Some(candidate),
+			}
+		}
+		responses.extend(batch);
+
+		Ok(responses)
+	}
+
+	/// Builds a DNS packet that answers a DNS-SD service type enumeration query, with a PTR record pointing to each distinct service type of `services`.
+	///
+	/// Returns `None` if there are no services.
+	pub(crate) fn service_types_response<'a>(services: impl IntoIterator<Item = &'a Service>) -> Option<DnsMessage> {
+		let meta_query = DnsName::from_ascii(SERVICES_META_QUERY).unwrap();
+
+		let mut service_types = BTreeSet::new();
+		let mut authoritative = true;
+		let answers = services
+			.into_iter()
+			.filter(|service| service_types.insert(&service.service_type))
+			.map(|service| {
+				authoritative &= service.authoritative;
+
 				let mut record = DnsRecord::new();
 
+				// Shared records, so no cache flush
 				record
 					.set_dns_class(DnsClass::IN)
-					.set_rr_type(match addr {
-						IpAddr::V4(_) => DnsRecordType::A,
-						IpAddr::V6(_) => DnsRecordType::AAAA,
-					})
-					.set_data(Some(match addr {
-						IpAddr::V4(addr) => RData::A(*addr),
-						IpAddr::V6(addr) => RData::AAAA(*addr),
-					}))
-					.set_name(self.service_hostname.clone())
-					.set_ttl(self.ttl)
-					.set_mdns_cache_flush(true);
+					.set_rr_type(DnsRecordType::PTR)
+					.set_data(Some(RData::PTR(service.service_type.clone())))
+					.set_name(meta_query.clone())
+					.set_ttl(service.ttl);
 
 				record
-			});
+			})
+			.collect::<Vec<_>>();
+
+		if answers.is_empty() {
+			return None;
 		}
 
-		response.add_additional({
-			let mut record = DnsRecord::new();
+		let mut response = DnsMessage::new();
 
-			record
-				.set_dns_class(DnsClass::IN)
-				.set_rr_type(DnsRecordType::SRV)
-				.set_data(Some(RData::SRV(SRV::new(0, 0, self.port, self.service_hostname.clone()))))
-				.set_name(self.service_id.clone())
-				.set_ttl(self.ttl);
+		response.set_header({
+			let mut header = DnsHeader::new();
+			header.set_authoritative(authoritative);
+			header.set_message_type(DnsMessageType::Response);
+			header.set_op_code(DnsOpCode::Query);
+			header.set_answer_count(answers.len() as u16);
+			header
+		});
 
-			record
+		response.add_answers(answers);
+
+		Some(response)
+	}
+
+	/// Builds a DNS packet that answers an address query for `query_name` (such as a client resolving an SRV target it already knows about),
+	/// with the A and/or AAAA records of `services` that are advertised under that name.
+	///
+	/// Returns `None` if none of the services have addresses of the requested type under that name.
+	pub(crate) fn address_dns_response<'a>(
+		services: impl IntoIterator<Item = &'a Service>,
+		query_name: &DnsName,
+		query_type: DnsRecordType,
+	) -> Option<DnsMessage> {
+		let mut authoritative = true;
+		let mut answers = Vec::new();
+		for service in services {
+			let records = service
+				.host
+				.address_records(service.ttl)
+				.filter(|record| record.name() == query_name)
+				.filter(|record| query_type == DnsRecordType::ANY || record.rr_type() == query_type);
+
+			for record in records {
+				authoritative &= service.authoritative;
+
+				// Services on the same host would otherwise repeat its addresses
+				if !answers.contains(&record) {
+					answers.push(record);
+				}
+			}
+		}
+
+		if answers.is_empty() {
+			return None;
+		}
+
+		let mut response = DnsMessage::new();
+
+		response.set_header({
+			let mut header = DnsHeader::new();
+			header.set_authoritative(authoritative);
+			header.set_message_type(DnsMessageType::Response);
+			header.set_op_code(DnsOpCode::Query);
+			header.set_answer_count(u16::try_from(answers.len()).ok()?);
+			header
+		});
+
+		response.add_answers(answers);
+
+		Some(response)
+	}
+
+	/// Builds a DNS packet that answers a DNS-SD domain enumeration query for `query_name`, with a PTR record pointing to each of `domains`.
+	///
+	/// Returns `None` if there are no domains.
+	pub(crate) fn browse_domains_response(query_name: &DnsName, domains: &[DnsName]) -> Option<DnsMessage> {
+		if domains.is_empty() {
+			return None;
+		}
+
+		let answers = domains
+			.iter()
+			.map(|domain| {
+				let mut record = DnsRecord::new();
+
+				// Shared records, so no cache flush. Domains rarely change, so they use the TTL recommended for records that aren't host names (RFC 6762 §10)
+				record
+					.set_dns_class(DnsClass::IN)
+					.set_rr_type(DnsRecordType::PTR)
+					.set_data(Some(RData::PTR(domain.clone())))
+					.set_name(query_name.clone())
+					.set_ttl(4500);
+
+				record
+			})
+			.collect::<Vec<_>>();
+
+		let mut response = DnsMessage::new();
+
+		response.set_header({
+			let mut header = DnsHeader::new();
+			header.set_authoritative(true);
+			header.set_message_type(DnsMessageType::Response);
+			header.set_op_code(DnsOpCode::Query);
+			header.set_answer_count(answers.len() as u16);
+			header
+		});
+
+		response.add_answers(answers);
+
+		Some(response)
+	}
+
+	/// Builds a DNS packet that answers a query for this service's instance name (`service_id`), which clients send to resolve a service they already know about.
+	///
+	/// Returns `None` if the query type isn't one that this service can answer (SRV, TXT or ANY).
+	pub(crate) fn instance_dns_response(&self, query_type: DnsRecordType) -> Option<DnsMessage> {
+		let answers = match query_type {
+			DnsRecordType::SRV => vec![self.srv_record()],
+			DnsRecordType::TXT => vec![self.txt_record()],
+			DnsRecordType::ANY => vec![self.srv_record(), self.txt_record()],
+			_ => return None,
+		};
+
+		// The client will need the host's addresses to connect to the SRV target
+		let additionals = if query_type == DnsRecordType::TXT {
+			Vec::new()
+		} else {
+			self.host.address_records(self.ttl).chain(self.additional_address_records()).collect()
+		};
+
+		let mut response = DnsMessage::new();
+
+		response.set_header({
+			let mut header = DnsHeader::new();
+			header.set_authoritative(self.authoritative);
+			header.set_message_type(DnsMessageType::Response);
+			header.set_op_code(DnsOpCode::Query);
+			header.set_answer_count(answers.len() as u16);
+			header.set_additional_count(u16::try_from(additionals.len()).ok()?);
+			header
 		});
 
-		response.add_additional({
+		response.add_answers(answers);
+		response.add_additionals(additionals);
+
+		Some(response)
+	}
+
+	/// Builds a DNS packet that answers a reverse lookup (`in-addr.arpa.` or `ip6.arpa.`) for one of this service's IP addresses with the name it's advertised under.
+	pub(crate) fn reverse_lookup_response(&self, addr: IpAddr) -> DnsMessage {
+		let mut response = DnsMessage::new();
+
+		response.set_header({
+			let mut header = DnsHeader::new();
+			header.set_authoritative(self.authoritative);
+			header.set_message_type(DnsMessageType::Response);
+			header.set_op_code(DnsOpCode::Query);
+			header.set_answer_count(1);
+			header
+		});
+
+		response.add_answer({
 			let mut record = DnsRecord::new();
 
 			record
 				.set_dns_class(DnsClass::IN)
-				.set_rr_type(DnsRecordType::TXT)
-				.set_data(Some(RData::TXT(TXT::from_bytes(
-					self.txt.iter().map(|txt| txt.as_ref()).collect::<Vec<&[u8]>>(),
-				))))
-				.set_name(self.service_id.clone())
+				.set_rr_type(DnsRecordType::PTR)
+				.set_data(Some(RData::PTR(self.host.ip_address_name(&addr).clone())))
+				.set_name(DnsName::from(addr))
 				.set_ttl(self.ttl)
 				.set_mdns_cache_flush(true);
 
 			record
 		});
 
-		Ok(response)
+		response
 	}
 }
 
+#[derive(Debug, Clone)]
 /// A builder for [`Service`]s.
-pub struct ServiceBuilder(Service);
+pub struct ServiceBuilder {
+	service: Service,
+	max_response_size: usize,
+}
 impl ServiceBuilder {
 	/// Creates a new [`ServiceBuilder`] for a service with the given type, name, and port.
 	pub fn new(service_type: impl IntoDnsName, service_name: impl IntoDnsName, port: u16) -> Result<Self, BadDnsNameError> {
-		let service_type = service_type.into_fqdn().map_err(|_| BadDnsNameError)?;
-		let service_name = service_name.into_fqdn().map_err(|_| BadDnsNameError)?;
-		Ok(Self(Service {
-			service_id: format!("{service_name}{service_type}").into_fqdn().map_err(|_| BadDnsNameError)?,
+		let service_type = service_type.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceType))?;
+		let service_name = service_name.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceName))?;
+		Ok(Self {
+			service: Service {
+				service_id: format!("{service_name}{service_type}")
+					.into_fqdn()
+					.map_err(BadDnsNameError::map(NameField::ServiceId))?,
 
-			service_hostname: format!("{service_name}local.").into_fqdn().map_err(|_| BadDnsNameError)?,
+				host: Arc::new(Host::new(format!("{service_name}local."))?),
 
-			service_type,
-			service_name,
-			service_subtype_suffix: None,
-			ip_addresses: BTreeSet::new(),
-			port,
-			txt: Vec::new(),
-			ttl: 120,
-		}))
+				service_type,
+				service_name,
+				service_subtype_suffix: None,
+				srv_target: None,
+				additional_addresses: Vec::new(),
+				port,
+				txt: Vec::new(),
+				ttl: 120,
+				extra_records: Vec::new(),
+				interfaces_v4: BTreeSet::new(),
+				interfaces_v6: BTreeSet::new(),
+				authoritative: true,
+				srv_txt_in_answers: false,
+			},
+			max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+		})
+	}
+
+	/// Creates a [`ServiceBuilder`] from a DNS response that advertises a service, so that it can be re-advertised (for example, by an mDNS proxy).
+	///
+	/// The service instance name, type, port, TTL and hostname are taken from the first SRV record in the response, the IP addresses
+	/// from the A and AAAA records of its target, and the TXT records from the TXT record of the instance. Records with a TTL of zero
+	/// (goodbye packets) are ignored.
+	///
+	/// The response's [authoritative](ServiceBuilder::authoritative) bit is kept, and so is its [layout](ServiceBuilder::srv_txt_in_answers),
+	/// depending on whether the SRV record was in the answer section.
+	///
+	/// The builder can then be adjusted before building, for example to [restrict the service to an interface](ServiceBuilder::add_interface_v4)
+	/// or to add an IP address if the response didn't include any.
+	pub fn from_response(response: &DnsMessage) -> Result<Self, ServiceFromResponseError> {
+		let records = || response.answers().iter().chain(response.additionals()).filter(|record| record.ttl() != 0);
+
+		let (service_id, srv, ttl) = records()
+			.filter(|record| record.name().num_labels() >= 2)
+			.find_map(|record| match record.data() {
+				Some(RData::SRV(srv)) => Some((record.name(), srv, record.ttl())),
+				_ => None,
+			})
+			.ok_or(ServiceFromResponseError::MissingSrvRecord)?;
+
+		let service_name = DnsName::from_labels(service_id.iter().take(1)).map_err(BadDnsNameError::map(NameField::ServiceName))?;
+
+		// Keep the instance name exactly as it was advertised
+		let mut builder = Self::new(service_id.base_name(), service_name, srv.port())?
+			.service_id(service_id.clone())?
+			.ttl(ttl)
+			.host(Host::new(srv.target().clone())?)
+			.authoritative(response.header().authoritative())
+			.srv_txt_in_answers(response.answers().iter().any(|record| record.record_type() == DnsRecordType::SRV));
+
+		for record in records() {
+			match record.data() {
+				Some(RData::A(ip)) if record.name() == srv.target() => builder = builder.add_ip_address(IpAddr::V4(*ip)),
+				Some(RData::AAAA(ip)) if record.name() == srv.target() => builder = builder.add_ip_address(IpAddr::V6(*ip)),
+				Some(RData::TXT(txt)) if record.name() == service_id => {
+					for txt in txt.txt_data().iter().filter(|txt| !txt.is_empty()) {
+						builder = builder.add_txt(txt.to_vec());
+					}
+				}
+				_ => {}
+			}
+		}
+
+		Ok(builder)
+	}
+
+	/// Overrides the full service instance name, which is the name of the service's SRV and TXT records and the target of its PTR record.
+	///
+	/// The instance name is normally the service name followed by the service type, which assumes the usual `<instance>.<service type>` structure.
+	/// Overriding it lets names that don't follow that structure be advertised exactly, such as an instance label that contains dots
+	/// (which can be given as a [`Name`](crate::dns::rr::Name) built from its labels), e.g. when relaying a service that was discovered elsewhere.
+	///
+	/// The name can't be the root name.
+	///
+	/// **Default: the service name followed by the service type**
+	pub fn service_id(mut self, service_id: impl IntoDnsName) -> Result<Self, BadDnsNameError> {
+		let service_id = service_id.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceId))?;
+		if service_id.is_root() {
+			return Err(BadDnsNameError {
+				field: NameField::ServiceId,
+				source: ProtoError::from("the service instance name can't be the root name"),
+			});
+		}
+
+		self.service.service_id = service_id;
+		Ok(self)
+	}
+
+	/// Checks that the service type follows the DNS-SD rules from [RFC 6763 §7](https://datatracker.ietf.org/doc/html/rfc6763#section-7),
+	/// which standard clients (such as Bonjour and Avahi) rely on.
+	///
+	/// The service type must start with an underscore-prefixed service label of at most 15 characters, followed by `_tcp` or `_udp`
+	/// and then the domain, e.g. `_http._tcp.local.` This catches typos such as `_http._tpc.local.`, which would otherwise be
+	/// advertised without any error, but never be discovered.
+	pub fn strict(self) -> Result<Self, BadDnsNameError> {
+		validate_service_type(&self.service.service_type).map_err(|err| BadDnsNameError {
+			field: NameField::ServiceType,
+			source: ProtoError::from(format!("{err} (in {})", self.service.service_type)),
+		})?;
+		Ok(self)
+	}
+
+	/// The largest that the service's DNS response may be when encoded, in bytes, which is checked by [`build`](ServiceBuilder::build).
+	///
+	/// Responses that are too large are usually dropped on the wire without any error, so catching them when the service is built is much easier to debug.
+	/// Lower this if you know that your network can't carry jumbo frames and want to keep responses to a single packet, e.g. to 1472 for IPv4 over Ethernet.
+	///
+	/// **Default: 9000** (the largest mDNS message allowed by [RFC 6762 §17](https://datatracker.ietf.org/doc/html/rfc6762#section-17))
+	pub fn max_response_size(mut self, max_response_size: usize) -> Self {
+		self.max_response_size = max_response_size;
+		self
+	}
+
+	/// Whether to set the authoritative answer bit in the header of the service's DNS responses.
+	///
+	/// [RFC 6762 §18.4](https://datatracker.ietf.org/doc/html/rfc6762#section-18.4) requires it for responses from the service's own host,
+	/// but a proxy re-advertising services on behalf of other devices may want to clear it.
+	///
+	/// If [combined responses](super::BroadcasterBuilder::combine_responses) include several services, the bit is only set if every one of them is authoritative.
+	///
+	/// **Default: true**
+	pub fn authoritative(mut self, authoritative: bool) -> Self {
+		self.service.authoritative = authoritative;
+		self
+	}
+
+	/// Whether to send the service's SRV and TXT records in the answer section of its DNS response, alongside the PTR record,
+	/// instead of the additional section.
+	///
+	/// Some clients only look at the answer section, so they would otherwise need a follow-up query to resolve the service.
+	/// The A/AAAA records of the service's host always stay in the additional section.
+	///
+	/// **Default: false**
+	pub fn srv_txt_in_answers(mut self, srv_txt_in_answers: bool) -> Self {
+		self.service.srv_txt_in_answers = srv_txt_in_answers;
+		self
 	}
 
 	/// Sets the TTL of the service.
 	pub fn ttl(mut self, ttl: u32) -> Self {
-		self.0.ttl = ttl;
+		self.service.ttl = ttl;
 		self
 	}
 
 	#[inline(always)]
 	/// Adds an IP address that the service is available on.
+	///
+	/// Addresses are advertised in the order they were added, and clients often try them in that order, so add the preferred address first.
+	/// Adding an address that was already added does nothing.
+	///
+	/// If the service's [`Host`] is shared with other services, the host is copied first, so the other services are unaffected.
 	pub fn add_ip_address(mut self, ip_address: IpAddr) -> Self {
-		self.0.ip_addresses.insert(ip_address);
+		Arc::make_mut(&mut self.service.host).insert_ip_address(ip_address);
 		self
 	}
 
+	/// Adds an IP address that the service is available on, advertised under `hostname` instead of the service's [hostname](Service::hostname).
+	///
+	/// See [`Host::add_ip_address_named`]. If every address is added this way, the SRV record targets the name of the first address.
+	///
+	/// If the service's [`Host`] is shared with other services, the host is copied first, so the other services are unaffected.
+	pub fn add_ip_address_named(mut self, ip_address: IpAddr, hostname: impl IntoDnsName) -> Result<Self, BadDnsNameError> {
+		let hostname = hostname.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceHostname))?;
+		Arc::make_mut(&mut self.service.host).insert_ip_address_named(ip_address, hostname);
+		Ok(self)
+	}
+
+	#[inline(always)]
+	/// Sets the [`Host`] this service is available on, replacing its hostname and any IP addresses that have been added so far.
+	///
+	/// Sharing the same `Arc<Host>` between several services means the broadcaster only stores the host's addresses once.
+	pub fn host(mut self, host: impl Into<Arc<Host>>) -> Self {
+		self.service.host = host.into();
+		self
+	}
+
+	/// Sets the target of the service's SRV record, independently of the hostname used for its A/AAAA records.
+	///
+	/// If the SRV target differs from the hostname, clients will need to make a follow-up query to resolve its address.
+	///
+	/// **Default: the service's [hostname](Service::hostname)**
+	pub fn srv_target(mut self, srv_target: impl IntoDnsName) -> Result<Self, BadDnsNameError> {
+		self.service.srv_target = Some(srv_target.into_fqdn().map_err(BadDnsNameError::map(NameField::SrvTarget))?);
+		Ok(self)
+	}
+
+	/// Includes an A/AAAA record for `ip_address`, named `hostname`, in the additional section of the service's DNS responses.
+	///
+	/// This is for services whose [SRV target](ServiceBuilder::srv_target) is a different host, so that clients can resolve the target
+	/// in one round-trip instead of having to make a follow-up query. Unlike [`add_ip_address_named`](ServiceBuilder::add_ip_address_named),
+	/// the address doesn't become one of the service's own addresses: the broadcaster doesn't answer address queries or reverse lookups for it,
+	/// and it's sent without the cache flush bit, as the other host may advertise it too.
+	///
+	/// Adding the same address and hostname again does nothing.
+	pub fn add_additional_address(mut self, ip_address: IpAddr, hostname: impl IntoDnsName) -> Result<Self, BadDnsNameError> {
+		let hostname = hostname.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceHostname))?;
+		if !self
+			.service
+			.additional_addresses
+			.iter()
+			.any(|(addr, name)| *addr == ip_address && *name == hostname)
+		{
+			self.service.additional_addresses.push((ip_address, hostname));
+		}
+		Ok(self)
+	}
+
 	#[inline(always)]
 	/// Adds a TXT record to the service.
 	pub fn add_txt(mut self, record: impl IntoServiceTxt) -> Self {
-		self.0.txt.push(record.into_service_txt());
+		self.service.txt.push(record.into_service_txt());
 		self
 	}
 
 	#[inline(always)]
 	/// Adds a TXT record to the service, truncating it if it is too long (more than 255 bytes)
 	pub fn add_txt_truncated(mut self, record: impl IntoServiceTxt) -> Self {
-		self.0.txt.push(record.into_service_txt());
+		self.service.txt.push(record.into_service_txt());
+		self
+	}
+
+	#[inline(always)]
+	/// Adds all the entries of a validated [`TxtRecord`] to the service's TXT records.
+	pub fn txt_record(mut self, txt: TxtRecord) -> Self {
+		self.service.txt.extend(txt.entries);
+		self
+	}
+
+	#[inline(always)]
+	/// Adds an arbitrary record to the additional section of the service's DNS response.
+	///
+	/// Extra records are emitted after the standard A/AAAA, SRV and TXT records, exactly as provided.
+	///
+	/// This is an escape hatch for advertising records that Searchlight doesn't model itself, such as a second SRV record for a fallback port.
+	pub fn add_extra_record(mut self, record: DnsRecord) -> Self {
+		self.service.extra_records.push(ExtraRecord(record));
+		self
+	}
+
+	#[inline(always)]
+	/// Restricts the service to only be advertised on the IPv4 interface with the given address.
+	///
+	/// By default, a service is advertised on every interface the broadcaster's socket is bound to. Once any interface has been added
+	/// with this method or [`add_interface_v6`](Self::add_interface_v6), the broadcaster only answers queries with this service if they
+	/// arrived on one of the added interfaces, and these answers are always sent by unicast so they don't leak onto other interfaces.
+	///
+	/// The broadcaster must also be targeting the interface (see [`BroadcasterBuilder::interface_v4`](super::BroadcasterBuilder::interface_v4)).
+	///
+	/// The receiving interface of a query is only known on Linux, Android, macOS and iOS. On other platforms, restricted services are never advertised.
+	pub fn add_interface_v4(mut self, iface: Ipv4Addr) -> Self {
+		self.service.interfaces_v4.insert(iface);
+		self
+	}
+
+	#[inline(always)]
+	/// Restricts the service to only be advertised on the given IPv6 interface.
+	///
+	/// See [`add_interface_v4`](Self::add_interface_v4) for details.
+	pub fn add_interface_v6(mut self, iface: Ipv6Interface) -> Self {
+		self.service.interfaces_v6.insert(iface);
 		self
 	}
 
 	#[inline(always)]
 	/// Can this service be subtyped? If so, the broadcaster will respond to queries with subtyped service types.
 	pub fn can_subtype(mut self) -> Result<Self, BadDnsNameError> {
-		self.0.service_subtype_suffix = Some(format!(
+		self.service.service_subtype_suffix = Some(format!(
 			".{}",
-			format!("_sub.{}", self.0.service_type)
+			format!("_sub.{}", self.service.service_type)
 				.into_fqdn()
-				.map_err(|_| BadDnsNameError)?
+				.map_err(BadDnsNameError::map(NameField::ServiceSubtype))?
 				.to_utf8()
 		));
 		Ok(self)
@@ -341,14 +1044,70 @@ impl ServiceBuilder {
 
 	/// Builds the [`Service`].
 	pub fn build(self) -> Result<Service, ServiceBuilderError> {
-		if self.0.ip_addresses.is_empty() {
+		if self.service.host.ip_addresses.is_empty() {
 			return Err(ServiceBuilderError::MissingAdvertisementAddr);
 		}
 
-		if !self.0.txt.iter().all(|txt| txt.len() <= TXT_MAX_LEN) {
+		if !self.service.txt.iter().all(|txt| txt.len() <= TXT_MAX_LEN) {
 			return Err(ServiceBuilderError::RecordTooLong);
 		}
 
-		Ok(self.0)
+		// Responses that can't be built at all are reported when broadcasting
+		if let Some(estimated) = self
+			.service
+			.dns_response()
+			.ok()
+			.and_then(|response| response.to_bytes().ok())
+			.map(|bytes| bytes.len())
+		{
+			if estimated > self.max_response_size {
+				return Err(ServiceBuilderError::ResponseTooLarge {
+					estimated,
+					max: self.max_response_size,
+				});
+			}
+		}
+
+		Ok(self.service)
+	}
+}
+
+/// Checks that a service type looks like `_service._tcp.domain.` or `_service._udp.domain.`, returning what's wrong with it if it doesn't.
+fn validate_service_type(service_type: &DnsName) -> Result<(), &'static str> {
+	let mut labels = service_type.iter();
+
+	let service = labels.next().ok_or("the service type is empty")?;
+	if service.len() < 2 || service[0] != b'_' {
+		return Err("the service label must start with an underscore, e.g. `_http`");
+	}
+	if service.len() > 16 {
+		return Err("the service label must be at most 15 characters long, excluding the underscore");
+	}
+
+	let protocol = labels.next().ok_or("the service label must be followed by `_tcp` or `_udp`")?;
+	if !protocol.eq_ignore_ascii_case(b"_tcp") && !protocol.eq_ignore_ascii_case(b"_udp") {
+		return Err("the protocol label must be `_tcp` or `_udp`");
+	}
+
+	if labels.next().is_none() {
+		return Err("the protocol label must be followed by a domain, e.g. `local.`");
+	}
+
+	Ok(())
+}
+
+#[cfg(feature = "discovery")]
+impl TryFrom<&crate::discovery::Responder> for Service {
+	type Error = ServiceFromResponseError;
+
+	/// Reconstructs the service that a discovered responder advertised in its last response, using [`ServiceBuilder::from_response`].
+	///
+	/// If the response didn't include any IP addresses for the service, the address the response was sent from is used instead.
+	fn try_from(responder: &crate::discovery::Responder) -> Result<Self, Self::Error> {
+		let mut builder = ServiceBuilder::from_response(&responder.last_response)?;
+		if builder.service.host.ip_addresses.is_empty() {
+			builder = builder.add_ip_address(responder.addr.ip());
+		}
+		Ok(builder.build()?)
 	}
 }
@@ -0,0 +1,57 @@
+use std::sync::{
+	atomic::{AtomicU64, Ordering},
+	Mutex,
+};
+use tokio::time::Instant;
+
+/// A token bucket that caps how many responses the broadcaster sends per second, across all services.
+///
+/// Up to a second's worth of responses can be sent in a burst, after which responses are dropped until the bucket refills.
+pub(crate) struct ResponseThrottle {
+	per_sec: u32,
+	bucket: Mutex<Bucket>,
+	dropped: AtomicU64,
+}
+
+struct Bucket {
+	tokens: f64,
+	refilled: Option<Instant>,
+}
+
+impl ResponseThrottle {
+	pub(crate) fn new(per_sec: u32) -> Self {
+		Self {
+			per_sec,
+			bucket: Mutex::new(Bucket {
+				tokens: per_sec as f64,
+				refilled: None,
+			}),
+			dropped: AtomicU64::new(0),
+		}
+	}
+
+	/// Takes a token for sending a response, returning `false` (and counting the response as dropped) if there are none left.
+	pub(crate) fn try_send(&self) -> bool {
+		let mut bucket = self.bucket.lock().unwrap();
+
+		let now = Instant::now();
+		if let Some(refilled) = bucket.refilled {
+			let refill = now.saturating_duration_since(refilled).as_secs_f64() * self.per_sec as f64;
+			bucket.tokens = (bucket.tokens + refill).min(self.per_sec as f64);
+		}
+		bucket.refilled = Some(now);
+
+		if bucket.tokens >= 1.0 {
+			bucket.tokens -= 1.0;
+			true
+		} else {
+			self.dropped.fetch_add(1, Ordering::Relaxed);
+			false
+		}
+	}
+
+	/// How many responses have been dropped because of the throttle.
+	pub(crate) fn dropped(&self) -> u64 {
+		self.dropped.load(Ordering::Relaxed)
+	}
+}
@@ -0,0 +1,77 @@
+use super::{errors::TxtRecordError, service::TXT_MAX_LEN};
+use std::{borrow::Cow, collections::BTreeSet};
+
+#[derive(Debug, Clone, Default)]
+/// A builder for DNS-SD compliant TXT records.
+///
+/// [RFC 6763 §6.4](https://www.rfc-editor.org/rfc/rfc6763#section-6.4) requires TXT keys to be at least one character of printable US-ASCII (excluding `=`),
+/// and each key to only appear once. Keys are case insensitive, and it's recommended that they are no longer than 9 characters.
+///
+/// This type validates keys as they are inserted, so that malformed TXT records are caught when the service is built rather than being silently rejected by clients.
+///
+/// Use [`ServiceBuilder::txt_record`](super::ServiceBuilder::txt_record) to add the TXT record to a service.
+pub struct TxtRecord {
+	keys: BTreeSet<String>,
+	pub(super) entries: Vec<Cow<'static, [u8]>>,
+}
+impl TxtRecord {
+	/// Creates a new, empty [`TxtRecord`].
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	fn insert_entry(mut self, key: &str, entry: Vec<u8>) -> Result<Self, TxtRecordError> {
+		if key.is_empty() {
+			return Err(TxtRecordError::EmptyKey);
+		}
+
+		if !key.bytes().all(|byte| (0x20..=0x7E).contains(&byte) && byte != b'=') {
+			return Err(TxtRecordError::InvalidKey(key.to_owned()));
+		}
+
+		if entry.len() > TXT_MAX_LEN {
+			return Err(TxtRecordError::RecordTooLong(key.to_owned()));
+		}
+
+		if !self.keys.insert(key.to_ascii_lowercase()) {
+			return Err(TxtRecordError::DuplicateKey(key.to_owned()));
+		}
+
+		self.entries.push(Cow::Owned(entry));
+
+		Ok(self)
+	}
+
+	/// Inserts a `key=value` pair.
+	///
+	/// The value can be any bytes, including an empty value (which is different from a [boolean attribute](TxtRecord::insert_bool)).
+	pub fn insert(self, key: &str, value: impl AsRef<[u8]>) -> Result<Self, TxtRecordError> {
+		let value = value.as_ref();
+
+		let mut entry = Vec::with_capacity(key.len() + 1 + value.len());
+		entry.extend_from_slice(key.as_bytes());
+		entry.push(b'=');
+		entry.extend_from_slice(value);
+
+		self.insert_entry(key, entry)
+	}
+
+	/// Inserts a boolean attribute, which is a key with no `=` or value.
+	///
+	/// The presence of the key indicates that the attribute is true.
+	pub fn insert_bool(self, key: &str) -> Result<Self, TxtRecordError> {
+		self.insert_entry(key, key.as_bytes().to_vec())
+	}
+
+	#[inline(always)]
+	/// Returns `true` if no keys have been inserted.
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	#[inline(always)]
+	/// Returns the number of keys that have been inserted.
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+}
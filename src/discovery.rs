@@ -53,24 +53,31 @@
 //!             );
 //!         }
 //!
-//!         DiscoveryEvent::ResponseUpdate { .. } => {}
+//!         _ => {}
 //!     })
 //!     .unwrap();
 //! ```
 
 use crate::{
+	background::Background,
 	errors::MultiIpIoError,
-	socket::{AsyncMdnsSocket, MdnsSocket},
+	logging::{self, Instrument},
+	net::ReconnectPolicy,
+	socket::{LoopError, MdnsSocket, MdnsSocketRecipe, RecvErrors},
+	transport::{MdnsTransport, MdnsTransportRecv},
 };
 use std::{
+	collections::BTreeSet,
+	convert::Infallible,
+	future::Future,
 	net::SocketAddr,
-	sync::Arc,
-	time::{Duration, Instant},
+	sync::{atomic::AtomicU64, Arc},
+	time::{Duration, Instant, SystemTime},
 };
 use trust_dns_client::{
-	op::{DnsResponse, Message as DnsMessage, MessageType as DnsMessageType, Query as DnsQuery},
+	op::{Message as DnsMessage, Query as DnsQuery},
 	rr::{DNSClass as DnsClass, Name as DnsName, RecordType as DnsRecordType},
-	serialize::binary::{BinDecodable, BinEncodable},
+	serialize::binary::BinEncodable,
 };
 
 /// Errors that can occur while broadcasting or initializing a broadcaster.
@@ -84,31 +91,226 @@ pub use event::DiscoveryEvent;
 use event::*;
 
 mod handle;
-pub use handle::DiscoveryHandle;
+#[cfg(test)]
+pub(crate) use handle::KnownResponders;
+pub(crate) use handle::LoopControl;
 use handle::*;
+pub use handle::{DiscoveryHandle, Shutdown};
+
+mod enumerate;
+pub use enumerate::ResolvedService;
+#[cfg(test)]
+pub(crate) use enumerate::{browse_domains, Enumeration};
 
 mod presence;
 pub use presence::Responder;
+pub(crate) use presence::ResponderMemory;
 use presence::*;
 
-fn discovery_packet(unicast: bool, service_name: Option<&DnsName>) -> Result<Vec<u8>, std::io::Error> {
-	DnsMessage::new()
-		.add_query({
-			let mut query = DnsQuery::new();
+mod records;
+pub use records::RecordChanges;
+
+mod txt;
+pub(crate) use txt::TxtFilter;
+
+mod resolve;
+use resolve::*;
+
+mod recv;
+#[cfg(test)]
+pub(crate) use recv::recv_worker;
+pub(crate) use recv::DiscoveryRecv;
+#[cfg(feature = "broadcast")]
+pub(crate) use recv::ResponseSender;
+use recv::*;
+
+#[cfg(feature = "stream")]
+mod stream;
+#[cfg(feature = "stream")]
+#[cfg_attr(docsrs, doc(cfg(feature = "stream")))]
+pub use stream::DiscoveryEventStream;
+
+/// The minimum time between discovery packets sent because of [`DiscoveryHandle::query_now`].
+const MIN_QUERY_NOW_INTERVAL: Duration = Duration::from_secs(1);
+
+fn discovery_query(unicast: bool, service_name: Option<&DnsName>) -> DnsQuery {
+	let mut query = DnsQuery::new();
+
+	if let Some(service_name) = service_name {
+		query.set_name(service_name.clone());
+	}
+
+	query
+		.set_query_type(DnsRecordType::PTR)
+		.set_query_class(DnsClass::IN)
+		.set_mdns_unicast_response(unicast);
+
+	query
+}
+
+/// A discovery packet, which asks about the service name (or the root, if there isn't one) and any service types with handlers of their own.
+fn discovery_packet(id: u16, unicast: bool, service_name: Option<&DnsName>, service_types: &[DnsName]) -> Result<Vec<u8>, std::io::Error> {
+	let mut message = DnsMessage::new();
+	message.set_id(id);
+	if service_name.is_some() || service_types.is_empty() {
+		message.add_query(discovery_query(unicast, service_name));
+	}
+	for service_type in service_types {
+		message.add_query(discovery_query(unicast, Some(service_type)));
+	}
+	message
+		.to_bytes()
+		.map_err(|err| std::io::Error::other(format!("Discovery packet failed to serialize: {err}")))
+}
+
+/// A conventional DNS query for a unicast resolver: no QU bit, and recursion desired.
+fn resolver_packet(id: u16, service_name: Option<&DnsName>) -> Result<Vec<u8>, std::io::Error> {
+	let mut message = DnsMessage::new();
+	message.set_id(id).set_recursion_desired(true);
+	message
+		.add_query(discovery_query(false, service_name))
+		.to_bytes()
+		.map_err(|err| std::io::Error::other(format!("Resolver packet failed to serialize: {err}")))
+}
 
-			if let Some(service_name) = service_name {
-				query.set_name(service_name.clone());
+/// The packets sent by each discovery query.
+struct DiscoveryPackets {
+	multicast: Vec<u8>,
+	resolver: Option<ResolverQuery>,
+}
+impl DiscoveryPackets {
+	/// Whether a response should be ignored because it came from the unicast resolver, but doesn't answer our latest query to it.
+	fn is_stale(&self, recv: &Received) -> bool {
+		match &self.resolver {
+			Some(resolver) if recv.addr == resolver.addr => {
+				let stale = recv.response.id() != resolver.id;
+				if stale {
+					logging::trace!(
+						"Ignoring response from unicast resolver {} with transaction ID {} (expected {})",
+						resolver.addr,
+						recv.response.id(),
+						resolver.id
+					);
+				}
+				stale
 			}
+			_ => false,
+		}
+	}
+}
+
+/// A query to a unicast resolver, which carries a transaction ID that its response must match.
+///
+/// Multicast queries use a transaction ID of zero, as recommended by [RFC 6762 §18.1](https://datatracker.ietf.org/doc/html/rfc6762#section-18.1),
+/// because mDNS responders ignore it. A unicast resolver echoes it back, so matching it filters out unrelated or late responses.
+struct ResolverQuery {
+	addr: SocketAddr,
+	id: u16,
+	packet: Vec<u8>,
+}
+impl ResolverQuery {
+	/// Moves on to a new transaction ID, so that responses to previous queries are ignored.
+	fn next_id(&mut self) {
+		self.id = match self.id.wrapping_add(1) {
+			0 => 1,
+			id => id,
+		};
+
+		// The transaction ID is the first field of the DNS header
+		self.packet[..2].copy_from_slice(&self.id.to_be_bytes());
+	}
+}
+
+/// A random, non-zero transaction ID to start from.
+fn initial_query_id() -> u16 {
+	use std::hash::{BuildHasher, Hasher};
+
+	match std::collections::hash_map::RandomState::new().build_hasher().finish() as u16 {
+		0 => 1,
+		id => id,
+	}
+}
+
+pub(crate) struct DiscoveryConfig {
+	service_name: Option<DnsName>,
+	subtype_of: Option<DnsName>,
+	require_txt: Option<TxtFilter>,
+	min_addresses: usize,
+	service_handlers: ServiceHandlers,
+	interval: Duration,
+	startup_queries: (u8, Duration),
+	passive: Option<bool>,
+	idle_timeout: Option<(Duration, bool)>,
+	max_ignored_packets: u8,
+	worker_threads: usize,
+	exclude_self: bool,
+	confirm_after: u8,
+	response_collect_window: Duration,
+	unicast_responses: bool,
+	unicast_resolver: Option<SocketAddr>,
+	raw_mode: bool,
+	auto_resolve: bool,
+	reconnect: ReconnectPolicy,
+
+	/// How many responses the receiving workers have dropped because the discovery loop fell behind.
+	dropped_responses: Arc<AtomicU64>,
+}
+impl DiscoveryConfig {
+	pub(crate) fn response_filter(&self) -> ResponseFilter {
+		ResponseFilter {
+			service_name: self.service_name.clone(),
+			subtype_of: self.subtype_of.clone(),
+			service_types: self.service_handlers.service_types(),
+			require_txt: self.require_txt.clone(),
+			min_addresses: self.min_addresses,
+			auto_resolve: self.auto_resolve(),
+		}
+	}
 
-			query
-				.set_query_type(DnsRecordType::PTR)
-				.set_query_class(DnsClass::IN)
-				.set_mdns_unicast_response(unicast);
+	#[inline(always)]
+	fn auto_resolve(&self) -> bool {
+		self.auto_resolve && !self.raw_mode
+	}
 
-			query
+	fn discovery_packets(&self) -> Result<DiscoveryPackets, std::io::Error> {
+		Ok(DiscoveryPackets {
+			multicast: discovery_packet(
+				0,
+				self.unicast_responses,
+				self.service_name.as_ref(),
+				&self.service_handlers.service_types(),
+			)?,
+			resolver: match self.unicast_resolver {
+				Some(addr) => {
+					let id = initial_query_id();
+					Some(ResolverQuery {
+						addr,
+						id,
+						packet: resolver_packet(id, self.service_name.as_ref())?,
+					})
+				}
+				None => None,
+			},
 		})
-		.to_bytes()
-		.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, format!("Discovery packet failed to serialize: {err}")))
+	}
+
+	#[inline]
+	pub(crate) fn responder_memory(&self) -> ResponderMemory {
+		ResponderMemory::new(self.confirm_after)
+	}
+
+	#[inline]
+	/// Counts the responses that were dropped because the discovery loop fell behind.
+	pub(crate) fn dropped_responses(&self) -> Arc<AtomicU64> {
+		self.dropped_responses.clone()
+	}
+}
+#[cfg(feature = "broadcast")]
+impl DiscoveryConfig {
+	#[inline(always)]
+	pub(crate) fn interval(&self) -> Duration {
+		self.interval
+	}
 }
 
 /// A built mDNS discovery (client) instance, ready to be started.
@@ -118,9 +320,8 @@ fn discovery_packet(unicast: bool, service_name: Option<&DnsName>) -> Result<Vec
 /// A `Discovery` instance can be built using [`DiscoveryBuilder`].
 pub struct Discovery {
 	socket: MdnsSocket,
-	service_name: Option<DnsName>,
-	interval: Duration,
-	max_ignored_packets: u8,
+	recipe: MdnsSocketRecipe,
+	config: DiscoveryConfig,
 }
 impl Discovery {
 	/// Returns a new [`DiscoveryBuilder`].
@@ -128,6 +329,13 @@ impl Discovery {
 		DiscoveryBuilder::new()
 	}
 
+	/// Returns the local addresses that discovery's socket(s) are bound to, one for each IP version in use (IPv4 first).
+	///
+	/// This is useful for logging. If the socket has to be [recreated](DiscoveryBuilder::reconnect_policy), it is bound the same way again.
+	pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, MultiIpIoError> {
+		self.socket.local_addrs()
+	}
+
 	/// Run discovery on a new thread; in the background.
 	///
 	/// Returns a [`DiscoveryHandle`] that can be used to cleanly shut down the background thread.
@@ -135,18 +343,79 @@ impl Discovery {
 	where
 		F: Fn(DiscoveryEvent) + Send + Sync + 'static,
 	{
-		let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+		self.spawn(Arc::new(handler), None, None)
+	}
 
-		let thread = std::thread::spawn(move || {
-			tokio::runtime::Builder::new_current_thread()
-				.thread_name("Searchlight mDNS Discovery (Tokio)")
-				.enable_all()
-				.build()
-				.unwrap()
-				.block_on(self.impl_run(Arc::new(handler), Some(shutdown_rx)))
+	/// Run discovery on a new thread; in the background, automatically shutting down after the given duration.
+	///
+	/// Returns a [`DiscoveryHandle`] that can be used to cleanly shut down the background thread early.
+	pub fn run_in_background_for<F>(self, duration: Duration, handler: F) -> DiscoveryHandle
+	where
+		F: Fn(DiscoveryEvent) + Send + Sync + 'static,
+	{
+		self.spawn(Arc::new(handler), Some(duration), None)
+	}
+
+	/// Runs discovery on a new thread until `count` responders matching `predicate` have been found, or until `timeout` elapses, whichever
+	/// comes first, then shuts discovery down and returns the matching responders in the order they were found.
+	///
+	/// Each responder is only counted once (by address), so updates to a responder that was already collected don't count towards `count`.
+	/// A responder that didn't match when it was found is collected if a later update makes it match.
+	///
+	/// Fewer than `count` responders are returned if the timeout elapses first, or if discovery stops because of a fatal error.
+	///
+	/// This blocks the current thread.
+	pub fn collect_until<P>(self, predicate: P, count: usize, timeout: Duration) -> Vec<Arc<Responder>>
+	where
+		P: Fn(&Responder) -> bool + Send + Sync + 'static,
+	{
+		let deadline = Instant::now() + timeout;
+
+		let (tx, rx) = std::sync::mpsc::channel();
+		let handle = self.run_in_background_for(timeout, move |event| match event {
+			DiscoveryEvent::ResponderFound(responder) | DiscoveryEvent::ResponseUpdate { new: responder, .. } | DiscoveryEvent::Raw(responder) => {
+				if predicate(&responder) {
+					tx.send(responder).ok();
+				}
+			}
+			DiscoveryEvent::ResponderLost(_) => {}
 		});
 
-		DiscoveryHandle(DiscoveryHandleDrop(Some(DiscoveryHandleInner { thread, shutdown_tx })))
+		let mut seen = BTreeSet::new();
+		let mut collected = Vec::with_capacity(count);
+		while collected.len() < count {
+			match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+				Ok(responder) => {
+					if seen.insert(responder.addr) {
+						collected.push(responder);
+					}
+				}
+
+				// Timed out, or discovery stopped
+				Err(_) => break,
+			}
+		}
+
+		handle.shutdown().ok();
+
+		collected
+	}
+
+	/// Run discovery in the background, on a Tokio runtime that you already have, instead of on a new thread with its own runtime.
+	///
+	/// Discovery runs on one of the runtime's blocking threads, using its I/O and timer drivers, so any thread names, stack sizes or
+	/// hooks configured on the runtime apply. No threads are created by Searchlight, and if [`DiscoveryBuilder::worker_threads`] is more than 1,
+	/// packets are received and parsed by tasks spawned on the runtime instead.
+	///
+	/// If the runtime is a current-thread runtime, it must be kept running (with [`Runtime::block_on`](tokio::runtime::Runtime::block_on))
+	/// for discovery to make progress.
+	///
+	/// Returns a [`DiscoveryHandle`] that can be used to cleanly shut down discovery.
+	pub fn run_on<F>(self, runtime: tokio::runtime::Handle, handler: F) -> DiscoveryHandle
+	where
+		F: Fn(DiscoveryEvent) + Send + Sync + 'static,
+	{
+		self.spawn(Arc::new(handler), None, Some(runtime))
 	}
 
 	/// Run discovery on the current thread.
@@ -156,24 +425,138 @@ impl Discovery {
 	where
 		F: Fn(DiscoveryEvent) + Send + Sync + 'static,
 	{
-		tokio::runtime::Builder::new_current_thread()
-			.thread_name("Searchlight mDNS Discovery (Tokio)")
-			.enable_all()
-			.build()
-			.unwrap()
-			.block_on(self.impl_run(Arc::new(handler), None))
+		match Self::runtime(self.config.worker_threads).block_on(self.impl_run(Arc::new(handler), None, LoopControl::default(), None)) {
+			Shutdown::Fatal(err) => Err(err),
+			Shutdown::Requested | Shutdown::Elapsed | Shutdown::Idle => Ok(()),
+		}
+	}
+
+	#[cfg(feature = "stream")]
+	#[cfg_attr(docsrs, doc(cfg(feature = "stream")))]
+	/// Run discovery on a new thread; in the background, yielding events as a [`Stream`](futures_core::Stream) instead of calling an event handler.
+	///
+	/// Discovery will be shut down when the returned [`DiscoveryEventStream`] is dropped.
+	pub fn into_event_stream(self) -> DiscoveryEventStream {
+		let (handler, mut stream) = DiscoveryEventStream::channel(stream::EVENT_STREAM_CAPACITY);
+		stream.handle = Some(self.run_in_background(handler));
+		stream
 	}
 }
 impl Discovery {
-	async fn impl_run(self, handler: EventHandler, shutdown_rx: Option<tokio::sync::oneshot::Receiver<()>>) -> Result<(), MultiIpIoError> {
-		let Discovery {
-			socket,
-			service_name,
-			interval,
-			max_ignored_packets,
-		} = self;
+	fn spawn(self, handler: EventHandler, run_for: Option<Duration>, runtime: Option<tokio::runtime::Handle>) -> DiscoveryHandle {
+		let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+		let (interval_tx, interval_rx) = tokio::sync::watch::channel(self.config.interval);
+		let (query_tx, query_rx) = tokio::sync::watch::channel(());
+		let (forget_tx, forget_rx) = tokio::sync::mpsc::unbounded_channel();
+
+		// Found responders are also kept for `DiscoveryHandle::wait_for_responder` and `DiscoveryHandle::replay_to`
+		let found = Arc::new(FoundResponder::default());
+		let known = Arc::new(KnownResponders::default());
+		let handler: EventHandler = {
+			let found = found.clone();
+			let known = known.clone();
+			Arc::new(move |event: DiscoveryEvent| {
+				if let DiscoveryEvent::ResponderFound(responder) = &event {
+					found.found(responder);
+				}
+				known.track(&event);
+				handler(event)
+			})
+		};
+
+		let counters = self.recipe.options.counters.clone();
+		let dropped_responses = self.config.dropped_responses.clone();
+		let worker_threads = self.config.worker_threads;
+		let control = LoopControl {
+			interval_rx: Some(interval_rx),
+			query_rx: Some(query_rx),
+			forget_rx: Some(forget_rx),
+		};
+		let run = move || self.impl_run(handler, Some(shutdown_rx), control, run_for);
+
+		let background = match runtime {
+			Some(runtime) => Background::spawn_on(runtime, run),
+			None => Background::Thread(std::thread::spawn(move || Self::runtime(worker_threads).block_on(run()))),
+		};
+
+		DiscoveryHandle(DiscoveryHandleDrop(Some(DiscoveryHandleInner {
+			background,
+			shutdown_tx,
+			interval_tx,
+			query_tx,
+			forget_tx,
+			found,
+			known,
+			counters,
+			dropped_responses,
+		})))
+	}
+
+	fn runtime(worker_threads: usize) -> tokio::runtime::Runtime {
+		let mut builder = if worker_threads > 1 {
+			let mut builder = tokio::runtime::Builder::new_multi_thread();
+			builder.worker_threads(worker_threads);
+			builder
+		} else {
+			tokio::runtime::Builder::new_current_thread()
+		};
+
+		builder.thread_name("Searchlight mDNS Discovery (Tokio)").enable_all().build().unwrap()
+	}
+
+	async fn impl_run(
+		self,
+		handler: EventHandler,
+		shutdown_rx: Option<tokio::sync::oneshot::Receiver<()>>,
+		mut control: LoopControl,
+		run_for: Option<Duration>,
+	) -> Shutdown {
+		let Discovery { socket, recipe, config } = self;
+
+		let (handler, idle) = match config.idle_timeout {
+			Some((timeout, count_updates)) => {
+				let (handler, idle) = Self::idle_timer(handler, timeout, count_updates);
+				(handler, Some(idle))
+			}
+			None => (handler, None),
+		};
+
+		// Failing to build the discovery packets is fatal, but any other error from the discovery loop is a reason to recreate the socket
+		if let Err(err) = config.discovery_packets() {
+			return Shutdown::Fatal(err.into());
+		}
+
+		// Responders are remembered across sockets, so they aren't found again if the socket is recreated
+		let mut responder_memory = config.responder_memory();
 
-		let socket = socket.into_async().await?;
+		let mut socket = match socket.into_async().await {
+			Ok(socket) => Arc::new(socket),
+			Err(err) => return Shutdown::Fatal(err),
+		};
+
+		let run = async {
+			loop {
+				let recv = if config.worker_threads > 1 {
+					DiscoveryRecv::workers(
+						socket.clone(),
+						config.worker_threads,
+						config.response_filter(),
+						config.dropped_responses.clone(),
+					)
+				} else {
+					DiscoveryRecv::inline(&*socket, config.response_filter())
+				};
+
+				let err = match Self::discovery_loop(handler.clone(), &config, &*socket, recv, &mut responder_memory, &mut control).await {
+					Ok(never) => match never {},
+					Err(LoopError::Recreate(err)) => err,
+					Err(LoopError::Abort(err)) => return Shutdown::Fatal(err),
+				};
+
+				logging::warn!("Receiving on mDNS socket keeps failing, recreating it: {err}");
+				socket = Arc::new(recipe.recreate(&config.reconnect).await);
+			}
+		};
 
 		let shutdown = async move {
 			if let Some(shutdown_rx) = shutdown_rx {
@@ -183,123 +566,381 @@ impl Discovery {
 			}
 		};
 
+		let timeout = async move {
+			if let Some(run_for) = run_for {
+				tokio::time::sleep(run_for).await
+			} else {
+				std::future::pending().await
+			}
+		};
+
+		let idle = async move {
+			if let Some(idle) = idle {
+				idle.await
+			} else {
+				std::future::pending().await
+			}
+		};
+
 		tokio::select! {
 			biased;
-			res = Self::discovery_loop(handler, service_name, interval, max_ignored_packets, &socket) => res,
-			_ = shutdown => Ok(()),
+			shutdown = run => shutdown,
+			_ = shutdown => Shutdown::Requested,
+			_ = timeout => Shutdown::Elapsed,
+			_ = idle => Shutdown::Idle,
 		}
 	}
 
-	async fn discovery_loop(
-		event_handler: EventHandler,
-		service_name: Option<DnsName>,
-		discovery_interval: Duration,
-		max_ignored_packets: u8,
-		socket: &AsyncMdnsSocket,
-	) -> Result<(), MultiIpIoError> {
-		let service_name = service_name.as_ref();
+	/// Wraps `handler` so that every event that counts as activity restarts the returned timer, which completes once there has been no activity for `timeout`.
+	pub(crate) fn idle_timer(handler: EventHandler, timeout: Duration, count_updates: bool) -> (EventHandler, impl Future<Output = ()>) {
+		let (activity_tx, mut activity_rx) = tokio::sync::watch::channel(());
+
+		let handler: EventHandler = Arc::new(move |event: DiscoveryEvent| {
+			if count_updates || !matches!(event, DiscoveryEvent::ResponseUpdate { .. }) {
+				activity_tx.send_replace(());
+			}
+			handler(event)
+		});
+
+		let idle = async move {
+			while let Ok(changed) = tokio::time::timeout(timeout, activity_rx.changed()).await {
+				if changed.is_err() {
+					// The handler is gone, so there can't be any more activity
+					break;
+				}
+			}
+		};
+
+		(handler, idle)
+	}
 
-		// Response listening
-		let mut socket_recv = socket.recv(vec![0; 4096]);
+	pub(crate) async fn discovery_loop(
+		event_handler: EventHandler,
+		config: &DiscoveryConfig,
+		socket: &impl MdnsTransport,
+		mut recv: DiscoveryRecv<impl MdnsTransportRecv>,
+		responder_memory: &mut ResponderMemory,
+		control: &mut LoopControl,
+	) -> Result<Infallible, LoopError> {
+		// Events about service types with handlers of their own are passed to those handlers instead
+		let event_handler = config.service_handlers.route(event_handler);
 
 		// Discovery
-		let discovery_packet = discovery_packet(false, service_name)?;
-		let mut discovery_interval = tokio::time::interval(discovery_interval);
+		let mut discovery_packets = config.discovery_packets().map_err(|err| LoopError::Abort(err.into()))?;
+		let mut discovery_interval = tokio::time::interval(config.interval);
 		discovery_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+		let mut last_query: Option<tokio::time::Instant> = None;
+		let (mut startup_queries, startup_spacing) = config.startup_queries;
+
+		// In passive mode, discovery packets are only sent when starting up (if enabled) or when requested
+		let mut passive_query = config.passive.unwrap_or(false);
+
+		// Our own addresses, if we're excluding ourselves
+		let mut local_addrs = LocalAddrs::new(config.exclude_self);
+
+		// Responses waiting for their instances to be resolved, if we're resolving them
+		let mut auto_resolve = config.auto_resolve().then(|| AutoResolve::new(config.unicast_responses));
 
-		// Presence
-		let mut responder_memory = ResponderMemory::default();
+		let mut errors = RecvErrors::new(&config.reconnect);
 
 		loop {
 			tokio::select! {
 				biased; // Prefer handling packets
-				recv = socket_recv.recv_multicast() => {
+				recv = recv.recv() => {
 					let recv = match recv {
-						Ok(recv) => recv,
+						Ok(Some(recv)) => recv,
+						Ok(None) => {
+							errors.ok();
+							continue;
+						}
 						Err(err) => {
-							log::warn!("Failed to receive on mDNS socket: {err}");
+							logging::warn!("Failed to receive on mDNS socket: {err}");
+							if let Some(stop) = errors.error().await {
+								return Err(stop(err));
+							}
 							continue;
 						}
 					};
-					Self::recv_multicast(service_name, &event_handler, &mut responder_memory, recv).await;
+					errors.ok();
+					if discovery_packets.is_stale(&recv) {
+						continue;
+					}
+					Self::recv_response(&event_handler, config.raw_mode, responder_memory, &local_addrs, socket, &mut auto_resolve, recv).await;
+				}
+
+				interval = Self::interval_changed(&mut control.interval_rx) => {
+					discovery_interval = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+					discovery_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+				}
+
+				_ = Self::query_requested(&mut control.query_rx) => {
+					passive_query = true;
+
+					// Send a discovery packet as soon as possible, but don't flood the network if this is called rapidly
+					match last_query {
+						Some(last_query) if last_query.elapsed() < MIN_QUERY_NOW_INTERVAL => discovery_interval.reset_at(last_query + MIN_QUERY_NOW_INTERVAL),
+						_ => discovery_interval.reset_immediately(),
+					}
+				}
+
+				addr = Self::forget_requested(&mut control.forget_rx) => {
+					responder_memory.forget(&event_handler, addr);
 				}
 
 				_ = discovery_interval.tick() => {
-					// Send discovery packet!
-					if let Err(err) = socket.send_multicast(&discovery_packet).await {
-						log::warn!("Failed to send discovery packet on mDNS socket: {err}");
-						continue;
+					// Interfaces may have come and gone since the last discovery packet
+					local_addrs.refresh();
+
+					// Responses that couldn't be resolved since the last discovery packet are reported as they are
+					if let Some(auto_resolve) = &mut auto_resolve {
+						for recv in auto_resolve.flush() {
+							Self::report_response(&event_handler, config.raw_mode, responder_memory, recv).await;
+						}
 					}
 
-					if max_ignored_packets == 0 {
+					if config.passive.is_some() {
+						if std::mem::take(&mut passive_query) {
+							last_query = Some(tokio::time::Instant::now());
+							Self::send_query(config, socket, &mut discovery_packets)
+								.instrument(logging::debug_span!("query", service_name = ?config.service_name))
+								.await?;
+						}
+
+						// Responders aren't queried, so they can't ignore queries; they're lost when their answers expire instead
+						responder_memory.expire(&event_handler);
 						continue;
 					}
 
-					// Give responders a chance to respond
-					let mut deadline = tokio::time::Instant::now() + Duration::from_secs(2);
-					loop {
-						let recv = match tokio::time::timeout_at(deadline, socket_recv.recv_multicast()).await {
-							Ok(Ok(recv)) => recv,
-							Ok(Err(err)) => return Err(err),
-							Err(_) => break,
-						};
-
-						let forgiveness = tokio::time::Instant::now();
-						Self::recv_multicast(service_name, &event_handler, &mut responder_memory, recv).await;
-						deadline += forgiveness.elapsed(); // Add the time we spent processing the packet to the deadline
+					let now = tokio::time::Instant::now();
+					last_query = Some(now);
+
+					// Front-load a few queries when we start up, in case some of them are lost
+					if startup_queries > 1 {
+						startup_queries -= 1;
+						discovery_interval.reset_at(now + startup_spacing);
 					}
 
-					// Remove stale responders
-					responder_memory.sweep(&event_handler, max_ignored_packets);
+					Self::query(&event_handler, config, socket, &mut recv, &mut discovery_packets, responder_memory, &local_addrs, &mut auto_resolve, &mut errors)
+						.instrument(logging::debug_span!("query", service_name = ?config.service_name))
+						.await?;
 				}
 			}
 		}
 	}
 
-	async fn recv_multicast(
-		service_name: Option<&DnsName>,
+	/// Sends a discovery packet (and a query to the unicast resolver, if configured), collects the responses to it, and then removes any responders that have stopped responding.
+	///
+	/// Returns an error if receiving keeps failing and the socket should be recreated, or if an error is fatal according to the [`ReconnectPolicy`].
+	#[allow(clippy::too_many_arguments)]
+	async fn query(
 		event_handler: &EventHandler,
-		response_memory_bank: &mut ResponderMemory,
-		recv: ((usize, SocketAddr), &[u8]),
-	) {
-		let ((count, addr), packet) = recv;
+		config: &DiscoveryConfig,
+		socket: &impl MdnsTransport,
+		recv: &mut DiscoveryRecv<impl MdnsTransportRecv>,
+		discovery_packets: &mut DiscoveryPackets,
+		responder_memory: &mut ResponderMemory,
+		local_addrs: &LocalAddrs,
+		auto_resolve: &mut Option<AutoResolve>,
+		errors: &mut RecvErrors<'_>,
+	) -> Result<(), LoopError> {
+		if !Self::send_query(config, socket, discovery_packets).await? {
+			return Ok(());
+		}
 
-		if count == 0 {
-			return;
+		if config.max_ignored_packets == 0 {
+			return Ok(());
+		}
+
+		// Give responders a chance to respond
+		let mut deadline = tokio::time::Instant::now() + config.response_collect_window;
+		loop {
+			let recv = match tokio::time::timeout_at(deadline, recv.recv()).await {
+				Ok(Ok(Some(recv))) => recv,
+				Ok(Ok(None)) => {
+					errors.ok();
+					continue;
+				}
+				Ok(Err(err)) => {
+					logging::warn!("Failed to receive on mDNS socket: {err}");
+					if let Some(stop) = errors.error().await {
+						return Err(stop(err));
+					}
+					continue;
+				}
+				Err(_) => break,
+			};
+			errors.ok();
+
+			if discovery_packets.is_stale(&recv) {
+				continue;
+			}
+
+			let forgiveness = tokio::time::Instant::now();
+			Self::recv_response(event_handler, config.raw_mode, responder_memory, local_addrs, socket, auto_resolve, recv).await;
+			deadline += forgiveness.elapsed(); // Add the time we spent processing the packet to the deadline
 		}
 
-		let response = match DnsMessage::from_bytes(&packet[..count]) {
-			Ok(response) if response.message_type() == DnsMessageType::Response => DnsResponse::from(response),
-			_ => return,
+		// Remove stale responders
+		responder_memory.sweep(event_handler, config.max_ignored_packets);
+
+		Ok(())
+	}
+
+	/// Sends a discovery packet, and a query to the unicast resolver if configured.
+	///
+	/// Returns whether either was sent, or an error if sending failed and the [`ReconnectPolicy`] says to abort.
+	async fn send_query(config: &DiscoveryConfig, socket: &impl MdnsTransport, discovery_packets: &mut DiscoveryPackets) -> Result<bool, LoopError> {
+		// Send discovery packet!
+		let mut sent = match socket.send_multicast(&discovery_packets.multicast).await {
+			Ok(_) => {
+				logging::trace!("Sent discovery packet");
+				true
+			}
+			Err(err) => {
+				logging::warn!("Failed to send discovery packet on mDNS socket: {err}");
+				config.reconnect.send_error(err)?;
+				false
+			}
 		};
 
-		if let Some(service_name) = service_name {
-			if !response.answers().iter().any(|answer| answer.name() == service_name) {
-				// This response does not contain the service we are looking for.
+		if let Some(resolver) = &mut discovery_packets.resolver {
+			resolver.next_id();
+			match socket.send_to(&resolver.packet, resolver.addr).await {
+				Ok(_) => {
+					logging::trace!(
+						"Sent discovery query to unicast resolver {} with transaction ID {}",
+						resolver.addr,
+						resolver.id
+					);
+					sent = true;
+				}
+				Err(err) => {
+					logging::warn!("Failed to send discovery query to unicast resolver {}: {err}", resolver.addr);
+					config.reconnect.send_error(err)?;
+				}
+			}
+		}
+
+		Ok(sent)
+	}
+
+	async fn query_requested(query_rx: &mut Option<tokio::sync::watch::Receiver<()>>) {
+		if let Some(rx) = query_rx {
+			if rx.changed().await.is_ok() {
 				return;
 			}
 		}
+		std::future::pending().await
+	}
+
+	async fn forget_requested(forget_rx: &mut Option<tokio::sync::mpsc::UnboundedReceiver<SocketAddr>>) -> SocketAddr {
+		if let Some(rx) = forget_rx {
+			if let Some(addr) = rx.recv().await {
+				return addr;
+			}
+		}
+		std::future::pending().await
+	}
+
+	async fn interval_changed(interval_rx: &mut Option<tokio::sync::watch::Receiver<Duration>>) -> Duration {
+		if let Some(rx) = interval_rx {
+			if rx.changed().await.is_ok() {
+				return *rx.borrow_and_update();
+			}
+		}
+		std::future::pending().await
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(level = "debug", name = "response", skip_all, fields(addr = %recv.addr, service_name = ?recv.service_name))
+	)]
+	async fn recv_response(
+		event_handler: &EventHandler,
+		raw_mode: bool,
+		response_memory_bank: &mut ResponderMemory,
+		local_addrs: &LocalAddrs,
+		socket: &impl MdnsTransport,
+		auto_resolve: &mut Option<AutoResolve>,
+		recv: Received,
+	) {
+		if local_addrs.contains(&recv.addr.ip()) {
+			// This response came from ourselves
+			return;
+		}
+
+		let recv = match auto_resolve {
+			Some(auto_resolve) => match auto_resolve.handle(recv) {
+				Resolve::Complete(recv) => recv,
+				Resolve::Pending(query) => {
+					logging::debug!("Response has unresolved service instances, waiting for them to be resolved");
+					if let Some(query) = query {
+						if let Err(err) = socket.send_multicast(&query).await {
+							logging::warn!("Failed to send resolve query on mDNS socket: {err}");
+						}
+					}
+					return;
+				}
+			},
+			None => recv,
+		};
+
+		Self::report_response(event_handler, raw_mode, response_memory_bank, recv).await;
+	}
+
+	/// Remembers a response, and reports the responder as found or updated (or the response itself, in raw mode).
+	///
+	/// A goodbye packet makes the responder lost straight away, instead of once it stops responding.
+	async fn report_response(event_handler: &EventHandler, raw_mode: bool, response_memory_bank: &mut ResponderMemory, recv: Received) {
+		if !raw_mode && recv.is_goodbye() {
+			logging::debug!("Responder {} said goodbye", recv.addr);
+			response_memory_bank.goodbye(event_handler, recv.addr, recv.service_name);
+			return;
+		}
+
+		let Received {
+			addr,
+			response,
+			raw_packet,
+			service_name,
+		} = recv;
 
 		let event = {
-			let old = response_memory_bank.get(&addr).map(|response_memory| response_memory.inner.clone());
-
-			let new = {
-				let responder = Arc::new(Responder {
-					addr,
-					last_response: response,
-					last_responded: Instant::now(),
-				});
-				response_memory_bank.replace(responder.clone());
-				responder
+			let new = Responder {
+				addr,
+				last_response: response,
+				raw_packet,
+				service_name,
+				last_responded: Instant::now(),
+				last_responded_at: SystemTime::now(),
+				seen_count: 0, // Counted by the responder memory
 			};
 
-			match old {
-				Some(old) => DiscoveryEvent::ResponseUpdate { old, new },
-				None => DiscoveryEvent::ResponderFound(new),
+			if raw_mode {
+				// Every response is reported as-is, without being remembered
+				DiscoveryEvent::Raw(Arc::new(Responder { seen_count: 1, ..new }))
+			} else {
+				match response_memory_bank.replace(new) {
+					Remembered::Unconfirmed => {
+						logging::trace!("Responder is not confirmed yet");
+						return;
+					}
+					Remembered::Found(new) => DiscoveryEvent::ResponderFound(new),
+					Remembered::Updated { old, new } => DiscoveryEvent::ResponseUpdate { old, new },
+				}
+			}
+		};
+
+		let span = match &event {
+			DiscoveryEvent::ResponderFound(_) => {
+				logging::debug!("Responder found");
+				logging::debug_span!("responder_found", addr = %addr)
 			}
+			DiscoveryEvent::Raw(_) => logging::debug_span!("response_received", addr = %addr),
+			_ => logging::debug_span!("responder_updated", addr = %addr),
 		};
 
 		let event_handler = event_handler.clone();
-		tokio::task::spawn_blocking(move || event_handler(event)).await.ok();
+		tokio::task::spawn_blocking(move || span.in_scope(|| event_handler(event))).await.ok();
 	}
 }
@@ -1,41 +1,148 @@
-use super::{errors::DiscoveryBuilderError, Discovery};
+use super::{errors::DiscoveryBuilderError, event::ServiceHandlers, Discovery, DiscoveryConfig, DiscoveryEvent, TxtFilter};
 use crate::{
-	errors::{BadDnsNameError, MultiIpIoError},
-	net::{IpVersion, TargetInterfaceV4, TargetInterfaceV6},
-	socket::MdnsSocket,
+	errors::{BadDnsNameError, NameField},
+	net::{ConfigureSocket, IpVersion, ReconnectPolicy, SocketOptions, TargetInterfaceV4, TargetInterfaceV6},
+	socket::{MdnsSocketOptions, MdnsSocketRecipe},
 	util::IntoDnsName,
 };
-use std::time::Duration;
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc, time::Duration};
 use trust_dns_client::rr::Name as DnsName;
 
+#[derive(Debug, Clone)]
 /// A builder for [`Discovery`].
 pub struct DiscoveryBuilder {
 	service_name: Option<DnsName>,
+	subtype_of: Option<DnsName>,
+	require_txt: Option<TxtFilter>,
+	min_addresses: usize,
+	service_handlers: ServiceHandlers,
 	interval: Duration,
-	loopback: bool,
+	startup_queries: (u8, Duration),
+	passive: Option<bool>,
+	idle_timeout: Option<(Duration, bool)>,
+	socket_options: MdnsSocketOptions,
 	interface_v4: TargetInterfaceV4,
 	interface_v6: TargetInterfaceV6,
 	max_ignored_packets: u8,
+	worker_threads: usize,
+	exclude_self: bool,
+	confirm_after: u8,
+	response_collect_window: Duration,
+	unicast_responses: bool,
+	unicast_resolver: Option<SocketAddr>,
+	raw_mode: bool,
+	auto_resolve: bool,
+	reconnect: ReconnectPolicy,
 }
 impl DiscoveryBuilder {
 	/// Creates a new [`DiscoveryBuilder`].
 	pub fn new() -> Self {
 		Self {
 			service_name: None,
+			subtype_of: None,
+			require_txt: None,
+			min_addresses: 0,
+			service_handlers: ServiceHandlers::default(),
 			interval: Duration::from_secs(10),
-			loopback: false,
+			startup_queries: (1, Duration::from_secs(1)),
+			passive: None,
+			idle_timeout: None,
+			socket_options: MdnsSocketOptions::default(),
 			interface_v4: TargetInterfaceV4::All,
 			interface_v6: TargetInterfaceV6::All,
 			max_ignored_packets: 2,
+			worker_threads: 1,
+			exclude_self: false,
+			confirm_after: 1,
+			response_collect_window: Duration::from_secs(2),
+			unicast_responses: false,
+			unicast_resolver: None,
+			raw_mode: false,
+			auto_resolve: false,
+			reconnect: ReconnectPolicy::default(),
 		}
 	}
 
 	/// Sets the service name to discover.
+	///
+	/// If no service name is set, discovery sends PTR queries for the root name and reports every responder that answers.
+	/// Searchlight broadcasters answer these with the service types they advertise, which can be read using [`Responder::service_types`](super::Responder::service_types).
 	pub fn service(mut self, service_name: impl IntoDnsName) -> Result<Self, BadDnsNameError> {
-		self.service_name = Some(service_name.into_fqdn().map_err(|_| BadDnsNameError)?);
+		self.service_name = Some(service_name.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceType))?);
+		self.subtype_of = None;
+		Ok(self)
+	}
+
+	/// Sets a subtype of a service type to discover, such as `_printer` of `_http._tcp.local.`, as described in
+	/// [RFC 6763 §7.1](https://datatracker.ietf.org/doc/html/rfc6763#section-7.1).
+	///
+	/// Discovery queries for the subtype's name (`_printer._sub._http._tcp.local.`), and accepts responses whose answers are named after either the subtype
+	/// or the base service type, as responders differ in how they answer (Searchlight broadcasters answer services that [can be subtyped](crate::broadcast::ServiceBuilder::can_subtype)
+	/// with the base service type). The [`service_name`](super::Responder::service_name) of responders is the subtype's name.
+	///
+	/// This replaces the service name set by [`service`](DiscoveryBuilder::service).
+	pub fn subtype(mut self, service_type: impl IntoDnsName, subtype: &str) -> Result<Self, BadDnsNameError> {
+		let service_type = service_type.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceType))?;
+		self.service_name = Some(
+			DnsName::from_labels([subtype, "_sub"])
+				.and_then(|subtype| subtype.append_domain(&service_type))
+				.map_err(BadDnsNameError::map(NameField::ServiceSubtype))?,
+		);
+		self.subtype_of = Some(service_type);
 		Ok(self)
 	}
 
+	/// Also discovers another service type, passing its events to `handler` instead of the handler that discovery is run with.
+	///
+	/// This lets an application that browses several unrelated service types handle each of them separately, rather than matching on
+	/// the [`service_name`](super::DiscoveryEvent::service_name) of every event. Each discovery packet asks about all of the service types at once,
+	/// and a responder that advertises more than one of them is remembered separately for each. A response that answers several of them is
+	/// reported for the first one it answers, with the service name set by [`service`](DiscoveryBuilder::service) coming first.
+	///
+	/// If no service name is set with [`service`](DiscoveryBuilder::service), only the service types registered here are discovered, and
+	/// responses that don't match any of them are dropped. Registering a handler for a service type again replaces the previous one.
+	///
+	/// Handlers registered here aren't used by [`DiscoveryHandle::wait_for_responder`](super::DiscoveryHandle::wait_for_responder) or
+	/// [`DiscoveryHandle::replay_to`](super::DiscoveryHandle::replay_to), which only know about the responders passed to the main handler.
+	pub fn on_service<F>(mut self, service_type: impl IntoDnsName, handler: F) -> Result<Self, BadDnsNameError>
+	where
+		F: Fn(DiscoveryEvent) + Send + Sync + 'static,
+	{
+		let service_type = service_type.into_fqdn().map_err(BadDnsNameError::map(NameField::ServiceType))?;
+		self.service_handlers.insert(service_type, Arc::new(handler));
+		Ok(self)
+	}
+
+	/// Only discover responders whose TXT records satisfy `predicate`.
+	///
+	/// The predicate is given the key/value pairs of each TXT record in a response, parsed as described in [RFC 6763 §6](https://datatracker.ietf.org/doc/html/rfc6763#section-6)
+	/// (keys are lowercased, and boolean attributes have a value of `None`). Responses without any TXT records are treated as having an empty one.
+	///
+	/// Responses that don't match are dropped before any [`DiscoveryEvent`](super::DiscoveryEvent) is fired.
+	///
+	/// **Default: all responses are accepted**
+	pub fn require_txt<F>(mut self, predicate: F) -> Self
+	where
+		F: Fn(&BTreeMap<Vec<u8>, Option<Vec<u8>>>) -> bool + Send + Sync + 'static,
+	{
+		self.require_txt = Some(TxtFilter::new(predicate));
+		self
+	}
+
+	/// Only discover responders whose responses include at least `min_addresses` distinct IP addresses (A/AAAA records with a non-zero TTL).
+	///
+	/// Some responders send responses without any address records (for example while they are still booting), so they can't be connected to.
+	/// Setting this to 1 keeps them from being reported until they advertise an address. Goodbye packets are always accepted, as they don't need
+	/// any addresses to say that a responder is going away.
+	///
+	/// Responses that don't match are dropped before any [`DiscoveryEvent`](super::DiscoveryEvent) is fired.
+	///
+	/// **Default: 0 (all responses are accepted)**
+	pub fn require_min_addresses(mut self, min_addresses: usize) -> Self {
+		self.min_addresses = min_addresses;
+		self
+	}
+
 	/// How often to send discovery packets.
 	///
 	/// I am not responsible for what happens to you if you set this too low :)
@@ -46,6 +153,52 @@ impl DiscoveryBuilder {
 		self
 	}
 
+	/// Sends a burst of `count` discovery packets, `spacing` apart, when discovery starts, before settling into the regular [`interval`](DiscoveryBuilder::interval).
+	///
+	/// Sending the first few queries close together makes responders appear faster on lossy networks (such as WiFi), where a single query or its responses are easily lost.
+	///
+	/// `count` includes the first discovery packet, which is always sent immediately, so a value of zero is treated as one.
+	/// Each query still waits for the [`response_collect_window`](DiscoveryBuilder::response_collect_window), so the queries will be at least that far apart.
+	///
+	/// **Default: 1 query** (no burst)
+	pub fn startup_queries(mut self, count: u8, spacing: Duration) -> Self {
+		self.startup_queries = (count, spacing);
+		self
+	}
+
+	/// Discovers passively: at most one discovery packet is sent when discovery starts, and after that, discovery only listens for the
+	/// announcements that responders make when they start up, change or shut down.
+	///
+	/// This keeps traffic on the network to a minimum, which suits battery-powered devices. Because responders aren't queried, they are
+	/// lost once the answers in their last response expire (or they announce that they are going away), instead of after ignoring
+	/// [`max_ignored_packets`](DiscoveryBuilder::max_ignored_packets) discovery packets. Expiry is checked every [`interval`](DiscoveryBuilder::interval).
+	///
+	/// If `initial_query` is false, no discovery packets are sent at all, so responders are only found once they announce themselves.
+	/// [`DiscoveryHandle::query_now`](super::DiscoveryHandle::query_now) still sends a discovery packet when called.
+	///
+	/// [`startup_queries`](DiscoveryBuilder::startup_queries) is ignored in passive mode.
+	///
+	/// **Default: discovery packets are sent every [`interval`](DiscoveryBuilder::interval)**
+	pub fn passive(mut self, initial_query: bool) -> Self {
+		self.passive = Some(initial_query);
+		self
+	}
+
+	/// Shuts discovery down by itself once no responder has been found or lost for `timeout`, which is useful for "scan until quiet" interfaces.
+	///
+	/// The timer starts when discovery starts, and restarts on every [`DiscoveryEvent`](super::DiscoveryEvent) that counts as activity.
+	/// If `count_updates` is false, [`ResponseUpdate`](super::DiscoveryEvent::ResponseUpdate)s don't count, as responders that are already known
+	/// keep sending them for as long as they are around.
+	///
+	/// When discovery stops this way, [`DiscoveryHandle::shutdown`](super::DiscoveryHandle::shutdown) returns [`Shutdown::Idle`](super::Shutdown::Idle),
+	/// and [`Discovery::run`] returns `Ok(())`. This has no effect when discovering as part of a [`Node`](crate::node::Node).
+	///
+	/// **Default: discovery runs until it is shut down**
+	pub fn idle_timeout(mut self, timeout: Duration, count_updates: bool) -> Self {
+		self.idle_timeout = Some((timeout, count_updates));
+		self
+	}
+
 	/// The number of discovery packets that a responder must ignore before it is considered to be offline.
 	///
 	/// If set to zero, a responder will never go offline.
@@ -56,11 +209,245 @@ impl DiscoveryBuilder {
 		self
 	}
 
+	/// Whether to ask responders to respond via unicast instead of multicast (QU queries instead of QM queries).
+	///
+	/// This is useful on networks where multicast replies are filtered but unicast works. Responses will be sent directly to
+	/// the discovery socket, which is bound to the mDNS port, so they can be received as normal.
+	///
+	/// **Default: false**
+	pub fn unicast_responses(mut self, unicast_responses: bool) -> Self {
+		self.unicast_responses = unicast_responses;
+		self
+	}
+
+	/// Also sends each discovery query to a unicast DNS server, such as `10.0.0.1:53`, for networks where multicast is blocked
+	/// but the DNS server serves the same DNS-SD records (see [RFC 6763 §11](https://datatracker.ietf.org/doc/html/rfc6763#section-11)).
+	///
+	/// The query is sent from the discovery socket, so the answers are received and processed just like mDNS responses.
+	/// Because a DNS server answers for every instance at once, they are all reported as a single [`Responder`](super::Responder)
+	/// with the address of the DNS server.
+	///
+	/// The [service name](DiscoveryBuilder::service) must be one that the DNS server knows about, which usually means it's in a domain other than `local.`.
+	///
+	/// **Default: None**
+	pub fn unicast_resolver(mut self, resolver: SocketAddr) -> Self {
+		self.unicast_resolver = Some(resolver);
+		self
+	}
+
+	/// Whether to report every matching response as it arrives, as a [`DiscoveryEvent::Raw`](super::DiscoveryEvent::Raw), instead of tracking responders.
+	///
+	/// Repeated responses from the same address aren't coalesced, and responders are never reported as found, updated or lost, so
+	/// [`confirm_after`](DiscoveryBuilder::confirm_after) and [`max_ignored_packets`](DiscoveryBuilder::max_ignored_packets) have no effect.
+	/// This is useful for packet analysis tools, or for measuring how chatty responders are.
+	///
+	/// **Default: false**
+	pub fn raw_mode(mut self, raw_mode: bool) -> Self {
+		self.raw_mode = raw_mode;
+		self
+	}
+
+	/// Whether to automatically resolve service instances that a responder only advertises with a PTR record, like `avahi-browse --resolve`.
+	///
+	/// Most responders include the SRV, TXT and A/AAAA records of an instance alongside its PTR record, but some only send the PTR record,
+	/// so the instance's port and addresses are unknown. With this enabled, such a response is held back while the instance's SRV and TXT
+	/// records are queried for, and the answers are merged into it, so the responder is only reported once it's fully populated.
+	///
+	/// Each instance is only queried for once per discovery packet, however many responses point to it. If an instance can't be resolved,
+	/// the responder is reported with what it did advertise when the next discovery packet is sent.
+	///
+	/// [`require_txt`](DiscoveryBuilder::require_txt) and [`require_min_addresses`](DiscoveryBuilder::require_min_addresses) are checked against
+	/// each packet as it arrives, so responders that only send a PTR record never get resolved if either is set. This has no effect in [raw mode](DiscoveryBuilder::raw_mode).
+	///
+	/// **Default: false**
+	pub fn auto_resolve(mut self, auto_resolve: bool) -> Self {
+		self.auto_resolve = auto_resolve;
+		self
+	}
+
+	/// How long to wait for responses after sending a discovery packet, before responders that didn't respond are considered to have ignored it.
+	///
+	/// Increase this on high-latency or congested networks if responders are being reported as lost when they are in fact still present.
+	///
+	/// **Default: 2 seconds**
+	pub fn response_collect_window(mut self, window: Duration) -> Self {
+		self.response_collect_window = window;
+		self
+	}
+
+	/// The number of responses a responder must send before it is reported via [`DiscoveryEvent::ResponderFound`](super::DiscoveryEvent::ResponderFound).
+	///
+	/// Until then, the responder is tracked but not reported, and if it goes away before it is confirmed, no [`DiscoveryEvent::ResponderLost`](super::DiscoveryEvent::ResponderLost) will be reported either.
+	/// This is useful for debouncing responders on flaky networks that send a single stray response and then disappear.
+	///
+	/// A value of zero is treated as one.
+	///
+	/// **Default: 1** (report immediately)
+	pub fn confirm_after(mut self, responses: u8) -> Self {
+		self.confirm_after = responses;
+		self
+	}
+
+	/// The number of worker threads to use for receiving and parsing packets.
+	///
+	/// If set to more than one, discovery will run on a multi-threaded Tokio runtime with this many worker threads, and as many tasks will receive and
+	/// parse packets on them, then queue the responses for the discovery loop to process. This can help avoid dropped packets on very busy networks.
+	///
+	/// If the discovery loop falls behind, responses are dropped once the queue is full rather than using more and more memory. The number of responses
+	/// dropped this way is available from [`DiscoveryHandle::dropped_responses`](super::DiscoveryHandle::dropped_responses).
+	///
+	/// **Default: 1** (single threaded)
+	pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+		self.worker_threads = worker_threads;
+		self
+	}
+
 	/// If loopback is enabled, any multicast packets that are sent can be received by the same socket and any other local sockets bound to the same port.
 	///
 	/// This is useful for testing, but is probably not very useful in production.
+	///
+	/// This enables loopback for both IPv4 and IPv6; use [`loopback_v4`](Self::loopback_v4) and [`loopback_v6`](Self::loopback_v6) to configure them separately.
 	pub fn loopback(mut self) -> Self {
-		self.loopback = true;
+		self.socket_options.loopback_v4 = true;
+		self.socket_options.loopback_v6 = true;
+		self
+	}
+
+	/// Whether multicast packets sent over IPv4 can be received by the same socket and any other local sockets bound to the same port.
+	///
+	/// See [`loopback`](Self::loopback).
+	///
+	/// **Default: false**
+	pub fn loopback_v4(mut self, loopback: bool) -> Self {
+		self.socket_options.loopback_v4 = loopback;
+		self
+	}
+
+	/// Whether multicast packets sent over IPv6 can be received by the same socket and any other local sockets bound to the same port.
+	///
+	/// See [`loopback`](Self::loopback).
+	///
+	/// **Default: false**
+	pub fn loopback_v6(mut self, loopback: bool) -> Self {
+		self.socket_options.loopback_v6 = loopback;
+		self
+	}
+
+	/// Whether to exclude IPv4 link-local addresses (`169.254.0.0/16`) when using [`TargetInterfaceV4::All`] or [`TargetInterfaceV4::Filter`].
+	///
+	/// Link-local addresses are usually assigned to interfaces that failed to get an address via DHCP, or to virtual adapters,
+	/// so joining multicast on them is often just noise.
+	///
+	/// **Default: false**
+	pub fn exclude_link_local_v4(mut self, exclude: bool) -> Self {
+		self.socket_options.exclude_link_local_v4 = exclude;
+		self
+	}
+
+	/// Whether to fail to [build](DiscoveryBuilder::build) if the mDNS multicast group can't be joined on any network interface when using
+	/// [`TargetInterfaceV4::All`] or [`TargetInterfaceV6::All`] (or a [filter](TargetInterfaceV4::filter)).
+	///
+	/// Normally, if no usable interfaces are found, the socket falls back to letting the OS choose a default interface, which may not
+	/// work at all, so discovery finds nothing (a warning is logged). With this enabled, [`DiscoveryBuilderError::NoInterfaces`] is returned instead.
+	///
+	/// When discovering over both IPv4 and IPv6, it's enough for either of them to have a usable interface.
+	///
+	/// **Default: false**
+	pub fn require_interface(mut self, require: bool) -> Self {
+		self.socket_options.require_interface = require;
+		self
+	}
+
+	/// Whether to also send every packet to the IPv4 limited broadcast address (`255.255.255.255`), as well as the mDNS multicast group.
+	///
+	/// **This is not part of the mDNS standard.** It is a best-effort fallback for networks that drop IPv4 multicast
+	/// (for example because of broken IGMP snooping) but still pass broadcast traffic, and only helps if the other side enables it too.
+	/// Broadcast packets are received on the mDNS port either way, but are only sent over the default IPv4 interface, and are always looped back to the local machine.
+	///
+	/// **Default: false**
+	pub fn also_broadcast(mut self, also_broadcast: bool) -> Self {
+		self.socket_options.also_broadcast = also_broadcast;
+		self
+	}
+
+	/// Whether the IPv6 socket only handles IPv6 traffic (`IPV6_V6ONLY`).
+	///
+	/// Turning this off makes the IPv6 socket dual-stack, so it can also receive IPv4 traffic (with IPv4-mapped addresses such as `::ffff:192.168.1.69`)
+	/// on platforms that support it. Only the IPv6 multicast group is joined either way, so this mostly affects unicast traffic from IPv4 peers.
+	///
+	/// This only applies to [`IpVersion::V6`]. With [`IpVersion::Both`], the IPv4 socket already receives IPv4 traffic on the mDNS port,
+	/// so a dual-stack IPv6 socket would receive it a second time; the IPv6 socket is always IPv6-only in that case.
+	///
+	/// **Default: true**
+	pub fn only_v6(mut self, only_v6: bool) -> Self {
+		self.socket_options.dual_stack_v6 = !only_v6;
+		self
+	}
+
+	/// Sets the low-level options applied to the mDNS sockets, such as the read timeout, receive buffer size and multicast TTL.
+	///
+	/// **Default: [`SocketOptions::default()`]**
+	pub fn socket_options(mut self, options: SocketOptions) -> Self {
+		self.socket_options.socket = options;
+		self
+	}
+
+	/// Sets the TTL (IPv4) or hop limit (IPv6) of outgoing multicast packets.
+	///
+	/// Standard mDNS never leaves the local link, but this can be raised to reach across routed segments on networks with mDNS reflection or multicast routing.
+	///
+	/// This is a shorthand for setting [`SocketOptions::multicast_ttl`].
+	///
+	/// **Default: 1**
+	pub fn multicast_ttl(mut self, ttl: u32) -> Self {
+		self.socket_options.socket.multicast_ttl = ttl;
+		self
+	}
+
+	/// Marks outgoing packets with a DSCP (Differentiated Services Code Point), so that they can be prioritized on networks with QoS policies.
+	///
+	/// This is a shorthand for setting [`SocketOptions::dscp`], which describes the platforms that support it.
+	///
+	/// **Default: the OS default (usually 0, best effort)**
+	///
+	/// [`build`](DiscoveryBuilder::build) returns [`DiscoveryBuilderError::InvalidDscp`] if `dscp` is larger than 63, as DSCPs are 6-bit values.
+	pub fn dscp(mut self, dscp: u8) -> Self {
+		self.socket_options.socket.dscp = Some(dscp);
+		self
+	}
+
+	/// Calls `configure` on each of the discovery sockets before they are bound, to set socket options that Searchlight doesn't set itself,
+	/// such as `SO_BINDTODEVICE`, `SO_PRIORITY` or `SO_SNDBUF`.
+	///
+	/// `configure` is called with the IP version of the socket, after Searchlight has set its own options (so it can override them) and before
+	/// the socket joins the mDNS multicast group. It is called again whenever the socket is [recreated](DiscoveryBuilder::reconnect_policy).
+	/// Returning an error fails setting up the socket at [`SocketSetupStage::SetOption`](crate::errors::SocketSetupStage::SetOption).
+	pub fn configure_socket<F>(mut self, configure: F) -> Self
+	where
+		F: Fn(&socket2::Socket, IpVersion) -> Result<(), std::io::Error> + Send + Sync + 'static,
+	{
+		self.socket_options.configure = Some(ConfigureSocket(Arc::new(configure)));
+		self
+	}
+
+	/// How to recover when receiving on the mDNS socket keeps failing, for example because the network went down.
+	///
+	/// **Default: [`ReconnectPolicy::default()`]**
+	pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+		self.reconnect = policy;
+		self
+	}
+
+	/// Whether to ignore responses that were sent from this machine.
+	///
+	/// This is done by comparing the address of each responder against the addresses of this machine's network interfaces,
+	/// so it will also ignore any other mDNS responders running on this machine, not just Searchlight broadcasters in the same process.
+	///
+	/// This is useful for user interfaces that shouldn't list the local machine, especially when [`loopback`](DiscoveryBuilder::loopback) is enabled.
+	///
+	/// **Default: false**
+	pub fn exclude_self(mut self, exclude_self: bool) -> Self {
+		self.exclude_self = exclude_self;
 		self
 	}
 
@@ -84,33 +471,73 @@ impl DiscoveryBuilder {
 	///
 	/// You must specify whether to discover over IPv4, IPv6, or both.
 	pub fn build(self, ip_version: IpVersion) -> Result<Discovery, DiscoveryBuilderError> {
+		if let Some(dscp) = self.socket_options.socket.dscp.filter(|dscp| *dscp > 63) {
+			return Err(DiscoveryBuilderError::InvalidDscp(dscp));
+		}
+
+		let recipe = MdnsSocketRecipe {
+			options: self.socket_options.clone(),
+			ip_version,
+			interface_v4: self.interface_v4.clone(),
+			interface_v6: self.interface_v6.clone(),
+		};
+
+		Ok(Discovery {
+			socket: recipe
+				.create()
+				.map_err(|err| DiscoveryBuilderError::socket_setup(err, recipe.options.port()))?,
+			recipe,
+			config: self.into_config(),
+		})
+	}
+
+	/// Creates the configuration of the discoverer, which is shared with [`Node`](crate::node::Node).
+	pub(crate) fn into_config(self) -> DiscoveryConfig {
 		let DiscoveryBuilder {
 			service_name,
+			subtype_of,
+			require_txt,
+			min_addresses,
+			service_handlers,
 			interval,
-			loopback,
-			interface_v4,
-			interface_v6,
+			startup_queries,
+			passive,
+			idle_timeout,
 			max_ignored_packets,
+			worker_threads,
+			exclude_self,
+			confirm_after,
+			response_collect_window,
+			unicast_responses,
+			unicast_resolver,
+			raw_mode,
+			auto_resolve,
+			reconnect,
+			..
 		} = self;
 
-		Ok(Discovery {
-			socket: match ip_version {
-				IpVersion::V4 => {
-					MdnsSocket::new_v4(loopback, interface_v4).map_err(|v4| DiscoveryBuilderError::MultiIpIoError(MultiIpIoError::V4(v4)))?
-				}
-
-				IpVersion::V6 => {
-					MdnsSocket::new_v6(loopback, interface_v6).map_err(|v6| DiscoveryBuilderError::MultiIpIoError(MultiIpIoError::V6(v6)))?
-				}
-
-				IpVersion::Both => MdnsSocket::new(loopback, interface_v4, interface_v6)
-					.map_err(|(v4, v6)| DiscoveryBuilderError::MultiIpIoError(MultiIpIoError::Both { v4, v6 }))?,
-			},
-
-			max_ignored_packets,
+		DiscoveryConfig {
 			service_name,
+			subtype_of,
+			require_txt,
+			min_addresses,
+			service_handlers,
 			interval,
-		})
+			startup_queries,
+			passive,
+			idle_timeout,
+			max_ignored_packets,
+			worker_threads,
+			exclude_self,
+			confirm_after,
+			response_collect_window,
+			unicast_responses,
+			unicast_resolver,
+			raw_mode,
+			auto_resolve,
+			reconnect,
+			dropped_responses: Default::default(),
+		}
 	}
 }
 impl Default for DiscoveryBuilder {
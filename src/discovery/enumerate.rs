@@ -0,0 +1,334 @@
+use super::{presence::LocalAddrs, records, Discovery};
+use crate::{
+	errors::MultiIpIoError,
+	logging,
+	transport::{MdnsTransport, MdnsTransportRecv},
+	util::{BROWSE_DOMAINS_QUERIES, SERVICES_META_QUERY},
+};
+use std::{
+	collections::{BTreeMap, BTreeSet, HashSet},
+	net::{IpAddr, SocketAddr},
+	time::Duration,
+};
+use trust_dns_client::{
+	op::{Message as DnsMessage, MessageType as DnsMessageType, Query as DnsQuery},
+	rr::{DNSClass as DnsClass, Name as DnsName, RData, RecordType as DnsRecordType},
+	serialize::binary::{BinDecodable, BinEncodable},
+};
+
+#[derive(Debug, Clone)]
+/// A service instance found by [`Discovery::enumerate_all`].
+pub struct ResolvedService {
+	/// The full name of the service instance, e.g. `My Web Server._http._tcp.local.`
+	pub name: DnsName,
+
+	/// The socket address that the service instance was advertised from.
+	pub addr: SocketAddr,
+
+	/// The target hostname of the service's SRV record, if it was resolved.
+	pub hostname: Option<DnsName>,
+
+	/// The port of the service's SRV record, if it was resolved.
+	pub port: Option<u16>,
+
+	/// The IP addresses of the service's hostname, if they were resolved.
+	pub ip_addresses: BTreeSet<IpAddr>,
+
+	/// The strings of the service's TXT record, if it was resolved.
+	pub txt: Vec<String>,
+}
+
+impl Discovery {
+	/// Enumerates every service advertised on the network, similar to `dns-sd -B` or `avahi-browse -a`.
+	///
+	/// The service types are browsed using the DNS-SD meta-query (`_services._dns-sd._udp.local.`), and then the instances of each service type are resolved.
+	/// Everything that was found before `timeout` elapses is returned, grouped and sorted by service type.
+	///
+	/// This will start a new Tokio runtime on the current thread and block until `timeout` has elapsed.
+	///
+	/// The service name set using [`DiscoveryBuilder::service`](super::DiscoveryBuilder::service) is ignored.
+	pub fn enumerate_all(self, timeout: Duration) -> Result<Vec<(DnsName, Vec<ResolvedService>)>, MultiIpIoError> {
+		Self::runtime(1).block_on(self.impl_enumerate_all(timeout))
+	}
+
+	async fn impl_enumerate_all(self, timeout: Duration) -> Result<Vec<(DnsName, Vec<ResolvedService>)>, MultiIpIoError> {
+		let deadline = tokio::time::Instant::now() + timeout;
+
+		let Discovery { socket, config, .. } = self;
+		let socket = socket.into_async().await?;
+
+		let local_addrs = LocalAddrs::new(config.exclude_self);
+
+		let mut enumeration = Enumeration::new();
+
+		socket
+			.send_multicast(&query_packet(
+				config.unicast_responses,
+				[(enumeration.meta_query.clone(), DnsRecordType::PTR)],
+			)?)
+			.await?;
+
+		let mut recv = socket.recv(vec![0; 4096]);
+		loop {
+			let ((count, addr), _, packet) = match tokio::time::timeout_at(deadline, recv.recv_multicast()).await {
+				Ok(Ok(recv)) => recv,
+				Ok(Err(err)) => {
+					logging::warn!("Failed to receive on mDNS socket: {err}");
+					continue;
+				}
+				Err(_) => break,
+			};
+
+			if count == 0 || local_addrs.contains(&addr.ip()) {
+				continue;
+			}
+
+			let response = match DnsMessage::from_bytes(&packet[..count]) {
+				Ok(response) if response.message_type() == DnsMessageType::Response => response,
+				_ => continue,
+			};
+
+			let queries = enumeration.handle_response(addr, &response);
+			if queries.is_empty() {
+				continue;
+			}
+
+			if let Err(err) = socket.send_multicast(&query_packet(config.unicast_responses, queries)?).await {
+				logging::warn!("Failed to send enumeration query on mDNS socket: {err}");
+			}
+		}
+
+		Ok(enumeration.finish())
+	}
+
+	/// Enumerates the domains that responders on the network recommend browsing for services in, as described in
+	/// [RFC 6763 §11](https://datatracker.ietf.org/doc/html/rfc6763#section-11).
+	///
+	/// The browsing domain (`b._dns-sd._udp.local.`) and default browsing domain (`db._dns-sd._udp.local.`) are queried once, and every distinct domain
+	/// that was found before `timeout` elapses is returned, sorted. Most networks only use `local.`, and many responders don't answer these queries at all.
+	///
+	/// This will start a new Tokio runtime on the current thread and block until `timeout` has elapsed.
+	///
+	/// The service name set using [`DiscoveryBuilder::service`](super::DiscoveryBuilder::service) is ignored.
+	pub fn enumerate_domains(self, timeout: Duration) -> Result<Vec<DnsName>, MultiIpIoError> {
+		Self::runtime(1).block_on(self.impl_enumerate_domains(timeout))
+	}
+
+	async fn impl_enumerate_domains(self, timeout: Duration) -> Result<Vec<DnsName>, MultiIpIoError> {
+		let deadline = tokio::time::Instant::now() + timeout;
+
+		let Discovery { socket, config, .. } = self;
+		let socket = socket.into_async().await?;
+
+		let local_addrs = LocalAddrs::new(config.exclude_self);
+
+		socket
+			.send_multicast(&query_packet(
+				config.unicast_responses,
+				BROWSE_DOMAINS_QUERIES[..2]
+					.iter()
+					.map(|query| (DnsName::from_ascii(query).unwrap(), DnsRecordType::PTR)),
+			)?)
+			.await?;
+
+		let mut domains = BTreeSet::new();
+
+		let mut recv = socket.recv(vec![0; 4096]);
+		loop {
+			let ((count, addr), _, packet) = match tokio::time::timeout_at(deadline, recv.recv_multicast()).await {
+				Ok(Ok(recv)) => recv,
+				Ok(Err(err)) => {
+					logging::warn!("Failed to receive on mDNS socket: {err}");
+					continue;
+				}
+				Err(_) => break,
+			};
+
+			if count == 0 || local_addrs.contains(&addr.ip()) {
+				continue;
+			}
+
+			match DnsMessage::from_bytes(&packet[..count]) {
+				Ok(response) if response.message_type() == DnsMessageType::Response => domains.extend(browse_domains(&response)),
+				_ => continue,
+			}
+		}
+
+		Ok(domains.into_iter().collect())
+	}
+}
+
+/// Returns the domains pointed to by the domain enumeration answers of a response.
+pub(crate) fn browse_domains(response: &DnsMessage) -> impl Iterator<Item = DnsName> + '_ {
+	let queries = BROWSE_DOMAINS_QUERIES.map(|query| DnsName::from_ascii(query).unwrap());
+
+	response.answers().iter().filter_map(move |record| match record.data() {
+		Some(RData::PTR(domain)) if record.ttl() != 0 && queries.contains(record.name()) => Some(domain.clone()),
+		_ => None,
+	})
+}
+
+pub(super) fn query_packet(unicast: bool, queries: impl IntoIterator<Item = (DnsName, DnsRecordType)>) -> Result<Vec<u8>, std::io::Error> {
+	let mut message = DnsMessage::new();
+
+	for (name, record_type) in queries {
+		let mut query = DnsQuery::query(name, record_type);
+		query.set_query_class(DnsClass::IN).set_mdns_unicast_response(unicast);
+		message.add_query(query);
+	}
+
+	message
+		.to_bytes()
+		.map_err(|err| std::io::Error::other(format!("Query failed to serialize: {err}")))
+}
+
+/// Everything learned about the network so far during an enumeration.
+pub(crate) struct Enumeration {
+	meta_query: DnsName,
+
+	/// Service type -> instances
+	service_types: BTreeMap<DnsName, BTreeSet<DnsName>>,
+
+	/// Instance -> the address it was first advertised from
+	instances: BTreeMap<DnsName, SocketAddr>,
+
+	/// Instance -> (SRV target, port)
+	srv: BTreeMap<DnsName, (DnsName, u16)>,
+
+	/// Instance -> TXT strings
+	txt: BTreeMap<DnsName, Vec<String>>,
+
+	/// Hostname -> IP addresses
+	hosts: BTreeMap<DnsName, BTreeSet<IpAddr>>,
+
+	/// Queries that have already been sent, so that they aren't repeated for every response
+	queried: HashSet<(DnsName, DnsRecordType)>,
+}
+impl Enumeration {
+	pub(crate) fn new() -> Self {
+		Self {
+			meta_query: DnsName::from_ascii(SERVICES_META_QUERY).unwrap(),
+			service_types: BTreeMap::new(),
+			instances: BTreeMap::new(),
+			srv: BTreeMap::new(),
+			txt: BTreeMap::new(),
+			hosts: BTreeMap::new(),
+			queried: HashSet::new(),
+		}
+	}
+
+	/// Learns from a response, returning any queries that should be sent to resolve what was newly found.
+	pub(crate) fn handle_response(&mut self, addr: SocketAddr, response: &DnsMessage) -> Vec<(DnsName, DnsRecordType)> {
+		// Flushed address records replace the addresses we already know for that host, rather than being merged with them
+		for (hostname, record_type, _) in records::flushed(response) {
+			if let Some(ip_addresses) = self.hosts.get_mut(hostname) {
+				match record_type {
+					DnsRecordType::A => ip_addresses.retain(IpAddr::is_ipv6),
+					DnsRecordType::AAAA => ip_addresses.retain(IpAddr::is_ipv4),
+					_ => {}
+				}
+			}
+		}
+
+		for record in response.answers().iter().chain(response.additionals()) {
+			match record.data() {
+				Some(RData::PTR(ptr)) if *record.name() == self.meta_query => {
+					self.service_types.entry(ptr.clone()).or_default();
+				}
+
+				Some(RData::PTR(ptr)) => {
+					if let Some(instances) = self.service_types.get_mut(record.name()) {
+						instances.insert(ptr.clone());
+						self.instances.entry(ptr.clone()).or_insert(addr);
+					}
+				}
+
+				Some(RData::SRV(srv)) => {
+					self.srv.insert(record.name().clone(), (srv.target().clone(), srv.port()));
+				}
+
+				Some(RData::TXT(txt)) => {
+					self.txt.insert(
+						record.name().clone(),
+						txt.txt_data().iter().map(|data| String::from_utf8_lossy(data).into_owned()).collect(),
+					);
+				}
+
+				Some(RData::A(ip)) => self.host_address(record.name(), IpAddr::V4(*ip), record.ttl()),
+
+				Some(RData::AAAA(ip)) => self.host_address(record.name(), IpAddr::V6(*ip), record.ttl()),
+
+				_ => {}
+			}
+		}
+
+		let mut queries = Vec::new();
+
+		for service_type in self.service_types.keys() {
+			queries.push((service_type.clone(), DnsRecordType::PTR));
+		}
+
+		for instance in self.instances.keys() {
+			if !self.srv.contains_key(instance) {
+				queries.push((instance.clone(), DnsRecordType::SRV));
+			}
+			if !self.txt.contains_key(instance) {
+				queries.push((instance.clone(), DnsRecordType::TXT));
+			}
+		}
+
+		for (hostname, _) in self.srv.values() {
+			if !self.hosts.contains_key(hostname) {
+				queries.push((hostname.clone(), DnsRecordType::A));
+				queries.push((hostname.clone(), DnsRecordType::AAAA));
+			}
+		}
+
+		queries.retain(|query| self.queried.insert(query.clone()));
+		queries
+	}
+
+	fn host_address(&mut self, hostname: &DnsName, ip: IpAddr, ttl: u32) {
+		let ip_addresses = self.hosts.entry(hostname.clone()).or_default();
+		if ttl == 0 {
+			// Goodbye
+			ip_addresses.remove(&ip);
+		} else {
+			ip_addresses.insert(ip);
+		}
+	}
+
+	pub(crate) fn finish(self) -> Vec<(DnsName, Vec<ResolvedService>)> {
+		let Enumeration {
+			service_types,
+			instances,
+			mut srv,
+			mut txt,
+			hosts,
+			..
+		} = self;
+
+		service_types
+			.into_iter()
+			.map(|(service_type, names)| {
+				let services = names
+					.into_iter()
+					.filter_map(|name| {
+						let addr = *instances.get(&name)?;
+						let (hostname, port) = srv.remove(&name).unzip();
+						Some(ResolvedService {
+							ip_addresses: hostname.as_ref().and_then(|hostname| hosts.get(hostname)).cloned().unwrap_or_default(),
+							txt: txt.remove(&name).unwrap_or_default(),
+							hostname,
+							port,
+							addr,
+							name,
+						})
+					})
+					.collect();
+
+				(service_type, services)
+			})
+			.collect()
+	}
+}
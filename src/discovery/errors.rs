@@ -1,9 +1,61 @@
-use crate::errors::MultiIpIoError;
+use crate::errors::{MultiIpIoError, SocketSetupError, SocketSetupStage};
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 /// An error occurred while building a [`Discovery`](super::Discovery)
+///
+/// More kinds of errors may be added in the future, so matches on this should have a catch-all arm.
 pub enum DiscoveryBuilderError {
 	#[error("{0}")]
 	/// An I/O error occurred (on potentially both IPv4 and IPv6 sockets)
 	MultiIpIoError(MultiIpIoError),
+
+	#[error("Port {port} is already in use by another process that doesn't allow it to be shared, such as mDNSResponder or avahi-daemon: {error}")]
+	/// The mDNS port is already bound by another socket (probably another mDNS responder, such as mDNSResponder or avahi-daemon) that doesn't allow it to be shared.
+	///
+	/// Consider stopping the other mDNS responder, or configuring it to allow the port to be shared.
+	PortInUse {
+		/// The port that couldn't be bound
+		port: u16,
+
+		/// The underlying error
+		error: MultiIpIoError,
+	},
+
+	#[error("{}", SocketSetupError::describe(.v4.as_ref(), .v6.as_ref()))]
+	/// An mDNS socket couldn't be set up; [`SocketSetupError::stage`] says which step failed.
+	///
+	/// At least one of `v4` and `v6` is set, depending on which sockets were being set up and failed.
+	SocketSetup {
+		/// Why the IPv4 socket couldn't be set up, if it failed
+		v4: Option<SocketSetupError>,
+
+		/// Why the IPv6 socket couldn't be set up, if it failed
+		v6: Option<SocketSetupError>,
+	},
+
+	#[error("No usable network interfaces were found: {0}")]
+	/// [`require_interface`](super::DiscoveryBuilder::require_interface) is enabled, but the mDNS multicast group couldn't be joined on any network interface.
+	///
+	/// This usually means there's no network connection.
+	NoInterfaces(MultiIpIoError),
+
+	#[error("The DSCP ({0}) must be 63 or less")]
+	/// The [DSCP](super::DiscoveryBuilder::dscp) is larger than 63, as DSCPs are 6-bit values
+	InvalidDscp(u8),
+}
+impl DiscoveryBuilderError {
+	/// Classifies an error that occurred while setting up sockets bound to `port`.
+	pub(crate) fn socket_setup(err: MultiIpIoError, port: u16) -> Self {
+		if err.is_addr_in_use() {
+			Self::PortInUse { port, error: err }
+		} else if err.socket_setup_errors().any(|err| err.stage == SocketSetupStage::RequireInterface) {
+			Self::NoInterfaces(err)
+		} else {
+			match err.into_socket_setup_errors() {
+				Ok((v4, v6)) => Self::SocketSetup { v4, v6 },
+				Err(err) => Self::MultiIpIoError(err),
+			}
+		}
+	}
 }
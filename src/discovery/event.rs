@@ -1,10 +1,56 @@
-use super::presence::Responder;
+use super::{presence::Responder, RecordChanges};
 use std::sync::Arc;
+use trust_dns_client::rr::Name as DnsName;
 
 pub type EventHandler = Arc<dyn Fn(DiscoveryEvent) + Send + Sync + 'static>;
 
+#[derive(Clone, Default)]
+/// Event handlers for specific service types, registered using [`DiscoveryBuilder::on_service`](super::DiscoveryBuilder::on_service).
+pub(crate) struct ServiceHandlers(Vec<(DnsName, EventHandler)>);
+impl ServiceHandlers {
+	/// Registers the handler for a service type, replacing any handler that was already registered for it.
+	pub(super) fn insert(&mut self, service_type: DnsName, handler: EventHandler) {
+		match self.0.iter_mut().find(|(registered, _)| *registered == service_type) {
+			Some((_, registered)) => *registered = handler,
+			None => self.0.push((service_type, handler)),
+		}
+	}
+
+	/// The service types that have a handler of their own.
+	pub(super) fn service_types(&self) -> Vec<DnsName> {
+		self.0.iter().map(|(service_type, _)| service_type.clone()).collect()
+	}
+
+	/// Wraps `handler` so that events about a service type with a handler of its own are passed to that handler instead.
+	pub(super) fn route(&self, handler: EventHandler) -> EventHandler {
+		if self.0.is_empty() {
+			return handler;
+		}
+
+		let handlers = self.0.clone();
+		Arc::new(move |event: DiscoveryEvent| {
+			let service_handler = event
+				.service_name()
+				.and_then(|service_name| handlers.iter().find(|(service_type, _)| service_type == service_name));
+
+			match service_handler {
+				Some((_, service_handler)) => service_handler(event),
+				None => handler(event),
+			}
+		})
+	}
+}
+impl std::fmt::Debug for ServiceHandlers {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_list().entries(self.0.iter().map(|(service_type, _)| service_type)).finish()
+	}
+}
+
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 /// An event that can occur during discovery.
+///
+/// More kinds of events may be added in the future, so matches on this should have a catch-all arm.
 pub enum DiscoveryEvent {
 	/// A new responder was found.
 	ResponderFound(Arc<Responder>),
@@ -17,6 +63,9 @@ pub enum DiscoveryEvent {
 	/// A responder was updated.
 	///
 	/// This will occur even if the data in the DNS response is the same, it's up to you to detect whether the data has changed in the context of your application.
+	///
+	/// [`Responder::response_changed`] can be used to cheaply check whether the new response advertises anything different.
+	/// [`DiscoveryEvent::record_changes`] can be used to find out which records were added or removed, respecting the cache-flush bit.
 	ResponseUpdate {
 		/// The previous state of the responder.
 		old: Arc<Responder>,
@@ -24,4 +73,41 @@ pub enum DiscoveryEvent {
 		/// The new state of the responder.
 		new: Arc<Responder>,
 	},
+
+	/// A response was received while in [raw mode](super::DiscoveryBuilder::raw_mode).
+	///
+	/// Every matching response is reported this way, including repeats from the same address, and nothing is remembered between them,
+	/// so the responder's [`seen_count`](Responder::seen_count) is always 1.
+	Raw(Arc<Responder>),
+}
+impl DiscoveryEvent {
+	#[inline]
+	/// The responder this event is about.
+	///
+	/// For [`ResponseUpdate`](DiscoveryEvent::ResponseUpdate), this is the new state of the responder.
+	pub fn responder(&self) -> &Arc<Responder> {
+		match self {
+			Self::ResponderFound(responder) | Self::ResponderLost(responder) | Self::ResponseUpdate { new: responder, .. } | Self::Raw(responder) => {
+				responder
+			}
+		}
+	}
+
+	#[inline]
+	/// The service name that the responder matched.
+	///
+	/// This is `None` if discovery isn't looking for a specific service.
+	pub fn service_name(&self) -> Option<&DnsName> {
+		self.responder().service_name.as_ref()
+	}
+
+	/// For [`ResponseUpdate`](DiscoveryEvent::ResponseUpdate), returns the records that were added and removed by the new response.
+	///
+	/// Returns `None` for any other event.
+	pub fn record_changes(&self) -> Option<RecordChanges> {
+		match self {
+			Self::ResponseUpdate { old, new } => Some(RecordChanges::between(&old.last_response, &new.last_response)),
+			_ => None,
+		}
+	}
 }
@@ -1,32 +1,139 @@
-use crate::errors::{MultiIpIoError, ShutdownError};
+use super::{DiscoveryEvent, Responder};
+use crate::{
+	background::Background,
+	errors::{MultiIpIoError, ShutdownError},
+	net::{Stats, TrafficCounters},
+};
+use std::{
+	collections::BTreeMap,
+	net::SocketAddr,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Condvar, Mutex,
+	},
+	time::Duration,
+};
+use trust_dns_client::rr::Name as DnsName;
+
+#[derive(Debug)]
+/// Why a background discovery instance stopped running.
+pub enum Shutdown {
+	/// Discovery was shut down using [`DiscoveryHandle::shutdown`].
+	Requested,
+
+	/// Discovery stopped by itself after the duration passed to [`Discovery::run_in_background_for`](super::Discovery::run_in_background_for) elapsed.
+	Elapsed,
+
+	/// Discovery stopped by itself because nothing happened for the [idle timeout](super::DiscoveryBuilder::idle_timeout).
+	Idle,
+
+	/// Discovery stopped because of a fatal error.
+	Fatal(MultiIpIoError),
+}
+
+#[derive(Default)]
+/// The first responder found that hasn't been returned by [`DiscoveryHandle::wait_for_responder`] yet.
+pub(super) struct FoundResponder {
+	responder: Mutex<Option<Arc<Responder>>>,
+	found: Condvar,
+}
+impl FoundResponder {
+	pub(super) fn found(&self, responder: &Arc<Responder>) {
+		let mut slot = self.responder.lock().unwrap();
+		if slot.is_none() {
+			*slot = Some(responder.clone());
+			self.found.notify_all();
+		}
+	}
+
+	fn wait(&self, timeout: Duration) -> Option<Arc<Responder>> {
+		let slot = self.responder.lock().unwrap();
+		let (mut slot, _) = self.found.wait_timeout_while(slot, timeout, |slot| slot.is_none()).unwrap();
+		slot.take()
+	}
+}
+
+#[derive(Default)]
+/// The responders that are currently known, for [`DiscoveryHandle::replay_to`].
+///
+/// Like the discovery loop, responders are told apart by their service name as well as their address, as one device can respond for several service types.
+pub(crate) struct KnownResponders(Mutex<BTreeMap<(SocketAddr, Option<DnsName>), Arc<Responder>>>);
+impl KnownResponders {
+	pub(crate) fn track(&self, event: &DiscoveryEvent) {
+		match event {
+			DiscoveryEvent::ResponderFound(responder) | DiscoveryEvent::ResponseUpdate { new: responder, .. } => {
+				self.0.lock().unwrap().insert(Self::key(responder), responder.clone());
+			}
+
+			DiscoveryEvent::ResponderLost(responder) => {
+				self.0.lock().unwrap().remove(&Self::key(responder));
+			}
+
+			// Nothing is remembered in raw mode
+			DiscoveryEvent::Raw(_) => {}
+		}
+	}
+
+	#[inline]
+	fn key(responder: &Responder) -> (SocketAddr, Option<DnsName>) {
+		(responder.addr, responder.service_name.clone())
+	}
+
+	pub(crate) fn snapshot(&self) -> Vec<Arc<Responder>> {
+		self.0.lock().unwrap().values().cloned().collect()
+	}
+}
+
+#[derive(Default)]
+/// The receiving ends of the channels that a [`DiscoveryHandle`] (or a node's handle) uses to control a running discovery loop.
+///
+/// Each channel is optional, as discovery that isn't running in the background can't be controlled.
+pub(crate) struct LoopControl {
+	pub(crate) interval_rx: Option<tokio::sync::watch::Receiver<Duration>>,
+	pub(crate) query_rx: Option<tokio::sync::watch::Receiver<()>>,
+	pub(crate) forget_rx: Option<tokio::sync::mpsc::UnboundedReceiver<SocketAddr>>,
+}
 
 pub(super) struct DiscoveryHandleInner {
-	pub(super) thread: std::thread::JoinHandle<Result<(), MultiIpIoError>>,
+	pub(super) background: Background<Shutdown>,
 	pub(super) shutdown_tx: tokio::sync::oneshot::Sender<()>,
+	pub(super) interval_tx: tokio::sync::watch::Sender<Duration>,
+	pub(super) query_tx: tokio::sync::watch::Sender<()>,
+	pub(super) forget_tx: tokio::sync::mpsc::UnboundedSender<SocketAddr>,
+	pub(super) found: Arc<FoundResponder>,
+	pub(super) known: Arc<KnownResponders>,
+	pub(super) counters: Arc<TrafficCounters>,
+	pub(super) dropped_responses: Arc<AtomicU64>,
 }
 
 pub(super) struct DiscoveryHandleDrop(pub(super) Option<DiscoveryHandleInner>);
 impl DiscoveryHandleDrop {
-	fn shutdown(&mut self) -> Result<(), ShutdownError> {
-		let DiscoveryHandleInner { thread, shutdown_tx } = match self.0.take() {
+	fn shutdown(&mut self) -> Result<Shutdown, ShutdownError> {
+		let DiscoveryHandleInner { background, shutdown_tx, .. } = match self.0.take() {
 			Some(inner) => inner,
-			None => return Ok(()),
+			None => return Ok(Shutdown::Requested),
 		};
 
-		if !thread.is_finished() {
+		if !background.is_finished() {
 			shutdown_tx.send(()).ok();
 		}
 
-		match thread.join() {
-			Ok(Ok(_)) => Ok(()),
-			Ok(Err(err)) => Err(ShutdownError::MultiIpIoError(err)),
-			Err(err) => Err(ShutdownError::ThreadJoinError(err)),
-		}
+		background.join().map_err(ShutdownError::ThreadJoinError)
 	}
 }
 impl Drop for DiscoveryHandleDrop {
 	fn drop(&mut self) {
-		self.shutdown().ok();
+		match self.0.take() {
+			// We might be dropped on the runtime that discovery is running on, so waiting for it to stop could deadlock
+			Some(inner) if inner.background.is_on_runtime() => {
+				inner.shutdown_tx.send(()).ok();
+			}
+
+			inner => {
+				self.0 = inner;
+				self.shutdown().ok();
+			}
+		}
 	}
 }
 
@@ -36,12 +143,116 @@ impl Drop for DiscoveryHandleDrop {
 #[must_use = "The discovery instance will shut down if the handle is dropped; store the handle somewhere or use `std::mem::forget` to keep it running"]
 pub struct DiscoveryHandle(pub(super) DiscoveryHandleDrop);
 impl DiscoveryHandle {
+	#[inline(always)]
+	fn inner(&self) -> Option<&DiscoveryHandleInner> {
+		self.0 .0.as_ref()
+	}
+
+	/// Changes how often discovery packets are sent.
+	///
+	/// The next discovery packet will be sent after the new interval has elapsed, and then at the new interval thereafter.
+	/// Responders that have already been discovered are not forgotten.
+	///
+	/// # Panics
+	///
+	/// Panics if `interval` is zero.
+	pub fn set_interval(&self, interval: Duration) {
+		assert!(!interval.is_zero(), "Discovery interval must be non-zero");
+
+		if let Some(inner) = self.inner() {
+			inner.interval_tx.send_replace(interval);
+		}
+	}
+
+	/// Sends a discovery packet right away, instead of waiting for the next interval, and restarts the interval from then.
+	///
+	/// This is useful for "pull to refresh" in user interfaces. Calls made in quick succession are coalesced, and at most one discovery packet per second is sent because of them.
+	pub fn query_now(&self) {
+		if let Some(inner) = self.inner() {
+			inner.query_tx.send_replace(());
+		}
+	}
+
+	/// Forgets the responder at `addr` right away, reporting it with a [`DiscoveryEvent::ResponderLost`] if it had been found.
+	///
+	/// This is for when the application knows that a device is gone before discovery does, such as when the user removed it or a connection to it dropped,
+	/// as discovery otherwise only loses a responder once it has ignored enough discovery packets (or its records have expired, in passive mode).
+	///
+	/// The responder is forgotten asynchronously, and nothing happens if it isn't known. If it is still there, it is found again the next time it responds.
+	pub fn forget(&self, addr: SocketAddr) {
+		if let Some(inner) = self.inner() {
+			inner.forget_tx.send(addr).ok();
+		}
+	}
+
+	/// Returns the traffic statistics of discovery, such as how many packets were sent and received on each network interface.
+	pub fn stats(&self) -> Stats {
+		self.inner().map(|inner| inner.counters.stats()).unwrap_or_default()
+	}
+
+	/// Returns how many responses have been dropped because discovery couldn't keep up with the [worker threads](super::DiscoveryBuilder::worker_threads).
+	pub fn dropped_responses(&self) -> u64 {
+		self.inner().map(|inner| inner.dropped_responses.load(Ordering::Relaxed)).unwrap_or(0)
+	}
+
+	/// Blocks until a responder is found, returning it, or returns `None` if none is found within `timeout`.
+	///
+	/// This returns the first responder reported via [`DiscoveryEvent::ResponderFound`](super::DiscoveryEvent::ResponderFound) since discovery
+	/// started or since the last call that returned one, so a responder that was found before calling this isn't missed.
+	/// Other responders found in the meantime are only reported to the event handler.
+	///
+	/// This is useful for simple "find one and connect" programs; the event handler is still called as normal.
+	pub fn wait_for_responder(&self, timeout: Duration) -> Option<Arc<Responder>> {
+		self.inner()?.found.wait(timeout)
+	}
+
+	/// Calls `handler` with a [`DiscoveryEvent::ResponderFound`] for every responder that is currently known,
+	/// in order of address and then service name.
+	///
+	/// This lets a component that is attached after discovery has started catch up with the responders that were already found, without
+	/// waiting for them to be found again (which they won't be, as each responder is only reported as found once). Responders that were
+	/// lost aren't replayed.
+	///
+	/// `handler` is called on the current thread, and only for the responders known at the time of the call. Nothing is replayed in
+	/// [raw mode](super::DiscoveryBuilder::raw_mode), as responders aren't remembered.
+	pub fn replay_to<F>(&self, mut handler: F)
+	where
+		F: FnMut(DiscoveryEvent),
+	{
+		if let Some(inner) = self.inner() {
+			// The lock isn't held while calling the handler, so it can use the handle
+			for responder in inner.known.snapshot() {
+				handler(DiscoveryEvent::ResponderFound(responder));
+			}
+		}
+	}
+
 	/// Shuts down the discovery instance if it is still running.
 	///
-	/// This function will block until the discovery instance has shut down, and will return an error if the shutdown failed, or the discovery instance encountered a fatal error during its lifetime.
-	pub fn shutdown(mut self) -> Result<(), ShutdownError> {
+	/// This function will block until the discovery instance has shut down, and will return why it stopped running, or an error if the shutdown failed.
+	///
+	/// If the discovery instance encountered a fatal error before it was asked to shut down, [`Shutdown::Fatal`] is returned.
+	///
+	/// If discovery is running on a runtime of your own (see [`Discovery::run_on`](super::Discovery::run_on)), don't call this from a task on a
+	/// current-thread runtime, as it would block the runtime from shutting discovery down. Drop the handle instead, which asks discovery to
+	/// shut down without waiting for it.
+	pub fn shutdown(mut self) -> Result<Shutdown, ShutdownError> {
 		let res = self.0.shutdown();
 		std::mem::forget(self.0);
 		res
 	}
+
+	/// Shuts down the discovery instance if it is still running, without blocking the async runtime that this is awaited on.
+	///
+	/// This is the same as [`shutdown`](Self::shutdown), but waits for discovery to stop on one of the runtime's blocking threads,
+	/// so it can be used in the shutdown sequence of an async application, including on a current-thread runtime.
+	///
+	/// # Panics
+	///
+	/// Panics if not called from within a Tokio runtime.
+	pub async fn shutdown_async(self) -> Result<Shutdown, ShutdownError> {
+		tokio::task::spawn_blocking(move || self.shutdown())
+			.await
+			.unwrap_or_else(|err| Err(ShutdownError::ThreadJoinError(Box::new(err))))
+	}
 }
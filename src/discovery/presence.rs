@@ -1,24 +1,199 @@
-use super::{event::EventHandler, DiscoveryEvent};
-use std::{borrow::Borrow, cell::Cell, collections::HashSet, hash::Hash, net::SocketAddr, ops::Deref, sync::Arc, time::Instant};
-use trust_dns_client::op::DnsResponse;
+use super::{event::EventHandler, records::same_records, txt::txt_records, DiscoveryEvent};
+use crate::{
+	logging,
+	net::{normalize_scope_id, IpVersion},
+	util::SERVICES_META_QUERY,
+};
+use std::{
+	cell::Cell,
+	collections::{BTreeMap, HashMap, HashSet},
+	net::{IpAddr, SocketAddr, SocketAddrV6},
+	ops::Deref,
+	sync::Arc,
+	time::{Duration, Instant, SystemTime},
+};
+use trust_dns_client::{
+	op::DnsResponse,
+	rr::{Name as DnsName, RData},
+};
 
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 /// A responder is a device that responds to our queries.
+///
+/// More fields may be added in the future, so this can't be constructed or exhaustively destructured outside of this crate.
 pub struct Responder {
 	/// The socket address they responded from.
+	///
+	/// On Linux, Android, macOS and iOS, link-local IPv6 addresses have their scope ID set to the interface the response was received on, so they can be connected to directly.
 	pub addr: SocketAddr,
 
 	/// The last response we received from them, as a raw DNS message.
 	pub last_response: DnsResponse,
 
+	/// The last response we received from them, exactly as it was received on the wire.
+	///
+	/// Re-encoding [`last_response`](Responder::last_response) doesn't necessarily give back the same bytes (for example, name compression may differ),
+	/// so this is useful for forwarding the response elsewhere or parsing it with a different DNS library.
+	pub raw_packet: Vec<u8>,
+
+	/// The service name that their last response matched.
+	///
+	/// This is `None` if discovery isn't looking for a specific service.
+	pub service_name: Option<DnsName>,
+
 	/// The last time we received a response from them.
 	pub last_responded: Instant,
+
+	/// The last time we received a response from them, as wall-clock time.
+	///
+	/// This is captured at the same time as [`last_responded`](Responder::last_responded), and is useful for displaying or serializing the time.
+	pub last_responded_at: SystemTime,
+
+	/// How many matching responses we've received from them, including this one.
+	///
+	/// This carries over across [`ResponseUpdate`](super::DiscoveryEvent::ResponseUpdate)s, and starts over from 1 if the responder is lost and found again,
+	/// so it gives an idea of how stable the responder's presence is.
+	pub seen_count: u64,
+}
+impl Responder {
+	#[inline]
+	/// How long ago we last received a response from them.
+	pub fn last_seen_elapsed(&self) -> Duration {
+		self.last_responded.elapsed()
+	}
+
+	#[inline]
+	/// The IP version that the last response was received over, which is either [`IpVersion::V4`] or [`IpVersion::V6`].
+	///
+	/// When discovering over [`IpVersion::Both`], a device that answers over both IPv4 and IPv6 is reported as two responders,
+	/// so this can be used to merge them or prefer one IP version. This is always the IP version of the socket that received the response,
+	/// so IPv4 traffic received by a [dual-stack](super::DiscoveryBuilder::only_v6) IPv6 socket (with an IPv4-mapped address) counts as IPv6.
+	pub fn ip_version(&self) -> IpVersion {
+		match self.addr {
+			SocketAddr::V4(_) => IpVersion::V4,
+			SocketAddr::V6(_) => IpVersion::V6,
+		}
+	}
+
+	/// Returns `true` if `other`'s last response advertises different addresses, ports, TXT records or service instances than this one's.
+	///
+	/// The A, AAAA, SRV, TXT and PTR records of both responses are compared, ignoring their order and TTLs, so this can be used to
+	/// cheaply decide whether a [`ResponseUpdate`](super::DiscoveryEvent::ResponseUpdate) is meaningful.
+	///
+	/// Use [`DiscoveryEvent::record_changes`](super::DiscoveryEvent::record_changes) to find out exactly which records changed.
+	pub fn response_changed(&self, other: &Responder) -> bool {
+		!same_records(&self.last_response, &other.last_response)
+	}
+
+	/// Returns the key/value pairs of the first TXT record in the last response.
+	///
+	/// The record is parsed as described in [RFC 6763 §6](https://datatracker.ietf.org/doc/html/rfc6763#section-6): keys are lowercased,
+	/// boolean attributes (keys without an `=`) have a value of `None`, and only the first occurrence of a key is used.
+	///
+	/// Returns an empty map if the last response didn't contain a TXT record.
+	pub fn txt(&self) -> BTreeMap<Vec<u8>, Option<Vec<u8>>> {
+		txt_records(&self.last_response).next().unwrap_or_default()
+	}
+
+	/// Returns the service types that the responder advertises, from the DNS-SD service type enumeration records (`_services._dns-sd._udp.local.` PTR records) in the last response.
+	///
+	/// Searchlight broadcasters answer with these records when discovery isn't looking for a specific service (see [`DiscoveryBuilder::service`](super::DiscoveryBuilder::service)),
+	/// so this is how you find out which service types are on the network.
+	///
+	/// Returns an empty `Vec` if the last response didn't contain any such records.
+	pub fn service_types(&self) -> Vec<DnsName> {
+		let meta_query = DnsName::from_ascii(SERVICES_META_QUERY).unwrap();
+
+		let mut service_types = Vec::new();
+		for record in self.last_response.answers().iter().filter(|record| *record.name() == meta_query) {
+			if let Some(RData::PTR(service_type)) = record.data() {
+				if !service_types.contains(service_type) {
+					service_types.push(service_type.clone());
+				}
+			}
+		}
+		service_types
+	}
+
+	/// The shortest TTL of the records in the last response, which is how long all of them could be cached for when it was received.
+	///
+	/// Returns `None` if the last response didn't contain any records. A goodbye packet (whose records have a TTL of zero) gives `Some(Duration::ZERO)`.
+	pub fn min_ttl(&self) -> Option<Duration> {
+		let records = self.last_response.answers().iter().chain(self.last_response.additionals());
+		records.map(|record| Duration::from_secs(u64::from(record.ttl()))).min()
+	}
+
+	/// How much of the [`min_ttl`](Responder::min_ttl) is left, counting from when the last response was received.
+	///
+	/// This is useful for showing when the responder's records expire, or for refreshing them before they do. It is zero once the TTL has run out.
+	///
+	/// Returns `None` if the last response didn't contain any records.
+	pub fn remaining_ttl(&self) -> Option<Duration> {
+		Some(self.min_ttl()?.saturating_sub(self.last_responded.elapsed()))
+	}
+
+	/// How long the answers in the last response can be cached for, which is the shortest of their TTLs.
+	fn answers_ttl(&self) -> Duration {
+		let ttl = self.last_response.answers().iter().map(|record| record.ttl()).min().unwrap_or(0);
+		Duration::from_secs(u64::from(ttl))
+	}
+
+	/// Returns the socket addresses that the responder's service(s) can be connected to.
+	///
+	/// The port of each SRV record in the last response is combined with each A and AAAA record of its target hostname.
+	/// If the response didn't include any addresses for the hostname, the address that the response was sent from is used instead.
+	///
+	/// Link-local IPv6 addresses are given the scope ID of the interface the response was received on (where known), so they can be connected to directly.
+	///
+	/// Returns an empty `Vec` if the last response didn't contain any SRV records.
+	pub fn endpoints(&self) -> Vec<SocketAddr> {
+		let records = || self.last_response.answers().iter().chain(self.last_response.additionals());
+
+		let scope_id = match self.addr {
+			SocketAddr::V6(addr) => addr.scope_id(),
+			SocketAddr::V4(_) => 0,
+		};
+
+		let mut endpoints = Vec::new();
+		for srv in records().filter_map(|record| match record.data() {
+			Some(RData::SRV(srv)) => Some(srv),
+			_ => None,
+		}) {
+			let mut ips = records()
+				.filter(|record| record.name() == srv.target() && record.ttl() != 0)
+				.filter_map(|record| match record.data() {
+					Some(RData::A(ip)) => Some(IpAddr::V4(*ip)),
+					Some(RData::AAAA(ip)) => Some(IpAddr::V6(*ip)),
+					_ => None,
+				})
+				.collect::<Vec<_>>();
+
+			if ips.is_empty() {
+				ips.push(self.addr.ip());
+			}
+
+			for ip in ips {
+				let endpoint = match ip {
+					IpAddr::V4(_) => SocketAddr::new(ip, srv.port()),
+					IpAddr::V6(ip) => SocketAddr::V6(normalize_scope_id(SocketAddrV6::new(ip, srv.port(), 0, 0), scope_id)),
+				};
+				if !endpoints.contains(&endpoint) {
+					endpoints.push(endpoint);
+				}
+			}
+		}
+		endpoints
+	}
 }
 
 #[derive(Clone)]
 pub(super) struct ResponderMemoryEntry {
 	pub(super) inner: Arc<Responder>,
 	pub(super) ignored_packets: Cell<u8>,
+
+	/// When the answers in the responder's last response expire, which is when it is lost in passive mode.
+	pub(super) expires: tokio::time::Instant,
 }
 impl Deref for ResponderMemoryEntry {
 	type Target = Responder;
@@ -28,51 +203,146 @@ impl Deref for ResponderMemoryEntry {
 		&self.inner
 	}
 }
-impl Borrow<SocketAddr> for ResponderMemoryEntry {
-	fn borrow(&self) -> &SocketAddr {
-		&self.addr
-	}
-}
-impl Hash for ResponderMemoryEntry {
-	fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-		self.addr.hash(state);
-	}
-}
-impl PartialEq for ResponderMemoryEntry {
-	fn eq(&self, other: &Self) -> bool {
-		self.addr == other.addr
-	}
+
+/// The outcome of remembering a response.
+pub(super) enum Remembered {
+	/// The responder hasn't responded enough times to be confirmed yet.
+	Unconfirmed,
+
+	/// The responder has just been confirmed.
+	Found(Arc<Responder>),
+
+	/// The responder was already confirmed.
+	Updated { old: Arc<Responder>, new: Arc<Responder> },
 }
-impl Eq for ResponderMemoryEntry {}
 
-#[derive(Default)]
-pub(super) struct ResponderMemory(HashSet<ResponderMemoryEntry>);
+pub(crate) struct ResponderMemory {
+	/// Responders by the address they responded from and the service name their responses matched,
+	/// as a responder that advertises several [service types](super::DiscoveryBuilder::on_service) is a separate responder for each.
+	responders: HashMap<(SocketAddr, Option<DnsName>), ResponderMemoryEntry>,
+	confirm_after: u8,
+}
 impl ResponderMemory {
-	#[inline(always)]
-	pub(super) fn get(&self, addr: &SocketAddr) -> Option<&ResponderMemoryEntry> {
-		self.0.get(addr)
+	pub(super) fn new(confirm_after: u8) -> Self {
+		Self {
+			responders: HashMap::new(),
+			confirm_after: confirm_after.max(1),
+		}
 	}
 
-	#[inline(always)]
-	pub(super) fn replace(&mut self, entry: Arc<Responder>) {
-		self.0.replace(ResponderMemoryEntry {
-			inner: entry,
-			ignored_packets: Cell::new(0),
-		});
+	/// Remembers a response, counting it towards the responder's [`seen_count`](Responder::seen_count).
+	pub(super) fn replace(&mut self, mut responder: Responder) -> Remembered {
+		let key = (responder.addr, responder.service_name.clone());
+		let old = self.responders.get(&key).map(|old| old.inner.clone());
+
+		responder.seen_count = old.as_ref().map(|old| old.seen_count.saturating_add(1)).unwrap_or(1);
+
+		let new = Arc::new(responder);
+		self.responders.insert(
+			key,
+			ResponderMemoryEntry {
+				expires: tokio::time::Instant::now() + new.answers_ttl(),
+				inner: new.clone(),
+				ignored_packets: Cell::new(0),
+			},
+		);
+
+		let confirm_after = u64::from(self.confirm_after);
+		match old {
+			_ if new.seen_count < confirm_after => Remembered::Unconfirmed,
+			Some(old) if old.seen_count >= confirm_after => Remembered::Updated { old, new },
+			_ => Remembered::Found(new),
+		}
 	}
 
 	pub(super) fn sweep(&mut self, event_handler: &EventHandler, max_ignored_packets: u8) {
-		self.0.retain(|entry| {
+		let confirm_after = u64::from(self.confirm_after);
+		self.responders.retain(|_, entry| {
 			let ignored_packets = entry.ignored_packets.get();
 			if ignored_packets < max_ignored_packets {
 				entry.ignored_packets.set(ignored_packets + 1);
 				true
 			} else {
-				let event_handler = event_handler.clone();
-				let responder = entry.inner.clone();
-				tokio::task::spawn_blocking(move || event_handler(DiscoveryEvent::ResponderLost(responder)));
+				Self::lost(event_handler, entry, confirm_after);
 				false
 			}
 		});
 	}
+
+	/// Forgets responders whose last response has expired, for passive discovery, where responders aren't queried and so can't ignore queries.
+	pub(super) fn expire(&mut self, event_handler: &EventHandler) {
+		let confirm_after = u64::from(self.confirm_after);
+		let now = tokio::time::Instant::now();
+		self.responders.retain(|_, entry| {
+			if entry.expires > now {
+				true
+			} else {
+				Self::lost(event_handler, entry, confirm_after);
+				false
+			}
+		});
+	}
+
+	/// Forgets a responder right away, regardless of whether it is still responding, for [`DiscoveryHandle::forget`](super::DiscoveryHandle::forget).
+	///
+	/// A responder that advertises several service types is forgotten for all of them.
+	pub(super) fn forget(&mut self, event_handler: &EventHandler, addr: SocketAddr) {
+		let confirm_after = u64::from(self.confirm_after);
+		self.responders.retain(|(responder_addr, _), entry| {
+			if *responder_addr != addr {
+				true
+			} else {
+				Self::lost(event_handler, entry, confirm_after);
+				false
+			}
+		});
+	}
+
+	/// Forgets a responder that said goodbye to the service that its response matched, reporting it as lost if it had been found.
+	pub(super) fn goodbye(&mut self, event_handler: &EventHandler, addr: SocketAddr, service_name: Option<DnsName>) {
+		if let Some(entry) = self.responders.remove(&(addr, service_name)) {
+			Self::lost(event_handler, &entry, u64::from(self.confirm_after));
+		}
+	}
+
+	fn lost(event_handler: &EventHandler, entry: &ResponderMemoryEntry, confirm_after: u64) {
+		if entry.seen_count >= confirm_after {
+			let event_handler = event_handler.clone();
+			let responder = entry.inner.clone();
+			let span = logging::debug_span!("responder_lost", addr = %responder.addr);
+			span.in_scope(|| logging::debug!("Responder lost"));
+			tokio::task::spawn_blocking(move || span.in_scope(|| event_handler(DiscoveryEvent::ResponderLost(responder))));
+		}
+	}
+}
+
+/// The IP addresses of this machine's network interfaces, used to recognise responses that we sent ourselves.
+pub(super) struct LocalAddrs(Option<HashSet<IpAddr>>);
+impl LocalAddrs {
+	pub(super) fn new(enabled: bool) -> Self {
+		let mut local_addrs = Self(if enabled { Some(HashSet::new()) } else { None });
+		local_addrs.refresh();
+		local_addrs
+	}
+
+	pub(super) fn refresh(&mut self) {
+		let local_addrs = match &mut self.0 {
+			Some(local_addrs) => local_addrs,
+			None => return,
+		};
+
+		match if_addrs::get_if_addrs() {
+			Ok(ifaces) => {
+				local_addrs.clear();
+				local_addrs.extend(ifaces.into_iter().map(|iface| iface.ip()));
+			}
+
+			Err(err) => logging::warn!("Failed to enumerate local addresses: {err}"),
+		}
+	}
+
+	#[inline(always)]
+	pub(super) fn contains(&self, addr: &IpAddr) -> bool {
+		self.0.as_ref().map(|local_addrs| local_addrs.contains(addr)).unwrap_or(false)
+	}
 }
@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+use trust_dns_client::{
+	op::Message as DnsMessage,
+	rr::{DNSClass as DnsClass, Name as DnsName, Record as DnsRecord, RecordType as DnsRecordType},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// The records that changed between two responses from the same responder, as returned by [`DiscoveryEvent::record_changes`](super::DiscoveryEvent::record_changes).
+pub struct RecordChanges {
+	/// Records in the new response that weren't in the old response.
+	pub added: Vec<DnsRecord>,
+
+	/// Records in the old response that are no longer valid.
+	///
+	/// Following [RFC 6762 §10.2](https://datatracker.ietf.org/doc/html/rfc6762#section-10.2), a record is only considered removed if the new response flushes it
+	/// (i.e. it contains a record with the same name, type and class that has the cache-flush bit set), or says goodbye to it with a TTL of zero.
+	///
+	/// Shared records that are just missing from the new response are assumed to still be valid.
+	pub removed: Vec<DnsRecord>,
+}
+impl RecordChanges {
+	#[inline]
+	/// Returns `true` if no records were added or removed.
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty()
+	}
+
+	pub(crate) fn between(old: &DnsMessage, new: &DnsMessage) -> Self {
+		let old_records = records(old).collect::<Vec<_>>();
+		let (new_records, goodbyes): (Vec<_>, Vec<_>) = records(new).partition(|record| record.ttl() != 0);
+
+		let flushed = flushed(new);
+
+		let mut changes = Self::default();
+
+		for record in new_records.iter().copied() {
+			if !old_records.contains(&record) && !changes.added.contains(record) {
+				changes.added.push(record.clone());
+			}
+		}
+
+		for record in old_records {
+			if new_records.contains(&record) || changes.removed.contains(record) {
+				continue;
+			}
+
+			if flushed.contains(&record_key(record)) || goodbyes.contains(&record) {
+				changes.removed.push(record.clone());
+			}
+		}
+
+		changes
+	}
+}
+
+/// Whether two messages contain the same A, AAAA, SRV, TXT and PTR records, ignoring their order, TTLs and cache-flush bits.
+///
+/// Goodbye records (with a TTL of zero) are treated as absent.
+pub(crate) fn same_records(a: &DnsMessage, b: &DnsMessage) -> bool {
+	fn relevant(message: &DnsMessage) -> Vec<&DnsRecord> {
+		records(message)
+			.filter(|record| record.ttl() != 0)
+			.filter(|record| {
+				matches!(
+					record.record_type(),
+					DnsRecordType::A | DnsRecordType::AAAA | DnsRecordType::SRV | DnsRecordType::TXT | DnsRecordType::PTR
+				)
+			})
+			.collect()
+	}
+
+	// `DnsRecord`'s `PartialEq` already ignores the TTL and cache-flush bit
+	let (a, b) = (relevant(a), relevant(b));
+	a.iter().all(|record| b.contains(record)) && b.iter().all(|record| a.contains(record))
+}
+
+/// The (name, type, class) of every record in `message` that has the cache-flush bit set.
+pub(crate) fn flushed(message: &DnsMessage) -> HashSet<(&DnsName, DnsRecordType, DnsClass)> {
+	records(message).filter(|record| record.mdns_cache_flush()).map(record_key).collect()
+}
+
+#[inline]
+fn record_key(record: &DnsRecord) -> (&DnsName, DnsRecordType, DnsClass) {
+	(record.name(), record.record_type(), record.dns_class())
+}
+
+#[inline]
+fn records(message: &DnsMessage) -> impl Iterator<Item = &DnsRecord> {
+	message.answers().iter().chain(message.additionals())
+}
@@ -0,0 +1,300 @@
+use super::txt::TxtFilter;
+use crate::{
+	errors::MultiIpIoError,
+	logging,
+	net::RecvInterface,
+	socket::{AsyncMdnsSocket, MdnsSocketRecv},
+	transport::{MdnsTransport, MdnsTransportRecv},
+};
+use std::{
+	collections::BTreeSet,
+	net::{IpAddr, SocketAddr},
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	},
+};
+use tokio::sync::mpsc::{error::TrySendError, Receiver, Sender};
+use trust_dns_client::{
+	op::{DnsResponse, Message as DnsMessage, MessageType as DnsMessageType, OpCode as DnsOpCode},
+	rr::{Name as DnsName, RData},
+	serialize::binary::BinDecodable,
+};
+
+type ParsedResponse = Result<Option<Received>, MultiIpIoError>;
+
+/// How many parsed responses can be queued for the discovery loop, by the receiving workers or by a `Node`, before they start being dropped.
+const WORKER_QUEUE_SIZE: usize = 256;
+
+/// A response that we're interested in.
+pub(crate) struct Received {
+	pub(super) addr: SocketAddr,
+	pub(super) response: DnsResponse,
+	pub(super) raw_packet: Vec<u8>,
+
+	/// The service name that the response matched, if we're looking for a specific service.
+	pub(super) service_name: Option<DnsName>,
+}
+impl Received {
+	/// Returns `true` if the response is a goodbye packet, which says that the responder's service is going away
+	/// ([RFC 6762 §10.1](https://datatracker.ietf.org/doc/html/rfc6762#section-10.1)).
+	///
+	/// This is the case if every answer about the service that the response matched (or every answer, if it didn't match a specific service,
+	/// or none of its answers are named after the service) has a TTL of zero. A response that also has live answers about the service is an update,
+	/// as only some of its records are going away.
+	pub(super) fn is_goodbye(&self) -> bool {
+		let answers = self.response.answers();
+		let about_service = answers
+			.iter()
+			.filter(|record| match &self.service_name {
+				Some(service_name) => record.name() == service_name || record.name().base_name() == *service_name,
+				None => true,
+			})
+			.collect::<Vec<_>>();
+
+		let relevant = if about_service.is_empty() {
+			answers.iter().collect()
+		} else {
+			about_service
+		};
+		!relevant.is_empty() && relevant.iter().all(|record| record.ttl() == 0)
+	}
+}
+
+#[derive(Clone)]
+/// Decides which responses the discovery loop is interested in.
+pub(crate) struct ResponseFilter {
+	/// Only responses to this service are accepted, if set.
+	pub(super) service_name: Option<DnsName>,
+
+	/// If `service_name` is a subtype, responses to its base service type are accepted too.
+	pub(super) subtype_of: Option<DnsName>,
+
+	/// Responses to these service types (which have handlers of their own) are accepted too.
+	pub(super) service_types: Vec<DnsName>,
+
+	/// Only responses whose TXT records satisfy this are accepted, if set.
+	pub(super) require_txt: Option<TxtFilter>,
+
+	/// Only responses that include at least this many distinct IP addresses are accepted (or goodbye packets).
+	pub(super) min_addresses: usize,
+
+	/// Whether responses that resolve an instance of `service_name` are accepted too, even if they don't mention the service itself.
+	pub(super) auto_resolve: bool,
+}
+
+/// Parses a received packet into a DNS response, discarding anything that isn't a response we're interested in.
+pub(super) fn parse_response(filter: &ResponseFilter, recv: ((usize, SocketAddr), Option<RecvInterface>, &[u8])) -> Option<Received> {
+	let ((count, addr), _, packet) = recv;
+
+	if count == 0 {
+		return None;
+	}
+
+	match DnsMessage::from_bytes(&packet[..count]) {
+		Ok(message) => filter_response(filter, addr, &packet[..count], message),
+		Err(_) => None,
+	}
+}
+
+/// Discards a message if it isn't a response we're interested in.
+fn filter_response(filter: &ResponseFilter, addr: SocketAddr, packet: &[u8], message: DnsMessage) -> Option<Received> {
+	let response = match message.message_type() {
+		DnsMessageType::Response => DnsResponse::from(message),
+		DnsMessageType::Query => return None,
+	};
+
+	// Only standard queries are supported over mDNS, so anything else must be silently ignored (RFC 6762, section 18.3)
+	if response.op_code() != DnsOpCode::Query {
+		return None;
+	}
+
+	let service_name = if filter.service_name.is_none() && filter.service_types.is_empty() {
+		None
+	} else {
+		let answers = |service_name: &DnsName, subtype_of: Option<&DnsName>| {
+			response.answers().iter().any(|answer| {
+				answer.name() == service_name
+					|| subtype_of == Some(answer.name())
+					|| (filter.auto_resolve && answer.name().base_name() == *service_name)
+			})
+		};
+
+		let matched = filter
+			.service_name
+			.iter()
+			.find(|service_name| answers(service_name, filter.subtype_of.as_ref()))
+			.or_else(|| filter.service_types.iter().find(|service_type| answers(service_type, None)));
+
+		match matched {
+			Some(service_name) => Some(service_name.clone()),
+
+			// This response does not contain any of the services we are looking for.
+			None => return None,
+		}
+	};
+
+	if let Some(require_txt) = &filter.require_txt {
+		if !require_txt.matches(&response) {
+			return None;
+		}
+	}
+
+	let recv = Received {
+		addr,
+		response,
+		raw_packet: packet.to_vec(),
+		service_name,
+	};
+
+	if filter.min_addresses > 0 && address_count(&recv.response) < filter.min_addresses && !recv.is_goodbye() {
+		return None;
+	}
+
+	Some(recv)
+}
+
+/// Counts the distinct IP addresses that a response advertises with A/AAAA records, ignoring goodbye records.
+fn address_count(response: &DnsResponse) -> usize {
+	response
+		.answers()
+		.iter()
+		.chain(response.additionals())
+		.filter(|record| record.ttl() != 0)
+		.filter_map(|record| match record.data() {
+			Some(RData::A(ip)) => Some(IpAddr::V4(*ip)),
+			Some(RData::AAAA(ip)) => Some(IpAddr::V6(*ip)),
+			_ => None,
+		})
+		.collect::<BTreeSet<_>>()
+		.len()
+}
+
+/// Where the discovery loop gets its responses from.
+pub(crate) enum DiscoveryRecv<R: MdnsTransportRecv> {
+	/// Packets are received and parsed inline by the discovery loop.
+	Inline { socket_recv: R, filter: ResponseFilter },
+
+	/// Packets are received and parsed by tasks running on the runtime's worker threads, and queued for the discovery loop.
+	Workers(Receiver<ParsedResponse>),
+
+	/// Packets are received and parsed by a [`Node`](crate::node::Node), and queued for the discovery loop.
+	Channel(Receiver<ParsedResponse>),
+}
+impl<R: MdnsTransportRecv> DiscoveryRecv<R> {
+	pub(crate) fn inline<'a, T>(socket: &'a T, filter: ResponseFilter) -> Self
+	where
+		T: MdnsTransport<Recv<'a> = R>,
+	{
+		Self::Inline {
+			socket_recv: socket.recv(vec![0; 4096]),
+			filter,
+		}
+	}
+
+	/// Receives the next response.
+	///
+	/// Returns `Ok(None)` if a packet was received but it was not a response we are interested in.
+	pub(super) async fn recv(&mut self) -> ParsedResponse {
+		match self {
+			Self::Inline { socket_recv, filter } => Ok(parse_response(filter, socket_recv.recv_multicast().await?)),
+
+			Self::Workers(rx) => match rx.recv().await {
+				Some(parsed) => parsed,
+				None => std::future::pending().await,
+			},
+
+			Self::Channel(rx) => match rx.recv().await {
+				Some(parsed) => parsed,
+				None => std::future::pending().await,
+			},
+		}
+	}
+}
+impl DiscoveryRecv<MdnsSocketRecv<'_>> {
+	/// Spawns `count` receiving tasks onto the current runtime.
+	///
+	/// Responses that arrive while the queue is full are dropped and counted in `dropped`. The tasks stop once this is dropped.
+	///
+	/// This should only be used with a multi-threaded runtime, otherwise the tasks will just compete with the discovery loop for the same thread.
+	pub(super) fn workers(socket: Arc<AsyncMdnsSocket>, count: usize, filter: ResponseFilter, dropped: Arc<AtomicU64>) -> Self {
+		let (tx, rx) = tokio::sync::mpsc::channel(WORKER_QUEUE_SIZE);
+		for _ in 0..count {
+			tokio::spawn(recv_worker(socket.clone(), filter.clone(), tx.clone(), dropped.clone()));
+		}
+		Self::Workers(rx)
+	}
+
+	#[cfg(feature = "broadcast")]
+	/// Creates a channel for feeding responses that were received elsewhere into the discovery loop.
+	///
+	/// Responses that arrive while the queue is full are dropped and counted in `dropped`, like with [`workers`](Self::workers).
+	pub(crate) fn channel(filter: ResponseFilter, dropped: Arc<AtomicU64>) -> (ResponseSender, Self) {
+		let (tx, rx) = tokio::sync::mpsc::channel(WORKER_QUEUE_SIZE);
+		(ResponseSender { tx, filter, dropped }, Self::Channel(rx))
+	}
+}
+
+#[cfg(feature = "broadcast")]
+/// Feeds responses that were received elsewhere into a discovery loop created with [`DiscoveryRecv::channel`].
+pub(crate) struct ResponseSender {
+	tx: Sender<ParsedResponse>,
+	filter: ResponseFilter,
+	dropped: Arc<AtomicU64>,
+}
+#[cfg(feature = "broadcast")]
+impl ResponseSender {
+	/// Queues a received message, decoded from `packet`, for the discovery loop if it is a response we're interested in.
+	///
+	/// Returns `false` if the discovery loop has gone away.
+	pub(crate) fn send(&self, addr: SocketAddr, packet: &[u8], message: DnsMessage) -> bool {
+		let received = match filter_response(&self.filter, addr, packet, message) {
+			Some(received) => received,
+			None => return true,
+		};
+
+		match self.tx.try_send(Ok(Some(received))) {
+			Ok(()) => true,
+			Err(TrySendError::Full(_)) => {
+				self.dropped.fetch_add(1, Ordering::Relaxed);
+				logging::debug!("Discovery is falling behind, dropped a response");
+				true
+			}
+			Err(TrySendError::Closed(_)) => false,
+		}
+	}
+}
+
+/// Receives and parses packets from `socket`, queueing the responses we're interested in on `tx` until its receiver is dropped.
+///
+/// Errors are queued too, waiting for room if the queue is full, and it's up to the discovery loop to back off if they're sustained.
+pub(crate) async fn recv_worker<T: MdnsTransport>(socket: Arc<T>, filter: ResponseFilter, tx: Sender<ParsedResponse>, dropped: Arc<AtomicU64>) {
+	let mut socket_recv = socket.recv(vec![0; 4096]);
+	loop {
+		let parsed = tokio::select! {
+			// The discovery loop has gone away
+			_ = tx.closed() => break,
+
+			recv = socket_recv.recv_multicast() => recv.map(|recv| parse_response(&filter, recv)),
+		};
+
+		match parsed {
+			Ok(None) => continue,
+
+			Ok(Some(_)) => match tx.try_send(parsed) {
+				Ok(()) => {}
+				Err(TrySendError::Full(_)) => {
+					dropped.fetch_add(1, Ordering::Relaxed);
+					logging::debug!("Discovery is falling behind, dropped a response");
+				}
+				Err(TrySendError::Closed(_)) => break,
+			},
+
+			Err(_) => {
+				if tx.send(parsed).await.is_err() {
+					break;
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,122 @@
+use super::{enumerate::query_packet, recv::Received};
+use crate::util::SERVICES_META_QUERY;
+use std::{
+	collections::{BTreeSet, HashMap, HashSet},
+	net::SocketAddr,
+};
+use trust_dns_client::rr::{Name as DnsName, RData, RecordType as DnsRecordType};
+
+/// What to do with a response, once [`AutoResolve`] has seen it.
+pub(super) enum Resolve {
+	/// The response advertises everything it points to, so it can be reported.
+	Complete(Received),
+
+	/// The response is waiting for some of its instances to be resolved, and this query (if any) should be sent to resolve them.
+	///
+	/// There is no query if every instance it's waiting for has already been asked about.
+	Pending(Option<Vec<u8>>),
+}
+
+/// Resolves the SRV and TXT records of service instances that were only advertised with a PTR record,
+/// for [`DiscoveryBuilder::auto_resolve`](super::DiscoveryBuilder::auto_resolve).
+pub(super) struct AutoResolve {
+	unicast: bool,
+	meta_query: DnsName,
+
+	/// Responses that are waiting for their instances to be resolved, by the address they were received from and the service name they matched.
+	pending: HashMap<(SocketAddr, Option<DnsName>), Received>,
+
+	/// Instances that have been asked about since the last discovery packet, so that they aren't asked about again for every response.
+	queried: HashSet<DnsName>,
+}
+impl AutoResolve {
+	pub(super) fn new(unicast: bool) -> Self {
+		Self {
+			unicast,
+			meta_query: DnsName::from_ascii(SERVICES_META_QUERY).unwrap(),
+			pending: HashMap::new(),
+			queried: HashSet::new(),
+		}
+	}
+
+	/// Merges a response with any earlier response from the same address (for the same service) that is still waiting to be resolved,
+	/// and decides whether it can be reported yet.
+	pub(super) fn handle(&mut self, recv: Received) -> Resolve {
+		let key = (recv.addr, recv.service_name.clone());
+		let recv = match self.pending.remove(&key) {
+			Some(pending) => merge(pending, recv),
+			None => recv,
+		};
+
+		let unresolved = self.unresolved(&recv);
+		if unresolved.is_empty() {
+			return Resolve::Complete(recv);
+		}
+
+		let queries = unresolved
+			.into_iter()
+			.filter(|instance| self.queried.insert(instance.clone()))
+			.flat_map(|instance| [(instance.clone(), DnsRecordType::SRV), (instance, DnsRecordType::TXT)])
+			.collect::<Vec<_>>();
+
+		self.pending.insert(key, recv);
+
+		if queries.is_empty() {
+			return Resolve::Pending(None);
+		}
+
+		// A query that can't be built is as good as lost; the response is reported as it is when discovery next queries
+		Resolve::Pending(query_packet(self.unicast, queries).ok())
+	}
+
+	/// Gives up on the responses that are still waiting to be resolved, returning them as they are, so that they can be reported anyway.
+	///
+	/// This is done every time a discovery packet is sent, which also lets unresolved instances be asked about again.
+	pub(super) fn flush(&mut self) -> Vec<Received> {
+		self.queried.clear();
+		self.pending.drain().map(|(_, recv)| recv).collect()
+	}
+
+	/// Returns the instances that the response points to with a PTR record, but doesn't include an SRV record for.
+	fn unresolved(&self, recv: &Received) -> BTreeSet<DnsName> {
+		let records = || recv.response.answers().iter().chain(recv.response.additionals());
+
+		let resolved = records()
+			.filter(|record| record.record_type() == DnsRecordType::SRV)
+			.map(|record| record.name())
+			.collect::<BTreeSet<_>>();
+
+		recv.response
+			.answers()
+			.iter()
+			.filter(|record| record.ttl() != 0 && *record.name() != self.meta_query)
+			.filter(|record| recv.service_name.as_ref().map_or(true, |service_name| record.name() == service_name))
+			.filter_map(|record| match record.data() {
+				Some(RData::PTR(instance)) if !resolved.contains(instance) => Some(instance.clone()),
+				_ => None,
+			})
+			.collect()
+	}
+}
+
+/// Adds the records of a later response to an earlier one from the same address, skipping any records it already has.
+///
+/// The raw packet is the later response's, as there is no single packet that the merged response was received as.
+fn merge(mut pending: Received, recv: Received) -> Received {
+	let Received { response, raw_packet, .. } = recv;
+
+	for record in response.answers() {
+		if !pending.response.answers().contains(record) {
+			pending.response.add_answer(record.clone());
+		}
+	}
+	for record in response.additionals() {
+		if !pending.response.additionals().contains(record) {
+			pending.response.add_additional(record.clone());
+		}
+	}
+	pending.response.update_counts();
+
+	pending.raw_packet = raw_packet;
+	pending
+}
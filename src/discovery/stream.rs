@@ -0,0 +1,75 @@
+use super::{DiscoveryEvent, DiscoveryHandle, Shutdown};
+use crate::logging;
+use futures_core::Stream;
+use std::{
+	pin::Pin,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	},
+	task::{Context, Poll},
+};
+use tokio::sync::mpsc::{error::TrySendError, Receiver};
+
+/// How many events a [`DiscoveryEventStream`] buffers before it starts dropping them.
+pub(super) const EVENT_STREAM_CAPACITY: usize = 1024;
+
+/// A [`Stream`] of [`DiscoveryEvent`]s, created by [`Discovery::into_event_stream`](super::Discovery::into_event_stream).
+///
+/// Discovery runs in the background for as long as the stream is alive, and is shut down when the stream is dropped.
+///
+/// Up to 1024 events are buffered while the stream isn't being polled. Discovery doesn't wait for the stream to catch up, so any more
+/// events are dropped; the number of events dropped this way is available from [`dropped_events`](DiscoveryEventStream::dropped_events).
+///
+/// The stream ends if discovery encounters a fatal error.
+#[must_use = "Streams do nothing unless polled, and discovery will shut down if the stream is dropped"]
+pub struct DiscoveryEventStream {
+	rx: Receiver<DiscoveryEvent>,
+	dropped: Arc<AtomicU64>,
+	pub(super) handle: Option<DiscoveryHandle>,
+}
+impl DiscoveryEventStream {
+	/// Creates a stream that buffers up to `capacity` events, and the event handler that feeds it.
+	///
+	/// The stream isn't attached to discovery until its `handle` is set.
+	pub(crate) fn channel(capacity: usize) -> (impl Fn(DiscoveryEvent) + Send + Sync + 'static, Self) {
+		let (tx, rx) = tokio::sync::mpsc::channel(capacity);
+		let dropped = Arc::new(AtomicU64::new(0));
+
+		let handler = {
+			let dropped = dropped.clone();
+			move |event| match tx.try_send(event) {
+				Ok(()) | Err(TrySendError::Closed(_)) => {}
+				Err(TrySendError::Full(_)) => {
+					dropped.fetch_add(1, Ordering::Relaxed);
+					logging::debug!("Discovery event stream is full, dropped an event");
+				}
+			}
+		};
+
+		(handler, Self { rx, dropped, handle: None })
+	}
+
+	/// Returns how many events have been dropped because the stream wasn't polled quickly enough.
+	pub fn dropped_events(&self) -> u64 {
+		self.dropped.load(Ordering::Relaxed)
+	}
+
+	/// Shuts down discovery and ends the stream.
+	///
+	/// This function will block until discovery has shut down, and will return why it stopped running, or an error if the shutdown failed.
+	pub fn shutdown(mut self) -> Result<Shutdown, crate::errors::ShutdownError> {
+		match self.handle.take() {
+			Some(handle) => handle.shutdown(),
+			None => Ok(Shutdown::Requested),
+		}
+	}
+}
+impl Stream for DiscoveryEventStream {
+	type Item = DiscoveryEvent;
+
+	#[inline]
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		self.rx.poll_recv(cx)
+	}
+}
@@ -0,0 +1,70 @@
+use std::{collections::BTreeMap, sync::Arc};
+use trust_dns_client::{
+	op::Message as DnsMessage,
+	rr::{rdata::TXT, RData},
+};
+
+type TxtPredicate = dyn Fn(&BTreeMap<Vec<u8>, Option<Vec<u8>>>) -> bool + Send + Sync;
+
+/// Parses the key/value pairs of a TXT record, as described in [RFC 6763 §6](https://datatracker.ietf.org/doc/html/rfc6763#section-6).
+///
+/// Keys are case-insensitive, so they are lowercased. Boolean attributes (keys without an `=`) have a value of `None`.
+/// Entries with an empty key are ignored, and if a key appears more than once, only the first occurrence is used.
+pub(crate) fn parse_txt(txt: &TXT) -> BTreeMap<Vec<u8>, Option<Vec<u8>>> {
+	let mut attributes = BTreeMap::new();
+
+	for entry in txt.txt_data() {
+		let (key, value) = match entry.iter().position(|&byte| byte == b'=') {
+			Some(eq) => (&entry[..eq], Some(entry[eq + 1..].to_vec())),
+			None => (&entry[..], None),
+		};
+
+		if key.is_empty() {
+			continue;
+		}
+
+		attributes.entry(key.to_ascii_lowercase()).or_insert(value);
+	}
+
+	attributes
+}
+
+/// Parses every TXT record in a message, answers first.
+pub(crate) fn txt_records(message: &DnsMessage) -> impl Iterator<Item = BTreeMap<Vec<u8>, Option<Vec<u8>>>> + '_ {
+	message
+		.answers()
+		.iter()
+		.chain(message.additionals())
+		.filter_map(|record| match record.data() {
+			Some(RData::TXT(txt)) => Some(parse_txt(txt)),
+			_ => None,
+		})
+}
+
+#[derive(Clone)]
+/// A predicate that responses' TXT records must satisfy, set using [`DiscoveryBuilder::require_txt`](super::DiscoveryBuilder::require_txt).
+pub(crate) struct TxtFilter(Arc<TxtPredicate>);
+impl TxtFilter {
+	pub(crate) fn new<F>(predicate: F) -> Self
+	where
+		F: Fn(&BTreeMap<Vec<u8>, Option<Vec<u8>>>) -> bool + Send + Sync + 'static,
+	{
+		Self(Arc::new(predicate))
+	}
+
+	/// Returns `true` if any of the message's TXT records satisfy the predicate.
+	///
+	/// A message without any TXT records is treated as having a single, empty TXT record.
+	pub(crate) fn matches(&self, message: &DnsMessage) -> bool {
+		let mut txt_records = txt_records(message).peekable();
+		if txt_records.peek().is_none() {
+			return (self.0)(&BTreeMap::new());
+		}
+		txt_records.any(|txt| (self.0)(&txt))
+	}
+}
+impl std::fmt::Debug for TxtFilter {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("TxtFilter").finish_non_exhaustive()
+	}
+}
@@ -1,16 +1,148 @@
 //! Errors that can occur when using this crate
 
 use std::any::Any;
+use trust_dns_client::proto::error::ProtoError;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Identifies which DNS name failed to parse or validate
+pub enum NameField {
+	/// The service type, e.g. `_http._tcp.local.`
+	ServiceType,
+
+	/// The service instance name, e.g. `My Web Server`
+	ServiceName,
+
+	/// The full service instance name derived from the service name and type (unless it was overridden), e.g. `My Web Server._http._tcp.local.`
+	ServiceId,
+
+	/// The hostname derived from the service name, e.g. `My Web Server.local.`
+	ServiceHostname,
+
+	/// The subtype suffix derived from the service type, e.g. `_sub._http._tcp.local.`
+	ServiceSubtype,
+
+	/// The target of the service's SRV record, if it was set separately from the hostname
+	SrvTarget,
+
+	/// The name being asked about in a simulated query
+	QueryName,
+}
+impl std::fmt::Display for NameField {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			NameField::ServiceType => "service type",
+			NameField::ServiceName => "service name",
+			NameField::ServiceId => "service instance name",
+			NameField::ServiceHostname => "service hostname",
+			NameField::ServiceSubtype => "service subtype",
+			NameField::SrvTarget => "SRV target",
+			NameField::QueryName => "query name",
+		})
+	}
+}
+
+#[derive(Debug, Error)]
+#[error("Bad DNS name ({field}): {source}")]
 /// A DNS name is invalid
-pub struct BadDnsNameError;
-impl std::fmt::Display for BadDnsNameError {
+pub struct BadDnsNameError {
+	/// Which DNS name was invalid
+	pub field: NameField,
+
+	/// The underlying DNS error
+	#[source]
+	pub source: ProtoError,
+}
+impl BadDnsNameError {
+	#[inline]
+	pub(crate) fn map(field: NameField) -> impl FnOnce(ProtoError) -> Self {
+		move |source| Self { field, source }
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Identifies which step of setting up an mDNS socket failed
+pub enum SocketSetupStage {
+	/// Creating the socket itself
+	Create,
+
+	/// Setting a socket option, such as the read timeout, address reuse or the outgoing multicast interface, or configuring the socket with a `configure_socket` callback
+	SetOption,
+
+	/// Looking up the addresses of a network interface
+	InterfaceLookup,
+
+	/// Joining the mDNS multicast group on an interface
+	Join,
+
+	/// Binding the socket to the mDNS port
+	Bind,
+
+	/// Sending a test multicast packet to make sure the socket works
+	Probe,
+
+	/// Checking that the mDNS multicast group was joined on at least one network interface, when this is required
+	RequireInterface,
+}
+impl std::fmt::Display for SocketSetupStage {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		f.write_str("Bad DNS name")
+		f.write_str(match self {
+			SocketSetupStage::Create => "create",
+			SocketSetupStage::SetOption => "set option",
+			SocketSetupStage::InterfaceLookup => "interface lookup",
+			SocketSetupStage::Join => "multicast join",
+			SocketSetupStage::Bind => "bind",
+			SocketSetupStage::Probe => "probe",
+			SocketSetupStage::RequireInterface => "require interface",
+		})
+	}
+}
+
+#[derive(Debug, Error)]
+#[error("Socket setup failed ({stage}): {source}")]
+/// An mDNS socket could not be set up
+///
+/// Building a broadcaster or discoverer reports these directly, as the `SocketSetup` variant of its builder error. Elsewhere, they are reported
+/// inside [`MultiIpIoError`]; use [`MultiIpIoError::socket_setup_errors`] to get at them.
+pub struct SocketSetupError {
+	/// Which step of the socket setup failed
+	pub stage: SocketSetupStage,
+
+	/// The underlying I/O error
+	#[source]
+	pub source: std::io::Error,
+}
+impl SocketSetupError {
+	#[inline]
+	pub(crate) fn map(stage: SocketSetupStage) -> impl FnOnce(std::io::Error) -> Self {
+		move |source| Self { stage, source }
+	}
+}
+impl SocketSetupError {
+	/// Describes the setup errors of the IPv4 and/or IPv6 sockets, like [`MultiIpIoError`] does.
+	pub(crate) fn describe(v4: Option<&Self>, v6: Option<&Self>) -> String {
+		match (v4, v6) {
+			(Some(v4), Some(v6)) => format!("{v4} (IPv4) {v6} (IPv6)"),
+			(Some(v4), None) => format!("{v4} (IPv4)"),
+			(None, Some(v6)) => format!("{v6} (IPv6)"),
+			(None, None) => "Socket setup failed".to_string(),
+		}
+	}
+
+	/// Takes the socket setup error out of an I/O error, or gives the I/O error back if it isn't one.
+	fn take(err: std::io::Error) -> Result<Self, std::io::Error> {
+		if err.get_ref().map_or(false, |err| err.is::<Self>()) {
+			Ok(*err.into_inner().unwrap().downcast::<Self>().unwrap())
+		} else {
+			Err(err)
+		}
+	}
+}
+impl From<SocketSetupError> for std::io::Error {
+	#[inline]
+	fn from(err: SocketSetupError) -> Self {
+		std::io::Error::new(err.source.kind(), err)
 	}
 }
-impl std::error::Error for BadDnsNameError {}
 
 #[derive(Debug, Error)]
 /// An error occurred while shutting down a broadcaster or discoverer
@@ -51,3 +183,41 @@ pub enum MultiIpIoError {
 		v6: std::io::Error,
 	},
 }
+impl MultiIpIoError {
+	/// Returns `true` if the error was caused by the mDNS port already being bound by another socket that doesn't allow it to be shared (on any of the sockets).
+	pub fn is_addr_in_use(&self) -> bool {
+		match self {
+			Self::IoError(err) | Self::V4(err) | Self::V6(err) => err.kind() == std::io::ErrorKind::AddrInUse,
+			Self::Both { v4, v6 } => v4.kind() == std::io::ErrorKind::AddrInUse || v6.kind() == std::io::ErrorKind::AddrInUse,
+		}
+	}
+
+	/// Returns the socket setup errors contained in this error, if it was caused by one or both of the sockets failing to be set up.
+	pub fn socket_setup_errors(&self) -> impl Iterator<Item = &SocketSetupError> {
+		let (a, b) = match self {
+			Self::IoError(err) | Self::V4(err) | Self::V6(err) => (Some(err), None),
+			Self::Both { v4, v6 } => (Some(v4), Some(v6)),
+		};
+
+		[a, b]
+			.into_iter()
+			.flatten()
+			.filter_map(|err| err.get_ref().and_then(|err| err.downcast_ref::<SocketSetupError>()))
+	}
+
+	/// Splits this error into the setup errors of the IPv4 and IPv6 sockets, or gives it back if it isn't made up of socket setup errors only.
+	pub(crate) fn into_socket_setup_errors(self) -> Result<(Option<SocketSetupError>, Option<SocketSetupError>), Self> {
+		match self {
+			Self::V4(err) => SocketSetupError::take(err).map(|v4| (Some(v4), None)).map_err(Self::V4),
+			Self::V6(err) => SocketSetupError::take(err).map(|v6| (None, Some(v6))).map_err(Self::V6),
+			Self::Both { v4, v6 } => match (SocketSetupError::take(v4), SocketSetupError::take(v6)) {
+				(Ok(v4), Ok(v6)) => Ok((Some(v4), Some(v6))),
+				(v4, v6) => Err(Self::Both {
+					v4: v4.unwrap_or_else(Into::into),
+					v6: v6.unwrap_or_else(Into::into),
+				}),
+			},
+			Self::IoError(_) => Err(self),
+		}
+	}
+}
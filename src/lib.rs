@@ -29,6 +29,12 @@
 //!
 //! - **`discovery` ᵈᵉᶠᵃᵘˡᵗ**<br>Provides the [`Discovery`](discovery::Discovery) type that will discover [`Responder`](discovery::Responder)s on the network and keep track of their presence, notifying you via [`DiscoveryEvent`](discovery::DiscoveryEvent)s.
 //!
+//! - **`stream`**<br>Provides [`Discovery::into_event_stream`](discovery::Discovery::into_event_stream), which yields [`DiscoveryEvent`](discovery::DiscoveryEvent)s as a [`Stream`](https://docs.rs/futures-core/latest/futures_core/stream/trait.Stream.html) instead of calling an event handler.
+//!
+//! - **`tracing`**<br>Emits diagnostics using [`tracing`](https://docs.rs/tracing) instead of [`log`](https://docs.rs/log), with spans for every query sent, response received, and responder found or lost.
+//!
+//! With both `broadcast` and `discovery` enabled, the [`Node`](node::Node) type is also available, which broadcasts and discovers on a single socket.
+//!
 //! # Examples
 //!
 //! Examples for [broadcasting](broadcast) and [discovery] can be found in the documentation for their respective modules.
@@ -45,7 +51,10 @@ extern crate thiserror;
 #[cfg(test)]
 mod tests;
 
+mod background;
+mod logging;
 mod socket;
+mod transport;
 mod util;
 
 pub mod errors;
@@ -59,6 +68,10 @@ pub mod broadcast;
 #[cfg_attr(docsrs, doc(cfg(feature = "discovery")))]
 pub mod discovery;
 
+#[cfg(all(feature = "broadcast", feature = "discovery"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "broadcast", feature = "discovery"))))]
+pub mod node;
+
 /// The port used for mDNS.
 pub const MDNS_PORT: u16 = 5353;
 
@@ -68,5 +81,37 @@ pub const MDNS_V4_IP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
 /// The IPv6 multicast address used for mDNS.
 pub const MDNS_V6_IP: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// The [feature flags](crate#feature-flags) that Searchlight was compiled with, as returned by [`features`].
+pub struct Features {
+	/// Whether the `broadcast` feature is enabled.
+	pub broadcast: bool,
+
+	/// Whether the `discovery` feature is enabled.
+	pub discovery: bool,
+
+	/// Whether the `stream` feature is enabled.
+	pub stream: bool,
+
+	/// Whether the `tracing` feature is enabled.
+	pub tracing: bool,
+}
+
+/// Returns the [feature flags](crate#feature-flags) that Searchlight was compiled with.
+///
+/// This is useful for diagnostics, such as an "about" screen or a bug report.
+pub const fn features() -> Features {
+	Features {
+		broadcast: cfg!(feature = "broadcast"),
+		discovery: cfg!(feature = "discovery"),
+		stream: cfg!(feature = "stream"),
+		tracing: cfg!(feature = "tracing"),
+	}
+}
+
 /// Searchlight uses [`trust-dns`](https://github.com/bluejekyll/trust-dns) internally for DNS parsing and packet building, so here's a re-export for your convenience.
 pub use trust_dns_client as dns;
+
+/// Searchlight uses [`socket2`](https://github.com/rust-lang/socket2) to set up its sockets, which are passed to [`configure_socket`](crate::broadcast::BroadcasterBuilder::configure_socket)
+/// callbacks, so here's a re-export for your convenience.
+pub use socket2;
@@ -0,0 +1,43 @@
+//! Diagnostics are emitted using the `log` crate by default, or the `tracing` crate (with spans) if the `tracing` feature is enabled.
+//!
+//! Use the macros in this module rather than `log` or `tracing` directly. Spans are no-ops without the `tracing` feature.
+
+// Not every macro is used by every combination of features
+#![allow(unused)]
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{debug, debug_span, trace, warn, Instrument};
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use log::{debug, trace, warn};
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug_span {
+	($($arg:tt)*) => {
+		$crate::logging::Span
+	};
+}
+#[cfg(not(feature = "tracing"))]
+pub(crate) use debug_span;
+
+#[cfg(not(feature = "tracing"))]
+/// Stands in for `tracing::Span` when the `tracing` feature is disabled.
+pub(crate) struct Span;
+#[cfg(not(feature = "tracing"))]
+impl Span {
+	#[inline(always)]
+	pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+		f()
+	}
+}
+
+#[cfg(not(feature = "tracing"))]
+/// Stands in for `tracing::Instrument` when the `tracing` feature is disabled.
+pub(crate) trait Instrument: Sized {
+	#[inline(always)]
+	fn instrument(self, _span: Span) -> Self {
+		self
+	}
+}
+#[cfg(not(feature = "tracing"))]
+impl<T: std::future::Future> Instrument for T {}
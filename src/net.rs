@@ -1,9 +1,12 @@
 //! Networking utilities and abstractions
 
+use crate::{errors::MultiIpIoError, socket::LoopError};
 use std::{
-	collections::BTreeSet,
-	net::{IpAddr, Ipv4Addr, Ipv6Addr},
+	collections::{BTreeMap, BTreeSet},
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
 	num::NonZeroU32,
+	sync::{Arc, Mutex},
+	time::Duration,
 };
 
 /// The [`if_addrs`](https://crates.io/crates/if_addrs) crate is used to discover network interfaces on the system.
@@ -76,6 +79,22 @@ impl Ipv6Interface {
 		Self(raw)
 	}
 
+	#[inline(always)]
+	/// Creates a new `Ipv6Interface` from a scope ID, such as the one of a received [`SocketAddrV6`].
+	///
+	/// Returns `None` if the scope ID is zero, which means no particular interface.
+	pub fn from_scope_id(scope_id: u32) -> Option<Self> {
+		NonZeroU32::new(scope_id).map(Self)
+	}
+
+	#[inline(always)]
+	/// Returns the interface that the given socket address is scoped to, if any.
+	///
+	/// Only link-local addresses are usually scoped, so this returns `None` for most other addresses.
+	pub fn from_socket_addr(addr: &SocketAddrV6) -> Option<Self> {
+		Self::from_scope_id(addr.scope_id())
+	}
+
 	#[inline(always)]
 	/// Returns the raw interface index.
 	///
@@ -99,7 +118,10 @@ pub enum IpVersion {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 /// The interface to use for multicast.
+///
+/// More ways of choosing interfaces may be added in the future, so matches on this should have a catch-all arm.
 pub enum TargetInterface<Addr> {
 	/// Let the OS decide which interface to use.
 	Default,
@@ -112,7 +134,57 @@ pub enum TargetInterface<Addr> {
 
 	/// Use the given interfaces.
 	Multi(BTreeSet<Addr>),
+
+	/// Like `All`, but only use the interfaces that the filter accepts, falling back to `Default` if none are available.
+	///
+	/// This is useful for excluding VPNs or virtual adapters.
+	Filter(InterfaceFilter),
 }
+impl<Addr> TargetInterface<Addr> {
+	/// Creates a [`TargetInterface::Filter`] that uses the interfaces for which `filter` returns true.
+	///
+	/// The filter is called with each (non-loopback) interface address returned by [`if_addrs::get_if_addrs`] whenever the socket is set up,
+	/// so it also applies to interfaces that appear later if the socket is [recreated](ReconnectPolicy).
+	///
+	/// ```rust
+	/// use searchlight::net::TargetInterfaceV4;
+	///
+	/// let interface = TargetInterfaceV4::filter(|iface| !iface.name.starts_with("tun"));
+	/// ```
+	pub fn filter<F>(filter: F) -> Self
+	where
+		F: Fn(&if_addrs::Interface) -> bool + Send + Sync + 'static,
+	{
+		Self::Filter(InterfaceFilter(Arc::new(filter)))
+	}
+
+	/// Whether to use `iface` when joining as many interfaces as possible, i.e. for `All` and `Filter`.
+	pub(crate) fn accepts(&self, iface: &if_addrs::Interface) -> bool {
+		match self {
+			Self::Filter(InterfaceFilter(filter)) => filter(iface),
+			_ => true,
+		}
+	}
+}
+
+type InterfaceFilterFn = dyn Fn(&if_addrs::Interface) -> bool + Send + Sync;
+
+#[derive(Clone)]
+/// A predicate that selects network interfaces, created by [`TargetInterface::filter`].
+///
+/// Filters are compared by identity, so two filters are only equal if one is a clone of the other.
+pub struct InterfaceFilter(Arc<InterfaceFilterFn>);
+impl std::fmt::Debug for InterfaceFilter {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("InterfaceFilter").finish_non_exhaustive()
+	}
+}
+impl PartialEq for InterfaceFilter {
+	fn eq(&self, other: &Self) -> bool {
+		Arc::ptr_eq(&self.0, &other.0)
+	}
+}
+impl Eq for InterfaceFilter {}
 
 /// A `TargetInterface` for IPv4.
 pub type TargetInterfaceV4 = TargetInterface<Ipv4Addr>;
@@ -120,6 +192,160 @@ pub type TargetInterfaceV4 = TargetInterface<Ipv4Addr>;
 /// A `TargetInterface` for IPv6.
 pub type TargetInterfaceV6 = TargetInterface<Ipv6Interface>;
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Low-level options applied to the mDNS sockets when they are created.
+///
+/// ```rust
+/// use searchlight::net::SocketOptions;
+///
+/// let options = SocketOptions {
+///     multicast_ttl: 4,
+///     ..Default::default()
+/// };
+/// ```
+pub struct SocketOptions {
+	/// The read timeout set on the sockets before they are switched to non-blocking mode, or `None` to not set one.
+	///
+	/// **Default: 100ms**
+	pub read_timeout: Option<Duration>,
+
+	/// The size of the socket receive buffer (`SO_RCVBUF`), or `None` to use the OS default.
+	///
+	/// **Default: None**
+	pub recv_buffer_size: Option<usize>,
+
+	/// The TTL (IPv4) or hop limit (IPv6) of outgoing multicast packets.
+	///
+	/// Standard mDNS only ever reaches the local link, but this can be raised for networks with multicast routing.
+	///
+	/// **Default: 1**
+	pub multicast_ttl: u32,
+
+	/// The DSCP (Differentiated Services Code Point) that outgoing packets are marked with, using `IP_TOS` (IPv4) or `IPV6_TCLASS` (IPv6),
+	/// or `None` to leave the OS default (usually 0, best effort).
+	///
+	/// Networks with QoS policies may prioritize or drop traffic based on its marking. DSCPs are 6-bit values (0 to 63); building fails with
+	/// a larger value. Marking is only supported on Linux, Android, macOS and iOS, and is ignored elsewhere.
+	///
+	/// **Default: None**
+	pub dscp: Option<u8>,
+}
+impl Default for SocketOptions {
+	fn default() -> Self {
+		Self {
+			read_timeout: Some(Duration::from_millis(100)),
+			recv_buffer_size: None,
+			multicast_ttl: 1,
+			dscp: None,
+		}
+	}
+}
+
+pub(crate) type ConfigureSocketFn = dyn Fn(&socket2::Socket, IpVersion) -> Result<(), std::io::Error> + Send + Sync;
+
+#[derive(Clone)]
+/// A callback that configures each mDNS socket before it is bound, for socket options that Searchlight doesn't set itself.
+pub(crate) struct ConfigureSocket(pub(crate) Arc<ConfigureSocketFn>);
+impl std::fmt::Debug for ConfigureSocket {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("ConfigureSocket").finish_non_exhaustive()
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// How to recover when receiving on the mDNS socket keeps failing, for example because the network went down.
+///
+/// Once `error_threshold` receive errors have happened in a row, each further error waits for a backoff delay (starting at `backoff`
+/// and doubling up to `max_backoff`) so that a dead socket doesn't spin the CPU. If `recreate_socket` is enabled, the socket is then
+/// thrown away and created again, backing off in the same way until an interface is available again.
+///
+/// Discovery, broadcasters and nodes all follow the same policy, which also decides (using [`ErrorPolicy`]) whether failing to send
+/// or receive is fatal.
+///
+/// ```rust
+/// use searchlight::net::ReconnectPolicy;
+/// use std::time::Duration;
+///
+/// let policy = ReconnectPolicy {
+///     max_backoff: Duration::from_secs(5),
+///     ..Default::default()
+/// };
+/// ```
+pub struct ReconnectPolicy {
+	/// How many receive errors in a row are tolerated before backing off.
+	///
+	/// **Default: 10**
+	pub error_threshold: u32,
+
+	/// The first backoff delay.
+	///
+	/// **Default: 1 second**
+	pub backoff: Duration,
+
+	/// The longest backoff delay.
+	///
+	/// **Default: 30 seconds**
+	pub max_backoff: Duration,
+
+	/// Whether to recreate the socket after backing off, which allows it to rejoin the multicast group on interfaces that have come back.
+	///
+	/// **Default: true**
+	pub recreate_socket: bool,
+
+	/// What to do once `error_threshold` receive errors have happened in a row.
+	///
+	/// [`ErrorPolicy::Continue`] backs off (and recreates the socket, if enabled) as described above, while [`ErrorPolicy::Abort`]
+	/// stops right away with the last error.
+	///
+	/// **Default: [`ErrorPolicy::Continue`]**
+	pub recv_errors: ErrorPolicy,
+
+	/// What to do when sending a query or response fails.
+	///
+	/// [`ErrorPolicy::Continue`] drops the packet, while [`ErrorPolicy::Abort`] stops with the error.
+	///
+	/// **Default: [`ErrorPolicy::Continue`]**
+	pub send_errors: ErrorPolicy,
+}
+impl Default for ReconnectPolicy {
+	fn default() -> Self {
+		Self {
+			error_threshold: 10,
+			backoff: Duration::from_secs(1),
+			max_backoff: Duration::from_secs(30),
+			recreate_socket: true,
+			recv_errors: ErrorPolicy::Continue,
+			send_errors: ErrorPolicy::Continue,
+		}
+	}
+}
+impl ReconnectPolicy {
+	#[inline]
+	pub(crate) fn next_backoff(&self, backoff: Duration) -> Duration {
+		backoff.saturating_mul(2).min(self.max_backoff)
+	}
+
+	/// Applies [`send_errors`](ReconnectPolicy::send_errors) to an error from sending a packet, which should already have been logged.
+	pub(crate) fn send_error(&self, err: MultiIpIoError) -> Result<(), LoopError> {
+		match self.send_errors {
+			ErrorPolicy::Continue => Ok(()),
+			ErrorPolicy::Abort => Err(LoopError::Abort(err)),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// What to do when sending or receiving on the mDNS socket fails, as part of a [`ReconnectPolicy`].
+///
+/// When a discovery, broadcaster or node instance aborts, the error is returned from `run`, or from `shutdown` on its handle if it's running in the background.
+pub enum ErrorPolicy {
+	/// Log the error and keep going.
+	Continue,
+
+	/// Log the error and stop.
+	Abort,
+}
+
 pub(crate) trait MulticastSocketEx<Iface> {
 	fn set_multicast_if(&self, iface: Iface) -> Result<(), std::io::Error>;
 }
@@ -212,3 +438,315 @@ impl MulticastSocketEx<Ipv4Addr> for tokio::net::UdpSocket {
 		}
 	}
 }
+
+/// Fixes up the scope ID of a link-local IPv6 source address so that it refers to the interface the packet was received on.
+///
+/// Link-local addresses are only reachable through the interface they were seen on, and depending on the OS the scope ID
+/// reported by `recvfrom` may be missing or wrong. `ifindex` is the receiving interface index, or `0` if it is not known.
+pub(crate) fn normalize_scope_id(addr: SocketAddrV6, ifindex: u32) -> SocketAddrV6 {
+	if ifindex == 0 || addr.ip().segments()[0] & 0xffc0 != 0xfe80 {
+		return addr;
+	}
+
+	SocketAddrV6::new(*addr.ip(), addr.port(), addr.flowinfo(), ifindex)
+}
+
+#[cfg(feature = "broadcast")]
+/// Returns the address that a unicast reply to `addr` should be sent to, so that it leaves through `interface`, which the query was received on.
+///
+/// The OS picks the outgoing interface of a link-local IPv6 destination from its scope ID, so that is set to the receiving interface;
+/// otherwise the reply could leave through the wrong interface and never reach the querier. Other addresses are routed as normal.
+pub(crate) fn reply_addr(addr: SocketAddr, interface: Option<RecvInterface>) -> SocketAddr {
+	match (addr, interface) {
+		(SocketAddr::V6(addr), Some(RecvInterface::V6(interface))) => SocketAddr::V6(normalize_scope_id(addr, interface.as_u32())),
+		_ => addr,
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// The network interface that a packet was received (or sent) on.
+pub enum RecvInterface {
+	/// The address of the IPv4 interface.
+	V4(Ipv4Addr),
+
+	/// The IPv6 interface.
+	V6(Ipv6Interface),
+}
+impl From<Ipv4Addr> for RecvInterface {
+	#[inline]
+	fn from(iface: Ipv4Addr) -> Self {
+		Self::V4(iface)
+	}
+}
+impl From<Ipv6Interface> for RecvInterface {
+	#[inline]
+	fn from(iface: Ipv6Interface) -> Self {
+		Self::V6(iface)
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+/// How many mDNS packets were sent and received on a network interface.
+pub struct InterfaceStats {
+	/// The number of multicast packets sent on the interface.
+	pub sent: u64,
+
+	/// The number of packets received on the interface.
+	pub received: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// Traffic statistics of a discovery, broadcaster or node instance.
+///
+/// Counts are kept for as long as the instance runs, including across [recreated sockets](ReconnectPolicy::recreate_socket).
+pub struct Stats {
+	/// Packets sent and received on each network interface, which is useful for finding out which interfaces are actually carrying mDNS traffic.
+	///
+	/// Packets are only counted against an interface if it is known. Received packets are attributed using packet info, which is only available on
+	/// Linux, Android, macOS and iOS. Sent packets are attributed when the multicast group was joined on specific interfaces (for example with
+	/// [`TargetInterfaceV4::All`]), but not when the OS chooses the interface (with [`TargetInterfaceV4::Default`]). Unicast packets aren't counted as sent.
+	pub per_interface: BTreeMap<RecvInterface, InterfaceStats>,
+
+	/// Responses that a broadcaster or node didn't send because its maximum number of responses per second
+	/// (`BroadcasterBuilder::max_responses_per_sec`) was reached. This is always 0 for discovery, which doesn't send responses.
+	pub dropped_responses: u64,
+}
+
+#[derive(Debug, Default)]
+/// Counts the packets sent and received on each interface, for [`Stats`].
+pub(crate) struct TrafficCounters(Mutex<BTreeMap<RecvInterface, InterfaceStats>>);
+impl TrafficCounters {
+	pub(crate) fn sent(&self, interface: RecvInterface) {
+		self.0.lock().unwrap().entry(interface).or_default().sent += 1;
+	}
+
+	pub(crate) fn received(&self, interface: RecvInterface) {
+		self.0.lock().unwrap().entry(interface).or_default().received += 1;
+	}
+
+	pub(crate) fn stats(&self) -> Stats {
+		Stats {
+			per_interface: self.0.lock().unwrap().clone(),
+			dropped_responses: 0,
+		}
+	}
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+fn set_sockopt_enable(socket: &socket2::Socket, level: libc::c_int, name: libc::c_int) -> Result<(), std::io::Error> {
+	set_sockopt_int(socket, level, name, 1)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+fn set_sockopt_int(socket: &socket2::Socket, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> Result<(), std::io::Error> {
+	use std::os::unix::io::AsRawFd;
+	unsafe {
+		let res = libc::setsockopt(
+			socket.as_raw_fd(),
+			level,
+			name,
+			&value as *const _ as *const _,
+			std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+		);
+		if res == 0 {
+			Ok(())
+		} else {
+			Err(std::io::Error::last_os_error())
+		}
+	}
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+/// Asks the OS to tell us which interface each packet on this IPv4 socket was received on.
+pub(crate) fn set_recv_pktinfo_v4(socket: &socket2::Socket) -> Result<(), std::io::Error> {
+	set_sockopt_enable(socket, libc::IPPROTO_IP, libc::IP_PKTINFO)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+/// Asks the OS to tell us which interface each packet on this IPv6 socket was received on.
+pub(crate) fn set_recv_pktinfo_v6(socket: &socket2::Socket) -> Result<(), std::io::Error> {
+	set_sockopt_enable(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO)
+}
+
+/// Returns the traffic class byte for a DSCP, which is the DSCP followed by the two ECN bits (left as zero).
+fn dscp_traffic_class(dscp: u8) -> Result<u8, std::io::Error> {
+	if dscp > 63 {
+		return Err(std::io::Error::new(
+			std::io::ErrorKind::InvalidInput,
+			format!("DSCP must be 63 or less, got {dscp}"),
+		));
+	}
+	Ok(dscp << 2)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+/// Marks the outgoing packets of this IPv4 socket with a DSCP.
+pub(crate) fn set_dscp_v4(socket: &socket2::Socket, dscp: u8) -> Result<(), std::io::Error> {
+	set_sockopt_int(socket, libc::IPPROTO_IP, libc::IP_TOS, libc::c_int::from(dscp_traffic_class(dscp)?))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+/// Marks the outgoing packets of this IPv6 socket with a DSCP.
+pub(crate) fn set_dscp_v6(socket: &socket2::Socket, dscp: u8) -> Result<(), std::io::Error> {
+	set_sockopt_int(
+		socket,
+		libc::IPPROTO_IPV6,
+		libc::IPV6_TCLASS,
+		libc::c_int::from(dscp_traffic_class(dscp)?),
+	)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios")))]
+/// DSCP marking isn't supported on this platform, so packets are sent with the OS default.
+pub(crate) fn set_dscp_v4(_socket: &socket2::Socket, dscp: u8) -> Result<(), std::io::Error> {
+	dscp_traffic_class(dscp).map(|_| ())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios")))]
+/// DSCP marking isn't supported on this platform, so packets are sent with the OS default.
+pub(crate) fn set_dscp_v6(_socket: &socket2::Socket, dscp: u8) -> Result<(), std::io::Error> {
+	dscp_traffic_class(dscp).map(|_| ())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios")))]
+/// Packet info isn't supported on this platform, so the receiving interface is unknown.
+pub(crate) fn set_recv_pktinfo_v4(_socket: &socket2::Socket) -> Result<(), std::io::Error> {
+	Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios")))]
+/// Packet info isn't supported on this platform, so the OS-provided source address is used as-is.
+pub(crate) fn set_recv_pktinfo_v6(_socket: &socket2::Socket) -> Result<(), std::io::Error> {
+	Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+/// Receives a packet with `recvmsg`, passing each control message to `on_cmsg`.
+///
+/// `Addr` must be the `sockaddr` type of the socket's address family.
+async fn recvmsg<Addr>(
+	socket: &tokio::net::UdpSocket,
+	buf: &mut [u8],
+	mut on_cmsg: impl FnMut(&libc::cmsghdr, *const u8),
+) -> Result<(usize, Addr), std::io::Error> {
+	use std::os::unix::io::AsRawFd;
+
+	let fd = socket.as_raw_fd();
+	socket
+		.async_io(tokio::io::Interest::READABLE, || unsafe {
+			let mut addr: Addr = std::mem::zeroed();
+			let mut iov = libc::iovec {
+				iov_base: buf.as_mut_ptr() as *mut _,
+				iov_len: buf.len(),
+			};
+
+			// u64 for cmsghdr alignment
+			let mut control = [0u64; 16];
+
+			let mut msg: libc::msghdr = std::mem::zeroed();
+			msg.msg_name = &mut addr as *mut _ as *mut _;
+			msg.msg_namelen = std::mem::size_of::<Addr>() as libc::socklen_t;
+			msg.msg_iov = &mut iov;
+			msg.msg_iovlen = 1;
+			msg.msg_control = control.as_mut_ptr() as *mut _;
+			msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+			let count = libc::recvmsg(fd, &mut msg, 0);
+			if count < 0 {
+				return Err(std::io::Error::last_os_error());
+			}
+
+			let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+			while !cmsg.is_null() {
+				on_cmsg(&*cmsg, libc::CMSG_DATA(cmsg));
+				cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+			}
+
+			Ok((count as usize, addr))
+		})
+		.await
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+/// Receives a packet on an IPv4 socket, along with the address of the interface it was received on.
+///
+/// The socket must have had [`set_recv_pktinfo_v4`] called on it.
+pub(crate) async fn recv_from_v4(
+	socket: &tokio::net::UdpSocket,
+	buf: &mut [u8],
+) -> Result<((usize, SocketAddr), Option<RecvInterface>), std::io::Error> {
+	let mut interface = None;
+
+	let (count, addr) = recvmsg::<libc::sockaddr_in>(socket, buf, |cmsg, data| {
+		if cmsg.cmsg_level == libc::IPPROTO_IP && cmsg.cmsg_type == libc::IP_PKTINFO {
+			let pktinfo = unsafe { std::ptr::read_unaligned(data as *const libc::in_pktinfo) };
+			let iface = Ipv4Addr::from(u32::from_be(pktinfo.ipi_spec_dst.s_addr));
+			if !iface.is_unspecified() {
+				interface = Some(RecvInterface::V4(iface));
+			}
+		}
+	})
+	.await?;
+
+	let addr = SocketAddr::new(
+		IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))),
+		u16::from_be(addr.sin_port),
+	);
+
+	Ok(((count, addr), interface))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+/// Receives a packet on an IPv6 socket, along with the interface it was received on.
+///
+/// The scope ID of link-local source addresses is normalized to the receiving interface.
+///
+/// The socket must have had [`set_recv_pktinfo_v6`] called on it.
+pub(crate) async fn recv_from_v6(
+	socket: &tokio::net::UdpSocket,
+	buf: &mut [u8],
+) -> Result<((usize, SocketAddr), Option<RecvInterface>), std::io::Error> {
+	let mut ifindex = 0;
+
+	let (count, addr) = recvmsg::<libc::sockaddr_in6>(socket, buf, |cmsg, data| {
+		if cmsg.cmsg_level == libc::IPPROTO_IPV6 && cmsg.cmsg_type == libc::IPV6_PKTINFO {
+			let pktinfo = unsafe { std::ptr::read_unaligned(data as *const libc::in6_pktinfo) };
+			ifindex = pktinfo.ipi6_ifindex as u32;
+		}
+	})
+	.await?;
+
+	let addr = SocketAddrV6::new(
+		Ipv6Addr::from(addr.sin6_addr.s6_addr),
+		u16::from_be(addr.sin6_port),
+		addr.sin6_flowinfo,
+		addr.sin6_scope_id,
+	);
+
+	Ok((
+		(count, SocketAddr::V6(normalize_scope_id(addr, ifindex))),
+		Ipv6Interface::from_scope_id(ifindex).map(RecvInterface::V6),
+	))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios")))]
+/// Receives a packet on an IPv4 socket.
+///
+/// Packet info isn't supported on this platform, so the receiving interface is unknown.
+pub(crate) async fn recv_from_v4(
+	socket: &tokio::net::UdpSocket,
+	buf: &mut [u8],
+) -> Result<((usize, SocketAddr), Option<RecvInterface>), std::io::Error> {
+	Ok((socket.recv_from(buf).await?, None))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios")))]
+/// Receives a packet on an IPv6 socket.
+///
+/// Packet info isn't supported on this platform, so the OS-provided source address is used as-is, and the receiving interface is unknown.
+pub(crate) async fn recv_from_v6(
+	socket: &tokio::net::UdpSocket,
+	buf: &mut [u8],
+) -> Result<((usize, SocketAddr), Option<RecvInterface>), std::io::Error> {
+	Ok((socket.recv_from(buf).await?, None))
+}
@@ -0,0 +1,358 @@
+//! A combined broadcaster and discoverer that share a single mDNS socket.
+//!
+//! Running a [`Broadcaster`](crate::broadcast::Broadcaster) and a [`Discovery`](crate::discovery::Discovery) in the same process
+//! opens two sockets on the mDNS port, which relies on the OS allowing the port to be shared (which is fragile on Windows),
+//! and joins the multicast group twice on every interface. A [`Node`] does both on one socket, in one background thread.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use searchlight::{
+//!     broadcast::{BroadcasterBuilder, ServiceBuilder},
+//!     discovery::{DiscoveryBuilder, DiscoveryEvent},
+//!     net::IpVersion,
+//!     node::Node,
+//! };
+//!
+//! let broadcaster = BroadcasterBuilder::new().add_service(
+//!     ServiceBuilder::new("_searchlight._udp.local.", "HELLO-WORLD", 1234)
+//!         .unwrap()
+//!         .build()
+//!         .unwrap(),
+//! );
+//!
+//! let discovery = DiscoveryBuilder::new().service("_searchlight._udp.local.").unwrap();
+//!
+//! let node = Node::build(broadcaster, discovery, IpVersion::Both)
+//!     .unwrap()
+//!     .run_in_background(|event| {
+//!         if let DiscoveryEvent::ResponderFound(responder) = event {
+//!             println!("Found {}", responder.addr);
+//!         }
+//!     });
+//!
+//! // ...
+//!
+//! node.shutdown().unwrap();
+//! ```
+
+use crate::{
+	background::Background,
+	broadcast::{errors::BroadcasterBuilderError, Broadcaster, BroadcasterBuilder, BroadcasterConfig, BroadcasterHandle, DelayedResponses},
+	discovery::{Discovery, DiscoveryBuilder, DiscoveryConfig, DiscoveryEvent, DiscoveryRecv, LoopControl, ResponseSender},
+	errors::{MultiIpIoError, ShutdownError},
+	logging,
+	net::{IpVersion, ReconnectPolicy, Stats},
+	socket::{LoopError, MdnsSocket, MdnsSocketRecipe, RecvErrors},
+	transport::{MdnsTransport, MdnsTransportRecv},
+};
+use std::{
+	net::SocketAddr,
+	sync::{Arc, RwLock},
+	time::Duration,
+};
+use trust_dns_client::{
+	op::{Message as DnsMessage, MessageType as DnsMessageType},
+	serialize::binary::BinDecodable,
+};
+
+/// A built mDNS node, which broadcasts services and discovers responders on a single socket, ready to be started.
+///
+/// You can choose to run the node on the current thread, or in the background, using [`Node::run`] or [`Node::run_in_background`].
+pub struct Node {
+	socket: MdnsSocket,
+	recipe: MdnsSocketRecipe,
+	broadcaster: Arc<RwLock<BroadcasterConfig>>,
+	discovery: DiscoveryConfig,
+}
+impl Node {
+	/// Builds a node from a broadcaster and a discovery configuration.
+	///
+	/// The socket is set up using the interfaces, socket options and reconnect policy of the [`BroadcasterBuilder`]; those of the [`DiscoveryBuilder`] are ignored.
+	///
+	/// Discovery always runs on the node's single thread, so [`DiscoveryBuilder::worker_threads`] is ignored too.
+	pub fn build(broadcaster: BroadcasterBuilder, discovery: DiscoveryBuilder, ip_version: IpVersion) -> Result<Self, BroadcasterBuilderError> {
+		let (socket, recipe, broadcaster) = broadcaster.into_parts(ip_version)?;
+
+		Ok(Self {
+			socket,
+			recipe,
+			broadcaster: Arc::new(RwLock::new(broadcaster)),
+			discovery: discovery.into_config(),
+		})
+	}
+
+	/// Returns the local addresses that the node's socket(s) are bound to, one for each IP version in use (IPv4 first).
+	pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, MultiIpIoError> {
+		self.socket.local_addrs()
+	}
+
+	/// Run the node on a new thread; in the background.
+	///
+	/// Returns a [`NodeHandle`] that can be used to manage the broadcasted services, control discovery and cleanly shut down the background thread.
+	pub fn run_in_background<F>(self, handler: F) -> NodeHandle
+	where
+		F: Fn(DiscoveryEvent) + Send + Sync + 'static,
+	{
+		let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+		let (interval_tx, interval_rx) = tokio::sync::watch::channel(self.discovery.interval());
+		let (query_tx, query_rx) = tokio::sync::watch::channel(());
+
+		let config = self.broadcaster.clone();
+		let counters = self.recipe.options.counters.clone();
+		let thread =
+			std::thread::spawn(move || Self::runtime().block_on(self.impl_run(handler, Some(shutdown_rx), Self::control(interval_rx, query_rx))));
+
+		NodeHandle {
+			broadcaster: BroadcasterHandle::new(config, Background::Thread(thread), shutdown_tx, counters),
+			interval_tx,
+			query_tx,
+		}
+	}
+
+	/// Run the node in the background, on a Tokio runtime that you already have, instead of on a new thread with its own runtime.
+	///
+	/// See [`Discovery::run_on`] for details.
+	pub fn run_on<F>(self, runtime: tokio::runtime::Handle, handler: F) -> NodeHandle
+	where
+		F: Fn(DiscoveryEvent) + Send + Sync + 'static,
+	{
+		let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+		let (interval_tx, interval_rx) = tokio::sync::watch::channel(self.discovery.interval());
+		let (query_tx, query_rx) = tokio::sync::watch::channel(());
+
+		let config = self.broadcaster.clone();
+		let counters = self.recipe.options.counters.clone();
+		let background = Background::spawn_on(runtime, move || {
+			self.impl_run(handler, Some(shutdown_rx), Self::control(interval_rx, query_rx))
+		});
+
+		NodeHandle {
+			broadcaster: BroadcasterHandle::new(config, background, shutdown_tx, counters),
+			interval_tx,
+			query_tx,
+		}
+	}
+
+	/// Run the node on the current thread.
+	///
+	/// This will start a new Tokio runtime on the current thread and block until a fatal error occurs.
+	pub fn run<F>(self, handler: F) -> Result<(), MultiIpIoError>
+	where
+		F: Fn(DiscoveryEvent) + Send + Sync + 'static,
+	{
+		Self::runtime().block_on(self.impl_run(handler, None, LoopControl::default()))
+	}
+}
+impl Node {
+	fn runtime() -> tokio::runtime::Runtime {
+		tokio::runtime::Builder::new_current_thread()
+			.thread_name("Searchlight mDNS Node (Tokio)")
+			.enable_all()
+			.build()
+			.unwrap()
+	}
+
+	/// Nodes can't forget responders, as [`NodeHandle`] has no way to ask them to.
+	fn control(interval_rx: tokio::sync::watch::Receiver<Duration>, query_rx: tokio::sync::watch::Receiver<()>) -> LoopControl {
+		LoopControl {
+			interval_rx: Some(interval_rx),
+			query_rx: Some(query_rx),
+			forget_rx: None,
+		}
+	}
+
+	async fn impl_run<F>(
+		self,
+		handler: F,
+		shutdown_rx: Option<tokio::sync::oneshot::Receiver<()>>,
+		mut control: LoopControl,
+	) -> Result<(), MultiIpIoError>
+	where
+		F: Fn(DiscoveryEvent) + Send + Sync + 'static,
+	{
+		let Node {
+			socket,
+			recipe,
+			broadcaster,
+			discovery,
+		} = self;
+
+		let handler = Arc::new(handler);
+		let reconnect = broadcaster.read().unwrap().reconnect().clone();
+
+		// Responders are remembered across sockets, so they aren't found again if the socket is recreated
+		let mut responder_memory = discovery.responder_memory();
+
+		let mut socket = socket.into_async().await?;
+
+		let run = async {
+			loop {
+				let (responses, recv) = DiscoveryRecv::channel(discovery.response_filter(), discovery.dropped_responses());
+
+				// The discovery loop never sees receive errors here, so it can only fail for good
+				let err = tokio::select! {
+					biased;
+					res = Discovery::discovery_loop(handler.clone(), &discovery, &socket, recv, &mut responder_memory, &mut control) => match res {
+						Ok(never) => match never {},
+						Err(LoopError::Recreate(err) | LoopError::Abort(err)) => return Err(err),
+					},
+					err = Self::recv_loop(&socket, &broadcaster, &reconnect, responses) => match err {
+						Some(LoopError::Recreate(err)) => err,
+						Some(LoopError::Abort(err)) => return Err(err),
+						None => return Ok(()),
+					},
+				};
+
+				logging::warn!("Receiving on mDNS socket keeps failing, recreating it: {err}");
+				socket = recipe.recreate(&reconnect).await;
+			}
+		};
+
+		let shutdown = async move {
+			if let Some(shutdown_rx) = shutdown_rx {
+				shutdown_rx.await
+			} else {
+				std::future::pending().await
+			}
+		};
+
+		tokio::select! {
+			biased;
+			res = run => res,
+			_ = shutdown => Ok(()),
+		}
+	}
+
+	/// Receives everything on the socket, answering queries and passing responses on to the discovery loop.
+	///
+	/// Returns `None` if the discovery loop has gone away, or the error if receiving keeps failing or an error is fatal according to the [`ReconnectPolicy`].
+	async fn recv_loop(
+		socket: &impl MdnsTransport,
+		broadcaster: &RwLock<BroadcasterConfig>,
+		reconnect: &ReconnectPolicy,
+		responses: ResponseSender,
+	) -> Option<LoopError> {
+		let mut errors = RecvErrors::new(reconnect);
+
+		let announcements = broadcaster.read().unwrap().announcements();
+		let mut rx = socket.recv(vec![0; 4096]);
+		let mut send_buf = vec![0u8; 4096];
+		let mut delayed = DelayedResponses::default();
+		loop {
+			let recv = tokio::select! {
+				recv = rx.recv_multicast() => recv,
+				_ = delayed.due() => {
+					if let Err(err) = delayed.send_due(socket, reconnect).await {
+						return Some(err);
+					}
+					continue;
+				}
+				_ = announcements.ready() => {
+					if let Err(err) = announcements.send(socket, reconnect).await {
+						return Some(err);
+					}
+					continue;
+				}
+			};
+
+			let ((count, addr), interface, packet) = match recv {
+				Ok(recv) => recv,
+				Err(err) => {
+					logging::warn!("Failed to receive on mDNS socket: {err}");
+					if let Some(stop) = errors.error().await {
+						return Some(stop(err));
+					}
+					continue;
+				}
+			};
+			errors.ok();
+			if count == 0 {
+				continue;
+			}
+
+			let message = match DnsMessage::from_bytes(&packet[..count]) {
+				Ok(message) => message,
+				Err(err) => {
+					Broadcaster::decode_error(broadcaster, addr, &packet[..count], &err);
+					continue;
+				}
+			};
+
+			match message.message_type() {
+				DnsMessageType::Query => {
+					if let Err(err) = Broadcaster::handle_query(socket, &mut send_buf, &mut delayed, broadcaster, &message, addr, interface).await {
+						return Some(err);
+					}
+				}
+
+				DnsMessageType::Response => {
+					if !responses.send(addr, &packet[..count], message) {
+						return None;
+					}
+				}
+			}
+		}
+	}
+}
+
+/// A handle to a [`Node`] that is running in the background.
+///
+/// You can use this handle to manage the broadcasted services, control discovery, and shut down the node remotely.
+#[must_use = "The node will shut down if the handle is dropped; store the handle somewhere or use `std::mem::forget` to keep it running"]
+pub struct NodeHandle {
+	broadcaster: BroadcasterHandle,
+	interval_tx: tokio::sync::watch::Sender<Duration>,
+	query_tx: tokio::sync::watch::Sender<()>,
+}
+impl NodeHandle {
+	#[inline(always)]
+	/// Returns the handle for managing the services that the node broadcasts.
+	pub fn broadcaster(&self) -> &BroadcasterHandle {
+		&self.broadcaster
+	}
+
+	/// Returns the traffic statistics of the node, such as how many packets were sent and received on each network interface.
+	///
+	/// See [`BroadcasterHandle::stats`].
+	pub fn stats(&self) -> Stats {
+		self.broadcaster.stats()
+	}
+
+	/// Changes how often discovery packets are sent.
+	///
+	/// See [`DiscoveryHandle::set_interval`](crate::discovery::DiscoveryHandle::set_interval).
+	///
+	/// # Panics
+	///
+	/// Panics if `interval` is zero.
+	pub fn set_interval(&self, interval: Duration) {
+		assert!(!interval.is_zero(), "Discovery interval must be non-zero");
+		self.interval_tx.send_replace(interval);
+	}
+
+	/// Sends a discovery packet right away, instead of waiting for the next interval.
+	///
+	/// See [`DiscoveryHandle::query_now`](crate::discovery::DiscoveryHandle::query_now).
+	pub fn query_now(&self) {
+		self.query_tx.send_replace(());
+	}
+
+	/// Shuts down the node if it is still running.
+	///
+	/// This function will block until the node has shut down, and will return an error if the shutdown failed, or the node encountered a fatal error during its lifetime.
+	pub fn shutdown(self) -> Result<(), ShutdownError> {
+		self.broadcaster.shutdown()
+	}
+
+	/// Shuts down the node if it is still running, without blocking the async runtime that this is awaited on.
+	///
+	/// See [`BroadcasterHandle::shutdown_async`].
+	///
+	/// # Panics
+	///
+	/// Panics if not called from within a Tokio runtime.
+	pub async fn shutdown_async(self) -> Result<(), ShutdownError> {
+		self.broadcaster.shutdown_async().await
+	}
+}
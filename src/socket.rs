@@ -1,16 +1,193 @@
 use crate::{
-	errors::MultiIpIoError,
-	net::{Ipv6Interface, MulticastSocketEx, TargetInterfaceV4, TargetInterfaceV6},
+	errors::{MultiIpIoError, SocketSetupError, SocketSetupStage},
+	logging,
+	net::{
+		recv_from_v4, recv_from_v6, set_dscp_v4, set_dscp_v6, set_recv_pktinfo_v4, set_recv_pktinfo_v6, ConfigureSocket, ErrorPolicy, IpVersion,
+		Ipv6Interface, MulticastSocketEx, ReconnectPolicy, RecvInterface, SocketOptions, TargetInterfaceV4, TargetInterfaceV6, TrafficCounters,
+	},
+	transport::{MdnsTransport, MdnsTransportRecv},
 	util::iface_v6_name_to_index,
 	MDNS_PORT, MDNS_V4_IP, MDNS_V6_IP,
 };
 use std::{
+	borrow::Cow,
 	collections::BTreeSet,
 	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, UdpSocket},
+	sync::Arc,
 	time::Duration,
 };
 use tokio::net::{ToSocketAddrs, UdpSocket as AsyncUdpSocket};
 
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MdnsSocketOptions {
+	pub loopback_v4: bool,
+	pub loopback_v6: bool,
+	pub exclude_link_local_v4: bool,
+	pub require_interface: bool,
+	pub also_broadcast: bool,
+	pub dual_stack_v6: bool,
+	pub strict_multicast: bool,
+	pub socket: SocketOptions,
+
+	/// The local port to bind to, if not the mDNS port. Packets are still sent to the mDNS port.
+	pub bind_port: Option<u16>,
+
+	/// Called on each socket after Searchlight has set its own options, before it joins the multicast group and is bound.
+	pub configure: Option<ConfigureSocket>,
+
+	/// Shared with every socket created from these options, so that counts survive the socket being recreated.
+	pub counters: Arc<TrafficCounters>,
+}
+impl MdnsSocketOptions {
+	#[inline]
+	/// The local port that sockets are bound to.
+	pub fn port(&self) -> u16 {
+		self.bind_port.unwrap_or(MDNS_PORT)
+	}
+}
+
+#[derive(Clone, Debug)]
+/// Everything needed to create an mDNS socket, so that it can be created again if it stops working.
+pub(crate) struct MdnsSocketRecipe {
+	pub options: MdnsSocketOptions,
+	pub ip_version: IpVersion,
+	pub interface_v4: TargetInterfaceV4,
+	pub interface_v6: TargetInterfaceV6,
+}
+impl MdnsSocketRecipe {
+	pub fn create(&self) -> Result<MdnsSocket, MultiIpIoError> {
+		MdnsSocket::with_ip_version(&self.options, self.ip_version, self.interface_v4.clone(), self.interface_v6.clone())
+	}
+
+	/// Creates the socket again, backing off as described by `policy` until it succeeds.
+	///
+	/// Creating the socket blocks (joining the multicast group is retried after a short sleep), so it's done on one of the runtime's blocking threads.
+	pub async fn recreate(&self, policy: &ReconnectPolicy) -> AsyncMdnsSocket {
+		let mut backoff = policy.backoff;
+		loop {
+			let recipe = self.clone();
+			let created = tokio::task::spawn_blocking(move || recipe.create())
+				.await
+				.unwrap_or_else(|err| Err(MultiIpIoError::IoError(std::io::Error::new(std::io::ErrorKind::Other, err))));
+
+			match created {
+				Ok(socket) => match socket.into_async().await {
+					Ok(socket) => return socket,
+					Err(err) => logging::warn!("Failed to recreate mDNS socket, retrying in {backoff:?}: {err}"),
+				},
+				Err(err) => logging::warn!("Failed to recreate mDNS socket, retrying in {backoff:?}: {err}"),
+			}
+
+			tokio::time::sleep(backoff).await;
+			backoff = policy.next_backoff(backoff);
+		}
+	}
+}
+
+#[derive(Debug)]
+/// Why a loop that receives on an mDNS socket stopped.
+pub(crate) enum LoopError {
+	/// Receiving keeps failing, so the socket should be recreated.
+	Recreate(MultiIpIoError),
+
+	/// The error is fatal, either by nature or because the [`ReconnectPolicy`] says to abort on it.
+	Abort(MultiIpIoError),
+}
+
+/// Counts consecutive receive errors, backing off once they are sustained so that a dead socket doesn't spin the CPU.
+pub(crate) struct RecvErrors<'a> {
+	policy: &'a ReconnectPolicy,
+	consecutive: u32,
+	backoff: Duration,
+}
+impl<'a> RecvErrors<'a> {
+	pub fn new(policy: &'a ReconnectPolicy) -> Self {
+		Self {
+			policy,
+			consecutive: 0,
+			backoff: policy.backoff,
+		}
+	}
+
+	#[inline]
+	/// Records a successful receive.
+	pub fn ok(&mut self) {
+		self.consecutive = 0;
+		self.backoff = self.policy.backoff;
+	}
+
+	/// Records a receive error, backing off if errors are sustained.
+	///
+	/// Returns how to wrap the error if the loop should stop, either to recreate the socket or to abort.
+	pub async fn error(&mut self) -> Option<fn(MultiIpIoError) -> LoopError> {
+		self.consecutive = self.consecutive.saturating_add(1);
+		if self.consecutive < self.policy.error_threshold {
+			return None;
+		}
+
+		if self.policy.recv_errors == ErrorPolicy::Abort {
+			return Some(LoopError::Abort);
+		}
+
+		tokio::time::sleep(self.backoff).await;
+		self.backoff = self.policy.next_backoff(self.backoff);
+
+		if self.policy.recreate_socket {
+			Some(LoopError::Recreate)
+		} else {
+			None
+		}
+	}
+}
+
+/// How many times to try joining the mDNS multicast group on an interface before giving up on it.
+const JOIN_ATTEMPTS: u32 = 3;
+
+/// Calls `join` until it succeeds, up to [`JOIN_ATTEMPTS`] times, sleeping for a short jittered delay between attempts.
+///
+/// Interfaces that have only just come up (for example right after a network change) can briefly reject joins, so they are given a few chances before being dropped.
+/// This blocks the current thread, so it mustn't be called from an async task; see [`MdnsSocketRecipe::recreate`].
+///
+/// Returns whether `join` succeeded.
+pub(crate) fn join_with_retry(mut join: impl FnMut() -> Result<(), std::io::Error>) -> bool {
+	for attempt in 1..=JOIN_ATTEMPTS {
+		match join() {
+			Ok(()) => return true,
+
+			Err(err) if attempt < JOIN_ATTEMPTS => {
+				let delay = join_retry_delay(attempt);
+				logging::debug!("Failed to join mDNS multicast group (attempt {attempt}/{JOIN_ATTEMPTS}), retrying in {delay:?}: {err}");
+				std::thread::sleep(delay);
+			}
+
+			Err(err) => logging::debug!("Failed to join mDNS multicast group, skipping interface: {err}"),
+		}
+	}
+	false
+}
+
+/// The delay before retrying a multicast join, which grows with each attempt and is jittered so that processes started together don't retry in lockstep.
+fn join_retry_delay(attempt: u32) -> Duration {
+	use std::hash::{BuildHasher, Hasher};
+
+	let jitter = std::collections::hash_map::RandomState::new().build_hasher().finish() % 25;
+	Duration::from_millis(25 * attempt as u64 + jitter)
+}
+
+/// Picks the IPv4 interfaces to join out of this machine's interfaces, when joining as many as possible (for `All` and `Filter`).
+pub(crate) fn usable_interfaces_v4(
+	ifaces: Vec<if_addrs::Interface>,
+	interface: &TargetInterfaceV4,
+	options: &MdnsSocketOptions,
+) -> BTreeSet<Ipv4Addr> {
+	ifaces
+		.into_iter()
+		.filter(|iface| !iface.is_loopback() && interface.accepts(iface))
+		.filter_map(|iface| if let IpAddr::V4(iface) = iface.addr.ip() { Some(iface) } else { None })
+		.filter(|iface| !(options.exclude_link_local_v4 && iface.is_link_local()))
+		.collect()
+}
+
 pub(crate) type AsyncMdnsSocket = MdnsSocket<AsyncUdpSocket>;
 pub(crate) enum MdnsSocket<Socket = UdpSocket> {
 	V4(InterfacedMdnsSocket<Socket, Ipv4Addr>),
@@ -18,74 +195,148 @@ pub(crate) enum MdnsSocket<Socket = UdpSocket> {
 	Multicol {
 		v4: InterfacedMdnsSocket<Socket, Ipv4Addr>,
 		v6: InterfacedMdnsSocket<Socket, Ipv6Interface>,
+
+		/// Whether multicasting must succeed on both sockets, rather than either of them.
+		strict: bool,
 	},
 }
 impl MdnsSocket<UdpSocket> {
-	pub fn new(loopback: bool, interface_v4: TargetInterfaceV4, interface_v6: TargetInterfaceV6) -> Result<Self, (std::io::Error, std::io::Error)> {
-		let v4 = Self::new_v4(loopback, interface_v4).map(|socket| match socket {
+	pub fn new(
+		options: &MdnsSocketOptions,
+		interface_v4: TargetInterfaceV4,
+		interface_v6: TargetInterfaceV6,
+	) -> Result<Self, (SocketSetupError, SocketSetupError)> {
+		let v4 = Self::new_v4(options, interface_v4).map(|socket| match socket {
 			MdnsSocket::V4(socket) => socket,
 			_ => unreachable!(),
 		});
 
-		let v6 = Self::new_v6(loopback, interface_v6).map(|socket| match socket {
+		// A dual-stack IPv6 socket would receive IPv4 traffic on the mDNS port too, so everything sent to the IPv4 socket would be received twice
+		let v6_options = if options.dual_stack_v6 {
+			logging::debug!("Ignoring dual-stack mode for the IPv6 mDNS socket, as an IPv4 socket is also in use");
+			Cow::Owned(MdnsSocketOptions {
+				dual_stack_v6: false,
+				..options.clone()
+			})
+		} else {
+			Cow::Borrowed(options)
+		};
+
+		let v6 = Self::new_v6(&v6_options, interface_v6).map(|socket| match socket {
 			MdnsSocket::V6(socket) => socket,
 			_ => unreachable!(),
 		});
 
 		match (v4, v6) {
-			(Ok(v4), Ok(v6)) => Ok(Self::Multicol { v4, v6 }),
+			(Ok(v4), Ok(v6)) => Ok(Self::Multicol {
+				v4,
+				v6,
+				strict: options.strict_multicast,
+			}),
 			(Err(v4), Err(v6)) => Err((v4, v6)),
-			(Ok(v4), Err(_)) => Ok(MdnsSocket::V4(v4)),
-			(Err(_), Ok(v6)) => Ok(MdnsSocket::V6(v6)),
+			(Ok(v4), Err(v6)) => {
+				logging::warn!("Failed to create IPv6 mDNS socket, falling back to IPv4 only: {v6}");
+				Ok(MdnsSocket::V4(v4))
+			}
+			(Err(v4), Ok(v6)) => {
+				logging::warn!("Failed to create IPv4 mDNS socket, falling back to IPv6 only: {v4}");
+				Ok(MdnsSocket::V6(v6))
+			}
 		}
 	}
 
-	pub fn new_v4(loopback: bool, interface: TargetInterfaceV4) -> Result<Self, std::io::Error> {
-		let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))?;
-		socket.set_read_timeout(Some(Duration::from_millis(100)))?;
-		socket.set_reuse_address(true)?;
-		socket.set_multicast_loop_v4(loopback)?;
+	/// Creates the socket(s) for the given IP version, as configured by a builder.
+	pub fn with_ip_version(
+		options: &MdnsSocketOptions,
+		ip_version: IpVersion,
+		interface_v4: TargetInterfaceV4,
+		interface_v6: TargetInterfaceV6,
+	) -> Result<Self, MultiIpIoError> {
+		match ip_version {
+			IpVersion::V4 => Self::new_v4(options, interface_v4).map_err(|err| MultiIpIoError::V4(err.into())),
+			IpVersion::V6 => Self::new_v6(options, interface_v6).map_err(|err| MultiIpIoError::V6(err.into())),
+			IpVersion::Both => Self::new(options, interface_v4, interface_v6).map_err(|(v4, v6)| MultiIpIoError::Both {
+				v4: v4.into(),
+				v6: v6.into(),
+			}),
+		}
+	}
+
+	pub fn new_v4(options: &MdnsSocketOptions, interface: TargetInterfaceV4) -> Result<Self, SocketSetupError> {
+		let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))
+			.map_err(SocketSetupError::map(SocketSetupStage::Create))?;
+		socket
+			.set_read_timeout(options.socket.read_timeout)
+			.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		socket
+			.set_reuse_address(true)
+			.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		socket
+			.set_multicast_loop_v4(options.loopback_v4)
+			.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		socket
+			.set_multicast_ttl_v4(options.socket.multicast_ttl)
+			.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		if let Some(size) = options.socket.recv_buffer_size {
+			socket
+				.set_recv_buffer_size(size)
+				.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		}
+		set_recv_pktinfo_v4(&socket).map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		if let Some(dscp) = options.socket.dscp {
+			set_dscp_v4(&socket, dscp).map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		}
+		if options.also_broadcast {
+			socket.set_broadcast(true).map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		}
 
 		#[cfg(unix)]
 		{
-			socket.set_reuse_port(true)?;
+			socket.set_reuse_port(true).map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		}
+
+		if let Some(ConfigureSocket(configure)) = &options.configure {
+			configure(&socket, IpVersion::V4).map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
 		}
 
 		let ifaces = match interface {
 			TargetInterfaceV4::Default => {
-				socket.join_multicast_v4(&MDNS_V4_IP, &Ipv4Addr::UNSPECIFIED)?;
+				socket
+					.join_multicast_v4(&MDNS_V4_IP, &Ipv4Addr::UNSPECIFIED)
+					.map_err(SocketSetupError::map(SocketSetupStage::Join))?;
 
 				BTreeSet::new()
 			}
 
 			TargetInterfaceV4::Specific(iface) => {
-				socket.join_multicast_v4(&MDNS_V4_IP, &iface)?;
+				socket
+					.join_multicast_v4(&MDNS_V4_IP, &iface)
+					.map_err(SocketSetupError::map(SocketSetupStage::Join))?;
 
 				BTreeSet::from_iter([iface])
 			}
 
 			TargetInterfaceV4::Multi(ifaces) => {
 				for iface in ifaces.iter() {
-					socket.join_multicast_v4(&MDNS_V4_IP, iface)?;
+					socket
+						.join_multicast_v4(&MDNS_V4_IP, iface)
+						.map_err(SocketSetupError::map(SocketSetupStage::Join))?;
 				}
 
 				ifaces
 			}
 
-			TargetInterfaceV4::All => {
+			TargetInterfaceV4::All | TargetInterfaceV4::Filter(_) => {
 				let mut all_interfaces = if_addrs::get_if_addrs()
-					.map(|ifaces| {
-						ifaces
-							.into_iter()
-							.filter(|iface| !iface.is_loopback())
-							.filter_map(|iface| if let IpAddr::V4(iface) = iface.addr.ip() { Some(iface) } else { None })
-							.collect::<BTreeSet<Ipv4Addr>>()
-					})
+					.map(|ifaces| usable_interfaces_v4(ifaces, &interface, options))
 					.unwrap_or_default();
 
 				let mut did_join = false;
 				all_interfaces.retain(|iface| {
-					if socket.set_multicast_if_v4(iface).is_ok() && socket.join_multicast_v4(&MDNS_V4_IP, iface).is_ok() {
+					if join_with_retry(|| {
+						socket.set_multicast_if_v4(iface)?;
+						socket.join_multicast_v4(&MDNS_V4_IP, iface)
+					}) {
 						did_join = true;
 						true
 					} else {
@@ -93,75 +344,124 @@ impl MdnsSocket<UdpSocket> {
 					}
 				});
 				if !did_join {
-					// Fallback to default
-					socket.join_multicast_v4(&MDNS_V4_IP, &Ipv4Addr::UNSPECIFIED)?;
+					if options.require_interface {
+						return Err(SocketSetupError {
+							stage: SocketSetupStage::RequireInterface,
+							source: std::io::Error::new(std::io::ErrorKind::AddrNotAvailable, "No usable IPv4 network interfaces"),
+						});
+					}
+
+					// Fallback to default, which may not work at all (e.g. if there's no network connection)
+					logging::warn!("No usable IPv4 network interfaces, falling back to the default interface; mDNS may not work");
+					socket
+						.join_multicast_v4(&MDNS_V4_IP, &Ipv4Addr::UNSPECIFIED)
+						.map_err(SocketSetupError::map(SocketSetupStage::Join))?;
 				}
 
 				all_interfaces
 			}
 		};
 
-		socket.bind(&socket2::SockAddr::from(SocketAddr::new(
-			IpAddr::V4(if cfg!(windows) && ifaces.len() == 1 {
-				*ifaces.iter().next().unwrap()
-			} else {
-				Ipv4Addr::UNSPECIFIED
-			}),
-			MDNS_PORT,
-		)))?;
+		socket
+			.bind(&socket2::SockAddr::from(SocketAddr::new(
+				IpAddr::V4(if cfg!(windows) && ifaces.len() == 1 {
+					*ifaces.iter().next().unwrap()
+				} else {
+					Ipv4Addr::UNSPECIFIED
+				}),
+				options.port(),
+			)))
+			.map_err(SocketSetupError::map(SocketSetupStage::Bind))?;
 
 		// Make sure the socket works
-		socket.set_multicast_if_v4(&Ipv4Addr::UNSPECIFIED)?; // Set to default interface
-		socket.send_to(&[0], &SocketAddrV4::new(MDNS_V4_IP, MDNS_PORT).into())?; // Send a multicast packet
+		socket
+			.set_multicast_if_v4(&Ipv4Addr::UNSPECIFIED)
+			.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?; // Set to default interface
+		socket
+			.send_to(&[0], &SocketAddrV4::new(MDNS_V4_IP, MDNS_PORT).into())
+			.map_err(SocketSetupError::map(SocketSetupStage::Probe))?; // Send a multicast packet
 
 		// If we're only using one interface, set it as the default
 		if ifaces.len() == 1 {
 			let addr = ifaces.iter().next().unwrap();
-			socket.set_multicast_if_v4(addr)?;
+			socket
+				.set_multicast_if_v4(addr)
+				.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
 		}
 
-		Ok(Self::V4(InterfacedMdnsSocket::new(socket.into(), ifaces)))
+		Ok(Self::V4(InterfacedMdnsSocket::new(socket.into(), ifaces, options.counters.clone())))
 	}
 
-	pub fn new_v6(loopback: bool, interface: TargetInterfaceV6) -> Result<Self, std::io::Error> {
-		let socket = socket2::Socket::new(socket2::Domain::IPV6, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))?;
-		socket.set_read_timeout(Some(Duration::from_millis(100)))?;
-		socket.set_reuse_address(true)?;
-		socket.set_only_v6(true)?;
-		socket.set_multicast_loop_v6(loopback)?;
+	pub fn new_v6(options: &MdnsSocketOptions, interface: TargetInterfaceV6) -> Result<Self, SocketSetupError> {
+		let socket = socket2::Socket::new(socket2::Domain::IPV6, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))
+			.map_err(SocketSetupError::map(SocketSetupStage::Create))?;
+		socket
+			.set_read_timeout(options.socket.read_timeout)
+			.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		socket
+			.set_reuse_address(true)
+			.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		socket
+			.set_only_v6(!options.dual_stack_v6)
+			.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		socket
+			.set_multicast_loop_v6(options.loopback_v6)
+			.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		socket
+			.set_multicast_hops_v6(options.socket.multicast_ttl)
+			.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		if let Some(size) = options.socket.recv_buffer_size {
+			socket
+				.set_recv_buffer_size(size)
+				.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		}
+		set_recv_pktinfo_v6(&socket).map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		if let Some(dscp) = options.socket.dscp {
+			set_dscp_v6(&socket, dscp).map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		}
 
 		#[cfg(unix)]
 		{
-			socket.set_reuse_port(true)?;
+			socket.set_reuse_port(true).map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
+		}
+
+		if let Some(ConfigureSocket(configure)) = &options.configure {
+			configure(&socket, IpVersion::V6).map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
 		}
 
 		let ifaces = match interface {
 			TargetInterfaceV6::Default => {
-				socket.join_multicast_v6(&MDNS_V6_IP, 0)?;
+				socket
+					.join_multicast_v6(&MDNS_V6_IP, 0)
+					.map_err(SocketSetupError::map(SocketSetupStage::Join))?;
 
 				BTreeSet::new()
 			}
 
 			TargetInterfaceV6::Specific(iface) => {
-				socket.join_multicast_v6(&MDNS_V6_IP, iface.as_u32())?;
+				socket
+					.join_multicast_v6(&MDNS_V6_IP, iface.as_u32())
+					.map_err(SocketSetupError::map(SocketSetupStage::Join))?;
 
 				BTreeSet::from_iter([iface])
 			}
 
 			TargetInterfaceV6::Multi(ifaces) => {
 				for iface in ifaces.iter() {
-					socket.join_multicast_v6(&MDNS_V6_IP, iface.as_u32())?;
+					socket
+						.join_multicast_v6(&MDNS_V6_IP, iface.as_u32())
+						.map_err(SocketSetupError::map(SocketSetupStage::Join))?;
 				}
 
 				ifaces
 			}
 
-			TargetInterfaceV6::All => {
+			TargetInterfaceV6::All | TargetInterfaceV6::Filter(_) => {
 				let mut all_interfaces = if_addrs::get_if_addrs()
 					.map(|ifaces| {
 						ifaces
 							.into_iter()
-							.filter(|iface| !iface.is_loopback() && iface.addr.ip().is_ipv6())
+							.filter(|iface| !iface.is_loopback() && iface.addr.ip().is_ipv6() && interface.accepts(iface))
 							.filter_map(|iface| iface_v6_name_to_index(&iface.name).ok().map(Ipv6Interface::from_raw))
 							.collect::<BTreeSet<_>>()
 					})
@@ -169,7 +469,10 @@ impl MdnsSocket<UdpSocket> {
 
 				let mut did_join = false;
 				all_interfaces.retain(|iface| {
-					if socket.set_multicast_if_v6(iface.as_u32()).is_ok() && socket.join_multicast_v6(&MDNS_V6_IP, iface.as_u32()).is_ok() {
+					if join_with_retry(|| {
+						socket.set_multicast_if_v6(iface.as_u32())?;
+						socket.join_multicast_v6(&MDNS_V6_IP, iface.as_u32())
+					}) {
 						did_join = true;
 						true
 					} else {
@@ -177,55 +480,90 @@ impl MdnsSocket<UdpSocket> {
 					}
 				});
 				if !did_join {
-					// Fallback to default
-					socket.join_multicast_v6(&MDNS_V6_IP, 0)?;
+					if options.require_interface {
+						return Err(SocketSetupError {
+							stage: SocketSetupStage::RequireInterface,
+							source: std::io::Error::new(std::io::ErrorKind::AddrNotAvailable, "No usable IPv6 network interfaces"),
+						});
+					}
+
+					// Fallback to default, which may not work at all (e.g. if there's no network connection)
+					logging::warn!("No usable IPv6 network interfaces, falling back to the default interface; mDNS may not work");
+					socket
+						.join_multicast_v6(&MDNS_V6_IP, 0)
+						.map_err(SocketSetupError::map(SocketSetupStage::Join))?;
 				}
 
 				all_interfaces
 			}
 		};
 
-		socket.bind(&socket2::SockAddr::from(SocketAddr::new(
-			IpAddr::V6({
-				let mut bind_addr = Ipv6Addr::UNSPECIFIED;
-				if cfg!(windows) && ifaces.len() == 1 {
-					let iface = ifaces.iter().next().unwrap();
-					let addrs = iface.addrs()?;
-					if addrs.len() == 1 {
-						bind_addr = addrs.into_iter().next().unwrap();
+		socket
+			.bind(&socket2::SockAddr::from(SocketAddr::new(
+				IpAddr::V6({
+					let mut bind_addr = Ipv6Addr::UNSPECIFIED;
+					if cfg!(windows) && ifaces.len() == 1 {
+						let iface = ifaces.iter().next().unwrap();
+						let addrs = iface.addrs().map_err(SocketSetupError::map(SocketSetupStage::InterfaceLookup))?;
+						if addrs.len() == 1 {
+							bind_addr = addrs.into_iter().next().unwrap();
+						}
 					}
-				}
-				bind_addr
-			}),
-			MDNS_PORT,
-		)))?;
+					bind_addr
+				}),
+				options.port(),
+			)))
+			.map_err(SocketSetupError::map(SocketSetupStage::Bind))?;
 
 		// Make sure the socket works
-		socket.set_multicast_if_v6(0)?; // Set to default interface
-		socket.send_to(&[0], &SocketAddr::new(IpAddr::V6(MDNS_V6_IP), MDNS_PORT).into())?; // Send a multicast packet
+		socket
+			.set_multicast_if_v6(0)
+			.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?; // Set to default interface
+		socket
+			.send_to(&[0], &SocketAddr::new(IpAddr::V6(MDNS_V6_IP), MDNS_PORT).into())
+			.map_err(SocketSetupError::map(SocketSetupStage::Probe))?; // Send a multicast packet
 
 		// If we're only using one interface, set it as the default
 		if ifaces.len() == 1 {
 			let iface = ifaces.iter().next().unwrap();
-			socket.set_multicast_if_v6(iface.as_u32())?;
+			socket
+				.set_multicast_if_v6(iface.as_u32())
+				.map_err(SocketSetupError::map(SocketSetupStage::SetOption))?;
 		}
 
-		Ok(Self::V6(InterfacedMdnsSocket::new(socket.into(), ifaces)))
+		Ok(Self::V6(InterfacedMdnsSocket::new(socket.into(), ifaces, options.counters.clone())))
+	}
+
+	/// The local addresses of the socket(s), IPv4 first.
+	pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, MultiIpIoError> {
+		match self {
+			Self::V4(v4) => Ok(vec![v4.socket().local_addr().map_err(MultiIpIoError::V4)?]),
+			Self::V6(v6) => Ok(vec![v6.socket().local_addr().map_err(MultiIpIoError::V6)?]),
+			Self::Multicol { v4, v6, .. } => match (v4.socket().local_addr(), v6.socket().local_addr()) {
+				(Ok(v4), Ok(v6)) => Ok(vec![v4, v6]),
+				(Err(v4), Err(v6)) => Err(MultiIpIoError::Both { v4, v6 }),
+				(Err(v4), Ok(_)) => Err(MultiIpIoError::V4(v4)),
+				(Ok(_), Err(v6)) => Err(MultiIpIoError::V6(v6)),
+			},
+		}
 	}
 
 	pub async fn into_async(self) -> Result<AsyncMdnsSocket, MultiIpIoError> {
 		Ok(match self {
 			Self::V4(v4) => AsyncMdnsSocket::V4(v4.into_async().map_err(MultiIpIoError::V4)?),
 			Self::V6(v6) => AsyncMdnsSocket::V6(v6.into_async().map_err(MultiIpIoError::V6)?),
-			Self::Multicol { v4, v6 } => AsyncMdnsSocket::Multicol {
+			Self::Multicol { v4, v6, strict } => AsyncMdnsSocket::Multicol {
 				v4: v4.into_async().map_err(MultiIpIoError::V4)?,
 				v6: v6.into_async().map_err(MultiIpIoError::V6)?,
+				strict,
 			},
 		})
 	}
 }
-impl AsyncMdnsSocket {
-	pub async fn send_to(&self, packet: &[u8], addr: SocketAddr) -> Result<(), MultiIpIoError> {
+impl MdnsTransport for AsyncMdnsSocket {
+	type Recv<'a> = MdnsSocketRecv<'a>;
+
+	async fn send_to(&self, packet: &[u8], addr: SocketAddr) -> Result<(), MultiIpIoError> {
 		match (addr, self) {
 			(SocketAddr::V4(addr), Self::V4(v4) | Self::Multicol { v4, .. }) => v4.send_to(packet, addr).await.map_err(MultiIpIoError::V4),
 			(SocketAddr::V6(addr), Self::V6(v6) | Self::Multicol { v6, .. }) => v6.send_to(packet, addr).await.map_err(MultiIpIoError::V6),
@@ -242,44 +580,54 @@ impl AsyncMdnsSocket {
 		}
 	}
 
-	pub async fn send_multicast(&self, packet: &[u8]) -> Result<(), MultiIpIoError> {
+	async fn send_multicast(&self, packet: &[u8]) -> Result<(), MultiIpIoError> {
 		match self {
-			Self::V4(v4) => v4
-				.send_to_multicast(packet, SocketAddrV4::new(MDNS_V4_IP, MDNS_PORT))
-				.await
-				.map_err(MultiIpIoError::V4),
+			Self::V4(v4) => v4.send_to_mdns_v4(packet).await.map_err(MultiIpIoError::V4),
 
 			Self::V6(v6) => v6
 				.send_to_multicast(packet, SocketAddr::new(IpAddr::V6(MDNS_V6_IP), MDNS_PORT))
 				.await
 				.map_err(MultiIpIoError::V6),
 
-			Self::Multicol { v4, v6 } => {
-				let v4 = v4.send_to_multicast(packet, SocketAddrV4::new(MDNS_V4_IP, MDNS_PORT));
+			Self::Multicol { v4, v6, strict } => {
+				let v4 = v4.send_to_mdns_v4(packet);
 				let v6 = v6.send_to_multicast(packet, SocketAddr::new(IpAddr::V6(MDNS_V6_IP), MDNS_PORT));
-				match tokio::join!(v4, v6) {
-					(Ok(_), _) | (_, Ok(_)) => Ok(()),
-					(Err(v4), Err(v6)) => Err(MultiIpIoError::Both { v4, v6 }),
-				}
+				let (v4, v6) = tokio::join!(v4, v6);
+				multicol_send_result(*strict, v4, v6)
 			}
 		}
 	}
 
-	pub fn recv(&self, buffer: Vec<u8>) -> MdnsSocketRecv {
+	#[cfg(feature = "broadcast")]
+	async fn send_multicast_split_v6(&self, packet: &[u8], packets_v6: &[Vec<u8>]) -> Result<(), MultiIpIoError> {
+		async fn send_all_v6(v6: &InterfacedMdnsSocket<AsyncUdpSocket, Ipv6Interface>, packets: &[Vec<u8>]) -> Result<(), std::io::Error> {
+			for packet in packets {
+				v6.send_to_multicast(packet, SocketAddr::new(IpAddr::V6(MDNS_V6_IP), MDNS_PORT)).await?;
+			}
+			Ok(())
+		}
+
 		match self {
-			#[rustfmt::skip]
-			Self::V4(InterfacedMdnsSocket::UniInterface(socket) | InterfacedMdnsSocket::MultiInterface { socket, .. }) => {
-				MdnsSocketRecv::V4(socket, buffer)
-			},
+			Self::V4(v4) => v4.send_to_mdns_v4(packet).await.map_err(MultiIpIoError::V4),
 
-			Self::V6(InterfacedMdnsSocket::UniInterface(socket) | InterfacedMdnsSocket::MultiInterface { socket, .. }) => {
-				MdnsSocketRecv::V6(socket, buffer)
+			Self::V6(v6) => send_all_v6(v6, packets_v6).await.map_err(MultiIpIoError::V6),
+
+			Self::Multicol { v4, v6, strict } => {
+				let v4 = v4.send_to_mdns_v4(packet);
+				let v6 = send_all_v6(v6, packets_v6);
+				let (v4, v6) = tokio::join!(v4, v6);
+				multicol_send_result(*strict, v4, v6)
 			}
+		}
+	}
 
-			Self::Multicol {
-				v4: InterfacedMdnsSocket::UniInterface(v4) | InterfacedMdnsSocket::MultiInterface { socket: v4, .. },
-				v6: InterfacedMdnsSocket::UniInterface(v6) | InterfacedMdnsSocket::MultiInterface { socket: v6, .. },
-			} => MdnsSocketRecv::Multicol {
+	fn recv(&self, buffer: Vec<u8>) -> MdnsSocketRecv<'_> {
+		match self {
+			Self::V4(socket) => MdnsSocketRecv::V4(socket, buffer),
+
+			Self::V6(socket) => MdnsSocketRecv::V6(socket, buffer),
+
+			Self::Multicol { v4, v6, .. } => MdnsSocketRecv::Multicol {
 				v4: (v4, buffer.clone()),
 				v6: (v6, buffer),
 			},
@@ -287,25 +635,53 @@ impl AsyncMdnsSocket {
 	}
 }
 
-pub enum MdnsSocketRecv<'a> {
-	V4(&'a AsyncUdpSocket, Vec<u8>),
-	V6(&'a AsyncUdpSocket, Vec<u8>),
+/// Combines the results of multicasting on both sockets.
+///
+/// Normally, multicasting only fails if it failed on both sockets. In `strict` mode, it fails if it failed on either of them.
+pub(crate) fn multicol_send_result(strict: bool, v4: Result<(), std::io::Error>, v6: Result<(), std::io::Error>) -> Result<(), MultiIpIoError> {
+	match (v4, v6) {
+		(Ok(()), Ok(())) => Ok(()),
+		(Err(v4), Err(v6)) => Err(MultiIpIoError::Both { v4, v6 }),
+
+		(Err(v4), Ok(())) if strict => Err(MultiIpIoError::V4(v4)),
+		(Ok(()), Err(v6)) if strict => Err(MultiIpIoError::V6(v6)),
+
+		(Err(err), Ok(())) => {
+			logging::debug!("Failed to multicast mDNS packet over IPv4, but IPv6 succeeded: {err}");
+			Ok(())
+		}
+		(Ok(()), Err(err)) => {
+			logging::debug!("Failed to multicast mDNS packet over IPv6, but IPv4 succeeded: {err}");
+			Ok(())
+		}
+	}
+}
+
+pub(crate) enum MdnsSocketRecv<'a> {
+	V4(&'a InterfacedMdnsSocket<AsyncUdpSocket, Ipv4Addr>, Vec<u8>),
+	V6(&'a InterfacedMdnsSocket<AsyncUdpSocket, Ipv6Interface>, Vec<u8>),
 	Multicol {
-		v4: (&'a AsyncUdpSocket, Vec<u8>),
-		v6: (&'a AsyncUdpSocket, Vec<u8>),
+		v4: (&'a InterfacedMdnsSocket<AsyncUdpSocket, Ipv4Addr>, Vec<u8>),
+		v6: (&'a InterfacedMdnsSocket<AsyncUdpSocket, Ipv6Interface>, Vec<u8>),
 	},
 }
-impl MdnsSocketRecv<'_> {
-	pub async fn recv_multicast(&mut self) -> Result<((usize, SocketAddr), &[u8]), MultiIpIoError> {
+impl MdnsTransportRecv for MdnsSocketRecv<'_> {
+	async fn recv_multicast(&mut self) -> Result<((usize, SocketAddr), Option<RecvInterface>, &[u8]), MultiIpIoError> {
 		match self {
-			Self::V4(socket, buf) => Ok((socket.recv_from(buf).await.map_err(MultiIpIoError::V4)?, buf)),
-			Self::V6(socket, buf) => Ok((socket.recv_from(buf).await.map_err(MultiIpIoError::V6)?, buf)),
+			Self::V4(socket, buf) => {
+				let (recv, interface) = socket.recv_from(buf).await.map_err(MultiIpIoError::V4)?;
+				Ok((recv, interface, buf))
+			}
+			Self::V6(socket, buf) => {
+				let (recv, interface) = socket.recv_from(buf).await.map_err(MultiIpIoError::V6)?;
+				Ok((recv, interface, buf))
+			}
 			Self::Multicol {
 				v4: (v4, buf_v4),
 				v6: (v6, buf_v6),
 			} => {
-				let v4 = async { v4.recv_from(buf_v4).await.map(|recv| (recv, &**buf_v4)) };
-				let v6 = async { v6.recv_from(buf_v6).await.map(|recv| (recv, &**buf_v6)) };
+				let v4 = async { v4.recv_from(buf_v4).await.map(|(recv, interface)| (recv, interface, &**buf_v4)) };
+				let v6 = async { v6.recv_from(buf_v6).await.map(|(recv, interface)| (recv, interface, &**buf_v6)) };
 				tokio::pin!(v4);
 				tokio::pin!(v6);
 				tokio::select! {
@@ -332,22 +708,30 @@ impl MdnsSocketRecv<'_> {
 	}
 }
 
-pub(crate) enum InterfacedMdnsSocket<Socket, Iface>
+/// An mDNS socket, along with the interfaces it joined the multicast group on.
+pub(crate) struct InterfacedMdnsSocket<Socket, Iface>
 where
 	Iface: PartialEq + Eq + PartialOrd + Ord + Copy,
 {
-	UniInterface(Socket),
-	MultiInterface { socket: Socket, ifaces: BTreeSet<Iface> },
+	socket: Socket,
+
+	/// If there's more than one interface, multicast packets are sent on each of them in turn; otherwise, the socket's default interface is used.
+	ifaces: BTreeSet<Iface>,
+
+	/// Packets sent and received on each interface.
+	counters: Arc<TrafficCounters>,
 }
 impl<Socket, Iface> InterfacedMdnsSocket<Socket, Iface>
 where
 	Iface: PartialEq + Eq + PartialOrd + Ord + Copy,
 {
-	fn new(socket: Socket, ifaces: BTreeSet<Iface>) -> Self {
-		match ifaces.len() {
-			0 | 1 => Self::UniInterface(socket),
-			_ => Self::MultiInterface { socket, ifaces },
-		}
+	pub(crate) fn new(socket: Socket, ifaces: BTreeSet<Iface>, counters: Arc<TrafficCounters>) -> Self {
+		Self { socket, ifaces, counters }
+	}
+
+	#[inline]
+	fn socket(&self) -> &Socket {
+		&self.socket
 	}
 }
 impl<Iface> InterfacedMdnsSocket<UdpSocket, Iface>
@@ -355,53 +739,100 @@ where
 	Iface: PartialEq + Eq + PartialOrd + Ord + Copy,
 {
 	fn into_async(self) -> Result<InterfacedMdnsSocket<AsyncUdpSocket, Iface>, std::io::Error> {
-		Ok(match self {
-			Self::UniInterface(socket) => {
-				socket.set_nonblocking(true)?;
-				InterfacedMdnsSocket::UniInterface(AsyncUdpSocket::from_std(socket)?)
-			}
+		let InterfacedMdnsSocket { socket, ifaces, counters } = self;
 
-			Self::MultiInterface { socket, ifaces } => InterfacedMdnsSocket::MultiInterface {
-				socket: {
-					socket.set_nonblocking(true)?;
-					AsyncUdpSocket::from_std(socket)?
-				},
+		socket.set_nonblocking(true)?;
 
-				ifaces,
-			},
+		Ok(InterfacedMdnsSocket {
+			socket: AsyncUdpSocket::from_std(socket)?,
+			ifaces,
+			counters,
 		})
 	}
 }
 impl<Iface> InterfacedMdnsSocket<AsyncUdpSocket, Iface>
 where
 	AsyncUdpSocket: MulticastSocketEx<Iface>,
-	Iface: PartialEq + Eq + PartialOrd + Ord + Copy + std::fmt::Debug,
+	Iface: PartialEq + Eq + PartialOrd + Ord + Copy + std::fmt::Debug + Into<RecvInterface>,
 {
 	pub async fn send_to(&self, packet: &[u8], addr: impl ToSocketAddrs + Copy) -> Result<(), std::io::Error> {
-		let socket = match self {
-			Self::UniInterface(socket) => socket,
-			Self::MultiInterface { socket, .. } => socket,
-		};
-
-		socket.send_to(packet, addr).await.map(|_| ())
+		self.socket.send_to(packet, addr).await.map(|_| ())
 	}
 
 	pub async fn send_to_multicast(&self, packet: &[u8], multicast_addr: impl ToSocketAddrs + Copy) -> Result<(), std::io::Error> {
-		match self {
-			Self::UniInterface(socket) => {
-				socket.send_to(packet, multicast_addr).await?;
+		if self.ifaces.len() <= 1 {
+			self.socket.send_to(packet, multicast_addr).await?;
+
+			// If the socket's default interface was chosen by the OS, we don't know which one it is
+			if let Some(iface) = self.ifaces.iter().next() {
+				self.counters.sent((*iface).into());
 			}
 
-			Self::MultiInterface { socket, ifaces } => {
-				debug_assert!(ifaces.len() > 1);
+			return Ok(());
+		}
 
-				for iface in ifaces.iter().copied() {
-					socket.set_multicast_if(iface)?;
-					socket.send_to(packet, multicast_addr).await?;
+		// One bad interface shouldn't stop multicast on the others, so this only fails if sending failed on every interface
+		let mut sent = false;
+		let mut last_err = None;
+		for iface in self.ifaces.iter().copied() {
+			let res = match self.socket.set_multicast_if(iface) {
+				Ok(()) => self.socket.send_to(packet, multicast_addr).await.map(|_| ()),
+				Err(err) => Err(err),
+			};
+
+			match res {
+				Ok(()) => {
+					logging::trace!("Sent mDNS multicast packet on interface {iface:?}");
+					self.counters.sent(iface.into());
+					sent = true;
 				}
+
+				Err(err) => {
+					logging::warn!("Failed to send mDNS multicast packet on interface {iface:?}: {err}");
+					last_err = Some(err);
+				}
+			}
+		}
+
+		if let (false, Some(err)) = (sent, last_err) {
+			return Err(err);
+		}
+
+		Ok(())
+	}
+
+	/// Counts a received packet against the interface it was received on, if known.
+	fn received(&self, interface: Option<RecvInterface>) {
+		if let Some(interface) = interface {
+			self.counters.received(interface);
+		}
+	}
+}
+impl InterfacedMdnsSocket<AsyncUdpSocket, Ipv4Addr> {
+	/// Sends a packet to the IPv4 mDNS multicast group, and to the limited broadcast address if the socket was created with `also_broadcast`.
+	async fn send_to_mdns_v4(&self, packet: &[u8]) -> Result<(), std::io::Error> {
+		self.send_to_multicast(packet, SocketAddrV4::new(MDNS_V4_IP, MDNS_PORT)).await?;
+
+		// Broadcasting is only a best-effort fallback, so it never fails the send
+		if self.socket().broadcast().unwrap_or(false) {
+			if let Err(err) = self.socket().send_to(packet, SocketAddrV4::new(Ipv4Addr::BROADCAST, MDNS_PORT)).await {
+				logging::debug!("Failed to broadcast mDNS packet: {err}");
 			}
 		}
 
 		Ok(())
 	}
+
+	async fn recv_from(&self, buf: &mut [u8]) -> Result<((usize, SocketAddr), Option<RecvInterface>), std::io::Error> {
+		let (recv, interface) = recv_from_v4(&self.socket, buf).await?;
+		self.received(interface);
+		Ok((recv, interface))
+	}
+}
+impl InterfacedMdnsSocket<AsyncUdpSocket, Ipv6Interface> {
+	async fn recv_from(&self, buf: &mut [u8]) -> Result<((usize, SocketAddr), Option<RecvInterface>), std::io::Error> {
+		let (recv, interface) = recv_from_v6(&self.socket, buf).await?;
+		self.received(interface);
+		Ok((recv, interface))
+	}
 }
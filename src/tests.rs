@@ -5,6 +5,9 @@ use std::{
 };
 use trust_dns_client::serialize::binary::{BinEncodable, BinEncoder};
 
+#[cfg(feature = "discovery")]
+mod harness;
+
 #[test]
 fn test_dns_parser_backwards_compatibility() {
 	let dns_message = ServiceBuilder::new("_venner-test._udp.local", "helloworld", 1337)
@@ -32,3 +35,4654 @@ fn test_readme_version() {
 	let version = env!("CARGO_PKG_VERSION");
 	assert!(readme.contains(format!("searchlight = \"{}\"", version).as_str()));
 }
+
+#[test]
+fn test_bad_dns_name_field() {
+	let err = ServiceBuilder::new("_searchlight-test._udp.local", "bad..name", 1337).err().unwrap();
+	assert_eq!(err.field, crate::errors::NameField::ServiceName);
+}
+
+#[test]
+fn test_extra_records() {
+	use trust_dns_client::rr::{rdata::SRV, DNSClass, Name, RData, Record, RecordType};
+
+	let mut extra = Record::new();
+	extra
+		.set_name(Name::from_str("helloworld._venner-test._udp.local.").unwrap())
+		.set_dns_class(DNSClass::IN)
+		.set_rr_type(RecordType::SRV)
+		.set_data(Some(RData::SRV(SRV::new(1, 0, 1338, Name::from_str("helloworld.local.").unwrap()))))
+		.set_ttl(120);
+
+	let dns_message = ServiceBuilder::new("_venner-test._udp.local", "helloworld", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.add_extra_record(extra.clone())
+		.build()
+		.unwrap()
+		.dns_response()
+		.unwrap();
+
+	assert_eq!(dns_message.additional_count() as usize, dns_message.additionals().len());
+	assert_eq!(dns_message.additionals().last(), Some(&extra));
+}
+
+#[test]
+fn test_reverse_lookup_response() {
+	use trust_dns_client::rr::{Name, RData};
+
+	let addr = IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap());
+	let service = ServiceBuilder::new("_venner-test._udp.local", "helloworld", 1337)
+		.unwrap()
+		.add_ip_address(addr)
+		.build()
+		.unwrap();
+
+	let dns_message = service.reverse_lookup_response(addr);
+	let answer = &dns_message.answers()[0];
+	assert_eq!(*answer.name(), Name::from_str("69.1.168.192.in-addr.arpa.").unwrap());
+	assert_eq!(answer.data(), Some(&RData::PTR(Name::from_str("helloworld.local.").unwrap())));
+}
+
+#[test]
+fn test_strict_service_type() {
+	use crate::errors::NameField;
+
+	let strict = |service_type: &str| ServiceBuilder::new(service_type, "searchlighttest", 1337).unwrap().strict();
+
+	assert!(strict("_http._tcp.local.").is_ok());
+	assert!(strict("_searchlight._UDP.local").is_ok());
+
+	for service_type in [
+		"_http._tpc.local.",
+		"http._tcp.local.",
+		"_._tcp.local.",
+		"_http._tcp.",
+		"_searchlight-service._udp.local.",
+	] {
+		let err = strict(service_type).unwrap_err();
+		assert_eq!(err.field, NameField::ServiceType, "{service_type}");
+	}
+}
+
+#[test]
+fn test_service_id_and_hostname() {
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.build()
+		.unwrap();
+
+	assert_eq!(service.service_id().to_string(), "searchlighttest._searchlight-test._udp.local.");
+	assert_eq!(service.hostname().to_string(), "searchlighttest.local.");
+}
+
+#[test]
+fn test_txt_record() {
+	use crate::broadcast::{errors::TxtRecordError, TxtRecord};
+
+	let txt = TxtRecord::new().insert("key", "value").unwrap().insert_bool("flag").unwrap();
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.txt_record(txt)
+		.build()
+		.unwrap();
+	assert_eq!(service.txt(), &[b"key=value".as_slice(), b"flag".as_slice()]);
+
+	assert!(matches!(TxtRecord::new().insert("", "value"), Err(TxtRecordError::EmptyKey)));
+	assert!(matches!(TxtRecord::new().insert("k=y", "value"), Err(TxtRecordError::InvalidKey(_))));
+	assert!(matches!(TxtRecord::new().insert("kéy", "value"), Err(TxtRecordError::InvalidKey(_))));
+	assert!(matches!(
+		TxtRecord::new().insert("key", "value").unwrap().insert_bool("KEY"),
+		Err(TxtRecordError::DuplicateKey(_))
+	));
+	assert!(matches!(TxtRecord::new().insert("key", [0; 255]), Err(TxtRecordError::RecordTooLong(_))));
+}
+
+#[test]
+fn test_response_too_large() {
+	use crate::broadcast::errors::ServiceBuilderError;
+
+	let builder = (0..40).fold(
+		ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+			.unwrap()
+			.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap())),
+		|builder, _| builder.add_txt("a".repeat(255)),
+	);
+
+	match builder.clone().build() {
+		Err(ServiceBuilderError::ResponseTooLarge { estimated, max }) => {
+			assert!(estimated > 40 * 255);
+			assert_eq!(max, 9000);
+		}
+		res => panic!("{res:?}"),
+	}
+
+	assert!(builder.max_response_size(20000).build().is_ok());
+}
+
+#[test]
+fn test_combined_response_shared_host() {
+	use crate::broadcast::{Host, Service};
+	use std::sync::Arc;
+	use trust_dns_client::rr::RecordType;
+
+	let host = Arc::new(
+		Host::new("searchlighttest.local")
+			.unwrap()
+			.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+			.add_ip_address(IpAddr::V6(Ipv6Addr::from_str("fe80::18e4:b943:8756:d855").unwrap())),
+	);
+
+	let http = ServiceBuilder::new("_http._tcp.local", "searchlighttest", 80)
+		.unwrap()
+		.host(host.clone())
+		.build()
+		.unwrap();
+
+	let ipp = ServiceBuilder::new("_ipp._tcp.local", "searchlighttest", 631)
+		.unwrap()
+		.host(host.clone())
+		.build()
+		.unwrap();
+
+	assert!(Arc::ptr_eq(http.host(), ipp.host()));
+
+	let dns_message = Service::combined_dns_response([&http, &ipp]).unwrap();
+	assert_eq!(dns_message.answers().len(), 2);
+	assert_eq!(dns_message.additional_count() as usize, dns_message.additionals().len());
+	assert_eq!(
+		dns_message
+			.additionals()
+			.iter()
+			.filter(|record| matches!(record.record_type(), RecordType::A | RecordType::AAAA))
+			.count(),
+		2
+	);
+}
+
+#[test]
+fn test_batched_responses() {
+	use crate::broadcast::{Host, Service};
+	use std::sync::Arc;
+	use trust_dns_client::{
+		op::Message,
+		rr::RecordType,
+		serialize::binary::{BinDecodable, BinEncodable},
+	};
+
+	let host = Arc::new(
+		Host::new("searchlighttest.local")
+			.unwrap()
+			.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap())),
+	);
+
+	let services = (0..20)
+		.map(|i| {
+			ServiceBuilder::new("_searchlight-batch._udp.local", format!("searchlighttest{i}"), 1337)
+				.unwrap()
+				.host(host.clone())
+				.add_txt("a".repeat(100))
+				.build()
+				.unwrap()
+		})
+		.collect::<Vec<_>>();
+	let services = services.iter().collect::<Vec<_>>();
+
+	// Everything fits in one packet if it's big enough
+	assert_eq!(Service::batched_dns_responses(&services, usize::MAX).unwrap().len(), 1);
+
+	let max_len = 1000;
+	let responses = Service::batched_dns_responses(&services, max_len).unwrap();
+	assert!(responses.len() > 1);
+
+	let mut answered = 0;
+	for response in responses {
+		let bytes = response.to_bytes().unwrap();
+		assert!(bytes.len() <= max_len);
+
+		let response = Message::from_bytes(&bytes).unwrap();
+		assert_eq!(response.additional_count() as usize, response.additionals().len());
+
+		// Each packet is a complete answer for its services, including the host's address
+		let count = |record_type| response.additionals().iter().filter(|record| record.record_type() == record_type).count();
+		assert_eq!(count(RecordType::SRV), response.answers().len());
+		assert_eq!(count(RecordType::TXT), response.answers().len());
+		assert_eq!(count(RecordType::A), 1);
+
+		answered += response.answers().len();
+	}
+	assert_eq!(answered, services.len());
+}
+
+#[test]
+fn test_ip_address_order() {
+	use trust_dns_client::rr::RData;
+
+	let ethernet = IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap());
+	let wifi = IpAddr::V4(Ipv4Addr::from_str("192.168.0.69").unwrap());
+	let link_local = IpAddr::V6(Ipv6Addr::from_str("fe80::18e4:b943:8756:d855").unwrap());
+
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(ethernet)
+		.add_ip_address(link_local)
+		.add_ip_address(wifi)
+		.add_ip_address(ethernet)
+		.build()
+		.unwrap();
+
+	// Addresses keep the order they were added in, without duplicates
+	assert_eq!(service.ip_addresses(), [ethernet, link_local, wifi]);
+
+	let advertised = service
+		.dns_response()
+		.unwrap()
+		.additionals()
+		.iter()
+		.filter_map(|record| match record.data() {
+			Some(RData::A(addr)) => Some(IpAddr::V4(*addr)),
+			Some(RData::AAAA(addr)) => Some(IpAddr::V6(*addr)),
+			_ => None,
+		})
+		.collect::<Vec<_>>();
+	assert_eq!(advertised, [ethernet, link_local, wifi]);
+}
+
+#[test]
+fn test_service_from_response() {
+	use crate::broadcast::Service;
+
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.ttl(4500)
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.add_ip_address(IpAddr::V6(Ipv6Addr::from_str("fe80::1").unwrap()))
+		.add_txt("key=value")
+		.add_txt("flag")
+		.build()
+		.unwrap();
+
+	let rebuilt = Service::from_response(&service.dns_response().unwrap()).unwrap();
+	assert_eq!(rebuilt.service_type(), service.service_type());
+	assert_eq!(rebuilt.service_name(), service.service_name());
+	assert_eq!(rebuilt.service_id(), service.service_id());
+	assert_eq!(rebuilt.hostname(), service.hostname());
+	assert_eq!(rebuilt.port(), service.port());
+	assert_eq!(rebuilt.ttl(), service.ttl());
+	assert_eq!(rebuilt.ip_addresses(), service.ip_addresses());
+	assert_eq!(rebuilt.txt(), service.txt());
+	assert_eq!(rebuilt.dns_response().unwrap(), service.dns_response().unwrap());
+
+	// Without an SRV record, there's nothing to go on
+	let mut response = service.dns_response().unwrap();
+	response.take_additionals();
+	assert!(matches!(
+		Service::from_response(&response),
+		Err(crate::broadcast::errors::ServiceFromResponseError::MissingSrvRecord)
+	));
+}
+
+#[test]
+fn test_srv_target() {
+	use trust_dns_client::rr::RData;
+
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.srv_target("alias.local")
+		.unwrap()
+		.build()
+		.unwrap();
+
+	let dns_message = service.dns_response().unwrap();
+	let srv = dns_message
+		.additionals()
+		.iter()
+		.find_map(|record| match record.data() {
+			Some(RData::SRV(srv)) => Some(srv),
+			_ => None,
+		})
+		.unwrap();
+	assert_eq!(srv.target().to_string(), "alias.local.");
+	assert!(dns_message
+		.additionals()
+		.iter()
+		.filter(|record| matches!(record.data(), Some(RData::A(_))))
+		.all(|record| record.name() == service.hostname()));
+}
+
+#[test]
+fn test_additional_addresses() {
+	use trust_dns_client::{
+		op::Message,
+		rr::{Name, RData},
+		serialize::binary::BinDecodable,
+	};
+
+	let alias_ip = Ipv4Addr::from_str("192.168.1.70").unwrap();
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.srv_target("alias.local")
+		.unwrap()
+		.add_additional_address(IpAddr::V4(alias_ip), "alias.local")
+		.unwrap()
+		.add_additional_address(IpAddr::V4(alias_ip), "alias.local")
+		.unwrap()
+		.build()
+		.unwrap();
+
+	// The address isn't one of the service's own
+	assert_eq!(service.ip_addresses(), [IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap())]);
+	assert_eq!(service.additional_addresses().len(), 1);
+
+	// The SRV target can be resolved from the response alone
+	let dns_message = Message::from_bytes(&service.dns_response().unwrap().to_bytes().unwrap()).unwrap();
+	let alias = dns_message
+		.additionals()
+		.iter()
+		.filter(|record| record.name() == &Name::from_str("alias.local.").unwrap())
+		.collect::<Vec<_>>();
+	assert_eq!(alias.len(), 1);
+	assert_eq!(alias[0].data(), Some(&RData::A(alias_ip)));
+	assert!(!alias[0].mdns_cache_flush());
+}
+
+#[test]
+fn test_named_ip_addresses() {
+	use trust_dns_client::rr::{Name, RData, RecordType};
+
+	let v4 = IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap());
+	let v6 = IpAddr::V6(Ipv6Addr::from_str("fe80::18e4:b943:8756:d855").unwrap());
+
+	let records = |service: &crate::broadcast::Service| {
+		let dns_message = service.dns_response().unwrap();
+		let mut srv_target = None;
+		let mut names = Vec::new();
+		for record in dns_message.answers().iter().chain(dns_message.additionals()) {
+			match record.data() {
+				Some(RData::SRV(srv)) => srv_target = Some(srv.target().to_string()),
+				Some(RData::A(_) | RData::AAAA(_)) => names.push((record.record_type(), record.name().to_string())),
+				_ => {}
+			}
+		}
+		(srv_target.unwrap(), names)
+	};
+
+	// Only the IPv6 address is renamed, so the SRV record still targets the hostname
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(v4)
+		.add_ip_address_named(v6, "host6.local")
+		.unwrap()
+		.build()
+		.unwrap();
+	assert_eq!(
+		records(&service),
+		(
+			"searchlighttest.local.".to_string(),
+			vec![
+				(RecordType::A, "searchlighttest.local.".to_string()),
+				(RecordType::AAAA, "host6.local.".to_string())
+			]
+		)
+	);
+	assert_eq!(
+		service.reverse_lookup_response(v6).answers()[0].data(),
+		Some(&RData::PTR(Name::from_str("host6.local.").unwrap()))
+	);
+
+	// Every address is renamed, so the SRV record targets the first one
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address_named(v4, "host4.local")
+		.unwrap()
+		.add_ip_address_named(v6, "host6.local")
+		.unwrap()
+		.build()
+		.unwrap();
+	assert_eq!(
+		records(&service),
+		(
+			"host4.local.".to_string(),
+			vec![
+				(RecordType::A, "host4.local.".to_string()),
+				(RecordType::AAAA, "host6.local.".to_string())
+			]
+		)
+	);
+}
+
+#[test]
+fn test_socket_setup_error() {
+	use crate::errors::{MultiIpIoError, SocketSetupError, SocketSetupStage};
+
+	let bind = SocketSetupError::map(SocketSetupStage::Bind)(std::io::ErrorKind::AddrInUse.into());
+	let join = SocketSetupError::map(SocketSetupStage::Join)(std::io::ErrorKind::Other.into());
+	let err = MultiIpIoError::Both {
+		v4: bind.into(),
+		v6: join.into(),
+	};
+
+	assert!(err.is_addr_in_use());
+	assert_eq!(
+		err.socket_setup_errors().map(|err| err.stage).collect::<Vec<_>>(),
+		[SocketSetupStage::Bind, SocketSetupStage::Join]
+	);
+	assert_eq!(MultiIpIoError::IoError(std::io::ErrorKind::Other.into()).socket_setup_errors().count(), 0);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_require_interface_error() {
+	use crate::{
+		discovery::errors::DiscoveryBuilderError,
+		errors::{MultiIpIoError, SocketSetupError, SocketSetupStage},
+	};
+
+	let no_interfaces = SocketSetupError::map(SocketSetupStage::RequireInterface)(std::io::ErrorKind::AddrNotAvailable.into());
+	assert!(matches!(
+		DiscoveryBuilderError::socket_setup(MultiIpIoError::V4(no_interfaces.into()), crate::MDNS_PORT),
+		DiscoveryBuilderError::NoInterfaces(_)
+	));
+
+	// Other socket setup errors are exposed as they are
+	let join = SocketSetupError::map(SocketSetupStage::Join)(std::io::ErrorKind::Other.into());
+	match DiscoveryBuilderError::socket_setup(MultiIpIoError::V6(join.into()), crate::MDNS_PORT) {
+		DiscoveryBuilderError::SocketSetup { v4: None, v6: Some(v6) } => assert_eq!(v6.stage, SocketSetupStage::Join),
+		err => panic!("unexpected error: {err}"),
+	}
+
+	let create = || SocketSetupError::map(SocketSetupStage::Create)(std::io::ErrorKind::Other.into()).into();
+	let err = DiscoveryBuilderError::socket_setup(MultiIpIoError::Both { v4: create(), v6: create() }, crate::MDNS_PORT);
+	assert!(matches!(err, DiscoveryBuilderError::SocketSetup { v4: Some(_), v6: Some(_) }));
+	assert!(err.to_string().ends_with("(IPv6)"));
+
+	// Errors that didn't come from setting up a socket aren't
+	assert!(matches!(
+		DiscoveryBuilderError::socket_setup(MultiIpIoError::V4(std::io::ErrorKind::Other.into()), crate::MDNS_PORT),
+		DiscoveryBuilderError::MultiIpIoError(_)
+	));
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_interface_filter() {
+	use crate::{
+		broadcast::{errors::BroadcasterBuilderError, BroadcasterBuilder},
+		discovery::{errors::DiscoveryBuilderError, DiscoveryBuilder},
+		net::{IpVersion, TargetInterfaceV4},
+	};
+
+	let filter = TargetInterfaceV4::filter(|iface| !iface.name.starts_with("tun"));
+	assert_eq!(filter.clone(), filter);
+	assert_ne!(TargetInterfaceV4::filter(|_| true), filter);
+
+	// A filter that accepts nothing leaves no interfaces to join
+	assert!(matches!(
+		DiscoveryBuilder::new()
+			.interface_v4(TargetInterfaceV4::filter(|_| false))
+			.require_interface(true)
+			.build(IpVersion::V4),
+		Err(DiscoveryBuilderError::NoInterfaces(_))
+	));
+	assert!(matches!(
+		BroadcasterBuilder::new()
+			.interface_v4(TargetInterfaceV4::filter(|_| false))
+			.require_interface(true)
+			.build(IpVersion::V4),
+		Err(BroadcasterBuilderError::NoInterfaces(_))
+	));
+}
+
+#[test]
+fn test_exclude_link_local_v4() {
+	use crate::{
+		net::TargetInterfaceV4,
+		socket::{usable_interfaces_v4, MdnsSocketOptions},
+	};
+	use if_addrs::{IfAddr, Ifv4Addr, Ifv6Addr, Interface};
+
+	let v4 = |name: &str, ip: [u8; 4]| Interface {
+		name: name.to_string(),
+		addr: IfAddr::V4(Ifv4Addr {
+			ip: Ipv4Addr::from(ip),
+			netmask: Ipv4Addr::new(255, 255, 0, 0),
+			broadcast: None,
+		}),
+	};
+	let ifaces = || {
+		vec![
+			v4("lo", [127, 0, 0, 1]),
+			v4("eth0", [192, 168, 1, 5]),
+			v4("eth1", [169, 254, 10, 20]),
+			v4("tun0", [169, 254, 99, 1]),
+			Interface {
+				name: "eth0".to_string(),
+				addr: IfAddr::V6(Ifv6Addr {
+					ip: Ipv6Addr::from_str("fe80::1").unwrap(),
+					netmask: Ipv6Addr::UNSPECIFIED,
+					broadcast: None,
+				}),
+			},
+		]
+	};
+	let usable = |interface: TargetInterfaceV4, exclude_link_local_v4: bool| {
+		let options = MdnsSocketOptions {
+			exclude_link_local_v4,
+			..Default::default()
+		};
+		usable_interfaces_v4(ifaces(), &interface, &options).into_iter().collect::<Vec<_>>()
+	};
+
+	// Link-local interfaces are joined like any other by default
+	assert_eq!(
+		usable(TargetInterfaceV4::All, false),
+		[
+			Ipv4Addr::new(169, 254, 10, 20),
+			Ipv4Addr::new(169, 254, 99, 1),
+			Ipv4Addr::new(192, 168, 1, 5)
+		]
+	);
+
+	// Excluding them leaves only routable interfaces, with or without a filter
+	assert_eq!(usable(TargetInterfaceV4::All, true), [Ipv4Addr::new(192, 168, 1, 5)]);
+	let not_tun = || TargetInterfaceV4::filter(|iface| !iface.name.starts_with("tun"));
+	assert_eq!(usable(not_tun(), false), [Ipv4Addr::new(169, 254, 10, 20), Ipv4Addr::new(192, 168, 1, 5)]);
+	assert_eq!(usable(not_tun(), true), [Ipv4Addr::new(192, 168, 1, 5)]);
+}
+
+#[test]
+fn test_normalize_scope_id() {
+	use crate::net::normalize_scope_id;
+	use std::net::SocketAddrV6;
+
+	let link_local = SocketAddrV6::new("fe80::1".parse().unwrap(), 5353, 0, 0);
+	assert_eq!(normalize_scope_id(link_local, 3).scope_id(), 3);
+	assert_eq!(normalize_scope_id(link_local, 0).scope_id(), 0);
+
+	let global = SocketAddrV6::new("2001:db8::1".parse().unwrap(), 5353, 0, 0);
+	assert_eq!(normalize_scope_id(global, 3).scope_id(), 0);
+}
+
+#[test]
+#[cfg(feature = "broadcast")]
+fn test_socket_options() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		net::{IpVersion, SocketOptions},
+	};
+
+	BroadcasterBuilder::new()
+		.loopback()
+		.socket_options(SocketOptions {
+			read_timeout: None,
+			recv_buffer_size: Some(64 * 1024),
+			multicast_ttl: 4,
+			dscp: Some(46),
+		})
+		.build(IpVersion::V4)
+		.unwrap();
+
+	BroadcasterBuilder::new().loopback().multicast_ttl(8).build(IpVersion::V4).unwrap();
+	BroadcasterBuilder::new().loopback().also_broadcast(true).build(IpVersion::V4).unwrap();
+	BroadcasterBuilder::new()
+		.loopback_v4(true)
+		.loopback_v6(false)
+		.build(IpVersion::V4)
+		.unwrap();
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_discovery_shutdown_reason() {
+	use crate::{
+		discovery::{DiscoveryBuilder, Shutdown},
+		net::IpVersion,
+	};
+	use std::time::Duration;
+
+	let handle = DiscoveryBuilder::new().loopback().build(IpVersion::V4).unwrap().run_in_background(|_| {});
+	assert!(matches!(handle.shutdown().unwrap(), Shutdown::Requested));
+
+	let handle = DiscoveryBuilder::new()
+		.loopback()
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background_for(Duration::from_millis(50), |_| {});
+	std::thread::sleep(Duration::from_millis(500));
+	assert!(matches!(handle.shutdown().unwrap(), Shutdown::Elapsed));
+
+	let handle = DiscoveryBuilder::new()
+		.loopback()
+		.service("_searchlight-idle-test._udp.local")
+		.unwrap()
+		.idle_timeout(Duration::from_millis(50), true)
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background(|_| {});
+	std::thread::sleep(Duration::from_millis(500));
+	assert!(matches!(handle.shutdown().unwrap(), Shutdown::Idle));
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_also_broadcast() {
+	use crate::{discovery::DiscoveryBuilder, net::IpVersion, MDNS_PORT};
+	use std::{
+		net::SocketAddr,
+		time::{Duration, Instant},
+	};
+	use trust_dns_client::{op::Message, serialize::binary::BinDecodable};
+
+	// Listens on the mDNS port without joining the multicast group, so only broadcast packets arrive. Multicast loopback is off,
+	// and other tests use different service names, so any multicast traffic that gets through anyway is told apart by the name queried
+	let receiver = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, Some(socket2::Protocol::UDP)).unwrap();
+	receiver.set_reuse_address(true).unwrap();
+	#[cfg(unix)]
+	receiver.set_reuse_port(true).unwrap();
+	receiver.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+	receiver
+		.bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), MDNS_PORT).into())
+		.unwrap();
+	let receiver = std::net::UdpSocket::from(receiver);
+
+	// Whether discovery's first query arrives at the receiver
+	let received_query = |also_broadcast: bool| {
+		let discovery = DiscoveryBuilder::new()
+			.service("_searchlight-also-broadcast._udp.local")
+			.unwrap()
+			.also_broadcast(also_broadcast)
+			.interval(Duration::from_secs(60))
+			.build(IpVersion::V4)
+			.unwrap()
+			.run_in_background(|_| {});
+
+		let mut buf = [0; 4096];
+		let deadline = Instant::now() + Duration::from_secs(2);
+		let mut received = false;
+		while !received && Instant::now() < deadline {
+			if let Ok(len) = receiver.recv(&mut buf) {
+				received = Message::from_bytes(&buf[..len]).map_or(false, |message| {
+					message
+						.queries()
+						.iter()
+						.any(|query| query.name().to_ascii() == "_searchlight-also-broadcast._udp.local.")
+				});
+			}
+		}
+
+		discovery.shutdown().unwrap();
+		received
+	};
+
+	assert!(!received_query(false));
+	assert!(received_query(true));
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_run_in_background_for() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::{DiscoveryBuilder, DiscoveryEvent, Shutdown},
+		net::IpVersion,
+	};
+	use std::{
+		sync::mpsc,
+		time::{Duration, Instant},
+	};
+
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.add_service(
+			ServiceBuilder::new("_searchlight-run-for._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+				.build()
+				.unwrap(),
+		)
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background();
+
+	let (tx, rx) = mpsc::sync_channel(64);
+	let started = Instant::now();
+	let discovery = DiscoveryBuilder::new()
+		.loopback()
+		.service("_searchlight-run-for._udp.local")
+		.unwrap()
+		.interval(Duration::from_millis(500))
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background_for(Duration::from_secs(3), move |event| {
+			tx.try_send(event).ok();
+		});
+
+	// Discovery runs normally until the duration elapses...
+	assert!(matches!(
+		rx.recv_timeout(Duration::from_secs(3)).unwrap(),
+		DiscoveryEvent::ResponderFound(_)
+	));
+
+	// ...and then stops by itself, dropping the handler, even though the broadcaster is still answering and nobody asked it to shut down
+	loop {
+		match rx.recv_timeout(Duration::from_secs(5)) {
+			Ok(_) => continue,
+			Err(mpsc::RecvTimeoutError::Disconnected) => break,
+			Err(mpsc::RecvTimeoutError::Timeout) => panic!("discovery is still running"),
+		}
+	}
+	assert!(started.elapsed() >= Duration::from_secs(3));
+
+	assert!(matches!(discovery.shutdown().unwrap(), Shutdown::Elapsed));
+	broadcaster.shutdown().unwrap();
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_idle_timer() {
+	use crate::discovery::{Discovery, DiscoveryEvent, Responder};
+	use std::{
+		sync::Arc,
+		time::{Duration, Instant, SystemTime},
+	};
+	use trust_dns_client::op::{DnsResponse, Message};
+
+	let responder = Arc::new(Responder {
+		addr: "192.168.1.69:5353".parse().unwrap(),
+		last_response: DnsResponse::from(Message::new()),
+		raw_packet: Vec::new(),
+		service_name: None,
+		last_responded: Instant::now(),
+		last_responded_at: SystemTime::now(),
+		seen_count: 1,
+	});
+	let update = || DiscoveryEvent::ResponseUpdate {
+		old: responder.clone(),
+		new: responder.clone(),
+	};
+
+	// Returns how long the timer ran for, with an event fired every second for the first `events` seconds
+	let idle_after = |count_updates: bool, events: u64, event: &dyn Fn() -> DiscoveryEvent| {
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.start_paused(true)
+			.build()
+			.unwrap();
+		runtime.block_on(async {
+			let (handler, idle) = Discovery::idle_timer(Arc::new(|_| {}), Duration::from_secs(5), count_updates);
+			let start = tokio::time::Instant::now();
+			let events = async {
+				for _ in 0..events {
+					tokio::time::sleep(Duration::from_secs(1)).await;
+					handler(event());
+				}
+				std::future::pending::<()>().await
+			};
+			tokio::select! {
+				_ = idle => start.elapsed().as_secs(),
+				_ = events => unreachable!(),
+			}
+		})
+	};
+
+	assert_eq!(idle_after(true, 0, &update), 5);
+	assert_eq!(idle_after(true, 3, &update), 8);
+	assert_eq!(idle_after(false, 3, &update), 5);
+	assert_eq!(idle_after(false, 3, &|| DiscoveryEvent::ResponderLost(responder.clone())), 8);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_enumeration() {
+	use crate::discovery::Enumeration;
+	use trust_dns_client::{
+		op::{Message, MessageType},
+		rr::{Name, RData, Record, RecordType},
+	};
+
+	let service = ServiceBuilder::new("_http._tcp.local", "searchlighttest", 80)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.add_txt("path=/")
+		.build()
+		.unwrap();
+
+	let addr = "192.168.1.69:5353".parse().unwrap();
+	let mut enumeration = Enumeration::new();
+
+	// Meta-query response
+	let mut meta = Message::new();
+	meta.set_message_type(MessageType::Response);
+	meta.add_answer(Record::from_rdata(
+		Name::from_str("_services._dns-sd._udp.local.").unwrap(),
+		4500,
+		RData::PTR(service.service_type().clone()),
+	));
+	assert_eq!(
+		enumeration.handle_response(addr, &meta),
+		[(service.service_type().clone(), RecordType::PTR)]
+	);
+
+	// Service type response, which has everything else in its additionals
+	assert!(enumeration.handle_response(addr, &service.dns_response().unwrap()).is_empty());
+
+	let enumerated = enumeration.finish();
+	assert_eq!(enumerated.len(), 1);
+
+	let (service_type, services) = &enumerated[0];
+	assert_eq!(service_type, service.service_type());
+	assert_eq!(services.len(), 1);
+	assert_eq!(&services[0].name, service.service_id());
+	assert_eq!(services[0].addr, addr);
+	assert_eq!(services[0].hostname.as_ref(), Some(service.hostname()));
+	assert_eq!(services[0].port, Some(80));
+	assert_eq!(services[0].ip_addresses, service.ip_addresses().iter().copied().collect());
+	assert_eq!(services[0].txt, ["path=/"]);
+
+	// A flushed address replaces the old one instead of being merged with it
+	let moved = ServiceBuilder::new("_http._tcp.local", "searchlighttest", 80)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.70").unwrap()))
+		.build()
+		.unwrap();
+	let mut enumeration = Enumeration::new();
+	enumeration.handle_response(addr, &meta);
+	enumeration.handle_response(addr, &service.dns_response().unwrap());
+	enumeration.handle_response(addr, &moved.dns_response().unwrap());
+	assert_eq!(
+		enumeration.finish()[0].1[0].ip_addresses,
+		[IpAddr::V4(Ipv4Addr::from_str("192.168.1.70").unwrap())].into_iter().collect()
+	);
+}
+
+#[test]
+fn test_record_changes() {
+	use crate::discovery::RecordChanges;
+	use trust_dns_client::{
+		op::Message,
+		rr::{RData, RecordType},
+		serialize::binary::{BinDecodable, BinEncodable},
+	};
+
+	let response = |ips: &[&str]| {
+		let mut service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337).unwrap();
+		for ip in ips {
+			service = service.add_ip_address(IpAddr::from_str(ip).unwrap());
+		}
+		let response = service.build().unwrap().dns_response().unwrap();
+
+		// Round trip, so that the cache-flush bit is parsed like it would be off the wire
+		Message::from_bytes(&response.to_bytes().unwrap()).unwrap()
+	};
+
+	let old = response(&["192.168.1.69", "fe80::1"]);
+
+	assert!(RecordChanges::between(&old, &old).is_empty());
+
+	// The A record is flushed and replaced, the PTR record (which is shared) and AAAA record are untouched
+	let new = response(&["192.168.1.70", "fe80::1"]);
+	let changes = RecordChanges::between(&old, &new);
+	assert_eq!(changes.added.len(), 1);
+	assert_eq!(changes.removed.len(), 1);
+	assert!(matches!(changes.added[0].data(), Some(RData::A(ip)) if ip.to_string() == "192.168.1.70"));
+	assert!(matches!(changes.removed[0].data(), Some(RData::A(ip)) if ip.to_string() == "192.168.1.69"));
+
+	// Without the cache-flush bit, a missing record is assumed to still be valid
+	let mut shared = new.clone();
+	let additionals = shared
+		.take_additionals()
+		.into_iter()
+		.map(|mut record| {
+			record.set_mdns_cache_flush(false);
+			record
+		})
+		.collect::<Vec<_>>();
+	shared.insert_additionals(additionals);
+	let changes = RecordChanges::between(&old, &shared);
+	assert_eq!(changes.added.len(), 1);
+	assert!(changes.removed.is_empty());
+
+	// Goodbye records remove the record they match
+	let mut goodbye = old.clone();
+	let additionals = goodbye
+		.take_additionals()
+		.into_iter()
+		.map(|mut record| {
+			if record.record_type() == RecordType::AAAA {
+				record.set_ttl(0);
+			}
+			record
+		})
+		.collect::<Vec<_>>();
+	goodbye.insert_additionals(additionals);
+	let changes = RecordChanges::between(&old, &goodbye);
+	assert!(changes.added.is_empty());
+	assert_eq!(changes.removed.len(), 1);
+	assert_eq!(changes.removed[0].record_type(), RecordType::AAAA);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_response_changed() {
+	use crate::discovery::Responder;
+	use std::time::{Instant, SystemTime};
+	use trust_dns_client::op::{DnsResponse, Message};
+
+	let responder = |ttl: u32, ips: &[&str], txt: &[&'static str]| {
+		let mut service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+			.unwrap()
+			.ttl(ttl);
+		for ip in ips {
+			service = service.add_ip_address(IpAddr::from_str(ip).unwrap());
+		}
+		for txt in txt {
+			service = service.add_txt(*txt);
+		}
+		Responder {
+			addr: "192.168.1.69:5353".parse().unwrap(),
+			last_response: DnsResponse::from(service.build().unwrap().dns_response().unwrap()),
+			raw_packet: Vec::new(),
+			service_name: None,
+			last_responded: Instant::now(),
+			last_responded_at: SystemTime::now(),
+			seen_count: 1,
+		}
+	};
+
+	let old = responder(120, &["192.168.1.69", "fe80::1"], &["key=value"]);
+	assert!(!old.response_changed(&old));
+
+	// TTLs don't matter
+	assert!(!old.response_changed(&responder(4500, &["192.168.1.69", "fe80::1"], &["key=value"])));
+
+	// Neither does record order
+	let mut reordered = old.clone();
+	let mut message = Message::from(reordered.last_response);
+	let mut additionals = message.take_additionals();
+	additionals.reverse();
+	message.insert_additionals(additionals);
+	reordered.last_response = DnsResponse::from(message);
+	assert!(!old.response_changed(&reordered));
+
+	assert!(old.response_changed(&responder(120, &["192.168.1.70", "fe80::1"], &["key=value"])));
+	assert!(old.response_changed(&responder(120, &["192.168.1.69"], &["key=value"])));
+	assert!(old.response_changed(&responder(120, &["192.168.1.69", "fe80::1"], &["key=other"])));
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_responder_endpoints() {
+	use crate::discovery::Responder;
+	use std::{
+		net::{Ipv6Addr, SocketAddr, SocketAddrV6},
+		time::{Instant, SystemTime},
+	};
+	use trust_dns_client::{op::DnsResponse, rr::RecordType};
+
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.add_ip_address(IpAddr::V6(Ipv6Addr::from_str("fe80::1").unwrap()))
+		.add_ip_address(IpAddr::V6(Ipv6Addr::from_str("2001:db8::1").unwrap()))
+		.build()
+		.unwrap();
+
+	let responder = |addr: SocketAddr, response| Responder {
+		addr,
+		last_response: DnsResponse::from(response),
+		raw_packet: Vec::new(),
+		service_name: None,
+		last_responded: Instant::now(),
+		last_responded_at: SystemTime::now(),
+		seen_count: 1,
+	};
+
+	// Link-local addresses get the scope ID of the interface the response was received on
+	let v6 = responder(
+		SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from_str("fe80::1").unwrap(), 5353, 0, 3)),
+		service.dns_response().unwrap(),
+	);
+	assert_eq!(v6.ip_version(), crate::net::IpVersion::V6);
+	let endpoints = v6.endpoints();
+	assert_eq!(endpoints.len(), 3);
+	assert!(endpoints.contains(&SocketAddr::from_str("192.168.1.69:1337").unwrap()));
+	assert!(endpoints.contains(&SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from_str("fe80::1").unwrap(), 1337, 0, 3))));
+	assert!(endpoints.contains(&SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from_str("2001:db8::1").unwrap(), 1337, 0, 0))));
+
+	// Without any address records, the source address is used
+	let mut response = service.dns_response().unwrap();
+	let srv = response
+		.take_additionals()
+		.into_iter()
+		.filter(|record| record.record_type() == RecordType::SRV)
+		.collect::<Vec<_>>();
+	response.insert_additionals(srv);
+	let v4 = responder(SocketAddr::from_str("192.168.1.70:5353").unwrap(), response);
+	assert_eq!(v4.endpoints(), [SocketAddr::from_str("192.168.1.70:1337").unwrap()]);
+	assert_eq!(v4.ip_version(), crate::net::IpVersion::V4);
+
+	// Without an SRV record, there's nothing to connect to
+	let mut response = service.dns_response().unwrap();
+	response.take_additionals();
+	assert!(responder(SocketAddr::from_str("192.168.1.70:5353").unwrap(), response)
+		.endpoints()
+		.is_empty());
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_responder_ttl() {
+	use crate::discovery::Responder;
+	use std::time::{Duration, Instant, SystemTime};
+	use trust_dns_client::op::{DnsResponse, Message};
+
+	let responder = |response, last_responded| Responder {
+		addr: "192.168.1.69:5353".parse().unwrap(),
+		last_response: DnsResponse::from(response),
+		raw_packet: Vec::new(),
+		service_name: None,
+		last_responded,
+		last_responded_at: SystemTime::now(),
+		seen_count: 1,
+	};
+
+	let service = |ttl| {
+		ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+			.unwrap()
+			.ttl(ttl)
+			.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+			.build()
+			.unwrap()
+			.dns_response()
+			.unwrap()
+	};
+
+	let mut response = service(120);
+	let mut additionals = response.take_additionals();
+	for record in &mut additionals {
+		record.set_ttl(60);
+	}
+	response.insert_additionals(additionals);
+
+	// The shortest TTL of any record counts
+	let fresh = responder(response, Instant::now());
+	assert_eq!(fresh.min_ttl(), Some(Duration::from_secs(60)));
+	assert!(fresh.remaining_ttl().unwrap() <= Duration::from_secs(60));
+	assert!(fresh.remaining_ttl().unwrap() > Duration::from_secs(50));
+
+	let stale = responder(service(1), Instant::now() - Duration::from_secs(5));
+	assert_eq!(stale.remaining_ttl(), Some(Duration::ZERO));
+
+	// Goodbye packets have already expired
+	assert_eq!(responder(service(0), Instant::now()).remaining_ttl(), Some(Duration::ZERO));
+
+	assert_eq!(responder(Message::new(), Instant::now()).min_ttl(), None);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_txt_filter() {
+	use crate::discovery::{Responder, TxtFilter};
+	use std::{
+		collections::BTreeMap,
+		time::{Instant, SystemTime},
+	};
+	use trust_dns_client::op::DnsResponse;
+
+	let response = |txt: &[&'static str]| {
+		let mut service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+			.unwrap()
+			.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST));
+		for txt in txt {
+			service = service.add_txt(*txt);
+		}
+		service.build().unwrap().dns_response().unwrap()
+	};
+
+	let txt = Responder {
+		addr: "192.168.1.69:5353".parse().unwrap(),
+		last_response: DnsResponse::from(response(&["RS=1", "flag", "rs=0", "=ignored", "empty="])),
+		raw_packet: Vec::new(),
+		service_name: None,
+		last_responded: Instant::now(),
+		last_responded_at: SystemTime::now(),
+		seen_count: 1,
+	}
+	.txt();
+	assert_eq!(
+		txt,
+		BTreeMap::from([
+			(b"rs".to_vec(), Some(b"1".to_vec())),
+			(b"flag".to_vec(), None),
+			(b"empty".to_vec(), Some(Vec::new())),
+		])
+	);
+
+	let rs = TxtFilter::new(|txt| txt.get(&b"rs"[..]) == Some(&Some(b"1".to_vec())));
+	assert!(rs.matches(&response(&["rs=1"])));
+	assert!(!rs.matches(&response(&["rs=0"])));
+
+	// Responses without a TXT record are treated as an empty map
+	let mut no_txt = response(&["rs=1"]);
+	let additionals = no_txt
+		.take_additionals()
+		.into_iter()
+		.filter(|record| record.record_type() != trust_dns_client::rr::RecordType::TXT)
+		.collect::<Vec<_>>();
+	no_txt.insert_additionals(additionals);
+	assert!(!rs.matches(&no_txt));
+	assert!(TxtFilter::new(|txt| txt.is_empty()).matches(&no_txt));
+}
+
+#[test]
+fn test_mtu_split() {
+	use crate::broadcast::{max_payload_v6, split_response};
+	use trust_dns_client::{
+		op::Message,
+		rr::{RData, RecordType},
+		serialize::binary::BinDecodable,
+	};
+
+	let mut builder = ServiceBuilder::new("_http._tcp.local", "searchlighttest", 80).unwrap();
+	for i in 0..16 {
+		builder = builder
+			.add_ip_address(IpAddr::V4(Ipv4Addr::new(192, 168, 1, i)))
+			.add_ip_address(IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, i as u16)));
+	}
+	let service = builder.add_txt("a".repeat(200)).build().unwrap();
+
+	let response = service.dns_response().unwrap();
+	let max_len = max_payload_v6(1000);
+	let packets = split_response(&response, max_len, true);
+	assert!(packets.len() > 1);
+
+	let packets = packets
+		.iter()
+		.map(|packet| {
+			assert!(packet.len() <= max_len);
+			Message::from_bytes(packet).unwrap()
+		})
+		.collect::<Vec<_>>();
+
+	// Nothing was lost
+	assert_eq!(
+		packets.iter().map(|packet| packet.answers().len()).sum::<usize>(),
+		response.answers().len()
+	);
+	assert_eq!(
+		packets.iter().map(|packet| packet.additionals().len()).sum::<usize>(),
+		response.additionals().len()
+	);
+
+	// All of the host's addresses are in the same packet
+	let address_packets = packets
+		.iter()
+		.filter(|packet| {
+			packet
+				.additionals()
+				.iter()
+				.any(|record| matches!(record.data(), Some(RData::A(_) | RData::AAAA(_))))
+		})
+		.collect::<Vec<_>>();
+	assert_eq!(address_packets.len(), 1);
+	assert_eq!(
+		address_packets[0]
+			.additionals()
+			.iter()
+			.filter(|record| matches!(record.record_type(), RecordType::A | RecordType::AAAA))
+			.count(),
+		32
+	);
+
+	// Small responses aren't split
+	assert_eq!(split_response(&response, usize::MAX, true).len(), 1);
+}
+
+#[test]
+fn test_name_compression() {
+	use crate::broadcast::encode;
+	use trust_dns_client::{op::Message, serialize::binary::BinDecodable};
+
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 69)))
+		.build()
+		.unwrap();
+	let response = service.dns_response().unwrap();
+
+	let compressed = encode(&response, true).unwrap();
+	let uncompressed = encode(&response, false).unwrap();
+	assert_eq!(compressed, response.to_bytes().unwrap());
+
+	// Without compression, every name is written out in full, so there are no compression pointers
+	assert!(uncompressed.len() > compressed.len());
+	let service_type = b"\x11_searchlight-test\x04_udp\x05local\x00";
+	let count = |packet: &[u8]| packet.windows(service_type.len()).filter(|window| *window == service_type).count();
+	assert!(count(&uncompressed) > count(&compressed));
+
+	// Both decode to the same records
+	let compressed = Message::from_bytes(&compressed).unwrap();
+	let uncompressed = Message::from_bytes(&uncompressed).unwrap();
+	assert_eq!(compressed.answers(), uncompressed.answers());
+	assert_eq!(compressed.additionals(), uncompressed.additionals());
+}
+
+#[test]
+fn test_builder_clone() {
+	use crate::{broadcast::BroadcasterBuilder, net::IpVersion};
+
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()));
+
+	let template = BroadcasterBuilder::new().loopback().add_service(service.clone().build().unwrap());
+
+	template.clone().build(IpVersion::V4).unwrap();
+	template
+		.add_service(service.add_txt("path=/").build().unwrap())
+		.build(IpVersion::V4)
+		.unwrap();
+}
+
+#[test]
+fn test_broadcaster_services() {
+	use crate::{broadcast::BroadcasterBuilder, net::IpVersion};
+	use trust_dns_client::{
+		op::Message,
+		rr::{RData, RecordType},
+		serialize::binary::BinDecodable,
+	};
+
+	let service = |name: &str| {
+		ServiceBuilder::new("_searchlight-test._udp.local", name, 1337)
+			.unwrap()
+			.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+			.build()
+			.unwrap()
+	};
+
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.add_service(service("searchlighttest1"))
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background();
+
+	broadcaster.add_service(service("searchlighttest2")).unwrap();
+
+	let services = broadcaster.services();
+	assert_eq!(services.len(), 2);
+	assert!(services.contains(&service("searchlighttest1")));
+	assert!(services.contains(&service("searchlighttest2")));
+
+	assert!(broadcaster.contains_service("_searchlight-test._udp.local", "searchlighttest1").unwrap());
+	assert!(!broadcaster.contains_service("_searchlight-test._udp.local", "searchlighttest3").unwrap());
+	assert!(!broadcaster.contains_service("_searchlight-other._udp.local", "searchlighttest1").unwrap());
+
+	assert!(broadcaster
+		.remove_named_service("_searchlight-test._udp.local", "searchlighttest1")
+		.unwrap());
+	assert_eq!(broadcaster.services(), vec![service("searchlighttest2")]);
+
+	assert!(broadcaster.update_port("_searchlight-test._udp.local", "searchlighttest2", 1338).unwrap());
+	assert!(!broadcaster.update_port("_searchlight-test._udp.local", "searchlighttest1", 1338).unwrap());
+	let services = broadcaster.services();
+	assert_eq!(services.len(), 1);
+	assert_eq!(services[0].port(), 1338);
+
+	// The rebuilt SRV record has the new port, and the cache-flush bit so that it replaces the old one
+	let srv = broadcaster
+		.simulate_query("_searchlight-test._udp.local")
+		.unwrap()
+		.iter()
+		.map(|response| Message::from_bytes(&response.to_bytes().unwrap()).unwrap())
+		.flat_map(|response| response.additionals().iter().chain(response.answers()).cloned().collect::<Vec<_>>())
+		.find(|record| record.record_type() == RecordType::SRV)
+		.unwrap();
+	assert!(matches!(srv.data(), Some(RData::SRV(srv)) if srv.port() == 1338));
+	assert!(srv.mdns_cache_flush());
+
+	broadcaster.shutdown().unwrap();
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_update_port_announcement() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::{DiscoveryBuilder, DiscoveryEvent},
+		net::IpVersion,
+	};
+	use std::{sync::mpsc, time::Duration};
+	use trust_dns_client::rr::{RData, Record, RecordType};
+
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.add_service(
+			ServiceBuilder::new("_searchlight-update-port._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+				.build()
+				.unwrap(),
+		)
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background();
+
+	let (tx, rx) = mpsc::sync_channel(8);
+	let discovery = DiscoveryBuilder::new()
+		.loopback()
+		.service("_searchlight-update-port._udp.local")
+		.unwrap()
+		.interval(Duration::from_secs(60))
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background(move |event| {
+			if let DiscoveryEvent::ResponderFound(responder) | DiscoveryEvent::ResponseUpdate { new: responder, .. } = event {
+				let srv = responder
+					.last_response
+					.answers()
+					.iter()
+					.chain(responder.last_response.additionals())
+					.find(|record| record.record_type() == RecordType::SRV)
+					.cloned();
+				tx.try_send(srv).ok();
+			}
+		});
+
+	// The port of a response's SRV record, and whether it has the cache-flush bit set
+	let port = |srv: Option<Record>| {
+		let srv = srv.expect("response has no SRV record");
+		match srv.data() {
+			Some(RData::SRV(data)) => (data.port(), srv.mdns_cache_flush()),
+			data => panic!("unexpected SRV data: {data:?}"),
+		}
+	};
+
+	// Found in response to the first discovery packet
+	assert_eq!(port(rx.recv_timeout(Duration::from_secs(5)).unwrap()), (1337, true));
+
+	// Without the announcement, the new port wouldn't be seen until the next query, a minute from now
+	assert!(broadcaster
+		.update_port("_searchlight-update-port._udp.local", "searchlighttest", 1338)
+		.unwrap());
+	let updated = std::iter::from_fn(|| rx.recv_timeout(Duration::from_secs(5)).ok())
+		.map(port)
+		.find(|(port, _)| *port != 1337);
+	assert_eq!(updated, Some((1338, true)));
+
+	discovery.shutdown().unwrap();
+	broadcaster.shutdown().unwrap();
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_wait_for_responder() {
+	use crate::{broadcast::BroadcasterBuilder, discovery::DiscoveryBuilder, net::IpVersion};
+	use std::time::Duration;
+
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.add_service(
+			ServiceBuilder::new("_searchlight-wait._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+				.build()
+				.unwrap(),
+		)
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background();
+
+	let discovery = DiscoveryBuilder::new()
+		.loopback()
+		.service("_searchlight-wait._udp.local")
+		.unwrap()
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background(|_| {});
+
+	let responder = discovery.wait_for_responder(Duration::from_secs(5)).unwrap();
+	assert_eq!(
+		responder.service_name.as_ref().map(ToString::to_string).as_deref(),
+		Some("_searchlight-wait._udp.local.")
+	);
+
+	// The same responder isn't returned twice
+	assert!(discovery.wait_for_responder(Duration::from_millis(100)).is_none());
+
+	discovery.shutdown().unwrap();
+	broadcaster.shutdown().unwrap();
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_replay_to() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::{DiscoveryBuilder, DiscoveryEvent},
+		net::IpVersion,
+	};
+	use std::time::Duration;
+
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.add_service(
+			ServiceBuilder::new("_searchlight-replay._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+				.build()
+				.unwrap(),
+		)
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background();
+
+	let discovery = DiscoveryBuilder::new()
+		.loopback()
+		.service("_searchlight-replay._udp.local")
+		.unwrap()
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background(|_| {});
+
+	let responder = discovery.wait_for_responder(Duration::from_secs(5)).unwrap();
+
+	// A handler attached later is told about the responder that was already found
+	let mut replayed = vec![];
+	discovery.replay_to(|event| replayed.push(event));
+	assert_eq!(replayed.len(), 1);
+	assert!(matches!(&replayed[0], DiscoveryEvent::ResponderFound(replayed) if replayed.addr == responder.addr));
+
+	discovery.shutdown().unwrap();
+	broadcaster.shutdown().unwrap();
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_replay_to_service_types() {
+	use crate::{
+		discovery::{DiscoveryBuilder, DiscoveryEvent, KnownResponders},
+		tests::harness::run_discovery_on,
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{
+		sync::{Arc, Mutex},
+		time::Duration,
+	};
+
+	let response = |service_type: &str| {
+		ServiceBuilder::new(service_type, "searchlighttest", 1337)
+			.unwrap()
+			.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+			.build()
+			.unwrap()
+			.dns_response()
+			.unwrap()
+			.to_bytes()
+			.unwrap()
+	};
+
+	let events = Arc::new(Mutex::new(Vec::new()));
+	let handler = || {
+		let events = events.clone();
+		move |event: DiscoveryEvent| events.lock().unwrap().push(event)
+	};
+	let discovery = DiscoveryBuilder::new()
+		.on_service("_searchlight-a._udp.local", handler())
+		.unwrap()
+		.on_service("_searchlight-b._udp.local", handler())
+		.unwrap()
+		.interval(Duration::from_secs(60));
+
+	// One device responds for both service types
+	let network = MemoryNetwork::new();
+	let responder = network.join("192.168.1.69:5353".parse().unwrap());
+	run_discovery_on(&network, discovery, |_| async {
+		let mut recv = responder.recv(vec![0; 4096]);
+		recv.recv_multicast().await.unwrap();
+
+		responder.send_multicast(&response("_searchlight-a._udp.local")).await.unwrap();
+		responder.send_multicast(&response("_searchlight-b._udp.local")).await.unwrap();
+		tokio::time::sleep(Duration::from_secs(1)).await;
+	});
+
+	let known = KnownResponders::default();
+	let events = events.lock().unwrap().clone();
+	for event in &events {
+		known.track(event);
+	}
+
+	let services = |known: &KnownResponders| {
+		known
+			.snapshot()
+			.iter()
+			.map(|responder| (responder.addr, responder.service_name.as_ref().unwrap().to_ascii()))
+			.collect::<Vec<_>>()
+	};
+
+	// Both are known, rather than one replacing the other
+	let addr = "192.168.1.69:5353".parse().unwrap();
+	assert_eq!(
+		services(&known),
+		[
+			(addr, "_searchlight-a._udp.local.".to_string()),
+			(addr, "_searchlight-b._udp.local.".to_string())
+		]
+	);
+
+	// Losing one of them doesn't forget the other
+	let lost = match &events[0] {
+		DiscoveryEvent::ResponderFound(responder) => responder.clone(),
+		event => panic!("unexpected event: {event:?}"),
+	};
+	assert_eq!(lost.service_name.as_ref().unwrap().to_ascii(), "_searchlight-a._udp.local.");
+	known.track(&DiscoveryEvent::ResponderLost(lost));
+	assert_eq!(services(&known), [(addr, "_searchlight-b._udp.local.".to_string())]);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_shutdown_async() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::{DiscoveryBuilder, Shutdown},
+		net::IpVersion,
+	};
+
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.add_service(
+			ServiceBuilder::new("_searchlight-shutdown._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+				.build()
+				.unwrap(),
+		)
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background();
+
+	let discovery = DiscoveryBuilder::new()
+		.loopback()
+		.service("_searchlight-shutdown._udp.local")
+		.unwrap()
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background(|_| {});
+
+	// Shutting down from a current-thread runtime doesn't block it
+	let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+	runtime.block_on(async {
+		assert!(matches!(discovery.shutdown_async().await, Ok(Shutdown::Requested)));
+		broadcaster.shutdown_async().await.unwrap();
+	});
+}
+
+#[test]
+fn test_simulate_query() {
+	use crate::broadcast::BroadcasterBuilder;
+	use trust_dns_client::rr::{Name, RData};
+
+	let broadcaster = BroadcasterBuilder::new()
+		.add_service(
+			ServiceBuilder::new("_searchlight-simulate._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 69)))
+				.build()
+				.unwrap(),
+		)
+		.into_config()
+		.unwrap();
+
+	// Asking for the service type gets the service's full response
+	let responses = broadcaster.simulate_query(Name::from_str("_searchlight-simulate._udp.local.").unwrap());
+	assert_eq!(responses.len(), 1);
+	assert!(responses[0].answers().iter().any(|record| matches!(
+		record.data(),
+		Some(RData::PTR(ptr)) if ptr.to_utf8() == "searchlighttest._searchlight-simulate._udp.local."
+	)));
+
+	// DNS-SD meta-queries are answered too
+	let responses = broadcaster.simulate_query(Name::from_str("_services._dns-sd._udp.local.").unwrap());
+	assert_eq!(responses.len(), 1);
+
+	// Anything else isn't
+	assert!(broadcaster.simulate_query(Name::from_str("_other._udp.local.").unwrap()).is_empty());
+}
+
+#[test]
+fn test_hostname_query() {
+	use crate::broadcast::{BroadcasterBuilder, Host, Service};
+	use std::sync::Arc;
+	use trust_dns_client::rr::{Name, RData, RecordType};
+
+	let host = Arc::new(
+		Host::new("searchlighttest.local.")
+			.unwrap()
+			.add_ip_address(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 69)))
+			.add_ip_address(IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0x69))),
+	);
+	let service = |service_type: &str| {
+		ServiceBuilder::new(service_type, "searchlighttest", 1337)
+			.unwrap()
+			.host(host.clone())
+			.build()
+			.unwrap()
+	};
+	let a = service("_searchlight-a._udp.local");
+	let b = service("_searchlight-b._udp.local");
+
+	let hostname = Name::from_str("searchlighttest.local.").unwrap();
+
+	// Only the requested address type is answered
+	let response = Service::address_dns_response([&a, &b], &hostname, RecordType::A).unwrap();
+	assert_eq!(response.answers().len(), 1);
+	assert_eq!(response.answers()[0].data(), Some(&RData::A(Ipv4Addr::new(192, 168, 1, 69))));
+	assert!(Service::address_dns_response([&a, &b], &Name::from_str("other.local.").unwrap(), RecordType::A).is_none());
+
+	// The broadcaster answers address queries for the hostname, without repeating the addresses of a shared host
+	let broadcaster = BroadcasterBuilder::new().add_service(a).add_service(b).into_config().unwrap();
+	let responses = broadcaster.simulate_query(hostname);
+	assert_eq!(responses.len(), 1);
+	assert_eq!(responses[0].answers().len(), 2);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_discovery_query_now() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::{DiscoveryBuilder, DiscoveryEvent},
+		net::IpVersion,
+	};
+	use std::{sync::mpsc, time::Duration};
+
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.add_service(
+			ServiceBuilder::new("_searchlight-query-now._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+				.build()
+				.unwrap(),
+		)
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background();
+
+	let (tx, rx) = mpsc::sync_channel(8);
+	let discovery = DiscoveryBuilder::new()
+		.loopback()
+		.service("_searchlight-query-now._udp.local")
+		.unwrap()
+		.interval(Duration::from_secs(60))
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background(move |event| {
+			tx.try_send(matches!(event, DiscoveryEvent::ResponderFound(_))).ok();
+		});
+
+	// The first discovery packet is sent immediately
+	assert!(rx.recv_timeout(Duration::from_secs(5)).unwrap());
+
+	// Without query_now, the next one wouldn't be sent for another minute
+	discovery.query_now();
+	assert!(!rx.recv_timeout(Duration::from_secs(5)).unwrap());
+
+	discovery.shutdown().unwrap();
+	broadcaster.shutdown().unwrap();
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_memory_transport() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::{DiscoveryBuilder, DiscoveryEvent},
+		tests::harness::{broadcast_for, kind, run_discovery_on},
+		transport::memory::MemoryNetwork,
+	};
+	use std::{sync::RwLock, time::Duration};
+	use trust_dns_client::{op::Message, serialize::binary::BinDecodable};
+
+	let broadcaster = RwLock::new(
+		BroadcasterBuilder::new()
+			.add_service(
+				ServiceBuilder::new("_searchlight-memory._udp.local", "searchlighttest", 1337)
+					.unwrap()
+					.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+					.build()
+					.unwrap(),
+			)
+			.into_config()
+			.unwrap(),
+	);
+
+	let network = MemoryNetwork::new();
+	let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+
+	// The broadcaster goes away after 10 seconds
+	let events = run_discovery_on(
+		&network,
+		DiscoveryBuilder::new().service("_searchlight-memory._udp.local").unwrap(),
+		|_| async {
+			broadcast_for(&broadcaster_socket, &broadcaster, Duration::from_secs(10)).await;
+			tokio::time::sleep(Duration::from_secs(110)).await;
+		},
+	);
+
+	let addr = "192.168.1.69:5353".parse().unwrap();
+	let found_and_lost = events
+		.iter()
+		.map(|event| (kind(event), event.responder().addr))
+		.filter(|(kind, _)| *kind != "update")
+		.collect::<Vec<_>>();
+	assert_eq!(found_and_lost, [("found", addr), ("lost", addr)]);
+
+	let responses = events
+		.iter()
+		.filter(|event| matches!(event, DiscoveryEvent::ResponderFound(_) | DiscoveryEvent::ResponseUpdate { .. }))
+		.map(DiscoveryEvent::responder)
+		.collect::<Vec<_>>();
+
+	// Each response counts towards the responder's seen count
+	assert!(!responses.is_empty());
+	assert!(responses.iter().map(|responder| responder.seen_count).eq(1..=responses.len() as u64));
+
+	// The raw packet is what the response was decoded from
+	assert!(responses
+		.iter()
+		.all(|responder| Message::from_bytes(&responder.raw_packet).ok().as_ref() == Some(&*responder.last_response)));
+}
+
+#[test]
+#[cfg(all(feature = "discovery", feature = "tracing"))]
+fn test_tracing_spans() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::DiscoveryBuilder,
+		tests::harness::{broadcast_for, run_discovery_on},
+		transport::memory::MemoryNetwork,
+	};
+	use std::{
+		fmt,
+		sync::{Arc, Mutex, RwLock},
+		time::Duration,
+	};
+	use tracing::{
+		field::{Field, Visit},
+		span, Event, Metadata, Subscriber,
+	};
+
+	#[derive(Default)]
+	struct Spans {
+		/// The name and `addr` field of every span, in the order they were created. A span's ID is its position in here, plus one.
+		created: Vec<(&'static str, Option<String>)>,
+
+		/// The names of the spans that were entered.
+		entered: Vec<&'static str>,
+	}
+
+	struct Addr<'a>(&'a mut Option<String>);
+	impl Visit for Addr<'_> {
+		fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+			if field.name() == "addr" {
+				*self.0 = Some(format!("{value:?}"));
+			}
+		}
+	}
+
+	// Records the spans that discovery creates
+	#[derive(Clone, Default)]
+	struct Recorder(Arc<Mutex<Spans>>);
+	impl Subscriber for Recorder {
+		fn enabled(&self, _: &Metadata<'_>) -> bool {
+			true
+		}
+
+		fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+			let mut addr = None;
+			span.record(&mut Addr(&mut addr));
+
+			let mut spans = self.0.lock().unwrap();
+			spans.created.push((span.metadata().name(), addr));
+			span::Id::from_u64(spans.created.len() as u64)
+		}
+
+		fn enter(&self, span: &span::Id) {
+			let mut spans = self.0.lock().unwrap();
+			let (name, _) = spans.created[span.into_u64() as usize - 1];
+			spans.entered.push(name);
+		}
+
+		fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+		fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+		fn event(&self, _: &Event<'_>) {}
+		fn exit(&self, _: &span::Id) {}
+	}
+
+	let broadcaster = RwLock::new(
+		BroadcasterBuilder::new()
+			.add_service(
+				ServiceBuilder::new("_searchlight-tracing._udp.local", "searchlighttest", 1337)
+					.unwrap()
+					.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+					.build()
+					.unwrap(),
+			)
+			.into_config()
+			.unwrap(),
+	);
+
+	let network = MemoryNetwork::new();
+	let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+
+	// The broadcaster answers one query and then goes away
+	let recorder = Recorder::default();
+	tracing::subscriber::with_default(recorder.clone(), || {
+		run_discovery_on(
+			&network,
+			DiscoveryBuilder::new().service("_searchlight-tracing._udp.local").unwrap(),
+			|_| async {
+				broadcast_for(&broadcaster_socket, &broadcaster, Duration::from_secs(5)).await;
+				tokio::time::sleep(Duration::from_secs(110)).await;
+			},
+		)
+	});
+
+	let spans = recorder.0.lock().unwrap();
+	let addr = Some("192.168.1.69:5353".to_string());
+
+	// Every query that discovery sends gets a span (the broadcaster's spans for the queries it receives carry their address), and the
+	// response to the first one is attributed to the broadcaster
+	assert!(spans.created.iter().filter(|span| **span == ("query", None)).count() > 1);
+	assert!(spans.created.contains(&("response", addr.clone())));
+
+	// Responders being found and lost get spans of their own, which the event handler is called in
+	assert!(spans.created.contains(&("responder_found", addr.clone())));
+	assert!(spans.created.contains(&("responder_lost", addr)));
+	assert!(spans.entered.contains(&"responder_found"));
+	assert!(spans.entered.contains(&"responder_lost"));
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_subtype_discovery() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::{DiscoveryBuilder, DiscoveryEvent},
+		tests::harness::{broadcast_for, run_discovery_on},
+		transport::memory::MemoryNetwork,
+	};
+	use std::{sync::RwLock, time::Duration};
+	use trust_dns_client::rr::Name;
+
+	let service = |name: &str| {
+		ServiceBuilder::new("_searchlight-subtype._udp.local", name, 1337)
+			.unwrap()
+			.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+	};
+
+	// Returns the service names of the responders that were found
+	let found = |service: ServiceBuilder| {
+		let broadcaster = RwLock::new(BroadcasterBuilder::new().add_service(service.build().unwrap()).into_config().unwrap());
+
+		let network = MemoryNetwork::new();
+		let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+
+		let events = run_discovery_on(
+			&network,
+			DiscoveryBuilder::new().subtype("_searchlight-subtype._udp.local", "_printer").unwrap(),
+			|_| broadcast_for(&broadcaster_socket, &broadcaster, Duration::from_secs(5)),
+		);
+
+		events
+			.into_iter()
+			.filter_map(|event| match event {
+				DiscoveryEvent::ResponderFound(responder) => Some(responder.service_name.clone()),
+				_ => None,
+			})
+			.collect::<Vec<_>>()
+	};
+
+	// The broadcaster answers the subtype query with the base service type, which discovery accepts
+	assert_eq!(
+		found(service("subtyped").can_subtype().unwrap()),
+		[Some(Name::from_str("_printer._sub._searchlight-subtype._udp.local.").unwrap())]
+	);
+	assert!(found(service("plain")).is_empty());
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_event_service_name() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::DiscoveryBuilder,
+		tests::harness::{broadcast_for, kind, run_discovery_on},
+		transport::memory::MemoryNetwork,
+	};
+	use std::{sync::RwLock, time::Duration};
+
+	// Returns the kind of each event along with the service name it carries
+	let service_names = |discovery: DiscoveryBuilder| {
+		let broadcaster = RwLock::new(
+			BroadcasterBuilder::new()
+				.add_service(
+					ServiceBuilder::new("_searchlight-name._udp.local", "searchlighttest", 1337)
+						.unwrap()
+						.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+						.build()
+						.unwrap(),
+				)
+				.into_config()
+				.unwrap(),
+		);
+
+		let network = MemoryNetwork::new();
+		let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+
+		let events = run_discovery_on(&network, discovery, |_| async {
+			broadcast_for(&broadcaster_socket, &broadcaster, Duration::from_secs(15)).await;
+			tokio::time::sleep(Duration::from_secs(110)).await;
+		});
+
+		let mut service_names = events
+			.iter()
+			.map(|event| (kind(event), event.service_name().map(|service_name| service_name.to_ascii())))
+			.collect::<Vec<_>>();
+		service_names.dedup();
+		service_names
+	};
+
+	// Every event about the responder carries the service name that its responses matched
+	let service_name = Some("_searchlight-name._udp.local.".to_string());
+	assert_eq!(
+		service_names(DiscoveryBuilder::new().service("_searchlight-name._udp.local").unwrap()),
+		[("found", service_name.clone()), ("update", service_name.clone()), ("lost", service_name)]
+	);
+
+	// Without a service name to match, there is nothing to carry
+	assert_eq!(
+		service_names(DiscoveryBuilder::new()),
+		[("found", None), ("update", None), ("lost", None)]
+	);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_service_types_query() {
+	use crate::{
+		broadcast::{Broadcaster, BroadcasterBuilder},
+		discovery::Responder,
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{
+		sync::RwLock,
+		time::{Duration, Instant, SystemTime},
+	};
+	use trust_dns_client::{
+		op::{DnsResponse, Message, MessageType, Query},
+		rr::{Name, RecordType},
+		serialize::binary::BinDecodable,
+	};
+
+	let service = |service_type: &str, name: &str| ServiceBuilder::new(service_type, name, 1337).unwrap().build().unwrap();
+	let builder = BroadcasterBuilder::new()
+		.add_service(service("_searchlight-a._udp.local", "one"))
+		.add_service(service("_searchlight-a._udp.local", "two"))
+		.add_service(service("_searchlight-b._udp.local", "three"));
+
+	let queries = [Name::root(), Name::from_str("_services._dns-sd._udp.local.").unwrap()].map(|name| {
+		let mut query = Query::new();
+		query.set_name(name).set_query_type(RecordType::PTR);
+		let mut message = Message::new();
+		message.add_query(query);
+		message.to_bytes().unwrap()
+	});
+
+	let responses = |builder: BroadcasterBuilder| {
+		let broadcaster = RwLock::new(builder.into_config().unwrap());
+
+		let network = MemoryNetwork::new();
+		let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+		let client = network.join("192.168.1.70:5353".parse().unwrap());
+
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.start_paused(true)
+			.build()
+			.unwrap();
+		runtime.block_on(async {
+			let mut recv = broadcaster_socket.recv(vec![0; 4096]);
+			for query in &queries {
+				client.send_multicast(query).await.unwrap();
+			}
+			tokio::time::timeout(
+				Duration::from_secs(1),
+				Broadcaster::recv_loop(&broadcaster_socket, &mut recv, &broadcaster),
+			)
+			.await
+			.ok();
+
+			let mut responses = Vec::new();
+			let mut recv = client.recv(vec![0; 4096]);
+			while let Ok(Ok(((count, _), _, packet))) = tokio::time::timeout(Duration::from_millis(10), recv.recv_multicast()).await {
+				let message = Message::from_bytes(&packet[..count]).unwrap();
+				if message.message_type() == MessageType::Response {
+					responses.push(message);
+				}
+			}
+			responses
+		})
+	};
+
+	// Both the root name and the meta-query name are answered with each service type once
+	let responses = responses(builder.clone());
+	assert_eq!(responses.len(), 2);
+	for response in responses {
+		let responder = Responder {
+			addr: "192.168.1.69:5353".parse().unwrap(),
+			last_response: DnsResponse::from(response),
+			raw_packet: Vec::new(),
+			service_name: None,
+			last_responded: Instant::now(),
+			last_responded_at: SystemTime::now(),
+			seen_count: 1,
+		};
+		assert_eq!(
+			responder.service_types(),
+			[
+				Name::from_str("_searchlight-a._udp.local.").unwrap(),
+				Name::from_str("_searchlight-b._udp.local.").unwrap()
+			]
+		);
+	}
+
+	assert!(responses(builder.answer_service_types(false)).is_empty());
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_browse_domains() {
+	use crate::{
+		broadcast::{Broadcaster, BroadcasterBuilder},
+		discovery::browse_domains,
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{sync::RwLock, time::Duration};
+	use trust_dns_client::{
+		op::{Message, MessageType, Query},
+		rr::{Name, RecordType},
+		serialize::binary::BinDecodable,
+	};
+
+	let queries = ["b._dns-sd._udp.local.", "db._dns-sd._udp.local."].map(|name| {
+		let mut query = Query::new();
+		query.set_name(Name::from_str(name).unwrap()).set_query_type(RecordType::PTR);
+		let mut message = Message::new();
+		message.add_query(query);
+		message.to_bytes().unwrap()
+	});
+
+	let domains = |builder: BroadcasterBuilder| {
+		let broadcaster = RwLock::new(builder.into_config().unwrap());
+
+		let network = MemoryNetwork::new();
+		let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+		let client = network.join("192.168.1.70:5353".parse().unwrap());
+
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.start_paused(true)
+			.build()
+			.unwrap();
+		runtime.block_on(async {
+			let mut recv = broadcaster_socket.recv(vec![0; 4096]);
+			for query in &queries {
+				client.send_multicast(query).await.unwrap();
+			}
+			tokio::time::timeout(
+				Duration::from_secs(1),
+				Broadcaster::recv_loop(&broadcaster_socket, &mut recv, &broadcaster),
+			)
+			.await
+			.ok();
+
+			let mut domains = Vec::new();
+			let mut recv = client.recv(vec![0; 4096]);
+			while let Ok(Ok(((count, _), _, packet))) = tokio::time::timeout(Duration::from_millis(10), recv.recv_multicast()).await {
+				let message = Message::from_bytes(&packet[..count]).unwrap();
+				if message.message_type() == MessageType::Response {
+					domains.push(browse_domains(&message).collect::<Vec<_>>());
+				}
+			}
+			domains
+		})
+	};
+
+	// Not answered unless domains are configured
+	let builder = BroadcasterBuilder::new().add_service(
+		ServiceBuilder::new("_searchlight._udp.local", "HELLO-WORLD", 1337)
+			.unwrap()
+			.build()
+			.unwrap(),
+	);
+	assert!(domains(builder.clone()).is_empty());
+
+	// Both queries are answered with every domain
+	let configured = vec![Name::from_str("local.").unwrap(), Name::from_str("example.com.").unwrap()];
+	assert_eq!(domains(builder.browse_domains(configured.clone())), [configured.clone(), configured]);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_passive_discovery() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::DiscoveryBuilder,
+		tests::harness::{broadcast_for, kind, run_discovery_on},
+		transport::memory::MemoryNetwork,
+	};
+	use std::{sync::RwLock, time::Duration};
+
+	let events = |initial_query: bool| {
+		let broadcaster = RwLock::new(
+			BroadcasterBuilder::new()
+				.add_service(
+					ServiceBuilder::new("_searchlight-passive._udp.local", "searchlighttest", 1337)
+						.unwrap()
+						.ttl(30)
+						.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+						.build()
+						.unwrap(),
+				)
+				.into_config()
+				.unwrap(),
+		);
+		let discovery = DiscoveryBuilder::new()
+			.service("_searchlight-passive._udp.local")
+			.unwrap()
+			.interval(Duration::from_secs(5))
+			.passive(initial_query);
+
+		let network = MemoryNetwork::new();
+		let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+
+		let events = run_discovery_on(&network, discovery, |_| {
+			broadcast_for(&broadcaster_socket, &broadcaster, Duration::from_secs(60))
+		});
+		events.iter().map(kind).collect::<Vec<_>>()
+	};
+
+	// The broadcaster is still there, but only answers the initial query, so the responder is lost when its answers expire
+	assert_eq!(events(true), ["found", "lost"]);
+
+	// Without the initial query, nothing is found until the broadcaster announces itself
+	assert!(events(false).is_empty());
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_raw_mode() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::DiscoveryBuilder,
+		tests::harness::{broadcast_for, kind, run_discovery_on},
+		transport::memory::MemoryNetwork,
+	};
+	use std::{sync::RwLock, time::Duration};
+
+	let broadcaster = RwLock::new(
+		BroadcasterBuilder::new()
+			.add_service(
+				ServiceBuilder::new("_searchlight-raw._udp.local", "searchlighttest", 1337)
+					.unwrap()
+					.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+					.build()
+					.unwrap(),
+			)
+			.into_config()
+			.unwrap(),
+	);
+	let discovery = DiscoveryBuilder::new()
+		.service("_searchlight-raw._udp.local")
+		.unwrap()
+		.interval(Duration::from_secs(4))
+		.raw_mode(true);
+
+	let network = MemoryNetwork::new();
+	let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+
+	// The broadcaster goes away after 10 seconds
+	let events = run_discovery_on(&network, discovery, |_| async {
+		broadcast_for(&broadcaster_socket, &broadcaster, Duration::from_secs(10)).await;
+		tokio::time::sleep(Duration::from_secs(50)).await;
+	});
+
+	// Every answer is reported on its own, and the broadcaster going away isn't
+	let events = events.iter().map(|event| (kind(event), event.responder().seen_count)).collect::<Vec<_>>();
+	assert_eq!(events, [("raw", 1), ("raw", 1), ("raw", 1)]);
+}
+
+#[test]
+fn test_decode_error_hook() {
+	use crate::{
+		broadcast::{Broadcaster, BroadcasterBuilder},
+		transport::{memory::MemoryNetwork, MdnsTransport},
+	};
+	use std::{
+		net::SocketAddr,
+		sync::{Arc, Mutex, RwLock},
+		time::Duration,
+	};
+
+	let errors = Arc::new(Mutex::new(Vec::new()));
+	let broadcaster = RwLock::new(
+		BroadcasterBuilder::new()
+			.on_decode_error({
+				let errors = errors.clone();
+				move |addr, packet, _err| errors.lock().unwrap().push((addr, packet.to_vec()))
+			})
+			.into_config()
+			.unwrap(),
+	);
+
+	let network = MemoryNetwork::new();
+	let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+	let sender = network.join("192.168.1.70:5353".parse().unwrap());
+
+	// A header claiming one question, followed by a name that never ends
+	let garbage = [0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0x3f, b'x'];
+
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.start_paused(true)
+		.build()
+		.unwrap();
+	runtime.block_on(async {
+		let mut recv = broadcaster_socket.recv(vec![0; 4096]);
+		sender.send_multicast(&garbage).await.unwrap();
+
+		tokio::time::timeout(
+			Duration::from_secs(1),
+			Broadcaster::recv_loop(&broadcaster_socket, &mut recv, &broadcaster),
+		)
+		.await
+		.ok();
+	});
+
+	let addr: SocketAddr = "192.168.1.70:5353".parse().unwrap();
+	assert_eq!(*errors.lock().unwrap(), [(addr, garbage.to_vec())]);
+}
+
+#[test]
+fn test_minimal_unicast() {
+	use crate::{
+		broadcast::{Broadcaster, BroadcasterBuilder},
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{sync::RwLock, time::Duration};
+	use trust_dns_client::{
+		op::{Message, MessageType, Query},
+		rr::{Name, RecordType},
+		serialize::binary::BinDecodable,
+	};
+
+	let broadcaster = RwLock::new(
+		BroadcasterBuilder::new()
+			.add_service(
+				ServiceBuilder::new("_searchlight._udp.local", "HELLO-WORLD", 1337)
+					.unwrap()
+					.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+					.add_txt("key=value")
+					.build()
+					.unwrap(),
+			)
+			.minimal_unicast(true)
+			.into_config()
+			.unwrap(),
+	);
+
+	let network = MemoryNetwork::new();
+	let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+	let client = network.join("192.168.1.70:5353".parse().unwrap());
+
+	let query = |unicast: bool| {
+		let mut query = Query::new();
+		query
+			.set_name(Name::from_str("_searchlight._udp.local.").unwrap())
+			.set_query_type(RecordType::PTR)
+			.set_mdns_unicast_response(unicast);
+		let mut message = Message::new();
+		message.add_query(query);
+		message.to_bytes().unwrap()
+	};
+
+	// Returns the types of the records in the response to the query
+	let response_records = |unicast: bool| {
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.start_paused(true)
+			.build()
+			.unwrap();
+		runtime.block_on(async {
+			let mut recv = broadcaster_socket.recv(vec![0; 4096]);
+			client.send_multicast(&query(unicast)).await.unwrap();
+			tokio::time::timeout(
+				Duration::from_secs(1),
+				Broadcaster::recv_loop(&broadcaster_socket, &mut recv, &broadcaster),
+			)
+			.await
+			.ok();
+
+			let mut recv = client.recv(vec![0; 4096]);
+			loop {
+				let ((count, _), _, packet) = recv.recv_multicast().await.unwrap();
+				let message = Message::from_bytes(&packet[..count]).unwrap();
+				if message.message_type() == MessageType::Response {
+					return message
+						.answers()
+						.iter()
+						.chain(message.additionals())
+						.map(|record| record.record_type())
+						.collect::<Vec<_>>();
+				}
+			}
+		})
+	};
+
+	assert_eq!(
+		response_records(false),
+		[RecordType::PTR, RecordType::A, RecordType::SRV, RecordType::TXT]
+	);
+	assert_eq!(response_records(true), [RecordType::PTR, RecordType::A, RecordType::SRV]);
+}
+
+#[test]
+fn test_multiple_questions() {
+	use crate::{
+		broadcast::{Broadcaster, BroadcasterBuilder},
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{sync::RwLock, time::Duration};
+	use trust_dns_client::{
+		op::{Message, MessageType, Query},
+		rr::{Name, RData, RecordType},
+		serialize::binary::BinDecodable,
+	};
+
+	let service = |service_type: &str| ServiceBuilder::new(service_type, "searchlighttest", 1337).unwrap().build().unwrap();
+	let broadcaster = RwLock::new(
+		BroadcasterBuilder::new()
+			.add_service(service("_searchlight-a._udp.local"))
+			.add_service(service("_searchlight-b._udp.local"))
+			.add_service(service("_searchlight-c._udp.local"))
+			.into_config()
+			.unwrap(),
+	);
+
+	let network = MemoryNetwork::new();
+	let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+	let client = network.join("192.168.1.70:5353".parse().unwrap());
+
+	// One query asking for two of the service types
+	let mut message = Message::new();
+	for service_type in ["_searchlight-a._udp.local.", "_searchlight-b._udp.local."] {
+		let mut query = Query::new();
+		query.set_name(Name::from_str(service_type).unwrap()).set_query_type(RecordType::PTR);
+		message.add_query(query);
+	}
+	let query = message.to_bytes().unwrap();
+
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.start_paused(true)
+		.build()
+		.unwrap();
+	let mut answered = runtime.block_on(async {
+		let mut recv = broadcaster_socket.recv(vec![0; 4096]);
+		client.send_multicast(&query).await.unwrap();
+		tokio::time::timeout(
+			Duration::from_secs(1),
+			Broadcaster::recv_loop(&broadcaster_socket, &mut recv, &broadcaster),
+		)
+		.await
+		.ok();
+
+		let mut answered = Vec::new();
+		let mut recv = client.recv(vec![0; 4096]);
+		while let Ok(Ok(((count, _), _, packet))) = tokio::time::timeout(Duration::from_millis(10), recv.recv_multicast()).await {
+			let message = Message::from_bytes(&packet[..count]).unwrap();
+			if message.message_type() == MessageType::Response {
+				answered.extend(message.answers().iter().filter_map(|answer| match answer.data() {
+					Some(RData::PTR(_)) => Some(answer.name().to_utf8()),
+					_ => None,
+				}));
+			}
+		}
+		answered
+	});
+	answered.sort();
+
+	// Both questions are answered, not just the first
+	assert_eq!(answered, ["_searchlight-a._udp.local.", "_searchlight-b._udp.local."]);
+}
+
+#[test]
+fn test_unmatched_query_hook() {
+	use crate::{
+		broadcast::{Broadcaster, BroadcasterBuilder},
+		transport::{memory::MemoryNetwork, MdnsTransport},
+	};
+	use std::{
+		net::SocketAddr,
+		sync::{Arc, Mutex, RwLock},
+		time::Duration,
+	};
+	use trust_dns_client::{
+		op::{Message, Query},
+		rr::{Name, RecordType},
+	};
+
+	let unmatched = Arc::new(Mutex::new(Vec::new()));
+	let broadcaster = RwLock::new(
+		BroadcasterBuilder::new()
+			.add_service(
+				ServiceBuilder::new("_searchlight._udp.local", "HELLO-WORLD", 1337)
+					.unwrap()
+					.build()
+					.unwrap(),
+			)
+			.on_unmatched_query({
+				let unmatched = unmatched.clone();
+				move |query, addr| unmatched.lock().unwrap().push((query.name().clone(), addr))
+			})
+			.into_config()
+			.unwrap(),
+	);
+
+	let network = MemoryNetwork::new();
+	let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+	let client = network.join("192.168.1.70:5353".parse().unwrap());
+
+	let query = |name: &str| {
+		let mut query = Query::new();
+		query.set_name(Name::from_str(name).unwrap()).set_query_type(RecordType::PTR);
+		let mut message = Message::new();
+		message.add_query(query);
+		message.to_bytes().unwrap()
+	};
+
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.start_paused(true)
+		.build()
+		.unwrap();
+	runtime.block_on(async {
+		let mut recv = broadcaster_socket.recv(vec![0; 4096]);
+		client.send_multicast(&query("_searchlight._udp.local.")).await.unwrap();
+		client.send_multicast(&query("_other._udp.local.")).await.unwrap();
+
+		tokio::time::timeout(
+			Duration::from_secs(1),
+			Broadcaster::recv_loop(&broadcaster_socket, &mut recv, &broadcaster),
+		)
+		.await
+		.ok();
+	});
+
+	// Only the query for the service type we aren't advertising is reported
+	let addr: SocketAddr = "192.168.1.70:5353".parse().unwrap();
+	assert_eq!(*unmatched.lock().unwrap(), [(Name::from_str("_other._udp.local.").unwrap(), addr)]);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_startup_queries() {
+	use crate::{
+		discovery::DiscoveryBuilder,
+		tests::harness::run_discovery_on,
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::time::Duration;
+
+	// Returns how many seconds after startup each discovery packet was sent, over the first 30 seconds
+	let query_times = |builder: DiscoveryBuilder| {
+		let builder = builder
+			.interval(Duration::from_secs(20))
+			.response_collect_window(Duration::from_millis(100));
+
+		let network = MemoryNetwork::new();
+		let observer = network.join("192.168.1.71:5353".parse().unwrap());
+
+		let mut times = Vec::new();
+		run_discovery_on(&network, builder, |_| async {
+			let start = tokio::time::Instant::now();
+			let mut recv = observer.recv(vec![0; 4096]);
+			loop {
+				tokio::select! {
+					_ = recv.recv_multicast() => times.push(start.elapsed().as_secs()),
+					_ = tokio::time::sleep_until(start + Duration::from_secs(30)) => break,
+				}
+			}
+		});
+		times
+	};
+
+	assert_eq!(query_times(DiscoveryBuilder::new()), [0, 20]);
+	assert_eq!(
+		query_times(DiscoveryBuilder::new().startup_queries(3, Duration::from_secs(1))),
+		[0, 1, 2, 22]
+	);
+}
+
+#[test]
+fn test_instance_dns_response() {
+	use trust_dns_client::rr::{RData, RecordType};
+
+	let service = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.add_txt("path=/")
+		.build()
+		.unwrap();
+
+	let srv = service.instance_dns_response(RecordType::SRV).unwrap();
+	assert_eq!(srv.answers().len(), 1);
+	assert_eq!(srv.answers()[0].name(), service.service_id());
+	assert!(matches!(srv.answers()[0].data(), Some(RData::SRV(srv)) if srv.port() == 1337));
+	assert_eq!(srv.additionals().len(), 1);
+
+	let txt = service.instance_dns_response(RecordType::TXT).unwrap();
+	assert_eq!(txt.answers().len(), 1);
+	assert_eq!(txt.answers()[0].record_type(), RecordType::TXT);
+	assert!(txt.additionals().is_empty());
+
+	let any = service.instance_dns_response(RecordType::ANY).unwrap();
+	assert_eq!(any.answers().len(), 2);
+
+	assert!(service.instance_dns_response(RecordType::A).is_none());
+}
+
+#[test]
+fn test_response_layout() {
+	use crate::broadcast::Service;
+	use trust_dns_client::rr::{Record, RecordType};
+
+	let builder = ServiceBuilder::new("_searchlight-test._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()));
+
+	let record_types = |records: &[Record]| records.iter().map(|record| record.record_type()).collect::<Vec<_>>();
+
+	// By default, only the PTR record is an answer
+	let response = builder.clone().build().unwrap().dns_response().unwrap();
+	assert!(response.header().authoritative());
+	assert_eq!(record_types(response.answers()), [RecordType::PTR]);
+	assert_eq!(record_types(response.additionals()), [RecordType::A, RecordType::SRV, RecordType::TXT]);
+
+	let service = builder.authoritative(false).srv_txt_in_answers(true).build().unwrap();
+	let response = service.dns_response().unwrap();
+	assert!(!response.header().authoritative());
+	assert_eq!(response.header().answer_count(), 3);
+	assert_eq!(record_types(response.answers()), [RecordType::PTR, RecordType::SRV, RecordType::TXT]);
+	assert_eq!(record_types(response.additionals()), [RecordType::A]);
+
+	// The layout survives a round trip through the response
+	let rebuilt = Service::from_response(&response).unwrap();
+	assert!(!rebuilt.is_authoritative());
+	assert!(rebuilt.srv_txt_in_answers());
+	assert_eq!(rebuilt.dns_response().unwrap(), response);
+
+	let other = ServiceBuilder::new("_searchlight-other._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.build()
+		.unwrap();
+	let combined = Service::combined_dns_response([&service, &other]).unwrap();
+	assert!(!combined.header().authoritative());
+	assert_eq!(
+		record_types(combined.answers()),
+		[RecordType::PTR, RecordType::SRV, RecordType::TXT, RecordType::PTR]
+	);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_node() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::{DiscoveryBuilder, DiscoveryEvent},
+		net::IpVersion,
+		node::Node,
+	};
+	use std::{sync::mpsc, time::Duration};
+
+	let (tx, rx) = mpsc::sync_channel(8);
+
+	// The node discovers its own service through the shared socket
+	let node = Node::build(
+		BroadcasterBuilder::new().loopback().add_service(
+			ServiceBuilder::new("_searchlight-node._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+				.build()
+				.unwrap(),
+		),
+		DiscoveryBuilder::new().service("_searchlight-node._udp.local").unwrap(),
+		IpVersion::V4,
+	)
+	.unwrap()
+	.run_in_background(move |event| {
+		if let DiscoveryEvent::ResponderFound(responder) = event {
+			tx.try_send(responder.service_name.clone()).ok();
+		}
+	});
+
+	assert_eq!(
+		rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap().to_string(),
+		"_searchlight-node._udp.local."
+	);
+
+	assert!(node.broadcaster().remove_service_type("_searchlight-node._udp.local").unwrap());
+	assert!(node.broadcaster().services().is_empty());
+
+	node.shutdown().unwrap();
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_run_on() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::{DiscoveryBuilder, DiscoveryEvent},
+		net::IpVersion,
+	};
+	use std::{sync::mpsc, time::Duration};
+
+	let runtime = tokio::runtime::Builder::new_multi_thread()
+		.worker_threads(1)
+		.enable_all()
+		.build()
+		.unwrap();
+
+	let (tx, rx) = mpsc::sync_channel(8);
+
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.add_service(
+			ServiceBuilder::new("_searchlight-run-on._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+				.build()
+				.unwrap(),
+		)
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_on(runtime.handle().clone());
+
+	let discovery = DiscoveryBuilder::new()
+		.loopback()
+		.service("_searchlight-run-on._udp.local")
+		.unwrap()
+		.build(IpVersion::V4)
+		.unwrap();
+
+	let local_addrs = discovery.local_addrs().unwrap();
+	assert_eq!(local_addrs.len(), 1);
+	assert!(local_addrs[0].is_ipv4());
+	assert_eq!(local_addrs[0].port(), crate::MDNS_PORT);
+
+	let discovery = discovery.run_on(runtime.handle().clone(), move |event| {
+		if let DiscoveryEvent::ResponderFound(responder) = event {
+			tx.try_send(responder.service_name.clone()).ok();
+		}
+	});
+
+	assert_eq!(
+		rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap().to_string(),
+		"_searchlight-run-on._udp.local."
+	);
+
+	discovery.shutdown().unwrap();
+	broadcaster.shutdown().unwrap();
+}
+
+#[test]
+fn test_service_interfaces() {
+	use crate::{
+		broadcast::{Broadcaster, BroadcasterBuilder, DelayedResponses},
+		net::RecvInterface,
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{net::SocketAddr, sync::RwLock, time::Duration};
+	use trust_dns_client::{
+		op::{Message as DnsMessage, Query as DnsQuery},
+		rr::{Name as DnsName, RecordType},
+		serialize::binary::BinDecodable,
+	};
+
+	let lan = Ipv4Addr::new(192, 168, 1, 69);
+	let vpn = Ipv4Addr::new(10, 0, 0, 69);
+
+	let broadcaster = RwLock::new(
+		BroadcasterBuilder::new()
+			.add_service(
+				ServiceBuilder::new("_searchlight-iface._udp.local", "lan", 1337)
+					.unwrap()
+					.add_ip_address(IpAddr::V4(lan))
+					.add_interface_v4(lan)
+					.build()
+					.unwrap(),
+			)
+			.add_service(
+				ServiceBuilder::new("_searchlight-iface._udp.local", "vpn", 1337)
+					.unwrap()
+					.add_ip_address(IpAddr::V4(vpn))
+					.add_interface_v4(vpn)
+					.build()
+					.unwrap(),
+			)
+			.add_service(
+				ServiceBuilder::new("_searchlight-iface._udp.local", "everywhere", 1337)
+					.unwrap()
+					.add_ip_address(IpAddr::V4(lan))
+					.build()
+					.unwrap(),
+			)
+			.into_config()
+			.unwrap(),
+	);
+
+	let querier_addr: SocketAddr = "192.168.1.70:5353".parse().unwrap();
+
+	let mut query = DnsMessage::new();
+	query.add_query(DnsQuery::query(
+		DnsName::from_str("_searchlight-iface._udp.local.").unwrap(),
+		RecordType::PTR,
+	));
+
+	// Returns the instances that answered a query received on `interface`
+	let answered = |interface: Option<RecvInterface>| {
+		let network = MemoryNetwork::new();
+		let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+		let querier = network.join(querier_addr);
+
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.start_paused(true)
+			.build()
+			.unwrap();
+		runtime.block_on(async {
+			Broadcaster::handle_query(
+				&broadcaster_socket,
+				&mut vec![0; 4096],
+				&mut DelayedResponses::default(),
+				&broadcaster,
+				&query,
+				querier_addr,
+				interface,
+			)
+			.await
+			.unwrap();
+
+			let mut answered = Vec::new();
+			let mut recv = querier.recv(vec![0; 4096]);
+			while let Ok(Ok(((count, _), _, packet))) = tokio::time::timeout(Duration::from_secs(1), recv.recv_multicast()).await {
+				let response = DnsMessage::from_bytes(&packet[..count]).unwrap();
+				answered.extend(response.answers().iter().filter_map(|answer| match answer.data() {
+					Some(trust_dns_client::rr::RData::PTR(name)) => Some(name.to_utf8()),
+					_ => None,
+				}));
+			}
+			answered.sort();
+			answered
+		})
+	};
+
+	assert_eq!(
+		answered(Some(RecvInterface::V4(lan))),
+		["everywhere._searchlight-iface._udp.local.", "lan._searchlight-iface._udp.local."]
+	);
+	assert_eq!(
+		answered(Some(RecvInterface::V4(vpn))),
+		["everywhere._searchlight-iface._udp.local.", "vpn._searchlight-iface._udp.local."]
+	);
+	assert_eq!(answered(None), ["everywhere._searchlight-iface._udp.local."]);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_unicast_resolver() {
+	use crate::{
+		discovery::{DiscoveryBuilder, DiscoveryEvent},
+		tests::harness::run_discovery_on,
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{net::SocketAddr, time::Duration};
+	use trust_dns_client::{op::Message as DnsMessage, serialize::binary::BinDecodable};
+
+	let resolver_addr: SocketAddr = "10.0.0.53:53".parse().unwrap();
+
+	let service = ServiceBuilder::new("_searchlight-resolver._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("10.0.0.69").unwrap()))
+		.build()
+		.unwrap();
+
+	// Returns the responders found when the resolver answers with the query's transaction ID plus `id_offset`
+	let found = |id_offset: u16| {
+		let network = MemoryNetwork::new();
+		let resolver = network.join(resolver_addr);
+
+		// A plain DNS server: it ignores mDNS queries, and answers recursive queries directly
+		let resolve = async {
+			let mut recv = resolver.recv(vec![0; 4096]);
+			loop {
+				let ((count, addr), _, packet) = recv.recv_multicast().await.unwrap();
+				let query = DnsMessage::from_bytes(&packet[..count]).unwrap();
+				if !query.recursion_desired() {
+					assert_eq!(query.id(), 0, "mDNS queries should have a transaction ID of zero");
+					continue;
+				}
+
+				assert_ne!(query.id(), 0);
+				assert!(query.queries().iter().all(|query| !query.mdns_unicast_response()));
+
+				let mut response = service.dns_response().unwrap();
+				response.set_id(query.id().wrapping_add(id_offset));
+				resolver.send_to(&response.to_bytes().unwrap(), addr).await.unwrap();
+			}
+		};
+
+		let events = run_discovery_on(
+			&network,
+			DiscoveryBuilder::new()
+				.service("_searchlight-resolver._udp.local")
+				.unwrap()
+				.unicast_resolver(resolver_addr),
+			|_| async {
+				tokio::select! {
+					_ = resolve => unreachable!(),
+					_ = tokio::time::sleep(Duration::from_secs(30)) => {}
+				}
+			},
+		);
+
+		events
+			.iter()
+			.filter_map(|event| match event {
+				DiscoveryEvent::ResponderFound(responder) => Some(responder.addr),
+				_ => None,
+			})
+			.collect::<Vec<_>>()
+	};
+
+	assert_eq!(found(0), [resolver_addr]);
+
+	// Responses that don't match the transaction ID are ignored
+	assert!(found(1).is_empty());
+}
+
+#[test]
+fn test_recv_errors_backoff() {
+	use crate::{
+		errors::MultiIpIoError,
+		net::{ErrorPolicy, ReconnectPolicy},
+		socket::{LoopError, RecvErrors},
+	};
+	use std::time::Duration;
+
+	let policy = ReconnectPolicy {
+		error_threshold: 3,
+		backoff: Duration::from_secs(1),
+		max_backoff: Duration::from_secs(4),
+		recreate_socket: true,
+		..Default::default()
+	};
+
+	let stop = |stop: Option<fn(MultiIpIoError) -> LoopError>| stop.map(|wrap| wrap(std::io::Error::from(std::io::ErrorKind::Other).into()));
+
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.start_paused(true)
+		.build()
+		.unwrap();
+	runtime.block_on(async {
+		let mut errors = RecvErrors::new(&policy);
+		let start = tokio::time::Instant::now();
+
+		// Below the threshold, errors are just counted
+		assert!(errors.error().await.is_none());
+		assert!(errors.error().await.is_none());
+		assert_eq!(start.elapsed(), Duration::ZERO);
+
+		// Then each error backs off, doubling up to the maximum
+		for _ in 0..4 {
+			assert!(matches!(stop(errors.error().await), Some(LoopError::Recreate(_))));
+		}
+		assert_eq!(start.elapsed(), Duration::from_secs(1 + 2 + 4 + 4));
+
+		// A successful receive starts over
+		errors.ok();
+		let start = tokio::time::Instant::now();
+		assert!(errors.error().await.is_none());
+		assert!(errors.error().await.is_none());
+		assert!(matches!(stop(errors.error().await), Some(LoopError::Recreate(_))));
+		assert_eq!(start.elapsed(), Duration::from_secs(1));
+	});
+
+	let policy = ReconnectPolicy {
+		recreate_socket: false,
+		..policy
+	};
+	runtime.block_on(async {
+		let mut errors = RecvErrors::new(&policy);
+		for _ in 0..10 {
+			assert!(errors.error().await.is_none());
+		}
+	});
+
+	// Aborting stops as soon as the threshold is reached, without backing off
+	let policy = ReconnectPolicy {
+		recv_errors: ErrorPolicy::Abort,
+		..policy
+	};
+	runtime.block_on(async {
+		let mut errors = RecvErrors::new(&policy);
+		let start = tokio::time::Instant::now();
+		assert!(errors.error().await.is_none());
+		assert!(errors.error().await.is_none());
+		assert!(matches!(stop(errors.error().await), Some(LoopError::Abort(_))));
+		assert_eq!(start.elapsed(), Duration::ZERO);
+	});
+
+	assert!(policy.send_error(std::io::Error::from(std::io::ErrorKind::Other).into()).is_ok());
+	let policy = ReconnectPolicy {
+		send_errors: ErrorPolicy::Abort,
+		..policy
+	};
+	assert!(matches!(
+		policy.send_error(std::io::Error::from(std::io::ErrorKind::Other).into()),
+		Err(LoopError::Abort(_))
+	));
+}
+
+#[test]
+fn test_join_with_retry() {
+	use crate::socket::join_with_retry;
+
+	// An interface that briefly rejects joins is kept
+	let mut attempts = 0;
+	assert!(join_with_retry(|| {
+		attempts += 1;
+		if attempts < 3 {
+			Err(std::io::ErrorKind::AddrNotAvailable.into())
+		} else {
+			Ok(())
+		}
+	}));
+	assert_eq!(attempts, 3);
+
+	// One that keeps rejecting them is given up on
+	let mut attempts = 0;
+	assert!(!join_with_retry(|| {
+		attempts += 1;
+		Err(std::io::ErrorKind::AddrNotAvailable.into())
+	}));
+	assert_eq!(attempts, 3);
+}
+
+#[test]
+fn test_max_responses_per_sec() {
+	use crate::broadcast::ResponseThrottle;
+	use std::time::Duration;
+
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.start_paused(true)
+		.build()
+		.unwrap();
+	runtime.block_on(async {
+		let throttle = ResponseThrottle::new(2);
+
+		// A second's worth of responses can be sent in a burst
+		assert!(throttle.try_send());
+		assert!(throttle.try_send());
+		assert!(!throttle.try_send());
+		assert_eq!(throttle.dropped(), 1);
+
+		// And then the bucket refills over time
+		tokio::time::advance(Duration::from_millis(500)).await;
+		assert!(throttle.try_send());
+		assert!(!throttle.try_send());
+		assert_eq!(throttle.dropped(), 2);
+	});
+}
+
+#[test]
+fn test_features() {
+	let features = crate::features();
+	assert_eq!(features.broadcast, cfg!(feature = "broadcast"));
+	assert_eq!(features.discovery, cfg!(feature = "discovery"));
+	assert_eq!(features.stream, cfg!(feature = "stream"));
+	assert_eq!(features.tracing, cfg!(feature = "tracing"));
+}
+
+#[test]
+fn test_interface_stats() {
+	use crate::net::{InterfaceStats, Ipv6Interface, RecvInterface, TrafficCounters};
+
+	let v4 = RecvInterface::from(Ipv4Addr::new(192, 168, 1, 69));
+	let v6 = RecvInterface::from(Ipv6Interface::from_scope_id(2).unwrap());
+
+	let counters = TrafficCounters::default();
+	assert!(counters.stats().per_interface.is_empty());
+
+	counters.sent(v4);
+	counters.received(v4);
+	counters.received(v4);
+	counters.received(v6);
+
+	let stats = counters.stats();
+	assert_eq!(stats.per_interface.len(), 2);
+	assert_eq!(stats.per_interface[&v4], InterfaceStats { sent: 1, received: 2 });
+	assert_eq!(stats.per_interface[&v6], InterfaceStats { sent: 0, received: 1 });
+}
+
+#[test]
+fn test_send_to_multicast_interfaces() {
+	use crate::{
+		net::{InterfaceStats, RecvInterface, TrafficCounters},
+		socket::InterfacedMdnsSocket,
+	};
+	use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+	let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+	runtime.block_on(async {
+		let receiver = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let receiver_addr = receiver.local_addr().unwrap();
+
+		// The packet is "multicast" to the receiver, which is fine as the interface is only used to route multicast packets.
+		// No machine has the address of the second interface, so it can't be used to send anything
+		let working = Ipv4Addr::LOCALHOST;
+		let broken = Ipv4Addr::new(198, 51, 100, 1);
+
+		let counters = Arc::new(TrafficCounters::default());
+		let socket = InterfacedMdnsSocket::new(
+			tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+			BTreeSet::from([working, broken]),
+			counters.clone(),
+		);
+
+		// The broken interface is skipped, rather than stopping the packet from going out on the working one
+		socket.send_to_multicast(b"searchlight", receiver_addr).await.unwrap();
+
+		let mut buf = [0; 64];
+		let (len, _) = tokio::time::timeout(Duration::from_secs(5), receiver.recv_from(&mut buf))
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(&buf[..len], b"searchlight");
+
+		// Only the copy that went out is counted
+		let stats = counters.stats();
+		assert_eq!(
+			stats.per_interface[&RecvInterface::from(working)],
+			InterfaceStats { sent: 1, received: 0 }
+		);
+		assert!(!stats.per_interface.contains_key(&RecvInterface::from(broken)));
+
+		// Sending only fails if it failed on every interface
+		let socket = InterfacedMdnsSocket::new(
+			tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+			BTreeSet::from([broken, Ipv4Addr::new(198, 51, 100, 2)]),
+			Arc::new(TrafficCounters::default()),
+		);
+		assert!(socket.send_to_multicast(b"searchlight", receiver_addr).await.is_err());
+	});
+}
+
+#[test]
+fn test_strict_multicast() {
+	use crate::{errors::MultiIpIoError, socket::multicol_send_result};
+
+	let err = || -> Result<(), std::io::Error> { Err(std::io::ErrorKind::NetworkUnreachable.into()) };
+
+	// Lenient mode only fails if both IP versions failed
+	assert!(multicol_send_result(false, Ok(()), Ok(())).is_ok());
+	assert!(multicol_send_result(false, err(), Ok(())).is_ok());
+	assert!(multicol_send_result(false, Ok(()), err()).is_ok());
+	assert!(matches!(multicol_send_result(false, err(), err()), Err(MultiIpIoError::Both { .. })));
+
+	// Strict mode fails if either of them failed
+	assert!(multicol_send_result(true, Ok(()), Ok(())).is_ok());
+	assert!(matches!(multicol_send_result(true, err(), Ok(())), Err(MultiIpIoError::V4(_))));
+	assert!(matches!(multicol_send_result(true, Ok(()), err()), Err(MultiIpIoError::V6(_))));
+	assert!(matches!(multicol_send_result(true, err(), err()), Err(MultiIpIoError::Both { .. })));
+}
+
+#[test]
+fn test_bind_port() {
+	use crate::{broadcast::BroadcasterBuilder, net::IpVersion};
+	use std::{net::UdpSocket, time::Duration};
+	use trust_dns_client::{
+		op::{Message, Query},
+		rr::{Name, RecordType},
+		serialize::binary::BinDecodable,
+	};
+
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.bind_port(0)
+		.add_service(
+			ServiceBuilder::new("_searchlight-bind._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+				.build()
+				.unwrap(),
+		)
+		.build(IpVersion::V4)
+		.unwrap();
+
+	let local_addrs = broadcaster.local_addrs().unwrap();
+	assert_eq!(local_addrs.len(), 1);
+	let port = local_addrs[0].port();
+	assert_ne!(port, crate::MDNS_PORT);
+
+	let broadcaster = broadcaster.run_in_background();
+
+	// Queries sent directly to the bound port are answered
+	let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+	client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+	let mut query = Query::new();
+	query
+		.set_name(Name::from_str("_searchlight-bind._udp.local.").unwrap())
+		.set_query_type(RecordType::PTR)
+		.set_mdns_unicast_response(true);
+	let mut message = Message::new();
+	message.add_query(query);
+	client.send_to(&message.to_bytes().unwrap(), (Ipv4Addr::LOCALHOST, port)).unwrap();
+
+	let mut buf = [0; 4096];
+	let (count, addr) = client.recv_from(&mut buf).unwrap();
+	assert_eq!(addr.port(), port);
+
+	let response = Message::from_bytes(&buf[..count]).unwrap();
+	assert!(response
+		.answers()
+		.iter()
+		.any(|record| record.name().to_string() == "_searchlight-bind._udp.local."));
+
+	broadcaster.shutdown().unwrap();
+}
+
+#[test]
+fn test_dropped_responses_stats() {
+	use crate::{broadcast::BroadcasterBuilder, net::IpVersion};
+	use std::{net::UdpSocket, time::Duration};
+	use trust_dns_client::{
+		op::{Message, Query},
+		rr::{Name, RecordType},
+	};
+
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.bind_port(0)
+		.max_responses_per_sec(1)
+		.add_service(
+			ServiceBuilder::new("_searchlight-dropped._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+				.build()
+				.unwrap(),
+		)
+		.build(IpVersion::V4)
+		.unwrap();
+	let port = broadcaster.local_addrs().unwrap()[0].port();
+	let broadcaster = broadcaster.run_in_background();
+	assert_eq!(broadcaster.stats().dropped_responses, 0);
+
+	let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+	client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+	let mut query = Query::new();
+	query
+		.set_name(Name::from_str("_searchlight-dropped._udp.local.").unwrap())
+		.set_query_type(RecordType::PTR)
+		.set_mdns_unicast_response(true);
+	let mut message = Message::new();
+	message.add_query(query);
+	let query = message.to_bytes().unwrap();
+
+	// A query storm: only the first query is answered straight away
+	for _ in 0..3 {
+		client.send_to(&query, (Ipv4Addr::LOCALHOST, port)).unwrap();
+	}
+	let mut buf = [0; 4096];
+	client.recv_from(&mut buf).unwrap();
+
+	// The others are dropped, which shows up in the broadcaster's stats
+	std::thread::sleep(Duration::from_millis(200));
+	assert!(broadcaster.stats().dropped_responses > 0);
+
+	broadcaster.shutdown().unwrap();
+}
+
+#[test]
+fn test_port_in_use() {
+	use crate::{
+		broadcast::{errors::BroadcasterBuilderError, BroadcasterBuilder},
+		net::IpVersion,
+	};
+	use std::net::UdpSocket;
+
+	// A socket that doesn't allow the port to be shared, like some other mDNS responders
+	let exclusive = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+	let port = exclusive.local_addr().unwrap().port();
+
+	let err = BroadcasterBuilder::new()
+		.loopback()
+		.bind_port(port)
+		.build(IpVersion::V4)
+		.err()
+		.expect("binding an exclusively bound port should fail");
+
+	match &err {
+		BroadcasterBuilderError::PortInUse { port: in_use, .. } => assert_eq!(*in_use, port),
+		err => panic!("expected PortInUse, got {err:?}"),
+	}
+
+	// The message names the port that was actually bound, not the default mDNS port
+	assert!(err.to_string().starts_with(&format!("Port {port} is already in use")));
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_collect_until() {
+	use crate::{broadcast::BroadcasterBuilder, discovery::DiscoveryBuilder, net::IpVersion};
+	use std::time::{Duration, Instant};
+
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.add_service(
+			ServiceBuilder::new("_searchlight-collect._udp.local", "searchlighttest", 1337)
+				.unwrap()
+				.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+				.build()
+				.unwrap(),
+		)
+		.build(IpVersion::V4)
+		.unwrap()
+		.run_in_background();
+
+	let discovery = || {
+		DiscoveryBuilder::new()
+			.loopback()
+			.interval(Duration::from_millis(100))
+			.service("_searchlight-collect._udp.local")
+			.unwrap()
+			.build(IpVersion::V4)
+			.unwrap()
+	};
+	let is_collect = |responder: &crate::discovery::Responder| {
+		responder.service_name.as_ref().map(ToString::to_string).as_deref() == Some("_searchlight-collect._udp.local.")
+	};
+
+	// Stops as soon as enough responders are found
+	let start = Instant::now();
+	let collected = discovery().collect_until(is_collect, 1, Duration::from_secs(5));
+	assert_eq!(collected.len(), 1);
+	assert!(start.elapsed() < Duration::from_secs(5));
+
+	// Updates to a responder that was already collected aren't counted again, so this runs until the timeout
+	let start = Instant::now();
+	let collected = discovery().collect_until(is_collect, 2, Duration::from_secs(1));
+	assert_eq!(collected.len(), 1);
+	assert!(start.elapsed() >= Duration::from_secs(1));
+
+	// Responders that don't match the predicate aren't collected
+	let collected = discovery().collect_until(|_| false, 1, Duration::from_millis(500));
+	assert!(collected.is_empty());
+
+	broadcaster.shutdown().unwrap();
+}
+
+#[test]
+fn test_ignore_non_query_opcodes() {
+	use crate::{
+		broadcast::{Broadcaster, BroadcasterBuilder},
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{sync::RwLock, time::Duration};
+	use trust_dns_client::{
+		op::{Message, MessageType, OpCode, Query},
+		rr::{Name, RecordType},
+		serialize::binary::BinDecodable,
+	};
+
+	let broadcaster = RwLock::new(
+		BroadcasterBuilder::new()
+			.add_service(
+				ServiceBuilder::new("_searchlight._udp.local", "searchlighttest", 1337)
+					.unwrap()
+					.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+					.build()
+					.unwrap(),
+			)
+			.into_config()
+			.unwrap(),
+	);
+
+	// Returns whether the broadcaster answered a message with the given opcode and type that asks about the service
+	let answered = |op_code: OpCode, message_type: MessageType| {
+		let network = MemoryNetwork::new();
+		let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+		let client = network.join("192.168.1.70:5353".parse().unwrap());
+
+		let mut message = Message::new();
+		message
+			.set_op_code(op_code)
+			.set_message_type(message_type)
+			.add_query(Query::query(Name::from_str("_searchlight._udp.local.").unwrap(), RecordType::PTR));
+		let packet = message.to_bytes().unwrap();
+
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.start_paused(true)
+			.build()
+			.unwrap();
+		runtime.block_on(async {
+			let mut client_recv = client.recv(vec![0; 4096]);
+			let mut recv = broadcaster_socket.recv(vec![0; 4096]);
+			client.send_multicast(&packet).await.unwrap();
+			tokio::time::timeout(
+				Duration::from_secs(1),
+				Broadcaster::recv_loop(&broadcaster_socket, &mut recv, &broadcaster),
+			)
+			.await
+			.ok();
+
+			// The client also receives what it sent itself, so look for anything with answers
+			tokio::time::timeout(Duration::from_secs(1), async {
+				loop {
+					let ((count, _), _, packet) = client_recv.recv_multicast().await.unwrap();
+					if !Message::from_bytes(&packet[..count]).unwrap().answers().is_empty() {
+						break;
+					}
+				}
+			})
+			.await
+			.is_ok()
+		})
+	};
+
+	assert!(answered(OpCode::Query, MessageType::Query));
+	assert!(!answered(OpCode::Update, MessageType::Query));
+	assert!(!answered(OpCode::Notify, MessageType::Query));
+	assert!(!answered(OpCode::Status, MessageType::Query));
+	assert!(!answered(OpCode::Query, MessageType::Response));
+}
+
+#[test]
+fn test_reply_addr() {
+	use crate::net::{reply_addr, Ipv6Interface, RecvInterface};
+	use std::net::SocketAddr;
+
+	let interface = Some(RecvInterface::V6(Ipv6Interface::from_scope_id(3).unwrap()));
+
+	// Link-local replies leave through the interface the query was received on, even if the source address had no (or the wrong) scope ID
+	let link_local: SocketAddr = "[fe80::1%0]:5353".parse().unwrap();
+	assert_eq!(reply_addr(link_local, interface), "[fe80::1%3]:5353".parse().unwrap());
+	assert_eq!(
+		reply_addr("[fe80::1%7]:5353".parse().unwrap(), interface),
+		"[fe80::1%3]:5353".parse().unwrap()
+	);
+
+	// Without a known interface, the source address is used as it is
+	assert_eq!(reply_addr(link_local, None), link_local);
+
+	// Other addresses are routed as normal
+	let global: SocketAddr = "[2001:db8::1]:5353".parse().unwrap();
+	assert_eq!(reply_addr(global, interface), global);
+	let v4: SocketAddr = "192.168.1.69:5353".parse().unwrap();
+	assert_eq!(reply_addr(v4, Some(RecvInterface::V4("192.168.1.70".parse().unwrap()))), v4);
+}
+
+#[test]
+fn test_ipv6_interface_from_scope_id() {
+	use crate::net::Ipv6Interface;
+	use std::net::SocketAddrV6;
+
+	assert_eq!(Ipv6Interface::from_scope_id(0), None);
+	assert_eq!(Ipv6Interface::from_scope_id(3).map(|iface| iface.as_u32()), Some(3));
+
+	let link_local = Ipv6Addr::from_str("fe80::18e4:b943:8756:d855").unwrap();
+	assert_eq!(
+		Ipv6Interface::from_socket_addr(&SocketAddrV6::new(link_local, 5353, 0, 3)),
+		Ipv6Interface::from_scope_id(3)
+	);
+	assert_eq!(Ipv6Interface::from_socket_addr(&SocketAddrV6::new(link_local, 5353, 0, 0)), None);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_auto_resolve() {
+	use crate::{
+		discovery::{DiscoveryBuilder, DiscoveryEvent},
+		tests::harness::run_discovery_on,
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{net::SocketAddr, time::Duration};
+	use trust_dns_client::{
+		op::{Message as DnsMessage, MessageType},
+		rr::RecordType,
+		serialize::binary::BinDecodable,
+	};
+
+	let service = ServiceBuilder::new("_searchlight-resolve._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.build()
+		.unwrap();
+
+	// A minimal responder that only advertises the PTR record, and answers SRV queries separately
+	let bare_ptr = {
+		let mut response = service.dns_response().unwrap();
+		response.take_additionals();
+		response.update_counts();
+		response.to_bytes().unwrap()
+	};
+	let resolved = {
+		let mut response = service.dns_response().unwrap();
+		response.take_answers();
+		let additionals = response.take_additionals();
+		response.add_answers(additionals).update_counts();
+		response.to_bytes().unwrap()
+	};
+
+	// Returns the endpoints of the responders found, and how many SRV queries the responder received
+	let discover = |auto_resolve: bool| {
+		let network = MemoryNetwork::new();
+		let responder = network.join("192.168.1.69:5353".parse().unwrap());
+
+		let mut srv_queries = 0;
+
+		let respond = async {
+			let mut recv = responder.recv(vec![0; 4096]);
+			loop {
+				let ((count, _), _, packet) = recv.recv_multicast().await.unwrap();
+				let message = DnsMessage::from_bytes(&packet[..count]).unwrap();
+				if message.message_type() != MessageType::Query {
+					continue;
+				}
+
+				if message.queries().iter().any(|query| query.query_type() == RecordType::PTR) {
+					// Sent twice, but the instance should only be resolved once
+					responder.send_multicast(&bare_ptr).await.unwrap();
+					responder.send_multicast(&bare_ptr).await.unwrap();
+				} else if message.queries().iter().any(|query| query.query_type() == RecordType::SRV) {
+					srv_queries += 1;
+					tokio::time::sleep(Duration::from_millis(100)).await;
+					responder.send_multicast(&resolved).await.unwrap();
+				}
+			}
+		};
+
+		let events = run_discovery_on(
+			&network,
+			DiscoveryBuilder::new()
+				.service("_searchlight-resolve._udp.local")
+				.unwrap()
+				.auto_resolve(auto_resolve),
+			|_| async {
+				tokio::select! {
+					_ = respond => unreachable!(),
+					_ = tokio::time::sleep(Duration::from_secs(5)) => {}
+				}
+			},
+		);
+
+		let found = events
+			.iter()
+			.filter_map(|event| match event {
+				DiscoveryEvent::ResponderFound(responder) => Some(responder.endpoints()),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		(found, srv_queries)
+	};
+
+	let endpoint: SocketAddr = "192.168.1.69:1337".parse().unwrap();
+	assert_eq!(discover(true), (vec![vec![endpoint]], 1));
+
+	// Without resolving, the responder is found straight away, but without the SRV record there are no endpoints
+	assert_eq!(discover(false), (vec![vec![]], 0));
+}
+
+#[test]
+fn test_response_delay() {
+	use crate::broadcast::ResponseDelay;
+	use std::time::Duration;
+
+	let (min, max) = (Duration::from_millis(20), Duration::from_millis(120));
+
+	// The delays are random, but always within the range
+	let delay = ResponseDelay::new(min, max, None);
+	let delays = (0..16).map(|_| delay.next()).collect::<Vec<_>>();
+	assert!(delays.iter().all(|delay| (min..=max).contains(delay)));
+	assert!(delays.windows(2).any(|pair| pair[0] != pair[1]));
+
+	// There's nothing random about an empty range
+	let fixed = ResponseDelay::new(min, min, None);
+	assert_eq!(fixed.next(), min);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_on_service() {
+	use crate::{
+		discovery::{DiscoveryBuilder, DiscoveryEvent},
+		tests::harness::run_discovery_on,
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{
+		sync::{Arc, Mutex},
+		time::Duration,
+	};
+	use trust_dns_client::{
+		op::{Message as DnsMessage, MessageType},
+		serialize::binary::BinDecodable,
+	};
+
+	let response = |service_type: &str| {
+		ServiceBuilder::new(service_type, "searchlighttest", 1337)
+			.unwrap()
+			.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+			.build()
+			.unwrap()
+			.dns_response()
+			.unwrap()
+			.to_bytes()
+			.unwrap()
+	};
+	let responses = [
+		response("_searchlight-a._udp.local"),
+		response("_searchlight-b._udp.local"),
+		response("_searchlight-c._udp.local"),
+	];
+
+	// Records the service name of every responder found by a handler
+	let found = || Arc::new(Mutex::new(Vec::new()));
+	let handler = |found: &Arc<Mutex<Vec<String>>>| {
+		let found = found.clone();
+		move |event: DiscoveryEvent| {
+			if let DiscoveryEvent::ResponderFound(responder) = event {
+				found.lock().unwrap().push(responder.service_name.as_ref().unwrap().to_ascii());
+			}
+		}
+	};
+	let (found_a, found_b) = (found(), found());
+
+	let discovery = DiscoveryBuilder::new()
+		.on_service("_searchlight-a._udp.local", handler(&found_a))
+		.unwrap()
+		.on_service("_searchlight-b._udp.local", handler(&found_b))
+		.unwrap();
+
+	let network = MemoryNetwork::new();
+	let responder = network.join("192.168.1.69:5353".parse().unwrap());
+
+	let mut questions = Vec::new();
+	let events = run_discovery_on(&network, discovery, |_| async {
+		let mut recv = responder.recv(vec![0; 4096]);
+		loop {
+			let ((count, _), _, packet) = recv.recv_multicast().await.unwrap();
+			let message = DnsMessage::from_bytes(&packet[..count]).unwrap();
+			if message.message_type() != MessageType::Query {
+				continue;
+			}
+
+			for response in &responses {
+				responder.send_multicast(response).await.unwrap();
+			}
+
+			questions = message.queries().iter().map(|query| query.name().to_ascii()).collect();
+			break;
+		}
+		tokio::time::sleep(Duration::from_secs(1)).await;
+	});
+
+	// A single discovery packet asks about both service types, and nothing else
+	assert_eq!(questions, ["_searchlight-a._udp.local.", "_searchlight-b._udp.local."]);
+
+	// Each service type's responder goes to its own handler, and the unregistered service type is dropped
+	assert_eq!(*found_a.lock().unwrap(), ["_searchlight-a._udp.local."]);
+	assert_eq!(*found_b.lock().unwrap(), ["_searchlight-b._udp.local."]);
+	assert!(events.is_empty());
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_forget() {
+	use crate::{
+		discovery::DiscoveryBuilder,
+		tests::harness::{kind, run_discovery_on},
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{net::SocketAddr, time::Duration};
+	use trust_dns_client::{
+		op::{Message as DnsMessage, MessageType},
+		serialize::binary::BinDecodable,
+	};
+
+	let response = ServiceBuilder::new("_searchlight-forget._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.build()
+		.unwrap()
+		.dns_response()
+		.unwrap()
+		.to_bytes()
+		.unwrap();
+
+	let discovery = DiscoveryBuilder::new()
+		.service("_searchlight-forget._udp.local")
+		.unwrap()
+		.interval(Duration::from_secs(10));
+
+	let network = MemoryNetwork::new();
+	let responder_addr: SocketAddr = "192.168.1.69:5353".parse().unwrap();
+	let responder = network.join(responder_addr);
+
+	let events = run_discovery_on(&network, discovery, |ctx| async move {
+		let respond = async {
+			let mut recv = responder.recv(vec![0; 4096]);
+			loop {
+				let ((count, _), _, packet) = recv.recv_multicast().await.unwrap();
+				if DnsMessage::from_bytes(&packet[..count]).unwrap().message_type() == MessageType::Query {
+					responder.send_multicast(&response).await.unwrap();
+				}
+			}
+		};
+
+		// The responder is forgotten well before it would be lost, and is found again when it responds to the next query
+		let forget = async {
+			tokio::time::sleep(Duration::from_secs(5)).await;
+			ctx.forget_tx.send("192.168.1.1:5353".parse().unwrap()).unwrap();
+			ctx.forget_tx.send(responder_addr).unwrap();
+
+			// Lost events are reported on a blocking thread, so wait for it before time moves on to the next query
+			while !ctx.events.lock().unwrap().iter().any(|event| kind(event) == "lost") {
+				tokio::task::yield_now().await;
+			}
+			std::future::pending::<()>().await
+		};
+
+		tokio::select! {
+			_ = respond => unreachable!(),
+			_ = forget => unreachable!(),
+			_ = tokio::time::sleep(Duration::from_secs(15)) => {}
+		}
+	});
+
+	// Forgetting an unknown responder does nothing, so the responder is only lost once
+	let events = events
+		.iter()
+		.map(|event| (kind(event), event.responder().addr))
+		.filter(|(kind, _)| *kind != "update")
+		.collect::<Vec<_>>();
+	assert_eq!(events, [("found", responder_addr), ("lost", responder_addr), ("found", responder_addr)]);
+}
+
+#[test]
+fn test_response_delay_seed() {
+	use crate::broadcast::ResponseDelay;
+	use std::time::Duration;
+
+	let (min, max) = (Duration::from_millis(20), Duration::from_millis(120));
+	let delays = |seed: u64| {
+		let delay = ResponseDelay::new(min, max, Some(seed));
+		(0..16).map(|_| delay.next()).collect::<Vec<_>>()
+	};
+
+	// The same seed always gives the same delays, within the range
+	assert_eq!(delays(1337), delays(1337));
+	assert!(delays(1337).iter().all(|delay| (min..=max).contains(delay)));
+	assert!(delays(1337).windows(2).any(|pair| pair[0] != pair[1]));
+
+	// A different seed gives different delays
+	assert_ne!(delays(1337), delays(69));
+}
+
+#[test]
+fn test_service_id_override() {
+	use trust_dns_client::rr::{Name, RData, RecordType};
+
+	// An instance label containing a dot can't be derived from the service name and type
+	let service_id = Name::from_labels([&b"Living Room v1.2"[..], b"_searchlight-id", b"_udp", b"local"]).unwrap();
+	let service = ServiceBuilder::new("_searchlight-id._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.service_id(service_id.clone())
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.add_txt("key=value")
+		.build()
+		.unwrap();
+	assert_eq!(*service.service_id(), service_id);
+	assert_eq!(service_id.num_labels(), 4);
+
+	// The PTR record points to the overridden name, which the SRV and TXT records are named after
+	let response = service.dns_response().unwrap();
+	let records = || response.answers().iter().chain(response.additionals());
+	assert!(records().any(|record| matches!(record.data(), Some(RData::PTR(target)) if *target == service_id)));
+	for record_type in [RecordType::SRV, RecordType::TXT] {
+		assert!(records().any(|record| record.record_type() == record_type && *record.name() == service_id));
+	}
+
+	// The name is kept when re-advertising the service
+	let relayed = ServiceBuilder::from_response(&response).unwrap().build().unwrap();
+	assert_eq!(*relayed.service_id(), service_id);
+
+	// The root name isn't an instance name
+	assert!(ServiceBuilder::new("_searchlight-id._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.service_id(".")
+		.is_err());
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_goodbye() {
+	use crate::{
+		discovery::DiscoveryBuilder,
+		tests::harness::{kind, run_discovery_on},
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::time::Duration;
+
+	let response = |service_name: &str, ttl: u32| {
+		ServiceBuilder::new("_searchlight-goodbye._udp.local", service_name, 1337)
+			.unwrap()
+			.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+			.ttl(ttl)
+			.build()
+			.unwrap()
+			.dns_response()
+			.unwrap()
+	};
+	let live = response("searchlighttest", 120).to_bytes().unwrap();
+	let goodbye = response("searchlighttest", 0).to_bytes().unwrap();
+
+	// One instance goes away, but another one is still there
+	let mixed = {
+		let mut mixed = response("searchlighttest", 0);
+		let other = response("searchlighttest2", 120);
+		mixed.add_answers(other.answers().iter().cloned()).update_counts();
+		mixed.to_bytes().unwrap()
+	};
+
+	let discovery = DiscoveryBuilder::new()
+		.service("_searchlight-goodbye._udp.local")
+		.unwrap()
+		.interval(Duration::from_secs(60));
+
+	let network = MemoryNetwork::new();
+	let responder = network.join("192.168.1.69:5353".parse().unwrap());
+
+	let events = run_discovery_on(&network, discovery, |_| async {
+		// Wait for the first discovery packet
+		let mut recv = responder.recv(vec![0; 4096]);
+		recv.recv_multicast().await.unwrap();
+
+		for packet in [&live, &mixed, &goodbye] {
+			responder.send_multicast(packet).await.unwrap();
+			tokio::time::sleep(Duration::from_secs(3)).await;
+		}
+		tokio::time::sleep(Duration::from_secs(6)).await;
+	});
+
+	// The mixed response is an update, and the goodbye makes the responder lost straight away, long before it would stop being queried
+	assert_eq!(events.iter().map(kind).collect::<Vec<_>>(), ["found", "update", "lost"]);
+}
+
+#[test]
+fn test_configure_socket() {
+	use crate::{
+		broadcast::{errors::BroadcasterBuilderError, BroadcasterBuilder},
+		errors::SocketSetupStage,
+		net::IpVersion,
+	};
+	use std::sync::{Arc, Mutex};
+
+	let service = ServiceBuilder::new("_searchlight-configure._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+		.build()
+		.unwrap();
+
+	// The callback is called on every socket, and can set options that Searchlight doesn't
+	let configured = Arc::new(Mutex::new(Vec::new()));
+	let broadcaster = BroadcasterBuilder::new()
+		.loopback()
+		.bind_port(0)
+		.configure_socket({
+			let configured = configured.clone();
+			move |socket, ip_version| {
+				socket.set_send_buffer_size(64 * 1024)?;
+				configured.lock().unwrap().push(ip_version);
+				Ok(())
+			}
+		})
+		.add_service(service.clone())
+		.build(IpVersion::V4)
+		.unwrap();
+	assert_eq!(*configured.lock().unwrap(), [IpVersion::V4]);
+	drop(broadcaster);
+
+	// Errors fail setting up the socket
+	let err = BroadcasterBuilder::new()
+		.loopback()
+		.bind_port(0)
+		.configure_socket(|_, _| Err(std::io::Error::new(std::io::ErrorKind::Other, "not today")))
+		.add_service(service)
+		.build(IpVersion::V4)
+		.err()
+		.unwrap();
+	match err {
+		BroadcasterBuilderError::SocketSetup { v4: Some(v4), .. } => assert_eq!(v4.stage, SocketSetupStage::SetOption),
+		err => panic!("unexpected error: {err}"),
+	}
+}
+
+#[test]
+fn test_only_v6() {
+	use crate::{broadcast::BroadcasterBuilder, net::IpVersion};
+	use std::sync::{Arc, Mutex};
+
+	// IPv6 isn't available everywhere
+	if std::net::UdpSocket::bind("[::1]:0").is_err() {
+		return;
+	}
+
+	// Returns whether the IPv6 socket was IPv6-only, as read back before it joins the multicast group (which can fail without an IPv6 route)
+	let only_v6 = |builder: BroadcasterBuilder, ip_version: IpVersion| {
+		let only_v6 = Arc::new(Mutex::new(None));
+		builder
+			.loopback()
+			.bind_port(0)
+			.configure_socket({
+				let only_v6 = only_v6.clone();
+				move |socket, ip_version| {
+					if ip_version == IpVersion::V6 {
+						*only_v6.lock().unwrap() = Some(socket.only_v6()?);
+					}
+					Ok(())
+				}
+			})
+			.build(ip_version)
+			.ok();
+
+		let only_v6 = only_v6.lock().unwrap().unwrap();
+		only_v6
+	};
+
+	assert!(only_v6(BroadcasterBuilder::new(), IpVersion::V6));
+	assert!(!only_v6(BroadcasterBuilder::new().only_v6(false), IpVersion::V6));
+
+	// Alongside an IPv4 socket, a dual-stack IPv6 socket would receive IPv4 traffic twice
+	assert!(only_v6(BroadcasterBuilder::new().only_v6(false), IpVersion::Both));
+}
+
+#[test]
+fn test_dscp() {
+	use crate::{
+		broadcast::{errors::BroadcasterBuilderError, BroadcasterBuilder},
+		net::{IpVersion, SocketOptions},
+	};
+	use std::sync::{Arc, Mutex};
+
+	// Searchlight's own options are set before the socket is configured, so the marking can be read back
+	let tos = Arc::new(Mutex::new(None));
+	BroadcasterBuilder::new()
+		.loopback()
+		.bind_port(0)
+		.dscp(46)
+		.configure_socket({
+			let tos = tos.clone();
+			move |socket, _| {
+				*tos.lock().unwrap() = Some(socket.tos()?);
+				Ok(())
+			}
+		})
+		.build(IpVersion::V4)
+		.unwrap();
+
+	// Expedited Forwarding, followed by the two (unset) ECN bits
+	#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"))]
+	assert_eq!(*tos.lock().unwrap(), Some(46 << 2));
+	assert!(tos.lock().unwrap().is_some());
+
+	// DSCPs are 6-bit values
+	let invalid = SocketOptions {
+		dscp: Some(64),
+		..Default::default()
+	};
+	assert!(matches!(
+		BroadcasterBuilder::new()
+			.loopback()
+			.bind_port(0)
+			.socket_options(invalid)
+			.build(IpVersion::V4),
+		Err(BroadcasterBuilderError::InvalidDscp(64))
+	));
+	assert!(matches!(
+		BroadcasterBuilder::new().loopback().bind_port(0).dscp(64).build(IpVersion::V4),
+		Err(BroadcasterBuilderError::InvalidDscp(64))
+	));
+	#[cfg(feature = "discovery")]
+	assert!(matches!(
+		crate::discovery::DiscoveryBuilder::new().loopback().dscp(64).build(IpVersion::V4),
+		Err(crate::discovery::errors::DiscoveryBuilderError::InvalidDscp(64))
+	));
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_require_min_addresses() {
+	use crate::{
+		discovery::DiscoveryBuilder,
+		tests::harness::{kind, run_discovery_on},
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{net::SocketAddr, time::Duration};
+	use trust_dns_client::rr::RecordType;
+
+	let response = |ttl: u32| {
+		ServiceBuilder::new("_searchlight-addrs._udp.local", "searchlighttest", 1337)
+			.unwrap()
+			.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+			.ttl(ttl)
+			.build()
+			.unwrap()
+			.dns_response()
+			.unwrap()
+	};
+	let with_address = response(120).to_bytes().unwrap();
+
+	// A responder that is still booting, and doesn't have an address yet
+	let without_address = {
+		let mut response = response(120);
+		let additionals = response.take_additionals();
+		response
+			.add_additionals(additionals.into_iter().filter(|record| record.record_type() != RecordType::A))
+			.update_counts();
+		response.to_bytes().unwrap()
+	};
+	let goodbye = {
+		let mut response = response(0);
+		response.take_additionals();
+		response.update_counts();
+		response.to_bytes().unwrap()
+	};
+
+	let discover = |min_addresses: usize| {
+		let discovery = DiscoveryBuilder::new()
+			.service("_searchlight-addrs._udp.local")
+			.unwrap()
+			.require_min_addresses(min_addresses)
+			.interval(Duration::from_secs(60));
+
+		let network = MemoryNetwork::new();
+		let addressed = network.join("192.168.1.69:5353".parse().unwrap());
+		let booting = network.join("192.168.1.68:5353".parse().unwrap());
+
+		let events = run_discovery_on(&network, discovery, |_| async {
+			// Wait for the first discovery packet
+			let mut recv = addressed.recv(vec![0; 4096]);
+			recv.recv_multicast().await.unwrap();
+
+			addressed.send_multicast(&with_address).await.unwrap();
+			booting.send_multicast(&without_address).await.unwrap();
+
+			// A goodbye doesn't need any addresses
+			tokio::time::sleep(Duration::from_secs(5)).await;
+			addressed.send_multicast(&goodbye).await.unwrap();
+			tokio::time::sleep(Duration::from_secs(5)).await;
+		});
+
+		events.iter().map(|event| (kind(event), event.responder().addr)).collect::<Vec<_>>()
+	};
+
+	let addressed: SocketAddr = "192.168.1.69:5353".parse().unwrap();
+	let booting: SocketAddr = "192.168.1.68:5353".parse().unwrap();
+	assert_eq!(discover(1), [("found", addressed), ("lost", addressed)]);
+
+	// Without the filter, the responder without an address is found too
+	let mut events = discover(0);
+	events.sort();
+	assert_eq!(events, [("found", booting), ("found", addressed), ("lost", addressed)]);
+}
+
+#[test]
+fn test_response_delay_keeps_answering() {
+	use crate::{
+		broadcast::{errors::BroadcasterBuilderError, Broadcaster, BroadcasterBuilder},
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+		IpVersion,
+	};
+	use std::{sync::RwLock, time::Duration};
+	use trust_dns_client::{
+		op::{Message, Query},
+		rr::{Name, RecordType},
+		serialize::binary::BinDecodable,
+	};
+
+	let delay = Duration::from_millis(100);
+	let broadcaster = RwLock::new(
+		BroadcasterBuilder::new()
+			.add_service(
+				ServiceBuilder::new("_searchlight._udp.local", "searchlighttest", 1337)
+					.unwrap()
+					.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+					.build()
+					.unwrap(),
+			)
+			.response_delay(delay, delay)
+			.into_config()
+			.unwrap(),
+	);
+
+	let network = MemoryNetwork::new();
+	let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+	let client = network.join("192.168.1.70:5353".parse().unwrap());
+
+	let query = |unicast: bool| {
+		let mut query = Query::query(Name::from_str("_searchlight._udp.local.").unwrap(), RecordType::PTR);
+		query.set_mdns_unicast_response(unicast);
+
+		let mut message = Message::new();
+		message.add_query(query);
+		message.to_bytes().unwrap()
+	};
+
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.start_paused(true)
+		.build()
+		.unwrap();
+	let answered_at = runtime.block_on(async {
+		let start = tokio::time::Instant::now();
+		let mut client_recv = client.recv(vec![0; 4096]);
+		let mut recv = broadcaster_socket.recv(vec![0; 4096]);
+
+		// A multicast query, and then a unicast query while the response to the first one is being delayed
+		let queries = async {
+			client.send_multicast(&query(false)).await.unwrap();
+			tokio::time::sleep(Duration::from_millis(10)).await;
+			client.send_multicast(&query(true)).await.unwrap();
+			std::future::pending::<()>().await
+		};
+
+		// The client also receives what it sent itself, so only look at packets with answers
+		let answers = async {
+			let mut answered_at = vec![];
+			while answered_at.len() < 2 {
+				let ((count, _), _, packet) = client_recv.recv_multicast().await.unwrap();
+				if !Message::from_bytes(&packet[..count]).unwrap().answers().is_empty() {
+					answered_at.push(start.elapsed());
+				}
+			}
+			answered_at
+		};
+
+		tokio::select! {
+			_ = Broadcaster::recv_loop(&broadcaster_socket, &mut recv, &broadcaster) => unreachable!(),
+			_ = queries => unreachable!(),
+			answered_at = answers => answered_at,
+		}
+	});
+
+	// The unicast query is answered straight away, without waiting for the delayed multicast response
+	assert!(answered_at[0] < delay);
+	assert!(answered_at[1] >= delay);
+
+	// An inverted range is rejected when building, rather than panicking
+	assert!(matches!(
+		BroadcasterBuilder::new().response_delay(delay * 2, delay).build(IpVersion::V4),
+		Err(BroadcasterBuilderError::InvalidResponseDelay { .. })
+	));
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_recv_worker_queue() {
+	use crate::{
+		discovery::{recv_worker, DiscoveryBuilder},
+		transport::{memory::MemoryNetwork, MdnsTransport},
+	};
+	use std::{
+		sync::{
+			atomic::{AtomicU64, Ordering},
+			Arc,
+		},
+		time::Duration,
+	};
+
+	let response = ServiceBuilder::new("_searchlight-workers._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.build()
+		.unwrap()
+		.dns_response()
+		.unwrap()
+		.to_bytes()
+		.unwrap();
+
+	let filter = DiscoveryBuilder::new()
+		.service("_searchlight-workers._udp.local")
+		.unwrap()
+		.into_config()
+		.response_filter();
+
+	let network = MemoryNetwork::new();
+	let socket = Arc::new(network.join("192.168.1.70:5353".parse().unwrap()));
+	let responder = network.join("192.168.1.69:5353".parse().unwrap());
+
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.start_paused(true)
+		.build()
+		.unwrap();
+	runtime.block_on(async {
+		let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+		let dropped = Arc::new(AtomicU64::new(0));
+
+		let worker = recv_worker(socket, filter, tx, dropped.clone());
+		tokio::pin!(worker);
+
+		// Nobody is draining the queue, so only the first two responses fit
+		for _ in 0..5 {
+			responder.send_multicast(&response).await.unwrap();
+		}
+		tokio::time::timeout(Duration::from_secs(1), &mut worker).await.unwrap_err();
+
+		assert_eq!(dropped.load(Ordering::Relaxed), 3);
+		assert!(matches!(rx.try_recv(), Ok(Ok(Some(_)))));
+		assert!(matches!(rx.try_recv(), Ok(Ok(Some(_)))));
+		assert!(rx.try_recv().is_err());
+
+		// The worker stops once the discovery loop has gone away, even if nothing is received
+		drop(rx);
+		tokio::time::timeout(Duration::from_secs(1), worker)
+			.await
+			.expect("worker should stop when the queue is closed");
+	});
+}
+
+#[test]
+#[cfg(all(feature = "discovery", feature = "broadcast"))]
+fn test_response_sender_queue() {
+	use crate::discovery::{DiscoveryBuilder, DiscoveryRecv};
+	use std::sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	};
+
+	let response = ServiceBuilder::new("_searchlight-channel._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.build()
+		.unwrap()
+		.dns_response()
+		.unwrap();
+	let packet = response.to_bytes().unwrap();
+
+	let filter = DiscoveryBuilder::new()
+		.service("_searchlight-channel._udp.local")
+		.unwrap()
+		.into_config()
+		.response_filter();
+
+	let dropped = Arc::new(AtomicU64::new(0));
+	let (tx, recv) = DiscoveryRecv::channel(filter, dropped.clone());
+	let addr = "192.168.1.69:5353".parse().unwrap();
+
+	// Nobody is draining the queue, so everything past its capacity is dropped and counted
+	for _ in 0..300 {
+		assert!(tx.send(addr, &packet, response.clone()));
+	}
+	assert_eq!(dropped.load(Ordering::Relaxed), 300 - 256);
+
+	// The sender reports when the discovery loop has gone away
+	drop(recv);
+	assert!(!tx.send(addr, &packet, response.clone()));
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_confirm_after() {
+	use crate::{
+		discovery::DiscoveryBuilder,
+		tests::harness::{kind, run_discovery_on},
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::time::Duration;
+	use trust_dns_client::{
+		op::{Message as DnsMessage, MessageType},
+		serialize::binary::BinDecodable,
+	};
+
+	let response = ServiceBuilder::new("_searchlight-confirm._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.build()
+		.unwrap()
+		.dns_response()
+		.unwrap()
+		.to_bytes()
+		.unwrap();
+
+	// Runs discovery for two minutes, with a responder that only answers the queries sent while `answer` returns true for the time elapsed
+	let discover = |answer: fn(Duration) -> bool| {
+		let discovery = DiscoveryBuilder::new()
+			.service("_searchlight-confirm._udp.local")
+			.unwrap()
+			.confirm_after(2)
+			.interval(Duration::from_secs(10));
+
+		let network = MemoryNetwork::new();
+		let responder = network.join("192.168.1.69:5353".parse().unwrap());
+
+		let events = run_discovery_on(&network, discovery, |_| async {
+			let start = tokio::time::Instant::now();
+			let respond = async {
+				let mut recv = responder.recv(vec![0; 4096]);
+				loop {
+					let ((count, _), _, packet) = recv.recv_multicast().await.unwrap();
+					if DnsMessage::from_bytes(&packet[..count]).unwrap().message_type() == MessageType::Query && answer(start.elapsed()) {
+						responder.send_multicast(&response).await.unwrap();
+					}
+				}
+			};
+
+			tokio::select! {
+				_ = respond => unreachable!(),
+				_ = tokio::time::sleep(Duration::from_secs(120)) => {}
+			}
+		});
+
+		events.iter().map(kind).filter(|kind| *kind != "update").collect::<Vec<_>>()
+	};
+
+	// A responder that is only seen once is never found, and so is never lost either
+	assert!(discover(|elapsed| elapsed < Duration::from_secs(5)).is_empty());
+
+	// A responder that keeps responding is found once it has been seen twice, and lost once it stops
+	assert_eq!(discover(|elapsed| elapsed < Duration::from_secs(35)), ["found", "lost"]);
+
+	// A responder that is forgotten before it is confirmed starts over, so responding once more doesn't confirm it
+	assert!(discover(|elapsed| elapsed < Duration::from_secs(5) || (Duration::from_secs(55)..Duration::from_secs(65)).contains(&elapsed)).is_empty());
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_exclude_self() {
+	use crate::{
+		discovery::DiscoveryBuilder,
+		tests::harness::{kind, run_discovery_on},
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{net::SocketAddr, time::Duration};
+
+	let response = ServiceBuilder::new("_searchlight-self._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.0.2.69").unwrap()))
+		.build()
+		.unwrap()
+		.dns_response()
+		.unwrap()
+		.to_bytes()
+		.unwrap();
+
+	// One responder sends from an address of this machine, and the other from a documentation address that can't be
+	let local: SocketAddr = (if_addrs::get_if_addrs().unwrap()[0].ip(), 5353).into();
+	let remote: SocketAddr = (IpAddr::V4(Ipv4Addr::new(192, 0, 2, 69)), 5353).into();
+
+	let discover = |exclude_self: bool| {
+		let discovery = DiscoveryBuilder::new()
+			.service("_searchlight-self._udp.local")
+			.unwrap()
+			.exclude_self(exclude_self)
+			.interval(Duration::from_secs(60));
+
+		let network = MemoryNetwork::new();
+		let (local_responder, remote_responder) = (network.join(local), network.join(remote));
+
+		let events = run_discovery_on(&network, discovery, |_| async {
+			let mut recv = remote_responder.recv(vec![0; 4096]);
+			recv.recv_multicast().await.unwrap();
+
+			local_responder.send_multicast(&response).await.unwrap();
+			remote_responder.send_multicast(&response).await.unwrap();
+			tokio::time::sleep(Duration::from_secs(1)).await;
+		});
+
+		let mut found = events
+			.iter()
+			.filter(|event| kind(event) == "found")
+			.map(|event| event.responder().addr)
+			.collect::<Vec<_>>();
+		found.sort();
+		found
+	};
+
+	// The response from this machine is dropped
+	assert_eq!(discover(true), [remote]);
+
+	let mut both = vec![local, remote];
+	both.sort();
+	assert_eq!(discover(false), both);
+}
+
+#[test]
+#[cfg(feature = "stream")]
+fn test_event_stream_capacity() {
+	use crate::discovery::{DiscoveryEvent, DiscoveryEventStream, Responder};
+	use futures_core::Stream;
+	use std::{
+		pin::Pin,
+		sync::Arc,
+		time::{Instant, SystemTime},
+	};
+	use trust_dns_client::op::{DnsResponse, Message};
+
+	let found = |seen_count: u64| {
+		DiscoveryEvent::ResponderFound(Arc::new(Responder {
+			addr: "192.168.1.69:5353".parse().unwrap(),
+			last_response: DnsResponse::from(Message::new()),
+			raw_packet: Vec::new(),
+			service_name: None,
+			last_responded: Instant::now(),
+			last_responded_at: SystemTime::now(),
+			seen_count,
+		}))
+	};
+
+	// Nobody is polling the stream, so only the first two events are kept
+	let (handler, mut stream) = DiscoveryEventStream::channel(2);
+	for seen_count in 1..=5 {
+		handler(found(seen_count));
+	}
+	assert_eq!(stream.dropped_events(), 3);
+
+	let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+	runtime.block_on(async {
+		async fn next(stream: &mut DiscoveryEventStream) -> Option<DiscoveryEvent> {
+			std::future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+		}
+
+		assert_eq!(next(&mut stream).await.unwrap().responder().seen_count, 1);
+		assert_eq!(next(&mut stream).await.unwrap().responder().seen_count, 2);
+
+		// The stream ends once discovery has gone away
+		drop(handler);
+		assert!(next(&mut stream).await.is_none());
+	});
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_set_interval_cadence() {
+	use crate::{
+		discovery::DiscoveryBuilder,
+		tests::harness::run_discovery_on,
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::time::Duration;
+	use trust_dns_client::{
+		op::{Message as DnsMessage, MessageType},
+		serialize::binary::BinDecodable,
+	};
+
+	let discovery = DiscoveryBuilder::new()
+		.service("_searchlight-interval._udp.local")
+		.unwrap()
+		.interval(Duration::from_secs(60));
+
+	let network = MemoryNetwork::new();
+	let listener = network.join("192.168.1.69:5353".parse().unwrap());
+
+	// Records when each discovery packet was sent, in seconds since discovery started
+	let mut queries = Vec::new();
+	run_discovery_on(&network, discovery, |ctx| async {
+		let start = tokio::time::Instant::now();
+		let listen = async {
+			let mut recv = listener.recv(vec![0; 4096]);
+			loop {
+				let ((count, _), _, packet) = recv.recv_multicast().await.unwrap();
+				if DnsMessage::from_bytes(&packet[..count]).unwrap().message_type() == MessageType::Query {
+					queries.push(start.elapsed().as_secs());
+				}
+			}
+		};
+
+		let set_interval = async move {
+			tokio::time::sleep(Duration::from_secs(5)).await;
+			ctx.interval_tx.send(Duration::from_secs(10)).unwrap();
+			std::future::pending::<()>().await
+		};
+
+		tokio::select! {
+			_ = listen => unreachable!(),
+			_ = set_interval => unreachable!(),
+			_ = tokio::time::sleep(Duration::from_secs(50)) => {}
+		}
+	});
+
+	// The next discovery packet is sent a new interval after it was changed, rather than a minute after the first, and then every new interval
+	assert_eq!(queries, [0, 15, 25, 35, 45]);
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_last_responded_at() {
+	use crate::{
+		discovery::{DiscoveryBuilder, DiscoveryEvent},
+		tests::harness::run_discovery_on,
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::time::{Duration, Instant, SystemTime};
+	use trust_dns_client::{
+		op::{Message as DnsMessage, MessageType},
+		serialize::binary::BinDecodable,
+	};
+
+	let response = ServiceBuilder::new("_searchlight-seen._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.build()
+		.unwrap()
+		.dns_response()
+		.unwrap()
+		.to_bytes()
+		.unwrap();
+
+	let discovery = DiscoveryBuilder::new()
+		.service("_searchlight-seen._udp.local")
+		.unwrap()
+		.interval(Duration::from_secs(10));
+
+	let network = MemoryNetwork::new();
+	let responder = network.join("192.168.1.69:5353".parse().unwrap());
+
+	let (started, started_at) = (Instant::now(), SystemTime::now());
+	let events = run_discovery_on(&network, discovery, |_| async {
+		let respond = async {
+			let mut recv = responder.recv(vec![0; 4096]);
+			loop {
+				let ((count, _), _, packet) = recv.recv_multicast().await.unwrap();
+				if DnsMessage::from_bytes(&packet[..count]).unwrap().message_type() == MessageType::Query {
+					responder.send_multicast(&response).await.unwrap();
+				}
+			}
+		};
+
+		tokio::select! {
+			_ = respond => unreachable!(),
+			_ = tokio::time::sleep(Duration::from_secs(15)) => {}
+		}
+	});
+	let finished_at = SystemTime::now();
+
+	// The wall-clock time of each response is captured when it is received
+	let (found, old, new) = match &events[..] {
+		[DiscoveryEvent::ResponderFound(found), DiscoveryEvent::ResponseUpdate { old, new }] => (found, old, new),
+		events => panic!("unexpected events: {events:?}"),
+	};
+	assert!((started_at..=finished_at).contains(&found.last_responded_at));
+	assert_eq!(old.last_responded_at, found.last_responded_at);
+	assert!(new.last_responded_at >= old.last_responded_at && new.last_responded_at <= finished_at);
+
+	// The last response was received after the test started
+	assert!(new.last_seen_elapsed() <= started.elapsed());
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_response_collect_window() {
+	use crate::{
+		discovery::DiscoveryBuilder,
+		tests::harness::{kind, run_discovery_on},
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::time::Duration;
+
+	let response = ServiceBuilder::new("_searchlight-window._udp.local", "searchlighttest", 1337)
+		.unwrap()
+		.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+		.build()
+		.unwrap()
+		.dns_response()
+		.unwrap()
+		.to_bytes()
+		.unwrap();
+
+	// Returns how long after discovery started that a responder which only answers the first discovery packet is lost
+	let lost_after = |window: Duration| {
+		let discovery = DiscoveryBuilder::new()
+			.service("_searchlight-window._udp.local")
+			.unwrap()
+			.interval(Duration::from_secs(10))
+			.max_ignored_packets(2)
+			.response_collect_window(window);
+
+		let network = MemoryNetwork::new();
+		let responder = network.join("192.168.1.69:5353".parse().unwrap());
+
+		let mut lost_after = None;
+		run_discovery_on(&network, discovery, |ctx| async {
+			let (start, events) = (tokio::time::Instant::now(), ctx.events);
+
+			let mut recv = responder.recv(vec![0; 4096]);
+			recv.recv_multicast().await.unwrap();
+			responder.send_multicast(&response).await.unwrap();
+
+			while !events.lock().unwrap().iter().any(|event| kind(event) == "lost") {
+				tokio::time::sleep(Duration::from_millis(100)).await;
+			}
+			lost_after = Some(start.elapsed());
+		});
+		lost_after.unwrap()
+	};
+
+	// Ignoring the discovery packets sent after 10 and 20 seconds loses the responder, but only once the window after the second one has passed
+	let lost = lost_after(Duration::from_secs(2));
+	assert!((Duration::from_secs(22)..Duration::from_secs(23)).contains(&lost), "{lost:?}");
+
+	let lost = lost_after(Duration::from_secs(5));
+	assert!((Duration::from_secs(25)..Duration::from_secs(26)).contains(&lost), "{lost:?}");
+}
+
+#[test]
+#[cfg(feature = "discovery")]
+fn test_unicast_responses() {
+	use crate::{
+		broadcast::BroadcasterBuilder,
+		discovery::DiscoveryBuilder,
+		tests::harness::{broadcast, kind, run_discovery_on, DISCOVERY_ADDR},
+		transport::{memory::MemoryNetwork, MdnsTransport, MdnsTransportRecv},
+	};
+	use std::{net::SocketAddr, sync::RwLock, time::Duration};
+	use trust_dns_client::{
+		op::{Message as DnsMessage, MessageType},
+		serialize::binary::BinDecodable,
+	};
+
+	let broadcaster = RwLock::new(
+		BroadcasterBuilder::new()
+			.add_service(
+				ServiceBuilder::new("_searchlight-qu._udp.local", "searchlighttest", 1337)
+					.unwrap()
+					.add_ip_address(IpAddr::V4(Ipv4Addr::from_str("192.168.1.69").unwrap()))
+					.build()
+					.unwrap(),
+			)
+			.into_config()
+			.unwrap(),
+	);
+
+	// Returns whether the discovery packet asked for unicast responses, the responders found, and the responses that a bystander on the network overheard
+	let discover = |unicast_responses: bool| {
+		let discovery = DiscoveryBuilder::new()
+			.service("_searchlight-qu._udp.local")
+			.unwrap()
+			.unicast_responses(unicast_responses)
+			.interval(Duration::from_secs(60));
+
+		let network = MemoryNetwork::new();
+		let broadcaster_socket = network.join("192.168.1.69:5353".parse().unwrap());
+		let bystander = network.join("192.168.1.71:5353".parse().unwrap());
+
+		let mut qu = Vec::new();
+		let mut overheard = 0;
+		let events = run_discovery_on(&network, discovery, |_| async {
+			let listen = async {
+				let mut recv = bystander.recv(vec![0; 4096]);
+				loop {
+					let ((count, from), _, packet) = recv.recv_multicast().await.unwrap();
+					let message = DnsMessage::from_bytes(&packet[..count]).unwrap();
+					match message.message_type() {
+						MessageType::Query if from == DISCOVERY_ADDR.parse::<SocketAddr>().unwrap() => {
+							qu.extend(message.queries().iter().map(|query| query.mdns_unicast_response()))
+						}
+						MessageType::Query => {}
+						MessageType::Response => overheard += 1,
+					}
+				}
+			};
+
+			tokio::select! {
+				_ = listen => unreachable!(),
+				_ = broadcast(&broadcaster_socket, &broadcaster) => unreachable!(),
+				_ = tokio::time::sleep(Duration::from_secs(5)) => {}
+			}
+		});
+
+		let found = events.iter().filter(|event| kind(event) == "found").count();
+		(qu, found, overheard)
+	};
+
+	// By default, the question asks for multicast responses, which everyone on the network hears
+	assert_eq!(discover(false), (vec![false], 1, 1));
+
+	// Asking for unicast responses sets the QU bit, and the response only goes to discovery
+	assert_eq!(discover(true), (vec![true], 1, 0));
+}
@@ -0,0 +1,101 @@
+//! Scaffolding for running discovery against a [`MemoryNetwork`], with time paused.
+
+use crate::{
+	broadcast::{Broadcaster, BroadcasterConfig},
+	discovery::{Discovery, DiscoveryBuilder, DiscoveryEvent, DiscoveryRecv, LoopControl},
+	transport::{
+		memory::{MemoryNetwork, MemoryTransport},
+		MdnsTransport,
+	},
+};
+use std::{
+	future::Future,
+	net::SocketAddr,
+	sync::{Arc, Mutex, RwLock},
+	time::Duration,
+};
+
+/// The address that discovery sends from in [`run_discovery_on`].
+pub(crate) const DISCOVERY_ADDR: &str = "192.168.1.70:5353";
+
+/// Controls a discovery loop started by [`run_discovery_on`], like a [`DiscoveryHandle`](crate::discovery::DiscoveryHandle) would.
+pub(crate) struct DiscoveryCtx {
+	/// The events emitted so far.
+	pub(crate) events: Arc<Mutex<Vec<DiscoveryEvent>>>,
+
+	pub(crate) interval_tx: tokio::sync::watch::Sender<Duration>,
+	pub(crate) forget_tx: tokio::sync::mpsc::UnboundedSender<SocketAddr>,
+}
+
+/// Runs discovery from [`DISCOVERY_ADDR`] on `network` until the future returned by `f` completes, and returns the events it emitted.
+///
+/// Time is paused, so this runs as fast as the protocol allows rather than in real time. The runtime is shut down before the
+/// events are returned, so that events emitted in the background (such as responders being lost) are included.
+pub(crate) fn run_discovery_on<F, Fut>(network: &MemoryNetwork, builder: DiscoveryBuilder, f: F) -> Vec<DiscoveryEvent>
+where
+	F: FnOnce(DiscoveryCtx) -> Fut,
+	Fut: Future<Output = ()>,
+{
+	let config = builder.into_config();
+	let socket = network.join(DISCOVERY_ADDR.parse().unwrap());
+
+	let events = Arc::new(Mutex::new(Vec::new()));
+	let handler = {
+		let events = events.clone();
+		Arc::new(move |event: DiscoveryEvent| events.lock().unwrap().push(event))
+	};
+
+	let (interval_tx, interval_rx) = tokio::sync::watch::channel(config.interval());
+	let (forget_tx, forget_rx) = tokio::sync::mpsc::unbounded_channel();
+	let mut control = LoopControl {
+		interval_rx: Some(interval_rx),
+		forget_rx: Some(forget_rx),
+		..Default::default()
+	};
+	let ctx = DiscoveryCtx {
+		events: events.clone(),
+		interval_tx,
+		forget_tx,
+	};
+
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.start_paused(true)
+		.build()
+		.unwrap();
+	runtime.block_on(async {
+		let recv = DiscoveryRecv::inline(&socket, config.response_filter());
+		let mut responder_memory = config.responder_memory();
+
+		tokio::select! {
+			res = Discovery::discovery_loop(handler, &config, &socket, recv, &mut responder_memory, &mut control) => panic!("{:?}", res.err()),
+			_ = f(ctx) => {}
+		}
+	});
+	drop(runtime);
+
+	let events = events.lock().unwrap().clone();
+	events
+}
+
+/// Answers queries on `socket` like a broadcaster, forever.
+pub(crate) async fn broadcast(socket: &MemoryTransport, config: &RwLock<BroadcasterConfig>) {
+	let mut recv = socket.recv(vec![0; 4096]);
+	let err = Broadcaster::recv_loop(socket, &mut recv, config).await;
+	panic!("{err:?}");
+}
+
+/// Answers queries on `socket` like a broadcaster for `duration`, and then goes away without saying goodbye.
+pub(crate) async fn broadcast_for(socket: &MemoryTransport, config: &RwLock<BroadcasterConfig>, duration: Duration) {
+	tokio::time::timeout(duration, broadcast(socket, config)).await.ok();
+}
+
+/// The kind of an event, for comparing the events emitted by discovery.
+pub(crate) fn kind(event: &DiscoveryEvent) -> &'static str {
+	match event {
+		DiscoveryEvent::ResponderFound(_) => "found",
+		DiscoveryEvent::ResponderLost(_) => "lost",
+		DiscoveryEvent::ResponseUpdate { .. } => "update",
+		DiscoveryEvent::Raw(_) => "raw",
+	}
+}
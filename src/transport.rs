@@ -0,0 +1,35 @@
+use crate::{errors::MultiIpIoError, net::RecvInterface};
+use std::net::SocketAddr;
+
+#[cfg(test)]
+pub(crate) mod memory;
+
+/// The operations that the broadcast and discovery loops need from an mDNS socket.
+///
+/// This is implemented by [`AsyncMdnsSocket`](crate::socket::AsyncMdnsSocket), and in tests by an in-memory transport that doesn't touch the OS network stack.
+pub(crate) trait MdnsTransport {
+	type Recv<'a>: MdnsTransportRecv
+	where
+		Self: 'a;
+
+	/// Sends a packet directly to `addr`.
+	async fn send_to(&self, packet: &[u8], addr: SocketAddr) -> Result<(), MultiIpIoError>;
+
+	/// Sends a packet to the mDNS multicast group(s).
+	async fn send_multicast(&self, packet: &[u8]) -> Result<(), MultiIpIoError>;
+
+	#[cfg(feature = "broadcast")]
+	/// Like [`send_multicast`](Self::send_multicast), but sends `packets_v6` instead of `packet` over IPv6.
+	///
+	/// This is used when `packet` is too large for the IPv6 path MTU and has been split.
+	async fn send_multicast_split_v6(&self, packet: &[u8], packets_v6: &[Vec<u8>]) -> Result<(), MultiIpIoError>;
+
+	/// Starts receiving packets into `buffer`.
+	fn recv(&self, buffer: Vec<u8>) -> Self::Recv<'_>;
+}
+
+/// Receives packets from an [`MdnsTransport`].
+pub(crate) trait MdnsTransportRecv {
+	/// Receives the next packet, returning the number of bytes received, who sent it, the interface it was received on (if known), and the receive buffer.
+	async fn recv_multicast(&mut self) -> Result<((usize, SocketAddr), Option<RecvInterface>, &[u8]), MultiIpIoError>;
+}
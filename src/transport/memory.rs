@@ -0,0 +1,100 @@
+//! An in-memory transport for testing the broadcast and discovery loops without the OS network stack.
+
+use super::{MdnsTransport, MdnsTransportRecv};
+use crate::{errors::MultiIpIoError, net::RecvInterface};
+use std::{
+	net::SocketAddr,
+	sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+type Packet = (SocketAddr, Vec<u8>);
+type Peer = (SocketAddr, UnboundedSender<Packet>);
+
+#[derive(Clone, Default)]
+/// A network that delivers packets between [`MemoryTransport`]s.
+///
+/// Like a socket with loopback enabled, multicast packets are also delivered back to their sender.
+pub(crate) struct MemoryNetwork {
+	peers: Arc<Mutex<Vec<Peer>>>,
+}
+impl MemoryNetwork {
+	pub(crate) fn new() -> Self {
+		Self::default()
+	}
+
+	/// Attaches a new transport to the network, which sends packets from `addr`.
+	pub(crate) fn join(&self, addr: SocketAddr) -> MemoryTransport {
+		let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+		self.peers.lock().unwrap().push((addr, tx));
+
+		MemoryTransport {
+			addr,
+			network: self.clone(),
+			rx: tokio::sync::Mutex::new(rx),
+		}
+	}
+
+	fn deliver(&self, from: SocketAddr, packet: &[u8], filter: impl Fn(&SocketAddr) -> bool) {
+		self.peers
+			.lock()
+			.unwrap()
+			.retain(|(addr, tx)| !filter(addr) || tx.send((from, packet.to_vec())).is_ok());
+	}
+}
+
+/// A transport attached to a [`MemoryNetwork`].
+pub(crate) struct MemoryTransport {
+	addr: SocketAddr,
+	network: MemoryNetwork,
+	rx: tokio::sync::Mutex<UnboundedReceiver<Packet>>,
+}
+impl MdnsTransport for MemoryTransport {
+	type Recv<'a> = MemoryRecv<'a>;
+
+	async fn send_to(&self, packet: &[u8], addr: SocketAddr) -> Result<(), MultiIpIoError> {
+		self.network.deliver(self.addr, packet, |peer| *peer == addr);
+		Ok(())
+	}
+
+	async fn send_multicast(&self, packet: &[u8]) -> Result<(), MultiIpIoError> {
+		self.network.deliver(self.addr, packet, |_| true);
+		Ok(())
+	}
+
+	#[cfg(feature = "broadcast")]
+	async fn send_multicast_split_v6(&self, packet: &[u8], packets_v6: &[Vec<u8>]) -> Result<(), MultiIpIoError> {
+		self.network.deliver(self.addr, packet, SocketAddr::is_ipv4);
+		for packet in packets_v6 {
+			self.network.deliver(self.addr, packet, SocketAddr::is_ipv6);
+		}
+		Ok(())
+	}
+
+	fn recv(&self, buffer: Vec<u8>) -> MemoryRecv<'_> {
+		MemoryRecv {
+			rx: self.rx.try_lock().expect("MemoryTransport can only be received from once at a time"),
+			buffer,
+		}
+	}
+}
+
+pub(crate) struct MemoryRecv<'a> {
+	rx: tokio::sync::MutexGuard<'a, UnboundedReceiver<Packet>>,
+	buffer: Vec<u8>,
+}
+impl MdnsTransportRecv for MemoryRecv<'_> {
+	async fn recv_multicast(&mut self) -> Result<((usize, SocketAddr), Option<RecvInterface>, &[u8]), MultiIpIoError> {
+		let (from, packet) = match self.rx.recv().await {
+			Some(packet) => packet,
+			None => std::future::pending().await,
+		};
+
+		// Truncate like a real socket would
+		let count = packet.len().min(self.buffer.len());
+		self.buffer[..count].copy_from_slice(&packet[..count]);
+
+		// There are no interfaces on a memory network
+		Ok(((count, from), None, &self.buffer))
+	}
+}
@@ -4,6 +4,13 @@ use trust_dns_client::{
 	rr::{IntoName, Name as DnsName},
 };
 
+/// The DNS-SD meta-query name, which responders answer with PTR records pointing to each service type they advertise.
+pub(crate) const SERVICES_META_QUERY: &str = "_services._dns-sd._udp.local.";
+
+/// The DNS-SD domain enumeration query names for browsing domains, the default browsing domain, and legacy browsing domains respectively,
+/// which responders answer with PTR records pointing to each domain.
+pub(crate) const BROWSE_DOMAINS_QUERIES: [&str; 3] = ["b._dns-sd._udp.local.", "db._dns-sd._udp.local.", "lb._dns-sd._udp.local."];
+
 pub(crate) fn iface_v6_name_to_index(name: &str) -> Result<NonZeroU32, std::io::Error> {
 	use std::ffi::CString;
 
@@ -126,7 +126,7 @@ fn client_and_server() {
 								assert_eq!(addr_v4.ip(), iface_v4.ip());
 							}
 
-							_ => assert!(false),
+							_ => unreachable!(),
 						}
 
 						if matches!(&event, DiscoveryEvent::ResponderFound(_)) {